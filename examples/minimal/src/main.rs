@@ -1,16 +1,6 @@
 use std::{env, path::PathBuf};
 
-use idevice::usbmuxd::{UsbmuxdAddr, UsbmuxdConnection};
-use isideload::{
-    anisette::remote_v3::RemoteV3AnisetteProvider,
-    auth::apple_account::AppleAccount,
-    dev::{
-        certificates::DevelopmentCertificate, developer_session::DeveloperSession,
-        teams::DeveloperTeam,
-    },
-    sideload::{SideloaderBuilder, TeamSelection, builder::MaxCertsBehavior},
-    util::keyring_storage::KeyringStorage,
-};
+use isideload::prelude::*;
 
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
@@ -56,21 +46,10 @@ async fn main() {
         .await
         .expect("Failed to create developer session");
 
-    let usbmuxd = UsbmuxdConnection::default().await;
-    if usbmuxd.is_err() {
-        panic!("Failed to connect to usbmuxd: {:?}", usbmuxd.err());
-    }
-    let mut usbmuxd = usbmuxd.unwrap();
-
-    let devs = usbmuxd.get_devices().await.unwrap();
-    if devs.is_empty() {
-        panic!("No devices found");
-    }
-
-    let provider = devs
-        .first()
-        .unwrap()
-        .to_provider(UsbmuxdAddr::from_env_var().unwrap(), "isideload-demo");
+    let devs = list_devices().await.expect("Failed to list devices");
+    let device = devs.first().expect("No devices found");
+    println!("Installing to {}", device);
+    let provider = device.provider("isideload-demo");
 
     let team_selection_prompt = |teams: &Vec<DeveloperTeam>| {
         println!("Please select a team:");
@@ -130,7 +109,19 @@ async fn main() {
 
     let result = sideloader.install_app(&provider, app_path, true).await;
     match result {
-        Ok(_) => println!("App installed successfully"),
+        Ok(outcome) => {
+            println!(
+                "Installed {} successfully in {:.1?}",
+                outcome
+                    .display_name
+                    .as_deref()
+                    .unwrap_or(&outcome.bundle_id),
+                outcome.sideload.duration
+            );
+            for warning in &outcome.warnings {
+                println!("Warning: {}", warning);
+            }
+        }
         Err(e) => panic!("{}", e),
     }
 }
@@ -0,0 +1,235 @@
+//! Anisette generated by macOS itself, without a remote server
+//!
+//! The anisette headers come from the system's own AuthKit/AOSKit frameworks, the same way Xcode gets them, so this
+//! module only exists on macOS. isideload doesn't generate anisette offline on other platforms: that needs Apple's
+//! Android ADI libraries (`libstoreservicescore.so` and `libCoreADI.so`) and an ELF loader for Android's libc to run
+//! them, as omnisette and Provision have. Use a remote anisette server there, with the `anisette-remote` feature.
+
+use std::sync::Arc;
+
+use rootcause::prelude::*;
+
+use crate::anisette::{AnisetteClientInfo, AnisetteData, AnisetteProvider};
+use crate::auth::grandslam::GrandSlam;
+
+const LOCAL_USER_AGENT: &str = "akd/1.0 CFNetwork/1494.0.7 Darwin/23.4.0";
+
+/// Generates anisette data with macOS's AOSKit framework
+pub struct MacosAnisetteProvider {
+    client_info: AnisetteClientInfo,
+}
+
+impl MacosAnisetteProvider {
+    /// Load the system's anisette implementation
+    ///
+    /// # Errors
+    /// Returns an error if the system frameworks could not be loaded
+    pub fn new() -> Result<Self, Report> {
+        let client_info = AnisetteClientInfo {
+            client_info: platform::client_info()?,
+            user_agent: LOCAL_USER_AGENT.to_string(),
+        };
+        Ok(Self { client_info })
+    }
+}
+
+#[async_trait::async_trait]
+impl AnisetteProvider for MacosAnisetteProvider {
+    async fn get_anisette_data(&self) -> Result<AnisetteData, Report> {
+        let headers = platform::headers()?;
+        Ok(AnisetteData::new(
+            headers.machine_id,
+            headers.one_time_password,
+            headers.routing_info,
+            headers.device_unique_identifier,
+            headers.local_user_id,
+            self.client_info.client_info.clone(),
+        ))
+    }
+
+    async fn get_client_info(&mut self) -> Result<AnisetteClientInfo, Report> {
+        Ok(self.client_info.clone())
+    }
+
+    async fn provision(&mut self, _gs: Arc<GrandSlam>) -> Result<(), Report> {
+        // the system is already provisioned
+        Ok(())
+    }
+
+    fn needs_provisioning(&self) -> Result<bool, Report> {
+        Ok(false)
+    }
+}
+
+struct SystemHeaders {
+    machine_id: String,
+    one_time_password: String,
+    routing_info: String,
+    device_unique_identifier: String,
+    local_user_id: String,
+}
+
+mod platform {
+    use std::ffi::{CStr, CString, c_char, c_int, c_void};
+
+    use rootcause::prelude::*;
+
+    use super::SystemHeaders;
+
+    type Id = *mut c_void;
+    type Sel = *mut c_void;
+
+    #[link(name = "objc")]
+    unsafe extern "C" {
+        fn objc_getClass(name: *const c_char) -> Id;
+        fn sel_registerName(name: *const c_char) -> Sel;
+        fn objc_msgSend();
+    }
+
+    #[link(name = "Foundation", kind = "framework")]
+    unsafe extern "C" {}
+
+    unsafe extern "C" {
+        fn dlopen(path: *const c_char, mode: c_int) -> *mut c_void;
+    }
+
+    const RTLD_LAZY: c_int = 0x1;
+    const AOSKIT_PATH: &str = "/System/Library/PrivateFrameworks/AOSKit.framework/AOSKit";
+    const AUTHKIT_PATH: &str = "/System/Library/PrivateFrameworks/AuthKit.framework/AuthKit";
+
+    // Apple's own clients send this routing info for a fresh machine
+    const DEFAULT_ROUTING_INFO: &str = "17106176";
+
+    fn load_frameworks() -> Result<(), Report> {
+        for path in [AOSKIT_PATH, AUTHKIT_PATH] {
+            let c_path = CString::new(path)?;
+            // SAFETY: dlopen is given a valid NUL terminated path. Loading the same framework again is a no-op.
+            let handle = unsafe { dlopen(c_path.as_ptr(), RTLD_LAZY) };
+            if handle.is_null() {
+                bail!("Failed to load {}", path);
+            }
+        }
+        Ok(())
+    }
+
+    fn class(name: &str) -> Result<Id, Report> {
+        let c_name = CString::new(name)?;
+        // SAFETY: objc_getClass only reads the NUL terminated name
+        let class = unsafe { objc_getClass(c_name.as_ptr()) };
+        if class.is_null() {
+            bail!("Objective-C class {} not found", name);
+        }
+        Ok(class)
+    }
+
+    fn sel(name: &str) -> Sel {
+        let c_name = CString::new(name).expect("selector names never contain NUL");
+        // SAFETY: sel_registerName only reads the NUL terminated name
+        unsafe { sel_registerName(c_name.as_ptr()) }
+    }
+
+    /// Send a message without arguments that returns an object
+    ///
+    /// # Safety
+    /// `receiver` must be a valid object or class that responds to `selector` with an object (or nil)
+    unsafe fn send(receiver: Id, selector: &str) -> Id {
+        // SAFETY: objc_msgSend must be called through a pointer with the method's exact signature
+        let msg_send: unsafe extern "C" fn(Id, Sel) -> Id =
+            unsafe { std::mem::transmute(objc_msgSend as unsafe extern "C" fn()) };
+        unsafe { msg_send(receiver, sel(selector)) }
+    }
+
+    /// Send a message with one pointer sized argument that returns an object
+    ///
+    /// # Safety
+    /// `receiver` must be a valid object or class that responds to `selector` with an object (or nil)
+    unsafe fn send_with(receiver: Id, selector: &str, arg: *const c_void) -> Id {
+        // SAFETY: see `send`
+        let msg_send: unsafe extern "C" fn(Id, Sel, *const c_void) -> Id =
+            unsafe { std::mem::transmute(objc_msgSend as unsafe extern "C" fn()) };
+        unsafe { msg_send(receiver, sel(selector), arg) }
+    }
+
+    fn ns_string(value: &str) -> Result<Id, Report> {
+        let c_value = CString::new(value)?;
+        // SAFETY: NSString responds to stringWithUTF8String: with an autoreleased string
+        Ok(unsafe {
+            send_with(
+                class("NSString")?,
+                "stringWithUTF8String:",
+                c_value.as_ptr() as *const c_void,
+            )
+        })
+    }
+
+    fn to_string(ns_string: Id) -> Option<String> {
+        if ns_string.is_null() {
+            return None;
+        }
+        // SAFETY: the object is an NSString, which responds to UTF8String with a C string that lives as long as it does
+        let c_str = unsafe { send(ns_string, "UTF8String") } as *const c_char;
+        if c_str.is_null() {
+            return None;
+        }
+        // SAFETY: UTF8String returns a valid NUL terminated string
+        Some(
+            unsafe { CStr::from_ptr(c_str) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
+    fn current_device() -> Result<Id, Report> {
+        // SAFETY: AKDevice responds to currentDevice with the shared device object
+        let device = unsafe { send(class("AKDevice")?, "currentDevice") };
+        if device.is_null() {
+            bail!("AKDevice currentDevice returned nil");
+        }
+        Ok(device)
+    }
+
+    fn device_string(selector: &str) -> Result<String, Report> {
+        let device = current_device()?;
+        // SAFETY: all AKDevice selectors used here return NSStrings
+        to_string(unsafe { send(device, selector) })
+            .ok_or_else(|| report!("AKDevice {} returned nil", selector))
+    }
+
+    pub(super) fn client_info() -> Result<String, Report> {
+        load_frameworks()?;
+        device_string("serverFriendlyDescription")
+    }
+
+    pub(super) fn headers() -> Result<SystemHeaders, Report> {
+        load_frameworks()?;
+
+        // -2 is the DSID used for requests that aren't tied to an account yet
+        let dsid = ns_string("-2")?;
+        // SAFETY: AOSUtilities responds to retrieveOTPHeadersForDSID: with an NSDictionary of header strings
+        let headers = unsafe {
+            send_with(
+                class("AOSUtilities")?,
+                "retrieveOTPHeadersForDSID:",
+                dsid as *const c_void,
+            )
+        };
+        if headers.is_null() {
+            bail!("AOSKit did not return any anisette headers");
+        }
+
+        let header = |name: &str| -> Result<String, Report> {
+            let key = ns_string(name)?;
+            // SAFETY: headers is an NSDictionary, which responds to objectForKey:
+            to_string(unsafe { send_with(headers, "objectForKey:", key as *const c_void) })
+                .ok_or_else(|| report!("AOSKit did not return the {} header", name))
+        };
+
+        Ok(SystemHeaders {
+            machine_id: header("X-Apple-MD-M")?,
+            one_time_password: header("X-Apple-MD")?,
+            routing_info: DEFAULT_ROUTING_INFO.to_string(),
+            device_unique_identifier: device_string("uniqueDeviceIdentifier")?,
+            local_user_id: device_string("localUserUUID")?,
+        })
+    }
+}
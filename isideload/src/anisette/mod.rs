@@ -1,21 +1,120 @@
+#[cfg(all(feature = "anisette-macos", target_os = "macos"))]
+pub mod macos;
+#[cfg(feature = "anisette-remote")]
 pub mod remote_v3;
 
-use crate::auth::grandslam::GrandSlam;
+use crate::{
+    auth::grandslam::GrandSlam,
+    util::clock::{Clock, SystemClock},
+};
 use plist::Dictionary;
 use plist_macro::plist;
 use reqwest::header::HeaderMap;
 use rootcause::prelude::*;
 use serde::Deserialize;
-use std::{collections::HashMap, sync::Arc, time::SystemTime};
-use tokio::sync::RwLock;
-use tracing::warn;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, warn};
 
+/// Identifies the client the anisette data was generated for, see [`AnisetteProvider::get_client_info`]
 #[derive(Deserialize, Debug, Clone)]
 pub struct AnisetteClientInfo {
+    /// Sent as `X-Mme-Client-Info`, e.g. `<MacBookPro13,2> <macOS;13.1;22C65> <com.apple.AuthKit/1 (com.apple.dt.Xcode/3594.4.19)>`
     pub client_info: String,
+    /// Sent as the `User-Agent` of GrandSlam requests, e.g. `akd/1.0 CFNetwork/1494.0.7 Darwin/23.4.0`
     pub user_agent: String,
 }
 
+/// Overrides applied on top of the client info reported by the anisette provider
+///
+/// Mismatched client info between anisette and GrandSlam requests is a known cause of -6005 errors,
+/// so this allows pinning either value instead of trusting whatever the provider reports.
+#[derive(Debug, Clone, Default)]
+pub struct AnisetteClientInfoOverride {
+    pub client_info: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AnisetteClientInfoOverride {
+    pub fn is_empty(&self) -> bool {
+        self.client_info.is_none() && self.user_agent.is_none()
+    }
+
+    pub fn apply(&self, mut info: AnisetteClientInfo) -> AnisetteClientInfo {
+        if let Some(client_info) = &self.client_info {
+            info.client_info = client_info.clone();
+        }
+        if let Some(user_agent) = &self.user_agent {
+            info.user_agent = user_agent.clone();
+        }
+        info
+    }
+}
+
+/// The locale sent to Apple until the account's region is known
+pub const DEFAULT_LOCALE: &str = "en_US";
+
+/// The locale used for an account's region, for the regions Apple's stores are available in most
+///
+/// Regions missing from here use [`DEFAULT_LOCALE`], since a locale made up from the region might not exist.
+const REGION_LOCALES: &[(&str, &str)] = &[
+    ("AE", "ar_AE"),
+    ("AR", "es_AR"),
+    ("AT", "de_AT"),
+    ("AU", "en_AU"),
+    ("BE", "fr_BE"),
+    ("BR", "pt_BR"),
+    ("CA", "en_CA"),
+    ("CH", "de_CH"),
+    ("CL", "es_CL"),
+    ("CN", "zh_CN"),
+    ("CO", "es_CO"),
+    ("CZ", "cs_CZ"),
+    ("DE", "de_DE"),
+    ("DK", "da_DK"),
+    ("ES", "es_ES"),
+    ("FI", "fi_FI"),
+    ("FR", "fr_FR"),
+    ("GB", "en_GB"),
+    ("GR", "el_GR"),
+    ("HK", "zh_HK"),
+    ("HU", "hu_HU"),
+    ("ID", "id_ID"),
+    ("IE", "en_IE"),
+    ("IL", "he_IL"),
+    ("IN", "en_IN"),
+    ("IT", "it_IT"),
+    ("JP", "ja_JP"),
+    ("KR", "ko_KR"),
+    ("MX", "es_MX"),
+    ("MY", "ms_MY"),
+    ("NL", "nl_NL"),
+    ("NO", "nb_NO"),
+    ("NZ", "en_NZ"),
+    ("PH", "en_PH"),
+    ("PL", "pl_PL"),
+    ("PT", "pt_PT"),
+    ("RO", "ro_RO"),
+    ("RU", "ru_RU"),
+    ("SA", "ar_SA"),
+    ("SE", "sv_SE"),
+    ("SG", "en_SG"),
+    ("TH", "th_TH"),
+    ("TR", "tr_TR"),
+    ("TW", "zh_TW"),
+    ("UA", "uk_UA"),
+    ("US", "en_US"),
+    ("VN", "vi_VN"),
+    ("ZA", "en_ZA"),
+];
+
+/// How long anisette data is reused before new data is requested from the provider
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Clone)]
 pub struct AnisetteData {
     machine_id: String,
@@ -25,10 +124,39 @@ pub struct AnisetteData {
     device_unique_identifier: String,
     _local_user_id: String,
     generated_at: SystemTime,
+    locale: String,
 }
 
 // Some headers don't seem to be required. I guess not including them is technically more efficient soooo
 impl AnisetteData {
+    /// Anisette data generated by an [`AnisetteProvider`], stamped with the current time
+    ///
+    /// - `machine_id`: `X-Apple-I-MD-M`, identifies the provisioned machine
+    /// - `one_time_password`: `X-Apple-I-MD`, only valid for a short time
+    /// - `routing_info`: `X-Apple-I-MD-RINFO`
+    /// - `device_unique_identifier`: `X-Mme-Device-Id`, must stay the same for as long as the machine is provisioned
+    /// - `local_user_id`: `X-Apple-I-MD-LU`
+    /// - `device_description`: `X-Mme-Client-Info`, usually the [`AnisetteClientInfo::client_info`]
+    pub fn new(
+        machine_id: String,
+        one_time_password: String,
+        routing_info: String,
+        device_unique_identifier: String,
+        local_user_id: String,
+        device_description: String,
+    ) -> Self {
+        AnisetteData {
+            machine_id,
+            one_time_password,
+            routing_info,
+            _device_description: device_description,
+            device_unique_identifier,
+            _local_user_id: local_user_id,
+            generated_at: SystemTime::now(),
+            locale: DEFAULT_LOCALE.to_string(),
+        }
+    }
+
     pub fn get_headers(&self) -> HashMap<String, String> {
         //let dt: DateTime<Utc> = Utc::now().round_subsecs(0);
 
@@ -39,7 +167,7 @@ impl AnisetteData {
             // ),
             // ("X-Apple-I-SRL-NO".to_string(), serial),
             // ("X-Apple-I-TimeZone".to_string(), "UTC".to_string()),
-            // ("X-Apple-Locale".to_string(), "en_US".to_string()),
+            ("X-Apple-Locale".to_string(), self.locale.clone()),
             // ("X-Apple-I-MD-RINFO".to_string(), self.routing_info.clone()),
             // ("X-Apple-I-MD-LU".to_string(), self.local_user_id.clone()),
             (
@@ -75,7 +203,7 @@ impl AnisetteData {
         let mut cpd = plist!(dict {
             "bootstrap": "true",
             "icscrec": "true",
-            "loc": "en_US",
+            "loc": self.locale.clone(),
             "pbe": "false",
             "prkgen": "true",
             "svct": "iCloud"
@@ -88,11 +216,15 @@ impl AnisetteData {
         cpd
     }
 
-    pub fn needs_refresh(&self) -> bool {
-        let elapsed = self.generated_at.elapsed();
-        match elapsed {
-            Ok(elapsed) => elapsed.as_secs() > 60,
-            Err(_) => {
+    pub fn needs_refresh(&self, clock: &dyn Clock) -> bool {
+        self.is_older_than(clock, DEFAULT_REFRESH_INTERVAL)
+    }
+
+    /// Whether the data was generated more than `max_age` ago according to `clock`
+    pub fn is_older_than(&self, clock: &dyn Clock, max_age: Duration) -> bool {
+        match clock.age(self.generated_at) {
+            Some(age) => age > max_age,
+            None => {
                 warn!("Unable to determine anisette data age, treating as expired");
                 true
             }
@@ -100,65 +232,226 @@ impl AnisetteData {
     }
 }
 
+/// A source of anisette data, the machine identity Apple requires on every login and developer request
+///
+/// Implement this to plug in other anisette sources, like a local ADI implementation, a Mac on the network or a
+/// proxy, and pass it to [`crate::auth::builder::AppleAccountBuilder::anisette_provider`]. The trait is object safe,
+/// so providers can also be chosen at runtime as a `Box<dyn AnisetteProvider>`.
+///
+/// [`AnisetteDataGenerator`] drives the provider: it checks [`Self::needs_provisioning`] before every fetch and calls
+/// [`Self::provision`] with exclusive access if it returns `true`, then calls [`Self::get_anisette_data`], caching
+/// the result for the refresh interval. Only one fetch runs at a time, but the provider may be shared by several
+/// accounts and sessions, so it must be `Send` and `Sync`.
 #[async_trait::async_trait]
-pub trait AnisetteProvider {
+pub trait AnisetteProvider: Send + Sync {
+    /// Generate fresh anisette data, see [`AnisetteData::new`]
+    ///
+    /// Only called once [`Self::needs_provisioning`] returned `false`. Fail with
+    /// [`crate::SideloadError::AnisetteNotProvisioned`] if the provider turns out not to be provisioned after all.
     async fn get_anisette_data(&self) -> Result<AnisetteData, Report>;
 
+    /// The client the anisette data is generated for
+    ///
+    /// It is sent along with the anisette data, and must match the machine the data comes from, otherwise Apple
+    /// rejects logins with error -6005. Called once when an account is created.
     async fn get_client_info(&mut self) -> Result<AnisetteClientInfo, Report>;
 
+    /// Provision the machine identity, e.g. by registering it with Apple through `gs`
+    ///
+    /// Providers whose machine is provisioned some other way, like the system's own anisette, can do nothing.
     async fn provision(&mut self, gs: Arc<GrandSlam>) -> Result<(), Report>;
 
+    /// Whether [`Self::provision`] has to be called before anisette data can be generated
     fn needs_provisioning(&self) -> Result<bool, Report>;
+
+    /// Forget the machine identity, so a new one is provisioned before the next request
+    ///
+    /// Apple treats the next login as coming from a new machine, which usually requires two factor authentication
+    /// again. Providers that can't change their identity, like the system's own anisette, fail.
+    async fn reset(&mut self) -> Result<(), Report> {
+        bail!("This anisette provider can't reset its identity")
+    }
+
+    /// Save any state needed to skip provisioning next time
+    fn persist(&self) -> Result<(), Report> {
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: AnisetteProvider + ?Sized> AnisetteProvider for Box<P> {
+    async fn get_anisette_data(&self) -> Result<AnisetteData, Report> {
+        (**self).get_anisette_data().await
+    }
+
+    async fn get_client_info(&mut self) -> Result<AnisetteClientInfo, Report> {
+        (**self).get_client_info().await
+    }
+
+    async fn provision(&mut self, gs: Arc<GrandSlam>) -> Result<(), Report> {
+        (**self).provision(gs).await
+    }
+
+    fn needs_provisioning(&self) -> Result<bool, Report> {
+        (**self).needs_provisioning()
+    }
+
+    async fn reset(&mut self) -> Result<(), Report> {
+        (**self).reset().await
+    }
+
+    fn persist(&self) -> Result<(), Report> {
+        (**self).persist()
+    }
 }
 
+/// Generates anisette data for requests to Apple, reusing it for the refresh interval
+///
+/// Clones share the cached data, and only one of them asks the provider for new data at a time.
+/// Concurrent requests wait for that fetch and reuse its result instead of all hitting the anisette server.
 #[derive(Clone)]
 pub struct AnisetteDataGenerator {
-    provider: Arc<RwLock<dyn AnisetteProvider + Send + Sync>>,
-    data: Option<Arc<AnisetteData>>,
+    provider: Arc<RwLock<dyn AnisetteProvider>>,
+    data: Arc<Mutex<Option<Arc<AnisetteData>>>>,
+    refresh_interval: Duration,
+    client_info_override: AnisetteClientInfoOverride,
+    locale: String,
+    locale_pinned: bool,
+    clock: Arc<dyn Clock>,
 }
 
 impl AnisetteDataGenerator {
-    pub fn new(provider: Arc<RwLock<dyn AnisetteProvider + Send + Sync>>) -> Self {
+    pub fn new(provider: Arc<RwLock<dyn AnisetteProvider>>) -> Self {
         AnisetteDataGenerator {
             provider,
-            data: None,
+            data: Arc::new(Mutex::new(None)),
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            client_info_override: AnisetteClientInfoOverride::default(),
+            locale: DEFAULT_LOCALE.to_string(),
+            locale_pinned: false,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Set the clock the age of the cached data is checked against. Defaults to the system clock.
+    ///
+    /// New data is stamped with this clock when it is received from the provider.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Set how long anisette data is reused before new data is requested. Defaults to [`DEFAULT_REFRESH_INTERVAL`].
+    ///
+    /// Apple rejects one-time passwords that are too old, so intervals longer than a couple of minutes will cause
+    /// login and developer requests to fail.
+    pub fn set_refresh_interval(&mut self, interval: Duration) {
+        self.refresh_interval = interval;
+    }
+
+    /// The locale sent in the `X-Apple-Locale` header and `loc` field, such as `en_US`
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// Always use this locale, instead of deriving it from the account's region
+    pub fn pin_locale(&mut self, locale: &str) {
+        self.locale = locale.to_string();
+        self.locale_pinned = true;
+    }
+
+    /// Use a locale matching the account's region (e.g. `de_DE` for `DE`), unless the locale was pinned
+    ///
+    /// Regions without a known locale use [`DEFAULT_LOCALE`], use [`Self::pin_locale`] to choose one for them.
+    pub fn set_region(&mut self, region: &str) {
+        if self.locale_pinned {
+            return;
+        }
+        let locale = REGION_LOCALES
+            .iter()
+            .find(|(r, _)| r.eq_ignore_ascii_case(region))
+            .map(|(_, locale)| *locale)
+            .unwrap_or_else(|| {
+                debug!(
+                    "No locale known for region {}, using {}",
+                    region, DEFAULT_LOCALE
+                );
+                DEFAULT_LOCALE
+            });
+        if locale != self.locale {
+            debug!("Using locale {} for region {}", locale, region);
+            self.locale = locale.to_string();
+        }
+    }
+
+    /// Override parts of the client info reported by the provider
+    pub fn set_client_info_override(&mut self, client_info_override: AnisetteClientInfoOverride) {
+        self.client_info_override = client_info_override;
+    }
+
     pub async fn get_anisette_data(
         &mut self,
         gs: Arc<GrandSlam>,
     ) -> Result<Arc<AnisetteData>, Report> {
-        if let Some(data) = &self.data
-            && !data.needs_refresh()
+        // Held while fetching, so concurrent callers wait for this fetch instead of starting their own
+        let mut cached = self.data.lock().await;
+        if let Some(data) = cached.as_ref()
+            && !data.is_older_than(self.clock.as_ref(), self.refresh_interval)
         {
-            return Ok(data.clone());
+            return Ok(self.localized(data));
         }
 
         // trying to avoid locking as write unless necessary to promote concurrency
         let provider = self.provider.read().await;
 
-        if provider.needs_provisioning()? {
+        let data = if provider.needs_provisioning()? {
             drop(provider);
             let mut provider_write = self.provider.write().await;
             provider_write.provision(gs).await?;
             drop(provider_write);
 
-            let provider = self.provider.read().await;
-            let data = provider.get_anisette_data().await?;
-            let arc_data = Arc::new(data);
-            self.data = Some(arc_data.clone());
-            Ok(arc_data)
+            self.provider.read().await.get_anisette_data().await?
         } else {
-            let data = provider.get_anisette_data().await?;
-            let arc_data = Arc::new(data);
-            self.data = Some(arc_data.clone());
-            Ok(arc_data)
+            provider.get_anisette_data().await?
+        };
+
+        let data = Arc::new(AnisetteData {
+            generated_at: self.clock.now(),
+            ..data
+        });
+        *cached = Some(data.clone());
+        Ok(self.localized(&data))
+    }
+
+    /// The cached data with this generator's locale, since clones may use different locales
+    fn localized(&self, data: &Arc<AnisetteData>) -> Arc<AnisetteData> {
+        if data.locale == self.locale {
+            return data.clone();
         }
+        Arc::new(AnisetteData {
+            locale: self.locale.clone(),
+            ..AnisetteData::clone(data)
+        })
+    }
+
+    /// Reset the provider's machine identity and drop the cached data, see [`AnisetteProvider::reset`]
+    pub async fn reset(&self) -> Result<(), Report> {
+        let mut cached = self.data.lock().await;
+        self.provider.write().await.reset().await?;
+        *cached = None;
+        Ok(())
+    }
+
+    /// Save the provider's state to its storage
+    pub async fn persist(&self) -> Result<(), Report> {
+        self.provider.read().await.persist()
     }
 
     pub async fn get_client_info(&self) -> Result<AnisetteClientInfo, Report> {
         let mut provider = self.provider.write().await;
-        provider.get_client_info().await
+        let info = provider.get_client_info().await?;
+        if !self.client_info_override.is_empty() {
+            debug!("Applying anisette client info override");
+        }
+        Ok(self.client_info_override.apply(info))
     }
 }
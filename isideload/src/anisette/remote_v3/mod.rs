@@ -1,7 +1,8 @@
 mod state;
 
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use base64::prelude::*;
 use plist_macro::plist;
@@ -16,24 +17,35 @@ use tracing::{debug, info, warn};
 use crate::SideloadError;
 use crate::anisette::remote_v3::state::AnisetteState;
 use crate::anisette::{AnisetteClientInfo, AnisetteData, AnisetteProvider};
-use crate::auth::grandslam::GrandSlam;
+use crate::auth::{account_manager::account_namespace, grandslam::GrandSlam, retry::RetryPolicy};
 use crate::util::plist::PlistDataExtract;
 use crate::util::storage::{SideloadingStorage, new_storage};
 use futures_util::{SinkExt, StreamExt};
 
 pub const DEFAULT_ANISETTE_V3_URL: &str = "https://ani.stikstore.app";
 
+/// How long an anisette server gets to return its client info before the next one is tried
+const SERVER_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The storage key of the anisette identity shared by every account that doesn't have its own
+const SHARED_STATE_KEY: &str = "anisette_state";
+
 pub struct RemoteV3AnisetteProvider {
     pub state: Option<AnisetteState>,
-    url: String,
+    /// The anisette servers to use, in order of preference
+    urls: Vec<String>,
+    /// The index in `urls` of the server that answered last, which is tried first
+    active_url: AtomicUsize,
     storage: Box<dyn SideloadingStorage>,
+    state_key: String,
     serial_number: String,
     client_info: Option<AnisetteClientInfo>,
+    legacy_state_path: Option<PathBuf>,
     client: reqwest::Client,
 }
 
 impl RemoteV3AnisetteProvider {
-    /// Create a new RemoteV3AnisetteProvider with the given URL and config path
+    /// Create a new RemoteV3AnisetteProvider with the given URL and storage
     ///
     /// # Arguments
     /// - `url`: The URL of the remote anisette service
@@ -46,10 +58,13 @@ impl RemoteV3AnisetteProvider {
     ) -> Result<Self, Report> {
         Ok(Self {
             state: None,
-            url: url.to_string(),
+            urls: vec![url.to_string()],
+            active_url: AtomicUsize::new(0),
             storage,
+            state_key: SHARED_STATE_KEY.to_string(),
             serial_number,
             client_info: None,
+            legacy_state_path: None,
             client: reqwest::ClientBuilder::new()
                 .build()
                 .context("Failed to build HTTP client")?,
@@ -65,19 +80,64 @@ impl RemoteV3AnisetteProvider {
     }
 
     pub fn set_url(mut self, url: &str) -> RemoteV3AnisetteProvider {
-        self.url = url.to_string();
+        self.urls = vec![url.to_string()];
+        self.active_url = AtomicUsize::new(0);
+        self
+    }
+
+    /// Use several anisette servers, in order of preference
+    ///
+    /// When the server in use fails, the next one is tried, and the one that answers is kept for later requests.
+    /// Anisette v3 servers don't store any state, so the provisioned identity works with all of them. An empty list
+    /// is ignored.
+    pub fn set_urls(mut self, urls: Vec<String>) -> RemoteV3AnisetteProvider {
+        if !urls.is_empty() {
+            self.urls = urls;
+            self.active_url = AtomicUsize::new(0);
+        }
         self
     }
 
+    /// The URL of the anisette server in use, which is the one that answered last
+    pub fn url(&self) -> &str {
+        &self.urls[self.active_url.load(Ordering::Relaxed)]
+    }
+
     pub fn set_storage(mut self, storage: Box<dyn SideloadingStorage>) -> RemoteV3AnisetteProvider {
         self.storage = storage;
         self
     }
 
+    /// Give the account its own anisette machine identity, instead of the one shared by every account using the
+    /// same storage
+    ///
+    /// Sharing one provisioned identity between Apple IDs makes security challenges more likely, and resetting it
+    /// affects all of them. The identity is stored under a key derived from `email`. If the storage still has a
+    /// shared identity from before, the first account to use this takes it over, so its session stays valid.
+    pub fn set_account(mut self, email: &str) -> RemoteV3AnisetteProvider {
+        self.state_key = format!("{}/{}", account_namespace(email), SHARED_STATE_KEY);
+        self
+    }
+
     pub fn set_serial_number(mut self, serial_number: String) -> RemoteV3AnisetteProvider {
         self.serial_number = serial_number;
         self
     }
+
+    /// Import the `state.plist` written by older versions into the storage backend
+    ///
+    /// `path` is the `state.plist` inside the old config directory. It is only read when the storage has no
+    /// anisette state yet, and is renamed to `state.plist.migrated` once imported.
+    pub fn set_legacy_state_path(mut self, path: PathBuf) -> RemoteV3AnisetteProvider {
+        self.legacy_state_path = Some(path);
+        self
+    }
+
+    /// Pin the client info instead of fetching it from the remote server's `client_info` endpoint
+    pub fn set_client_info(mut self, client_info: AnisetteClientInfo) -> RemoteV3AnisetteProvider {
+        self.client_info = Some(client_info);
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -96,41 +156,41 @@ impl AnisetteProvider for RemoteV3AnisetteProvider {
             .as_ref()
             .ok_or(SideloadError::AnisetteNotProvisioned)?;
 
-        let headers = self
-            .client
-            .post(format!("{}/v3/get_headers", self.url))
-            .header(CONTENT_TYPE, "application/json")
-            .body(
-                serde_json::json!({
-                "identifier": BASE64_STANDARD.encode(state.keychain_identifier),
-                "adi_pb": BASE64_STANDARD.encode(adi_pb)
-                })
-                .to_string(),
-            )
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<AnisetteHeaders>()
-            .await?;
+        let mut last_error = None;
+        let mut headers = None;
+        for index in url_order(self.urls.len(), self.active_url.load(Ordering::Relaxed)) {
+            let url = &self.urls[index];
+            match self.get_headers(url, state, adi_pb).await {
+                Ok(h) => {
+                    self.use_url(index);
+                    headers = Some(h);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Anisette server {} failed: {:?}", url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        let headers = match (headers, last_error) {
+            (Some(headers), _) => headers,
+            (None, Some(e)) => return Err(e.context("All anisette servers failed").into_dynamic()),
+            (None, None) => bail!("No anisette servers configured"),
+        };
 
         match headers {
             AnisetteHeaders::Headers {
                 machine_id,
                 one_time_password,
                 routing_info,
-            } => {
-                let data = AnisetteData {
-                    machine_id,
-                    one_time_password,
-                    routing_info,
-                    _device_description: client_info.client_info.clone(),
-                    device_unique_identifier: state.get_device_id(),
-                    _local_user_id: hex::encode(state.get_md_lu()),
-                    generated_at: SystemTime::now(),
-                };
-
-                Ok(data)
-            }
+            } => Ok(AnisetteData::new(
+                machine_id,
+                one_time_password,
+                routing_info,
+                state.get_device_id(),
+                hex::encode(state.get_md_lu()),
+                client_info.client_info.clone(),
+            )),
             AnisetteHeaders::GetHeadersError { message } => {
                 Err(report!("Failed to get anisette headers").attach(message))
             }
@@ -141,15 +201,7 @@ impl AnisetteProvider for RemoteV3AnisetteProvider {
         match self.client_info {
             Some(ref info) => Ok(info.clone()),
             None => {
-                let resp = self
-                    .client
-                    .get(format!("{}/v3/client_info", self.url))
-                    .send()
-                    .await?
-                    .error_for_status()?
-                    .json::<AnisetteClientInfo>()
-                    .await?;
-
+                let resp = self.select_server().await?;
                 self.client_info = Some(resp.clone());
                 Ok(resp)
             }
@@ -169,12 +221,103 @@ impl AnisetteProvider for RemoteV3AnisetteProvider {
         self.get_state(gs).await?;
         Ok(())
     }
+
+    async fn reset(&mut self) -> Result<(), Report> {
+        self.reset_identity()
+    }
+
+    fn persist(&self) -> Result<(), Report> {
+        if let Some(state) = &self.state
+            && state.is_provisioned()
+        {
+            Self::save_state(self.storage.as_ref(), &self.state_key, state)?;
+        }
+        self.storage.flush()
+    }
 }
 
 impl RemoteV3AnisetteProvider {
+    /// Forget the anisette identity, so a new one is provisioned on the next request
+    ///
+    /// Only this provider's identity is removed, other accounts with their own identity are unaffected. Apple will
+    /// treat the next login as coming from a new machine, which usually requires two factor authentication again.
+    pub fn reset_identity(&mut self) -> Result<(), Report> {
+        self.state = None;
+        self.storage.delete(&self.state_key)?;
+        self.storage.flush()?;
+        info!("Reset anisette identity");
+        Ok(())
+    }
+
+    /// Probe the anisette servers in order of preference and switch to the first one that returns its client info
+    ///
+    /// Called automatically the first time the client info is needed, unless it was pinned with
+    /// [`Self::set_client_info`]. Returns the client info of the selected server, see [`Self::url`] for which it is.
+    pub async fn select_server(&self) -> Result<AnisetteClientInfo, Report> {
+        let mut errors = Vec::new();
+        for index in url_order(self.urls.len(), self.active_url.load(Ordering::Relaxed)) {
+            let url = &self.urls[index];
+            match self.fetch_client_info(url).await {
+                Ok(client_info) => {
+                    self.use_url(index);
+                    return Ok(client_info);
+                }
+                Err(e) => {
+                    warn!("Anisette server {} is unavailable: {:?}", url, e);
+                    errors.push(format!("{}: {}", url, e));
+                }
+            }
+        }
+        bail!("No anisette server is available:\n{}", errors.join("\n"))
+    }
+
+    async fn fetch_client_info(&self, url: &str) -> Result<AnisetteClientInfo, Report> {
+        Ok(self
+            .client
+            .get(format!("{}/v3/client_info", url))
+            .timeout(SERVER_PROBE_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<AnisetteClientInfo>()
+            .await?)
+    }
+
+    async fn get_headers(
+        &self,
+        url: &str,
+        state: &AnisetteState,
+        adi_pb: &[u8],
+    ) -> Result<AnisetteHeaders, Report> {
+        Ok(self
+            .client
+            .post(format!("{}/v3/get_headers", url))
+            .header(CONTENT_TYPE, "application/json")
+            .body(
+                serde_json::json!({
+                "identifier": BASE64_STANDARD.encode(state.keychain_identifier),
+                "adi_pb": BASE64_STANDARD.encode(adi_pb)
+                })
+                .to_string(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<AnisetteHeaders>()
+            .await?)
+    }
+
+    fn use_url(&self, index: usize) {
+        if index != self.active_url.swap(index, Ordering::Relaxed) {
+            info!("Switched to anisette server {}", self.urls[index]);
+        }
+    }
+
     async fn get_state(&mut self, gs: Arc<GrandSlam>) -> Result<&mut AnisetteState, Report> {
         if self.state.is_none() {
-            if let Ok(Some(state)) = &self.storage.retrieve_data("anisette_state") {
+            self.migrate_legacy_state();
+            self.claim_shared_state();
+            if let Ok(Some(state)) = &self.storage.retrieve_data(&self.state_key) {
                 if let Ok(state) = plist::from_bytes(state) {
                     info!("Loaded existing anisette state");
                     self.state = Some(state);
@@ -191,19 +334,106 @@ impl RemoteV3AnisetteProvider {
         let state = self.state.as_mut().ok_or_report()?;
         if !state.is_provisioned() {
             info!("Provisioning required...");
-            Self::provision(state, gs, &self.url)
-                .await
-                .context("Failed to provision")?;
+            let mut provisioned = false;
+            for index in url_order(self.urls.len(), self.active_url.load(Ordering::Relaxed)) {
+                match Self::provision(state, gs.clone(), &self.urls[index]).await {
+                    Ok(()) => {
+                        if index != self.active_url.swap(index, Ordering::Relaxed) {
+                            info!("Switched to anisette server {}", self.urls[index]);
+                        }
+                        provisioned = true;
+                        break;
+                    }
+                    Err(e) => warn!(
+                        "Failed to provision with anisette server {}: {:?}",
+                        self.urls[index], e
+                    ),
+                }
+            }
+            if !provisioned {
+                bail!("Failed to provision with any anisette server");
+            }
         }
-        let buf = Vec::new();
-        let mut writer = std::io::BufWriter::new(buf);
-        plist::to_writer_xml(&mut writer, &state)?;
-        self.storage
-            .store_data("anisette_state", &writer.into_inner()?)?;
+        Self::save_state(self.storage.as_ref(), &self.state_key, state)?;
 
         Ok(state)
     }
 
+    fn migrate_legacy_state(&self) {
+        let Some(path) = &self.legacy_state_path else {
+            return;
+        };
+        if !path.exists() || matches!(self.storage.retrieve_data(&self.state_key), Ok(Some(_))) {
+            return;
+        }
+
+        let state: AnisetteState = match plist::from_file(path) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Failed to parse legacy anisette state, ignoring: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = Self::save_state(self.storage.as_ref(), &self.state_key, &state) {
+            warn!("Failed to migrate legacy anisette state: {:?}", e);
+            return;
+        }
+        info!("Migrated legacy anisette state from {}", path.display());
+
+        if let Err(e) = std::fs::rename(path, path.with_extension("plist.migrated")) {
+            warn!("Failed to rename legacy anisette state: {}", e);
+        }
+    }
+
+    /// Move the shared identity, or one stored under the account's old key, to this account, if the account has its
+    /// own key and no identity yet
+    fn claim_shared_state(&self) {
+        if self.state_key == SHARED_STATE_KEY
+            || matches!(self.storage.retrieve_data(&self.state_key), Ok(Some(_)))
+        {
+            return;
+        }
+        // older versions kept per-account identities under `anisette_state.<account>`
+        let previous_key = self
+            .state_key
+            .split_once('/')
+            .map(|(account, _)| format!("{}.{}", SHARED_STATE_KEY, account));
+        for key in previous_key
+            .as_deref()
+            .into_iter()
+            .chain([SHARED_STATE_KEY])
+        {
+            let Ok(Some(state)) = self.storage.retrieve_data(key) else {
+                continue;
+            };
+            if state.is_empty() {
+                continue;
+            }
+
+            if let Err(e) = self.storage.store_data(&self.state_key, &state) {
+                warn!("Failed to move anisette state from {}: {:?}", key, e);
+                return;
+            }
+            if let Err(e) = self.storage.delete(key) {
+                warn!("Failed to remove anisette state from {}: {:?}", key, e);
+            }
+            info!("Moved the anisette identity from {} to this account", key);
+            return;
+        }
+    }
+
+    fn save_state(
+        storage: &dyn SideloadingStorage,
+        key: &str,
+        state: &AnisetteState,
+    ) -> Result<(), Report> {
+        let buf = Vec::new();
+        let mut writer = std::io::BufWriter::new(buf);
+        plist::to_writer_xml(&mut writer, state)?;
+        storage.store_data(key, &writer.into_inner()?)?;
+        Ok(())
+    }
+
     async fn provisioning_headers(state: &AnisetteState) -> Result<HeaderMap, Report> {
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -322,11 +552,13 @@ impl RemoteV3AnisetteProvider {
                         }
                     });
 
+                    // the cpim can only be used once, so a retry couldn't succeed
                     let response = gs
-                        .plist_request(
+                        .plist_request_with_policy(
                             &end_provisioning,
                             &body,
                             Some(Self::provisioning_headers(state).await?),
+                            &RetryPolicy::none(),
                         )
                         .await
                         .context("Failed to send end provisioning request")?;
@@ -404,3 +636,8 @@ enum AnisetteHeaders {
         routing_info: String,
     },
 }
+
+/// Indices of `count` servers, starting with `active` and then the rest in order
+fn url_order(count: usize, active: usize) -> impl Iterator<Item = usize> {
+    std::iter::once(active).chain((0..count).filter(move |i| *i != active))
+}
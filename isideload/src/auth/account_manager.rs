@@ -0,0 +1,142 @@
+use std::{collections::HashMap, sync::Arc};
+
+use rootcause::prelude::*;
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "anisette-remote")]
+use crate::anisette::remote_v3::RemoteV3AnisetteProvider;
+use crate::{
+    auth::{apple_account::AppleAccount, builder::AppleAccountBuilder},
+    util::storage::{NamespacedStorage, SideloadingStorage},
+};
+
+/// Keeps several logged in Apple IDs apart, for frontends that manage more than one account
+///
+/// Every account gets its own namespace in the shared storage, keyed by a hash of its email, so saved sessions,
+/// anisette state, certificates and cached profiles of one account are never picked up by another.
+pub struct AccountManager {
+    storage: Arc<dyn SideloadingStorage>,
+    accounts: HashMap<String, AppleAccount>,
+}
+
+impl AccountManager {
+    /// Create a manager that stores the data of every account in `storage`
+    pub fn new(storage: Arc<dyn SideloadingStorage>) -> Self {
+        AccountManager {
+            storage,
+            accounts: HashMap::new(),
+        }
+    }
+
+    /// The storage namespace of an account, which can be shared with other code that keeps per-account data
+    pub fn storage_for(&self, email: &str) -> NamespacedStorage {
+        NamespacedStorage::new(self.storage.clone(), &account_namespace(email))
+    }
+
+    /// Create a builder for an account that saves its session in the account's namespace
+    ///
+    /// When the `anisette-remote` feature is enabled, the builder also uses a [`RemoteV3AnisetteProvider`] that keeps
+    /// its state in the namespace. Setting a different anisette provider on the builder replaces it.
+    pub fn account_builder(&self, email: &str) -> Result<AppleAccountBuilder, Report> {
+        let builder =
+            AppleAccountBuilder::new(email).session_storage(Box::new(self.storage_for(email)));
+
+        #[cfg(feature = "anisette-remote")]
+        let builder = builder.anisette_provider(
+            RemoteV3AnisetteProvider::default()?
+                .set_storage(Box::new(self.storage_for(email)))
+                .set_account(email),
+        );
+
+        Ok(builder)
+    }
+
+    /// Add a logged in account, replacing and returning the account with the same email, if any
+    pub fn insert(&mut self, account: AppleAccount) -> Option<AppleAccount> {
+        self.accounts
+            .insert(normalize_email(&account.email), account)
+    }
+
+    pub fn get(&self, email: &str) -> Option<&AppleAccount> {
+        self.accounts.get(&normalize_email(email))
+    }
+
+    pub fn get_mut(&mut self, email: &str) -> Option<&mut AppleAccount> {
+        self.accounts.get_mut(&normalize_email(email))
+    }
+
+    /// Stop managing an account. Its saved data stays in the storage.
+    pub fn remove(&mut self, email: &str) -> Option<AppleAccount> {
+        self.accounts.remove(&normalize_email(email))
+    }
+
+    /// Stop managing an account and delete everything stored in its namespace
+    ///
+    /// This removes its saved session, anisette identity, private key and cached profiles, for a "forget this account"
+    /// action. Certificates stay registered with Apple and can be revoked separately. Returns how many entries were
+    /// deleted. Requires a storage backend that can list its entries, see [`SideloadingStorage::list`].
+    pub fn forget(&mut self, email: &str) -> Result<usize, Report> {
+        self.remove(email);
+        let deleted = self.storage_for(email).delete_all("")?;
+        self.storage.flush()?;
+        Ok(deleted)
+    }
+
+    /// Forget the anisette identity of an account, so a new one is provisioned the next time it logs in
+    ///
+    /// The account stops being managed and is returned, since its session belongs to the old identity.
+    #[cfg(feature = "anisette-remote")]
+    pub fn reset_anisette_identity(&mut self, email: &str) -> Result<Option<AppleAccount>, Report> {
+        let account = self.remove(email);
+        RemoteV3AnisetteProvider::default()?
+            .set_storage(Box::new(self.storage_for(email)))
+            .set_account(email)
+            .reset_identity()?;
+        Ok(account)
+    }
+
+    /// The emails of the managed accounts
+    pub fn emails(&self) -> impl Iterator<Item = &str> {
+        self.accounts.values().map(|account| account.email.as_str())
+    }
+
+    /// Create a developer session for a managed account
+    #[cfg(feature = "dev-api")]
+    pub async fn developer_session(
+        &mut self,
+        email: &str,
+    ) -> Result<crate::dev::developer_session::DeveloperSession, Report> {
+        let account = self
+            .get_mut(email)
+            .ok_or_else(|| report!("Account {} is not managed by this AccountManager", email))?;
+        crate::dev::developer_session::DeveloperSession::from_account(account).await
+    }
+
+    /// Create a [`crate::sideload::SideloaderBuilder`] for a managed account that uses the account's namespace
+    /// as its storage
+    #[cfg(feature = "sign")]
+    pub async fn sideloader_builder(
+        &mut self,
+        email: &str,
+    ) -> Result<crate::sideload::SideloaderBuilder, Report> {
+        let developer_session = self.developer_session(email).await?;
+        let storage = self.storage_for(email);
+        let email = self
+            .get(email)
+            .map(|account| account.email.clone())
+            .unwrap_or_else(|| email.to_string());
+
+        Ok(
+            crate::sideload::SideloaderBuilder::new(developer_session, email)
+                .storage(Box::new(storage)),
+        )
+    }
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub(crate) fn account_namespace(email: &str) -> String {
+    hex::encode(Sha256::digest(normalize_email(email).as_bytes()))
+}
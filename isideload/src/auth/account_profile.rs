@@ -0,0 +1,76 @@
+use plist::Dictionary;
+use rootcause::prelude::*;
+
+use crate::util::plist::{PlistDataExtract, SensitivePlistAttachment};
+
+/// Typed view of the server provided data (SPD) returned by GrandSlam after login
+///
+/// The SPD is parsed once when it is received so that missing fields are reported in one place,
+/// instead of every caller digging through the raw dictionary.
+#[derive(Clone)]
+pub struct AccountProfile {
+    /// The alternate directory services ID of the account
+    pub adsid: String,
+    /// The IDMS token, used together with the adsid to authenticate follow-up requests
+    pub idms_token: String,
+    /// The session key used to checksum app token requests and decrypt their responses
+    pub session_key: Vec<u8>,
+    /// The opaque `c` value that must be echoed back in app token requests
+    pub c: Vec<u8>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    /// The two letter country code of the account's region, such as `US` or `DE`
+    pub region: Option<String>,
+    /// The password equivalent token, only present when the server issued one
+    pub pet: Option<String>,
+}
+
+impl AccountProfile {
+    /// Parse the profile from a decrypted SPD dictionary
+    pub fn from_spd(spd: &Dictionary) -> Result<Self, Report> {
+        Ok(Self::parse(spd)
+            .context("Failed to parse account profile from SPD")
+            .attach_with(|| SensitivePlistAttachment::new(spd.clone()))?)
+    }
+
+    fn parse(spd: &Dictionary) -> Result<Self, Report> {
+        let pet = spd
+            .get_dict("t")
+            .and_then(|t| t.get_dict("com.apple.gs.idms.pet"))
+            .and_then(|pet| pet.get_string("token"))
+            .ok();
+
+        Ok(AccountProfile {
+            adsid: spd.get_string("adsid")?,
+            idms_token: spd.get_string("GsIdmsToken")?,
+            session_key: spd.get_data("sk")?.to_vec(),
+            c: spd.get_data("c")?.to_vec(),
+            first_name: spd.get_string("fn").ok(),
+            last_name: spd.get_string("ln").ok(),
+            region: spd
+                .get_string("countryCode")
+                .or_else(|_| spd.get_string("cc"))
+                .ok(),
+            pet,
+        })
+    }
+}
+
+// the automatic debug implementation would print the tokens and session key
+impl std::fmt::Debug for AccountProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccountProfile")
+            .field("adsid", &"<redacted>")
+            .field("idms_token", &"<redacted>")
+            .field(
+                "session_key",
+                &format!("[{} bytes]", self.session_key.len()),
+            )
+            .field("c", &format!("[{} bytes]", self.c.len()))
+            .field("first_name", &self.first_name)
+            .field("last_name", &self.last_name)
+            .field("region", &self.region)
+            .field("pet", &self.pet.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
@@ -1,12 +1,19 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use crate::{
     anisette::{AnisetteData, AnisetteDataGenerator},
     auth::{
+        account_profile::AccountProfile,
         builder::AppleAccountBuilder,
         grandslam::{GrandSlam, GrandSlamErrorChecker},
+        retry::RetryPolicy,
+        session::StoredSession,
+    },
+    util::{
+        clock::{Clock, SystemClock},
+        plist::PlistDataExtract,
+        storage::SideloadingStorage,
     },
-    util::plist::{PlistDataExtract, SensitivePlistAttachment},
 };
 use aes::{
     Aes256,
@@ -20,6 +27,7 @@ use plist::Dictionary;
 use plist_macro::plist;
 use reqwest::header::{HeaderMap, HeaderValue};
 use rootcause::prelude::*;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use srp::{ClientVerifier, groups::G2048};
 use tracing::{debug, info, warn};
@@ -27,10 +35,56 @@ use tracing::{debug, info, warn};
 pub struct AppleAccount {
     pub email: String,
     pub spd: Option<plist::Dictionary>,
+    pub profile: Option<AccountProfile>,
     pub anisette_generator: AnisetteDataGenerator,
     pub grandslam_client: Arc<GrandSlam>,
     login_state: LoginState,
     debug: bool,
+    app_tokens: HashMap<String, AppToken>,
+    session_storage: Option<Box<dyn SideloadingStorage>>,
+    two_factor_policy: TwoFactorPolicy,
+    sms_phone_number_id: u32,
+    clock: Arc<dyn Clock>,
+}
+
+/// A way of receiving the two-factor authentication code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TwoFactorMethod {
+    /// A code shown on one of the account's trusted Apple devices
+    TrustedDevice,
+    /// A code sent by text message to the account's trusted phone number
+    Sms,
+}
+
+/// Which two-factor method to use when the server offers trusted device codes
+///
+/// Accounts without trusted devices only get SMS codes, so the policy only matters for accounts that have both.
+#[derive(Default)]
+pub enum TwoFactorPolicy {
+    /// Use trusted device codes
+    #[default]
+    PreferDevice,
+    /// Fall back to SMS codes right away, for users without their Apple devices nearby
+    PreferSms,
+    /// Use trusted device codes, and send an SMS code in the same login if that fails
+    ///
+    /// Returning `None` from the two-factor callback for the trusted device code falls back to SMS too, so the
+    /// callback is simply asked again instead of the whole login having to be retried.
+    DeviceThenSms,
+    /// Ask the user which method to use each time trusted device codes are offered
+    Prompt(Box<dyn Fn() -> TwoFactorMethod + Send + Sync>),
+}
+
+impl TwoFactorPolicy {
+    fn choose(&self) -> TwoFactorMethod {
+        match self {
+            TwoFactorPolicy::PreferDevice | TwoFactorPolicy::DeviceThenSms => {
+                TwoFactorMethod::TrustedDevice
+            }
+            TwoFactorPolicy::PreferSms => TwoFactorMethod::Sms,
+            TwoFactorPolicy::Prompt(prompt) => prompt(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -77,13 +131,113 @@ impl AppleAccount {
         Ok(AppleAccount {
             email: email.to_string(),
             spd: None,
+            profile: None,
             anisette_generator,
             grandslam_client: Arc::new(grandslam_client),
             debug,
             login_state: LoginState::NeedsLogin,
+            app_tokens: HashMap::new(),
+            session_storage: None,
+            two_factor_policy: TwoFactorPolicy::default(),
+            sms_phone_number_id: 1,
+            clock: Arc::new(SystemClock),
         })
     }
 
+    /// Restore a previously saved login session, without needing the password or 2FA
+    ///
+    /// Returns `None` if no session was saved for this email. Use [`AppleAccountBuilder::restore`] to
+    /// configure the anisette provider.
+    pub async fn restore(
+        email: &str,
+        storage: Box<dyn SideloadingStorage>,
+    ) -> Result<Option<Self>, Report> {
+        Self::builder(email)
+            .session_storage(storage)
+            .restore()
+            .await
+    }
+
+    /// Set which two-factor method to use when the server offers trusted device codes
+    ///
+    /// See [`TwoFactorPolicy`] for details.
+    pub fn set_two_factor_policy(&mut self, policy: TwoFactorPolicy) {
+        self.two_factor_policy = policy;
+    }
+
+    /// Set which of the account's trusted phone numbers SMS codes are sent to
+    ///
+    /// Numbers are counted from 1 in the order the Apple ID settings list them. Defaults to 1.
+    pub fn set_sms_phone_number(&mut self, id: u32) {
+        self.sms_phone_number_id = id;
+    }
+
+    /// Set how failed requests to Apple are retried, for this account and the developer sessions created from it
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.grandslam_client.set_retry_policy(policy);
+    }
+
+    /// Set the clock app tokens and anisette data are checked against, for this account and the developer sessions
+    /// created from it. Defaults to the system clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.anisette_generator.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Set the storage that login sessions are saved to and restored from
+    ///
+    /// Once set, a successful login and every newly fetched app token are saved automatically.
+    pub fn set_session_storage(&mut self, storage: Box<dyn SideloadingStorage>) {
+        self.session_storage = Some(storage);
+    }
+
+    /// Load the saved login session from the session storage
+    ///
+    /// Returns `false` if there is no session storage or no saved session.
+    pub fn restore_session(&mut self) -> Result<bool, Report> {
+        let Some(storage) = &self.session_storage else {
+            return Ok(false);
+        };
+        let Some(session) = StoredSession::load(storage.as_ref(), &self.email)? else {
+            return Ok(false);
+        };
+
+        self.profile = Some(
+            AccountProfile::from_spd(&session.spd).context("Failed to restore login session")?,
+        );
+        self.spd = Some(session.spd);
+        self.app_tokens = session.app_tokens;
+        self.login_state = LoginState::LoggedIn;
+        self.apply_region();
+        info!("Restored login session for {}", censor_email(&self.email));
+        Ok(true)
+    }
+
+    /// Save the current login session to the session storage
+    pub fn save_session(&self) -> Result<(), Report> {
+        let (Some(storage), Some(spd)) = (&self.session_storage, &self.spd) else {
+            return Ok(());
+        };
+        StoredSession {
+            spd: spd.clone(),
+            app_tokens: self.app_tokens.clone(),
+        }
+        .save(storage.as_ref(), &self.email)
+    }
+
+    /// Remove the saved login session, so the next run has to log in again
+    pub fn forget_session(&mut self) -> Result<(), Report> {
+        self.app_tokens.clear();
+        match &self.session_storage {
+            Some(storage) => StoredSession::delete(storage.as_ref(), &self.email),
+            None => Ok(()),
+        }
+    }
+
     /// Log in to the Apple ID account
     /// # Arguments
     /// - `password`: The Apple ID password
@@ -108,6 +262,7 @@ impl AppleAccount {
         debug!("Initial login successful");
 
         let mut attempts = 0;
+        let mut fell_back_to_sms = false;
 
         loop {
             attempts += 1;
@@ -120,14 +275,40 @@ impl AppleAccount {
             match &self.login_state {
                 LoginState::LoggedIn => {
                     info!("Successfully logged in to Apple ID");
+                    // tokens from a previous session belong to the old IDMS token
+                    self.app_tokens.clear();
+                    self.apply_region();
+                    if let Err(e) = self.save_session() {
+                        warn!("Failed to save login session: {:?}", e);
+                    }
                     return Ok(());
                 }
                 LoginState::NeedsDevice2FA => {
-                    self.trusted_device_2fa(&two_factor_callback)
+                    if self.two_factor_policy.choose() == TwoFactorMethod::Sms {
+                        // the SMS endpoints also work for accounts that default to trusted devices
+                        info!("Falling back to SMS 2FA");
+                        self.login_state = LoginState::NeedsSMS2FA;
+                        continue;
+                    }
+                    let result = self
+                        .trusted_device_2fa(&two_factor_callback)
                         .await
-                        .context("Failed to complete trusted device 2FA")?;
-                    debug!("Trusted device 2FA completed, need to login again");
-                    self.login_state = LoginState::NeedsLogin;
+                        .context("Failed to complete trusted device 2FA");
+                    match result {
+                        Ok(()) => {
+                            debug!("Trusted device 2FA completed, need to login again");
+                            self.login_state = LoginState::NeedsLogin;
+                        }
+                        Err(e)
+                            if matches!(self.two_factor_policy, TwoFactorPolicy::DeviceThenSms)
+                                && !fell_back_to_sms =>
+                        {
+                            warn!("Trusted device 2FA failed, falling back to SMS: {:?}", e);
+                            fell_back_to_sms = true;
+                            self.login_state = LoginState::NeedsSMS2FA;
+                        }
+                        Err(e) => return Err(e.into_dynamic()),
+                    }
                 }
                 LoginState::NeedsSMS2FA => {
                     info!("SMS 2FA required");
@@ -155,28 +336,43 @@ impl AppleAccount {
         }
     }
 
-    /// Get the user's first and last name associated with the Apple ID
-    pub fn get_name(&self) -> Result<(String, String), Report> {
-        let spd = self
-            .spd
+    /// Get the typed account profile parsed from the SPD
+    ///
+    /// # Errors
+    /// Returns an error if the account has not received an SPD yet (i.e. not logged in)
+    pub fn profile(&self) -> Result<&AccountProfile, Report> {
+        self.profile
             .as_ref()
-            .ok_or_else(|| report!("SPD not available, cannot get name"))?;
+            .ok_or_else(|| report!("Account profile not available, log in first"))
+    }
 
-        Ok((spd.get_string("fn")?, spd.get_string("ln")?))
+    /// The two letter country code of the account's region, if the server reported one
+    pub fn region(&self) -> Option<&str> {
+        self.profile.as_ref().and_then(|p| p.region.as_deref())
     }
 
-    fn get_pet(&self) -> Result<String, Report> {
-        let spd = self
-            .spd
-            .as_ref()
-            .ok_or_else(|| report!("SPD not available, cannot get pet"))?;
+    /// Keep the locale sent to Apple consistent with the account's region
+    fn apply_region(&mut self) {
+        if let Some(region) = self.region().map(str::to_string) {
+            self.anisette_generator.set_region(&region);
+        }
+    }
 
-        let pet = spd
-            .get_dict("t")?
-            .get_dict("com.apple.gs.idms.pet")?
-            .get_string("token")?;
+    /// Get the user's first and last name associated with the Apple ID
+    pub fn get_name(&self) -> Result<(String, String), Report> {
+        let profile = self.profile()?;
+
+        match (&profile.first_name, &profile.last_name) {
+            (Some(first), Some(last)) => Ok((first.clone(), last.clone())),
+            _ => bail!("Name not available in account profile"),
+        }
+    }
 
-        Ok(pet)
+    fn get_pet(&self) -> Result<String, Report> {
+        self.profile()?
+            .pet
+            .clone()
+            .ok_or_else(|| report!("No pet token available in account profile"))
     }
 
     async fn trusted_device_2fa(
@@ -247,16 +443,40 @@ impl AppleAccount {
             .await
             .context("Failed to get anisette data for 2FA")?;
 
-        let request_code_url = self.grandslam_client.get_url("secondaryAuth")?;
-
-        self.grandslam_client
-            .get_sms(&request_code_url)?
-            .headers(self.build_2fa_headers(&anisette_data).await?)
-            .send()
-            .await
-            .context("Failed to request SMS 2FA")?
-            .error_for_status()
-            .context("SMS 2FA request failed")?;
+        if self.sms_phone_number_id == 1 {
+            // secondaryAuth sends the code to the first number
+            let request_code_url = self.grandslam_client.get_url("secondaryAuth")?;
+            self.grandslam_client
+                .get_sms(&request_code_url)?
+                .headers(self.build_2fa_headers(&anisette_data).await?)
+                .send()
+                .await
+                .context("Failed to request SMS 2FA")?
+                .error_for_status()
+                .context("SMS 2FA request failed")?;
+        } else {
+            // ask for the code on the chosen number only, so the first number isn't sent one too
+            let body = serde_json::json!({
+                "phoneNumber": {
+                    "id": self.sms_phone_number_id
+                },
+                "mode": "sms"
+            });
+            let mut headers = self.build_2fa_headers(&anisette_data).await?;
+            headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+            self.grandslam_client
+                .put("https://gsa.apple.com/auth/verify/phone")?
+                .headers(headers)
+                .body(body.to_string())
+                .send()
+                .await
+                .context("Failed to request SMS 2FA to the chosen phone number")?
+                .error_for_status()
+                .context(format!(
+                    "SMS 2FA request to phone number {} failed",
+                    self.sms_phone_number_id
+                ))?;
+        }
 
         info!("SMS 2FA request sent");
 
@@ -268,7 +488,7 @@ impl AppleAccount {
                 "code": code
             },
             "phoneNumber": {
-                "id": 1
+                "id": self.sms_phone_number_id
             },
             "mode": "sms"
         });
@@ -332,18 +552,8 @@ impl AppleAccount {
     async fn build_2fa_headers(&self, anisette_data: &AnisetteData) -> Result<HeaderMap, Report> {
         let mut headers = anisette_data.get_header_map()?;
 
-        let spd = self
-            .spd
-            .as_ref()
-            .ok_or_else(|| report!("SPD data not available, cannot build 2FA headers"))?;
-
-        let adsid = spd
-            .get_str("adsid")
-            .context("Failed to build 2FA headers")?;
-        let token = spd
-            .get_str("GsIdmsToken")
-            .context("Failed to build 2FA headers")?;
-        let identity = BASE64_STANDARD.encode(format!("{}:{}", adsid, token));
+        let profile = self.profile().context("Failed to build 2FA headers")?;
+        let identity = BASE64_STANDARD.encode(format!("{}:{}", profile.adsid, profile.idms_token));
 
         headers.insert(
             "X-Apple-Identity-Token",
@@ -393,11 +603,9 @@ impl AppleAccount {
 
         let response = self
             .grandslam_client
-            .plist_request(&gs_service_url, &req1, None)
+            .checked_plist_request_with_policy(&gs_service_url, &req1, None, &RetryPolicy::none())
             .await
-            .context("Failed to send initial login request")?
-            .check_grandslam_error()
-            .context("GrandSlam error during initial login request")?;
+            .context("Initial login request failed")?;
 
         debug!("Login step 1 completed");
 
@@ -462,11 +670,14 @@ impl AppleAccount {
 
         let response2 = self
             .grandslam_client
-            .plist_request(&gs_service_url, &req2, Some(close_headers))
+            .checked_plist_request_with_policy(
+                &gs_service_url,
+                &req2,
+                Some(close_headers),
+                &RetryPolicy::none(),
+            )
             .await
-            .context("Failed to send proof login request")?
-            .check_grandslam_error()
-            .context("GrandSlam error during proof login request")?;
+            .context("Proof login request failed")?;
 
         debug!("Login step 2 response received, verifying server proof");
 
@@ -488,6 +699,7 @@ impl AppleAccount {
         let spd: plist::Dictionary =
             plist::from_bytes(&spd_decrypted).context("Failed to parse decrypted SPD plist")?;
 
+        self.profile = Some(AccountProfile::from_spd(&spd)?);
         self.spd = Some(spd);
 
         let status = response2
@@ -508,6 +720,7 @@ impl AppleAccount {
         Ok(LoginState::LoggedIn)
     }
 
+    /// Get an app token, reusing a cached one until it is about to expire
     pub async fn get_app_token(&mut self, app: &str) -> Result<AppToken, Report> {
         let app = if app.contains("com.apple.gs.") {
             app.to_string()
@@ -515,27 +728,39 @@ impl AppleAccount {
             format!("com.apple.gs.{}", app)
         };
 
+        if let Some(token) = self.app_tokens.get(&app)
+            && !token.is_expired(self.clock.as_ref())
+        {
+            debug!("Using cached app token for {}", app);
+            return Ok(token.clone());
+        }
+
+        let token = self.fetch_app_token(&app).await?;
+        self.app_tokens.insert(app, token.clone());
+        if let Err(e) = self.save_session() {
+            warn!("Failed to save login session: {:?}", e);
+        }
+        Ok(token)
+    }
+
+    async fn fetch_app_token(&mut self, app: &str) -> Result<AppToken, Report> {
+        let app = app.to_string();
+
         let anisette_data = self
             .anisette_generator
             .get_anisette_data(self.grandslam_client.clone())
             .await
             .context("Failed to get anisette data for login")?;
 
-        let spd = self
-            .spd
-            .as_ref()
-            .ok_or_else(|| report!("SPD data not available, cannot get app token"))?;
+        let profile = self.profile().context("Failed to get app token")?;
 
-        let dsid = spd.get_str("adsid").context("Failed to get app token")?;
-        let auth_token = spd
-            .get_str("GsIdmsToken")
-            .context("Failed to get app token")?;
-        let session_key = spd.get_data("sk").context("Failed to get app token")?;
-        let c = spd.get_data("c").context("Failed to get app token")?;
+        let dsid = profile.adsid.as_str();
+        let auth_token = profile.idms_token.as_str();
+        let session_key = profile.session_key.as_slice();
+        let c = profile.c.as_slice();
 
         let checksum = Hmac::<Sha256>::new_from_slice(session_key)
-            .context("Failed to create HMAC for app token checksum")
-            .attach_with(|| SensitivePlistAttachment::new(spd.clone()))?
+            .context("Failed to create HMAC for app token checksum")?
             .chain_update("apptokens".as_bytes())
             .chain_update(dsid.as_bytes())
             .chain_update(app.as_bytes())
@@ -563,11 +788,9 @@ impl AppleAccount {
 
         let resp = self
             .grandslam_client
-            .plist_request(&gs_service_url, &request, None)
+            .checked_plist_request(&gs_service_url, &request, None)
             .await
-            .context("Failed to send app token request")?
-            .check_grandslam_error()
-            .context("GrandSlam error during app token request")?;
+            .context("App token request failed")?;
 
         let encrypted_token = resp
             .get_data("et")
@@ -689,13 +912,21 @@ impl std::fmt::Display for AppleAccount {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppToken {
     pub token: String,
     pub duration: u64,
+    /// Expiry time in milliseconds since the unix epoch
     pub expiry: u64,
 }
 
+impl AppToken {
+    /// Whether the token has expired or will within the next five minutes
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        self.expiry <= clock.now_millis() + 5 * 60 * 1000
+    }
+}
+
 fn censor_email(email: &str) -> String {
     if std::env::var("DEBUG_SENSITIVE").is_ok() {
         return email.to_string();
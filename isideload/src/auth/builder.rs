@@ -1,17 +1,33 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use rootcause::prelude::*;
 use tokio::sync::RwLock;
 
+#[cfg(feature = "anisette-remote")]
+use crate::anisette::remote_v3::RemoteV3AnisetteProvider;
 use crate::{
-    anisette::{AnisetteDataGenerator, AnisetteProvider, remote_v3::RemoteV3AnisetteProvider},
-    auth::apple_account::AppleAccount,
+    anisette::{AnisetteClientInfoOverride, AnisetteDataGenerator, AnisetteProvider},
+    auth::{
+        apple_account::{AppleAccount, TwoFactorPolicy},
+        retry::RetryPolicy,
+    },
+    util::{clock::Clock, storage::SideloadingStorage},
 };
 
 pub struct AppleAccountBuilder {
     email: String,
     debug: Option<bool>,
     anisette_generator: Option<AnisetteDataGenerator>,
+    client_info_override: AnisetteClientInfoOverride,
+    locale: Option<String>,
+    session_storage: Option<Box<dyn SideloadingStorage>>,
+    two_factor_policy: TwoFactorPolicy,
+    sms_phone_number: Option<u32>,
+    retry_policy: Option<RetryPolicy>,
+    anisette_refresh_interval: Option<Duration>,
+    clock: Option<Arc<dyn Clock>>,
+    #[cfg(feature = "anisette-remote")]
+    anisette_urls: Vec<String>,
 }
 
 impl AppleAccountBuilder {
@@ -24,6 +40,16 @@ impl AppleAccountBuilder {
             email: email.to_string(),
             debug: None,
             anisette_generator: None,
+            client_info_override: AnisetteClientInfoOverride::default(),
+            locale: None,
+            session_storage: None,
+            two_factor_policy: TwoFactorPolicy::default(),
+            sms_phone_number: None,
+            retry_policy: None,
+            anisette_refresh_interval: None,
+            clock: None,
+            #[cfg(feature = "anisette-remote")]
+            anisette_urls: Vec::new(),
         }
     }
 
@@ -36,31 +62,154 @@ impl AppleAccountBuilder {
         self
     }
 
-    pub fn anisette_provider(
-        mut self,
-        anisette_provider: impl AnisetteProvider + Send + Sync + 'static,
-    ) -> Self {
+    /// Use a custom anisette provider instead of the default `RemoteV3AnisetteProvider`
+    ///
+    /// Accepts a `Box<dyn AnisetteProvider>` too, for providers chosen at runtime.
+    pub fn anisette_provider(mut self, anisette_provider: impl AnisetteProvider + 'static) -> Self {
         self.anisette_generator = Some(AnisetteDataGenerator::new(Arc::new(RwLock::new(
             anisette_provider,
         ))));
         self
     }
 
+    /// Set the anisette servers the default `RemoteV3AnisetteProvider` uses, in order of preference
+    ///
+    /// The next server is tried when one fails, see [`RemoteV3AnisetteProvider::set_urls`]. Ignored if a provider was
+    /// set with [`Self::anisette_provider`].
+    #[cfg(feature = "anisette-remote")]
+    pub fn anisette_urls(mut self, urls: Vec<String>) -> Self {
+        self.anisette_urls = urls;
+        self
+    }
+
+    /// Set the storage used to save the login session, so later runs can skip the password and 2FA
+    ///
+    /// See [`AppleAccount::restore`] for details.
+    pub fn session_storage(mut self, storage: Box<dyn SideloadingStorage>) -> Self {
+        self.session_storage = Some(storage);
+        self
+    }
+
+    /// Set which two-factor method to use when the server offers trusted device codes
+    ///
+    /// Defaults to [`TwoFactorPolicy::PreferDevice`]. See [`TwoFactorPolicy`] for details.
+    pub fn two_factor_policy(mut self, policy: TwoFactorPolicy) -> Self {
+        self.two_factor_policy = policy;
+        self
+    }
+
+    /// Set which of the account's trusted phone numbers SMS codes are sent to, counted from 1
+    ///
+    /// Use with [`TwoFactorPolicy::PreferSms`] or [`TwoFactorPolicy::DeviceThenSms`] to preconfigure where codes
+    /// go. See [`AppleAccount::set_sms_phone_number`].
+    pub fn sms_phone_number(mut self, id: u32) -> Self {
+        self.sms_phone_number = Some(id);
+        self
+    }
+
+    /// Set how failed requests to Apple are retried. Defaults to [`RetryPolicy::default`].
+    ///
+    /// Developer sessions created from the account start with the same policy.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Set how long anisette data is reused before new data is requested from the provider. Defaults to 60 seconds.
+    ///
+    /// Frontends with many accounts or devices can raise this to avoid hammering the anisette server,
+    /// but Apple rejects anisette data that is more than a couple of minutes old.
+    pub fn anisette_refresh_interval(mut self, interval: Duration) -> Self {
+        self.anisette_refresh_interval = Some(interval);
+        self
+    }
+
+    /// Set the clock app tokens and anisette data are checked against. Defaults to the system clock.
+    ///
+    /// Developer sessions created from the account use the same clock, see [`AppleAccount::set_clock`].
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Always use this locale (e.g. `de_DE`) in requests to Apple
+    ///
+    /// By default `en_US` is used until login, after which a locale matching the account's region is used.
+    pub fn locale(mut self, locale: &str) -> Self {
+        self.locale = Some(locale.to_string());
+        self
+    }
+
+    /// Override the `X-Mme-Client-Info` string reported by the anisette provider
+    ///
+    /// Useful for experimenting with -6005 errors caused by mismatched client info.
+    pub fn client_info(mut self, client_info: &str) -> Self {
+        self.client_info_override.client_info = Some(client_info.to_string());
+        self
+    }
+
+    /// Override the `User-Agent` reported by the anisette provider
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.client_info_override.user_agent = Some(user_agent.to_string());
+        self
+    }
+
     /// Build the AppleAccount without logging in
     ///
+    /// If no anisette provider was set, a `RemoteV3AnisetteProvider` is used when the `anisette-remote` feature is enabled. It keeps a
+    /// separate anisette identity for each email, see [`RemoteV3AnisetteProvider::set_account`].
+    ///
     /// # Errors
-    /// Returns an error if the reqwest client cannot be built
+    /// Returns an error if the reqwest client cannot be built, or if no anisette provider is available
     pub async fn build(self) -> Result<AppleAccount, Report> {
         let debug = self.debug.unwrap_or(false);
-        let anisette_generator = match self.anisette_generator {
+        let mut anisette_generator = match self.anisette_generator {
             Some(generator) => generator,
+            #[cfg(feature = "anisette-remote")]
             None => {
-                let provider = RemoteV3AnisetteProvider::default()?;
+                let provider = RemoteV3AnisetteProvider::default()?
+                    .set_account(&self.email)
+                    .set_urls(self.anisette_urls);
                 AnisetteDataGenerator::new(Arc::new(RwLock::new(provider)))
             }
+            #[cfg(not(feature = "anisette-remote"))]
+            None => bail!("No anisette provider set and the `anisette-remote` feature is disabled"),
         };
+        anisette_generator.set_client_info_override(self.client_info_override);
+        if let Some(locale) = &self.locale {
+            anisette_generator.pin_locale(locale);
+        }
+        if let Some(interval) = self.anisette_refresh_interval {
+            anisette_generator.set_refresh_interval(interval);
+        }
 
-        AppleAccount::new(&self.email, anisette_generator, debug).await
+        let mut account = AppleAccount::new(&self.email, anisette_generator, debug).await?;
+        account.set_two_factor_policy(self.two_factor_policy);
+        if let Some(id) = self.sms_phone_number {
+            account.set_sms_phone_number(id);
+        }
+        if let Some(policy) = self.retry_policy {
+            account.set_retry_policy(policy);
+        }
+        if let Some(storage) = self.session_storage {
+            account.set_session_storage(storage);
+        }
+        if let Some(clock) = self.clock {
+            account.set_clock(clock);
+        }
+        Ok(account)
+    }
+
+    /// Build the AppleAccount and restore the login session saved in the session storage
+    ///
+    /// Returns `None` if no session storage was set or no session was saved.
+    pub async fn restore(self) -> Result<Option<AppleAccount>, Report> {
+        let mut account = self.build().await?;
+        if account.restore_session()? {
+            Ok(Some(account))
+        } else {
+            Ok(None)
+        }
     }
 
     /// Build the AppleAccount and log in
@@ -1,3 +1,5 @@
+use std::sync::RwLock;
+
 use plist::Dictionary;
 use plist_macro::plist_to_xml_string;
 use plist_macro::pretty_print_dictionary;
@@ -7,16 +9,24 @@ use reqwest::{
 };
 use rootcause::prelude::*;
 use tracing::debug;
+use uuid::Uuid;
 
-use crate::{SideloadError, anisette::AnisetteClientInfo, util::plist::PlistDataExtract};
+use crate::{
+    SideloadError,
+    anisette::AnisetteClientInfo,
+    auth::retry::RetryPolicy,
+    error::{RequestId, RequestService},
+    util::plist::PlistDataExtract,
+};
 
 const APPLE_ROOT: &[u8] = include_bytes!("./apple_root.der");
-const URL_BAG: &str = "https://gsa.apple.com/grandslam/GsService2/lookup";
+pub(crate) const URL_BAG: &str = "https://gsa.apple.com/grandslam/GsService2/lookup";
 
 pub struct GrandSlam {
     pub client: reqwest::Client,
     pub client_info: AnisetteClientInfo,
     url_bag: Dictionary,
+    retry_policy: RwLock<RetryPolicy>,
 }
 
 impl GrandSlam {
@@ -32,6 +42,21 @@ impl GrandSlam {
             client,
             client_info,
             url_bag,
+            retry_policy: RwLock::new(RetryPolicy::default()),
+        })
+    }
+
+    /// Create a GrandSlam instance without fetching the URL bag
+    ///
+    /// For sessions that only talk to the developer services with an existing token, which don't need the URL bag.
+    /// Logging in with this instance will fail.
+    pub fn without_url_bag(client_info: AnisetteClientInfo, debug: bool) -> Result<Self, Report> {
+        let client = Self::build_reqwest_client(debug).context("Failed to build HTTP client")?;
+        Ok(Self {
+            client,
+            client_info,
+            url_bag: Dictionary::new(),
+            retry_policy: RwLock::new(RetryPolicy::default()),
         })
     }
 
@@ -62,6 +87,22 @@ impl GrandSlam {
         Ok(urls)
     }
 
+    /// The policy used to retry failed requests, also used by developer sessions created from this client
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Set the policy used to retry failed [`Self::plist_request`]s
+    pub fn set_retry_policy(&self, policy: RetryPolicy) {
+        *self
+            .retry_policy
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = policy;
+    }
+
     pub fn get_url(&self, key: &str) -> Result<String, Report> {
         let url = self
             .url_bag
@@ -97,6 +138,15 @@ impl GrandSlam {
         Ok(builder)
     }
 
+    pub fn put(&self, url: &str) -> Result<reqwest::RequestBuilder, Report> {
+        let builder = self
+            .client
+            .put(url)
+            .headers(Self::base_headers(&self.client_info, false)?);
+
+        Ok(builder)
+    }
+
     pub fn patch(&self, url: &str) -> Result<reqwest::RequestBuilder, Report> {
         let builder = self
             .client
@@ -106,11 +156,52 @@ impl GrandSlam {
         Ok(builder)
     }
 
+    /// Send a plist request, retrying failures according to the [`RetryPolicy`]
     pub async fn plist_request(
         &self,
         url: &str,
         body: &Dictionary,
         additional_headers: Option<HeaderMap>,
+    ) -> Result<Dictionary, Report> {
+        self.plist_request_with_policy(url, body, additional_headers, &self.retry_policy())
+            .await
+    }
+
+    /// Send a plist request, retrying failures according to `policy` instead of the client's policy
+    pub async fn plist_request_with_policy(
+        &self,
+        url: &str,
+        body: &Dictionary,
+        additional_headers: Option<HeaderMap>,
+        policy: &RetryPolicy,
+    ) -> Result<Dictionary, Report> {
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .plist_request_once(url, body, additional_headers.clone())
+                .await;
+            let retry = match &result {
+                Ok(response) => response
+                    .get("Status")
+                    .and_then(|v| v.as_dictionary())
+                    .and_then(|status| status.get("ec"))
+                    .and_then(|v| v.as_signed_integer())
+                    .is_some_and(|code| policy.retries_result_code(code)),
+                Err(report) => policy.should_retry(report),
+            };
+            if !retry || !policy.has_attempts_left(attempt) {
+                return result;
+            }
+            policy.wait(attempt, "GrandSlam request").await;
+            attempt += 1;
+        }
+    }
+
+    async fn plist_request_once(
+        &self,
+        url: &str,
+        body: &Dictionary,
+        additional_headers: Option<HeaderMap>,
     ) -> Result<Dictionary, Report> {
         let resp = self
             .post(url)?
@@ -141,6 +232,41 @@ impl GrandSlam {
         Ok(response_plist)
     }
 
+    /// Send a plist request and check the response for a GrandSlam error
+    ///
+    /// A [`RequestId`] is logged and attached to any error, so the failed request can be found in the logs.
+    pub async fn checked_plist_request(
+        &self,
+        url: &str,
+        body: &Dictionary,
+        additional_headers: Option<HeaderMap>,
+    ) -> Result<Dictionary, Report> {
+        self.checked_plist_request_with_policy(url, body, additional_headers, &self.retry_policy())
+            .await
+    }
+
+    /// Like [`Self::checked_plist_request`], retrying failures according to `policy` instead of the client's policy
+    pub async fn checked_plist_request_with_policy(
+        &self,
+        url: &str,
+        body: &Dictionary,
+        additional_headers: Option<HeaderMap>,
+        policy: &RetryPolicy,
+    ) -> Result<Dictionary, Report> {
+        let request_id = RequestId {
+            service: RequestService::GrandSlam,
+            id: Uuid::new_v4().to_string().to_uppercase(),
+        };
+        debug!("Sending GrandSlam request {}", request_id.id);
+
+        let response = self
+            .plist_request_with_policy(url, body, additional_headers, policy)
+            .await
+            .attach(request_id.clone())?;
+
+        Ok(response.check_grandslam_error().attach(request_id)?)
+    }
+
     fn base_headers(
         client_info: &AnisetteClientInfo,
         sms: bool,
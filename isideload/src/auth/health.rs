@@ -0,0 +1,178 @@
+use rootcause::prelude::*;
+use tracing::{info, warn};
+
+#[cfg(feature = "dev-api")]
+use crate::{
+    SideloadError,
+    dev::{
+        developer_session::DeveloperSession,
+        teams::{DeveloperTeam, TEAM_BOOTSTRAP_URL, TeamsApi},
+    },
+};
+use crate::{auth::apple_account::AppleAccount, error::ErrorSummary};
+
+/// The outcome of one check of [`AppleAccount::health_check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(tag = "status", content = "error", rename_all = "snake_case")
+)]
+pub enum HealthCheck {
+    Passed,
+    Failed(ErrorSummary),
+    /// The check couldn't run because an earlier one failed
+    Skipped,
+}
+
+impl HealthCheck {
+    fn from_result<T>(result: &Result<T, Report>) -> Self {
+        match result {
+            Ok(_) => HealthCheck::Passed,
+            Err(e) => HealthCheck::Failed(ErrorSummary::from_report(e)),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        *self == HealthCheck::Passed
+    }
+}
+
+/// What [`AppleAccount::health_check`] found out about an account
+///
+/// Each check only runs if the ones it depends on passed, so the first failed check is usually the one to show.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct AccountHealthReport {
+    /// Whether the account is logged in
+    pub session: HealthCheck,
+    /// Whether fresh anisette data can be generated
+    pub anisette: HealthCheck,
+    /// Whether GrandSlam still accepts the session, checked by getting the Xcode app token
+    pub token: HealthCheck,
+    /// Whether the developer services accept the token and list the account's teams
+    #[cfg(feature = "dev-api")]
+    pub developer_services: HealthCheck,
+    /// Whether the developer agreement is accepted and the account has a team to sign with
+    #[cfg(feature = "dev-api")]
+    pub agreement: HealthCheck,
+    /// The account's teams, if they could be listed
+    #[cfg(feature = "dev-api")]
+    pub teams: Vec<DeveloperTeam>,
+}
+
+impl AccountHealthReport {
+    /// Whether every check passed
+    pub fn is_healthy(&self) -> bool {
+        let healthy = self.session.passed() && self.anisette.passed() && self.token.passed();
+        #[cfg(feature = "dev-api")]
+        let healthy = healthy && self.developer_services.passed() && self.agreement.passed();
+        healthy
+    }
+}
+
+impl AppleAccount {
+    /// Check that the account can be used for sideloading, without changing anything
+    ///
+    /// Meant for frontends to run at startup, so problems like an expired session, an unreachable anisette server or
+    /// an unaccepted developer agreement are shown before the user picks an app. Failures are part of the report
+    /// instead of being returned as errors.
+    pub async fn health_check(&mut self) -> AccountHealthReport {
+        let session = HealthCheck::from_result(&self.check_session());
+
+        let anisette = HealthCheck::from_result(
+            &self
+                .anisette_generator
+                .get_anisette_data(self.grandslam_client.clone())
+                .await
+                .context("Failed to get anisette data")
+                .map_err(|e| e.into_dynamic()),
+        );
+
+        let token_result = if session.passed() && anisette.passed() {
+            Some(
+                self.get_app_token("xcode.auth")
+                    .await
+                    .context("Failed to get xcode token from Apple account")
+                    .map_err(|e| e.into_dynamic()),
+            )
+        } else {
+            None
+        };
+        let token = token_result
+            .as_ref()
+            .map_or(HealthCheck::Skipped, HealthCheck::from_result);
+
+        #[cfg(feature = "dev-api")]
+        let (developer_services, agreement, teams) = if token.passed() {
+            self.check_teams().await
+        } else {
+            (HealthCheck::Skipped, HealthCheck::Skipped, Vec::new())
+        };
+
+        let report = AccountHealthReport {
+            session,
+            anisette,
+            token,
+            #[cfg(feature = "dev-api")]
+            developer_services,
+            #[cfg(feature = "dev-api")]
+            agreement,
+            #[cfg(feature = "dev-api")]
+            teams,
+        };
+        if report.is_healthy() {
+            info!("Account health check passed");
+        } else {
+            warn!("Account health check failed: {:?}", report);
+        }
+        report
+    }
+
+    fn check_session(&self) -> Result<(), Report> {
+        self.profile()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "dev-api")]
+    async fn check_teams(&mut self) -> (HealthCheck, HealthCheck, Vec<DeveloperTeam>) {
+        let teams = match DeveloperSession::from_account(self).await {
+            Ok(mut session) => session.list_teams().await,
+            Err(e) => Err(e),
+        };
+
+        match teams {
+            Ok(teams) if teams.is_empty() => (
+                HealthCheck::Passed,
+                HealthCheck::Failed(ErrorSummary::from_report(
+                    &report!(SideloadError::NeedsTeamBootstrap(
+                        TEAM_BOOTSTRAP_URL.to_string()
+                    ))
+                    .into_dynamic(),
+                )),
+                teams,
+            ),
+            Ok(teams) => (HealthCheck::Passed, HealthCheck::Passed, teams),
+            // the developer services answered, they just require the agreement to be accepted first
+            Err(e)
+                if e.iter_reports().any(|node| {
+                    matches!(
+                        node.downcast_current_context::<SideloadError>(),
+                        Some(SideloadError::LicenseAgreementNotAccepted(_))
+                    )
+                }) =>
+            {
+                (
+                    HealthCheck::Passed,
+                    HealthCheck::Failed(ErrorSummary::from_report(&e)),
+                    Vec::new(),
+                )
+            }
+            Err(e) => (
+                HealthCheck::Failed(ErrorSummary::from_report(&e)),
+                HealthCheck::Skipped,
+                Vec::new(),
+            ),
+        }
+    }
+}
@@ -1,3 +1,8 @@
+pub mod account_manager;
+pub mod account_profile;
 pub mod apple_account;
 pub mod builder;
 pub mod grandslam;
+pub mod health;
+pub mod retry;
+mod session;
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use rootcause::prelude::*;
+use tracing::warn;
+
+use crate::SideloadError;
+
+/// Result codes Apple returns for failures that usually go away when the request is sent again
+pub const TRANSIENT_RESULT_CODES: &[i64] = &[-22421];
+
+/// When and how often failed requests to GrandSlam and the developer services are sent again
+///
+/// Retries wait with exponential backoff, starting at `initial_backoff` and doubling up to `max_backoff`,
+/// randomized by `jitter` so that many clients failing at once don't retry in lockstep.
+///
+/// The policy only applies to requests that read data. Requests that change something, like logging in, adding an
+/// app ID or registering a device, are sent once with [`RetryPolicy::none`], since Apple may have handled them even
+/// if the response was lost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times a request is sent in total, including the first attempt. `1` disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// How much of the backoff is randomized, from `0.0` (none) to `1.0` (anywhere between zero and twice the backoff)
+    pub jitter: f64,
+    /// Retry when the server responds with a 5xx status
+    pub retry_server_errors: bool,
+    /// Retry when the request couldn't be sent or timed out
+    pub retry_network_errors: bool,
+    /// Retry when Apple responds with one of these result codes
+    pub retry_result_codes: Vec<i64>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            jitter: 0.2,
+            retry_server_errors: true,
+            retry_network_errors: true,
+            retry_result_codes: TRANSIENT_RESULT_CODES.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries, for requests that must not be sent twice
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Whether a request that failed with `report` should be sent again
+    pub fn should_retry(&self, report: &Report) -> bool {
+        report.iter_reports().any(|node| {
+            if let Some(error) = node.downcast_current_context::<reqwest::Error>() {
+                return match error.status() {
+                    Some(status) => self.retry_server_errors && status.is_server_error(),
+                    None => {
+                        self.retry_network_errors
+                            && (error.is_timeout() || error.is_connect() || error.is_request())
+                    }
+                };
+            }
+            match node.downcast_current_context::<SideloadError>() {
+                Some(
+                    SideloadError::AuthWithMessage(code, _)
+                    | SideloadError::DeveloperError(code, _),
+                ) => self.retries_result_code(*code),
+                _ => false,
+            }
+        })
+    }
+
+    /// Whether a response with this result code should be sent again
+    pub fn retries_result_code(&self, code: i64) -> bool {
+        self.retry_result_codes.contains(&code)
+    }
+
+    /// How long to wait after the given attempt (starting at 1) failed
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        exponential.mul_f64(1.0 + jitter * (rand::random::<f64>() * 2.0 - 1.0))
+    }
+
+    /// Whether another attempt is allowed after `attempt` attempts were made
+    pub(crate) fn has_attempts_left(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// Log the failed attempt and wait before the next one
+    pub(crate) async fn wait(&self, attempt: u32, request: &str) {
+        let delay = self.backoff(attempt);
+        warn!(
+            "{} failed (attempt {}/{}), retrying in {:?}",
+            request, attempt, self.max_attempts, delay
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use plist::Dictionary;
+use rootcause::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{auth::apple_account::AppToken, util::storage::SideloadingStorage};
+
+/// The parts of a logged in account needed to skip logging in again
+///
+/// The SPD holds the IDMS token, which stays valid for much longer than the app tokens derived from it,
+/// so expired app tokens can be refreshed without the password or 2FA.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct StoredSession {
+    pub spd: Dictionary,
+    #[serde(default)]
+    pub app_tokens: HashMap<String, AppToken>,
+}
+
+impl StoredSession {
+    pub fn load(storage: &dyn SideloadingStorage, email: &str) -> Result<Option<Self>, Report> {
+        let Some(data) = storage.retrieve_data(&storage_key(email))? else {
+            return Ok(None);
+        };
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(
+            plist::from_bytes(&data).context("Failed to parse stored login session")?,
+        ))
+    }
+
+    pub fn save(&self, storage: &dyn SideloadingStorage, email: &str) -> Result<(), Report> {
+        let mut buf = Vec::new();
+        plist::to_writer_binary(&mut buf, self).context("Failed to serialize login session")?;
+        storage.store_data(&storage_key(email), &buf)
+    }
+
+    pub fn delete(storage: &dyn SideloadingStorage, email: &str) -> Result<(), Report> {
+        storage.delete(&storage_key(email))
+    }
+}
+
+fn storage_key(email: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(email.as_bytes());
+    format!("{}/session", hex::encode(hasher.finalize()))
+}
@@ -1,13 +1,14 @@
+use crate::auth::retry::RetryPolicy;
 use crate::dev::{
     app_ids::AppId,
-    developer_session::DeveloperSession,
+    developer_session::{DeveloperSession, is_already_exists_error},
     device_type::{DeveloperDeviceType, dev_url},
     teams::DeveloperTeam,
 };
 use plist_macro::plist;
 use rootcause::prelude::*;
 use serde::Deserialize;
-use tracing::info;
+use tracing::{debug, info, warn};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -17,6 +18,13 @@ pub struct AppGroup {
     pub application_group: String,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AppIdDetail {
+    #[serde(default)]
+    associated_application_groups: Vec<AppGroup>,
+}
+
 #[async_trait::async_trait]
 pub trait AppGroupsApi {
     fn developer_session(&mut self) -> &mut DeveloperSession;
@@ -58,10 +66,11 @@ pub trait AppGroupsApi {
 
         let app_group: AppGroup = self
             .developer_session()
-            .send_dev_request(
+            .send_dev_request_with_policy(
                 &dev_url("addApplicationGroup", device_type),
                 body,
                 "applicationGroup",
+                &RetryPolicy::none(),
             )
             .await
             .context("Failed to add developer app group")?;
@@ -83,9 +92,10 @@ pub trait AppGroupsApi {
         });
 
         self.developer_session()
-            .send_dev_request_no_response(
+            .send_dev_request_no_response_with_policy(
                 &dev_url("assignApplicationGroupToAppId", device_type),
                 body,
+                &RetryPolicy::none(),
             )
             .await
             .context("Failed to assign developer app group")?;
@@ -93,6 +103,88 @@ pub trait AppGroupsApi {
         Ok(())
     }
 
+    /// The app groups an app ID is currently assigned to
+    async fn list_group_assignments(
+        &mut self,
+        team: &DeveloperTeam,
+        app_id: &AppId,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<Vec<AppGroup>, Report> {
+        let body = plist!(dict {
+            "teamId": &team.team_id,
+            "appIdId": &app_id.app_id_id,
+        });
+
+        let detail: AppIdDetail = self
+            .developer_session()
+            .send_dev_request(&dev_url("getAppIdDetail", device_type), body, "appId")
+            .await
+            .context("Failed to list app group assignments")?;
+
+        Ok(detail.associated_application_groups)
+    }
+
+    /// Assign an app group to an app ID unless it is already assigned, returning whether it was assigned by this call
+    ///
+    /// If the current assignments can't be listed, the group is assigned anyway.
+    async fn ensure_app_group_assigned(
+        &mut self,
+        team: &DeveloperTeam,
+        app_group: &AppGroup,
+        app_id: &AppId,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<bool, Report> {
+        let device_type = device_type.into();
+        match self
+            .list_group_assignments(team, app_id, device_type.clone())
+            .await
+        {
+            Ok(groups)
+                if groups
+                    .iter()
+                    .any(|g| g.application_group == app_group.application_group) =>
+            {
+                debug!(
+                    "{} is already assigned to {}",
+                    app_group.identifier, app_id.identifier
+                );
+                return Ok(false);
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Failed to list app groups of {}, assigning anyway: {:?}",
+                app_id.identifier, e
+            ),
+        }
+
+        self.assign_app_group(team, app_group, app_id, device_type)
+            .await?;
+        Ok(true)
+    }
+
+    async fn delete_app_group(
+        &mut self,
+        team: &DeveloperTeam,
+        app_group: &AppGroup,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<(), Report> {
+        let body = plist!(dict {
+            "teamId": &team.team_id,
+            "applicationGroup": &app_group.application_group,
+        });
+
+        self.developer_session()
+            .send_dev_request_no_response_with_policy(
+                &dev_url("deleteApplicationGroup", device_type),
+                body,
+                &RetryPolicy::none(),
+            )
+            .await
+            .context("Failed to delete developer app group")?;
+
+        Ok(())
+    }
+
     async fn ensure_app_group(
         &mut self,
         team: &DeveloperTeam,
@@ -100,18 +192,47 @@ pub trait AppGroupsApi {
         identifier: &str,
         device_type: impl Into<Option<DeveloperDeviceType>> + Send,
     ) -> Result<AppGroup, Report> {
+        Ok(self
+            .ensure_app_group_tracked(team, name, identifier, device_type)
+            .await?
+            .0)
+    }
+
+    /// Like [`Self::ensure_app_group`], also returning whether the group was added by this call
+    async fn ensure_app_group_tracked(
+        &mut self,
+        team: &DeveloperTeam,
+        name: &str,
+        identifier: &str,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<(AppGroup, bool), Report> {
         let device_type = device_type.into();
         let groups = self.list_app_groups(team, device_type.clone()).await?;
         let matching_group = groups.iter().find(|g| g.identifier == identifier);
 
         if let Some(group) = matching_group {
-            Ok(group.clone())
-        } else {
-            info!("Adding application group");
-            let group = self
-                .add_app_group(team, name, identifier, device_type)
-                .await?;
-            Ok(group)
+            return Ok((group.clone(), false));
+        }
+
+        info!("Adding application group");
+        match self
+            .add_app_group(team, name, identifier, device_type.clone())
+            .await
+        {
+            Ok(group) => Ok((group, true)),
+            Err(e) => {
+                // the group may have been added by a request whose response was lost, so check before failing
+                let groups = self.list_app_groups(team, device_type).await?;
+                match groups.into_iter().find(|g| g.identifier == identifier) {
+                    Some(group) => {
+                        if !is_already_exists_error(&e) {
+                            warn!("Adding app group failed, but it exists now: {:?}", e);
+                        }
+                        Ok((group, !is_already_exists_error(&e)))
+                    }
+                    None => Err(e),
+                }
+            }
         }
     }
 }
@@ -1,16 +1,22 @@
 use crate::{
+    auth::retry::RetryPolicy,
     dev::{
-        developer_session::DeveloperSession,
+        developer_session::{DeveloperSession, is_already_exists_error},
         device_type::{DeveloperDeviceType, dev_url},
         teams::DeveloperTeam,
     },
-    util::plist::{PlistDataExtract, SensitivePlistAttachment},
+    util::{
+        clock::Clock,
+        plist::{PlistDataExtract, SensitivePlistAttachment},
+    },
 };
 use plist::{Data, Date, Dictionary, Value};
 use plist_macro::plist;
 use reqwest::header::HeaderValue;
 use rootcause::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::warn;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -30,6 +36,16 @@ pub struct ListAppIdsResponse {
     pub available_quantity: Option<i64>,
 }
 
+/// An app ID together with whether it can be cleaned up
+#[derive(Debug, Clone)]
+pub struct AppIdUsage {
+    pub app_id: AppId,
+    /// Whether the app ID's expiration date has passed
+    pub expired: bool,
+    /// Whether the app ID belongs to one of the bundle identifiers passed as in use
+    pub in_use: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Profile {
@@ -70,7 +86,12 @@ pub trait AppIdsApi {
 
         let app_id: AppId = self
             .developer_session()
-            .send_dev_request(&dev_url("addAppId", device_type), body, "appId")
+            .send_dev_request_with_policy(
+                &dev_url("addAppId", device_type),
+                body,
+                "appId",
+                &RetryPolicy::none(),
+            )
             .await
             .context("Failed to add developer app ID")?;
 
@@ -107,6 +128,111 @@ pub trait AppIdsApi {
         Ok(app_ids)
     }
 
+    /// Return the team's app ID for `identifier`, adding it only if it isn't registered yet
+    ///
+    /// If adding fails, the app IDs are listed again, since a retried request may have been added by an earlier
+    /// attempt whose response was lost.
+    async fn ensure_app_id(
+        &mut self,
+        team: &DeveloperTeam,
+        name: &str,
+        identifier: &str,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<AppId, Report> {
+        let device_type = device_type.into();
+        if let Some(app_id) = self
+            .find_app_id(team, identifier, device_type.clone())
+            .await?
+        {
+            return Ok(app_id);
+        }
+
+        let error = match self
+            .add_app_id(team, name, identifier, device_type.clone())
+            .await
+        {
+            Ok(app_id) => return Ok(app_id),
+            Err(e) => e,
+        };
+
+        match self.find_app_id(team, identifier, device_type).await? {
+            Some(app_id) => {
+                if !is_already_exists_error(&error) {
+                    warn!("Adding app ID failed, but it exists now: {:?}", error);
+                }
+                Ok(app_id)
+            }
+            None => Err(error),
+        }
+    }
+
+    /// The team's app ID for `identifier`, if it is registered
+    async fn find_app_id(
+        &mut self,
+        team: &DeveloperTeam,
+        identifier: &str,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<Option<AppId>, Report> {
+        Ok(self
+            .list_app_ids(team, device_type)
+            .await?
+            .app_ids
+            .into_iter()
+            .find(|app_id| app_id.identifier == identifier))
+    }
+
+    /// List the app IDs of the team, marking which are expired and which belong to `in_use` bundle identifiers
+    async fn list_app_ids_with_usage(
+        &mut self,
+        team: &DeveloperTeam,
+        in_use: &[&str],
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<Vec<AppIdUsage>, Report> {
+        let response = self.list_app_ids(team, device_type).await?;
+        let clock = self.developer_session().clock();
+
+        Ok(response
+            .app_ids
+            .into_iter()
+            .map(|app_id| AppIdUsage {
+                expired: app_id.is_expired(clock.as_ref()),
+                in_use: in_use.contains(&app_id.identifier.as_str()),
+                app_id,
+            })
+            .collect())
+    }
+
+    /// Delete every expired app ID of the team that doesn't belong to one of the `in_use` bundle identifiers
+    ///
+    /// Returns the deleted app IDs.
+    async fn cleanup_expired(
+        &mut self,
+        team: &DeveloperTeam,
+        in_use: &[&str],
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<Vec<AppId>, Report> {
+        let device_type = device_type.into();
+        let usage = self
+            .list_app_ids_with_usage(team, in_use, device_type.clone())
+            .await?;
+
+        let mut deleted = Vec::new();
+        for entry in usage {
+            if !entry.expired || entry.in_use {
+                continue;
+            }
+            self.delete_app_id(team, &entry.app_id.app_id_id, device_type.clone())
+                .await
+                .context(format!(
+                    "Failed to delete expired app ID {}",
+                    entry.app_id.identifier
+                ))?;
+            deleted.push(entry.app_id);
+        }
+
+        Ok(deleted)
+    }
+
     async fn update_app_id(
         &mut self,
         team: &DeveloperTeam,
@@ -125,7 +251,12 @@ pub trait AppIdsApi {
 
         Ok(self
             .developer_session()
-            .send_dev_request(&dev_url("updateAppId", device_type), body, "appId")
+            .send_dev_request_with_policy(
+                &dev_url("updateAppId", device_type),
+                body,
+                "appId",
+                &RetryPolicy::none(),
+            )
             .await
             .context("Failed to update developer app ID")?)
     }
@@ -142,7 +273,11 @@ pub trait AppIdsApi {
         });
 
         self.developer_session()
-            .send_dev_request_no_response(&dev_url("deleteAppId", device_type), body)
+            .send_dev_request_no_response_with_policy(
+                &dev_url("deleteAppId", device_type),
+                body,
+                &RetryPolicy::none(),
+            )
             .await
             .context("Failed to delete developer app ID")?;
 
@@ -219,6 +354,12 @@ impl AppIdsApi for DeveloperSession {
 }
 
 impl AppId {
+    /// Whether the app ID has an expiration date in the past
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        self.expiration_date
+            .is_some_and(|date| clock.expires_within(date.into(), Duration::ZERO))
+    }
+
     pub async fn ensure_group_feature(
         &mut self,
         dev_session: &mut DeveloperSession,
@@ -1,12 +1,17 @@
-use crate::dev::{
-    developer_session::DeveloperSession,
-    device_type::{DeveloperDeviceType, dev_url},
-    teams::DeveloperTeam,
+use crate::{
+    auth::retry::RetryPolicy,
+    dev::{
+        developer_session::DeveloperSession,
+        device_type::{DeveloperDeviceType, dev_url},
+        teams::DeveloperTeam,
+    },
+    util::clock::Clock,
 };
 use plist::{Data, Date};
 use plist_macro::plist;
 use rootcause::prelude::*;
 use serde::Deserialize;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Deserialize, Clone)]
@@ -45,6 +50,14 @@ pub struct CertRequest {
     pub cert_request_id: String,
 }
 
+impl DevelopmentCertificate {
+    /// Whether the certificate has an expiration date in the past
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        self.expiration_date
+            .is_some_and(|date| clock.expires_within(date.into(), Duration::ZERO))
+    }
+}
+
 // the automatic debug implementation spams the console with the cert content bytes
 impl std::fmt::Debug for DevelopmentCertificate {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -136,9 +149,10 @@ pub trait CertificatesApi {
         });
 
         self.developer_session()
-            .send_dev_request_no_response(
+            .send_dev_request_no_response_with_policy(
                 &dev_url("revokeDevelopmentCert", device_type),
                 Some(body),
+                &RetryPolicy::none(),
             )
             .await
             .context("Failed to revoke development certificate")?;
@@ -160,12 +174,14 @@ pub trait CertificatesApi {
             "machineId": Uuid::new_v4().to_string().to_uppercase(),
         });
 
+        // Every submitted CSR creates a certificate, so a retry could use up another certificate slot
         let cert: CertRequest = self
             .developer_session()
-            .send_dev_request(
+            .send_dev_request_with_policy(
                 &dev_url("submitDevelopmentCSR", device_type),
                 body,
                 "certRequest",
+                &RetryPolicy::none(),
             )
             .await
             .context("Failed to submit development CSR")?;
@@ -10,12 +10,17 @@ use uuid::Uuid;
 
 use crate::{
     SideloadError,
-    anisette::AnisetteDataGenerator,
+    anisette::{AnisetteClientInfo, AnisetteDataGenerator},
     auth::{
         apple_account::{AppToken, AppleAccount},
         grandslam::GrandSlam,
+        retry::RetryPolicy,
+    },
+    error::{RequestId, RequestService},
+    util::{
+        clock::{Clock, SystemClock},
+        plist::PlistDataExtract,
     },
-    util::plist::PlistDataExtract,
 };
 
 pub use super::app_groups::*;
@@ -23,14 +28,59 @@ pub use super::app_ids::*;
 pub use super::certificates::*;
 pub use super::device_type::DeveloperDeviceType;
 pub use super::devices::*;
+pub use super::errors::*;
 pub use super::teams::*;
 
+/// Whether the error is the developer services reporting that the entity being added already exists
+///
+/// Used to make add requests idempotent, since a retried request may find its own earlier attempt.
+pub fn is_already_exists_error(report: &Report) -> bool {
+    matches!(
+        DeveloperServicesError::from_report(report),
+        Some(DeveloperServicesError::AlreadyExists { .. })
+    )
+}
+
+/// Whether the error is the developer services refusing an app ID because another team already owns the identifier
+///
+/// App ID identifiers are unique across all teams, so retrying with the same identifier will never succeed.
+pub fn is_identifier_unavailable_error(report: &Report) -> bool {
+    matches!(
+        DeveloperServicesError::from_report(report),
+        Some(DeveloperServicesError::IdentifierUnavailable { .. })
+    )
+}
+
+/// A request sent to the developer services, passed to the [`DeveloperRequestHook`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct DeveloperRequestTrace {
+    pub url: String,
+    /// The `requestId` sent to Apple
+    pub request_id: String,
+    /// The `resultCode` of the response, `None` if no response was received or it had no result code
+    pub result_code: Option<i64>,
+}
+
+/// Callback that is run after every developer services request
+pub type DeveloperRequestHook = Arc<dyn Fn(&DeveloperRequestTrace) + Send + Sync>;
+
+/// Answers developer services requests in place of Apple, see [`DeveloperSession::set_transport`]
+///
+/// Called with the URL and the full request body, including the `requestId`, and returns the response plist.
+pub type DeveloperTransport =
+    Arc<dyn Fn(&str, &Dictionary) -> Result<Dictionary, Report> + Send + Sync>;
+
 #[derive(Clone)]
 pub struct DeveloperSession {
     token: AppToken,
     adsid: String,
     client: Arc<GrandSlam>,
     anisette_generator: AnisetteDataGenerator,
+    request_hook: Option<DeveloperRequestHook>,
+    transport: Option<DeveloperTransport>,
+    retry_policy: RetryPolicy,
+    clock: Arc<dyn Clock>,
 }
 
 impl DeveloperSession {
@@ -43,8 +93,12 @@ impl DeveloperSession {
         DeveloperSession {
             token,
             adsid,
+            retry_policy: client.retry_policy(),
             client,
             anisette_generator,
+            request_hook: None,
+            transport: None,
+            clock: Arc::new(SystemClock),
         }
     }
 
@@ -54,19 +108,72 @@ impl DeveloperSession {
             .await
             .context("Failed to get xcode token from Apple account")?;
 
-        let spd = account
-            .spd
-            .as_ref()
-            .ok_or_else(|| report!("SPD not available, cannot get adsid"))?;
+        let adsid = account
+            .profile()
+            .context("Failed to create developer session")?
+            .adsid
+            .clone();
 
-        Ok(DeveloperSession::new(
+        let mut session = DeveloperSession::new(
             token,
-            spd.get_string("adsid")?,
+            adsid,
             account.grandslam_client.clone(),
             account.anisette_generator.clone(),
+        );
+        session.clock = account.clock();
+        Ok(session)
+    }
+
+    /// Create a developer session from an Xcode token obtained elsewhere, without contacting GrandSlam
+    ///
+    /// For environments where tokens are provisioned by other infrastructure. The anisette data must be for the same
+    /// machine the token was issued to, or Apple will reject the requests. `client_info` defaults to the one
+    /// reported by the anisette provider.
+    ///
+    /// The token isn't checked until the first request, use [`Self::validate_token`] to check it up front.
+    pub async fn from_token(
+        token: AppToken,
+        adsid: String,
+        anisette_generator: AnisetteDataGenerator,
+        client_info: Option<AnisetteClientInfo>,
+    ) -> Result<Self, Report> {
+        if token.is_expired(&SystemClock) {
+            warn!("Creating developer session from an expired token");
+        }
+
+        let client_info = match client_info {
+            Some(info) => info,
+            None => anisette_generator
+                .get_client_info()
+                .await
+                .context("Failed to get anisette client info")?,
+        };
+        let client = GrandSlam::without_url_bag(client_info, false)?;
+
+        Ok(DeveloperSession::new(
+            token,
+            adsid,
+            Arc::new(client),
+            anisette_generator,
         ))
     }
 
+    /// Check that the token is accepted by the developer services, with a cheap request to list the teams
+    pub async fn validate_token(&mut self) -> Result<Vec<DeveloperTeam>, Report> {
+        if self.token.is_expired(self.clock.as_ref()) {
+            bail!("The Xcode token has expired");
+        }
+        Ok(self
+            .list_teams()
+            .await
+            .context("The developer services rejected the Xcode token")?)
+    }
+
+    /// Save the anisette state so the next session can skip provisioning
+    pub async fn persist_anisette_state(&self) -> Result<(), Report> {
+        self.anisette_generator.persist().await
+    }
+
     pub async fn get_headers(&mut self) -> Result<HeaderMap, Report> {
         let mut headers = self
             .anisette_generator
@@ -87,48 +194,163 @@ impl DeveloperSession {
         self.client.clone()
     }
 
+    /// Set a callback that is run after every developer services request, with the `requestId` sent to Apple
+    pub fn set_request_hook(&mut self, hook: Option<DeveloperRequestHook>) {
+        self.request_hook = hook;
+    }
+
+    /// Send developer services requests to `transport` instead of Apple, e.g. the `MockDeveloperServices` of the
+    /// `test-util` feature
+    ///
+    /// No anisette data is fetched for requests answered by the transport. Responses are handled like Apple's,
+    /// including retries and error codes.
+    pub fn set_transport(&mut self, transport: Option<DeveloperTransport>) {
+        self.transport = transport;
+    }
+
+    /// Set how failed requests are retried. Defaults to the policy of the GrandSlam client the session was created with.
+    ///
+    /// Use [`Self::send_dev_request_with_policy`] to override it for a single request.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Set the clock the token, app IDs and anisette data are checked against. Defaults to the clock of the account
+    /// the session was created from, or the system clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.anisette_generator.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Send a developer request, retrying failures according to `policy`
+    ///
+    /// Returns the response and the error the server reported, if any.
     async fn send_dev_request_internal(
         &mut self,
         url: &str,
-        body: impl Into<Option<Dictionary>>,
-    ) -> Result<(Dictionary, Option<SideloadError>), Report> {
-        let body = body.into().unwrap_or_else(Dictionary::new);
+        body: Option<Dictionary>,
+        policy: &RetryPolicy,
+    ) -> Result<(Dictionary, Option<Report>), Report> {
+        let mut attempt = 1;
+        loop {
+            let result = self.send_dev_request_attempt(url, body.clone()).await;
+            let retry = match &result {
+                Ok((_, Some(server_error))) => DeveloperServicesError::from_report(server_error)
+                    .is_some_and(|e| policy.retries_result_code(e.code())),
+                Ok((_, None)) => false,
+                Err(report) => policy.should_retry(report),
+            };
+            if !retry || !policy.has_attempts_left(attempt) {
+                return result;
+            }
+            policy.wait(attempt, "Developer request").await;
+            attempt += 1;
+        }
+    }
+
+    /// Send a developer request once, returning the response and the error the server reported, if any
+    ///
+    /// The `requestId` is attached to every error as a [`RequestId`] and passed to the request hook.
+    async fn send_dev_request_attempt(
+        &mut self,
+        url: &str,
+        body: Option<Dictionary>,
+    ) -> Result<(Dictionary, Option<Report>), Report> {
+        let request_id = RequestId {
+            service: RequestService::DeveloperServices,
+            id: Uuid::new_v4().to_string().to_uppercase(),
+        };
+
+        let result = self
+            .send_dev_request_with_id(url, body, &request_id.id)
+            .await;
+
+        if let Some(hook) = &self.request_hook {
+            hook(&DeveloperRequestTrace {
+                url: url.to_string(),
+                request_id: request_id.id.clone(),
+                result_code: result
+                    .as_ref()
+                    .ok()
+                    .and_then(|(dict, _)| dict.get("resultCode"))
+                    .and_then(|v| v.as_signed_integer()),
+            });
+        }
+
+        let (dict, server_error) = result.attach(request_id.clone())?;
+        let server_error = server_error.map(|(code, message)| {
+            let classified = DeveloperServicesError::from_result(code, &message);
+            let agreement_url = classified.action_url();
+            let report = report!(SideloadError::DeveloperError(code, message))
+                .attach(request_id)
+                .context(classified);
+            match agreement_url {
+                Some(url) => report
+                    .context(SideloadError::LicenseAgreementNotAccepted(url.to_string()))
+                    .into_dynamic(),
+                None => report.into_dynamic(),
+            }
+        });
+
+        Ok((dict, server_error))
+    }
+
+    async fn send_dev_request_with_id(
+        &mut self,
+        url: &str,
+        body: Option<Dictionary>,
+        request_id: &str,
+    ) -> Result<(Dictionary, Option<(i64, String)>), Report> {
+        let body = body.unwrap_or_default();
 
         let base = plist!(dict {
             "clientId": "XABBG36SBA",
             "protocolVersion": "QH65B2",
-            "requestId": Uuid::new_v4().to_string().to_uppercase(),
-            "userLocale": ["en_US"],
+            "requestId": request_id,
+            "userLocale": [self.anisette_generator.locale()],
         });
 
         let body = base.into_iter().chain(body.into_iter()).collect();
 
-        let text = self
-            .client
-            .post(url)?
-            .body(plist_to_xml_string(&body))
-            .headers(
-                self.get_headers()
+        let dict: Dictionary = match self.transport.clone() {
+            Some(transport) => transport(url, &body).context("Developer request failed")?,
+            None => {
+                let text = self
+                    .client
+                    .post(url)?
+                    .body(plist_to_xml_string(&body))
+                    .headers(
+                        self.get_headers()
+                            .await
+                            .context("Failed to get anisette headers")?,
+                    )
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .context("Developer request failed")?
+                    .text()
                     .await
-                    .context("Failed to get anisette headers")?,
-            )
-            .send()
-            .await?
-            .error_for_status()
-            .context("Developer request failed")?
-            .text()
-            .await
-            .context("Failed to read developer request response text")?;
+                    .context("Failed to read developer request response text")?;
 
-        let dict: Dictionary = plist::from_bytes(text.as_bytes())
-            .context("Failed to parse developer request plist")?;
+                plist::from_bytes(text.as_bytes())
+                    .context("Failed to parse developer request plist")?
+            }
+        };
 
         // All this error handling is here to ensure that:
         // 1. We always warn/log errors from the server even if it returns the expected data
         // 2. We return server errors if the expected data is missing
         // 3. We return parsing errors if there is no server error but the expected data is missing
         let response_code = dict.get("resultCode").and_then(|v| v.as_signed_integer());
-        let mut server_error: Option<SideloadError> = None;
+        let mut server_error: Option<(i64, String)> = None;
         if let Some(code) = response_code {
             if code != 0 {
                 let result_string = dict
@@ -139,11 +361,11 @@ impl DeveloperSession {
                     .get("userString")
                     .and_then(|v| v.as_string())
                     .unwrap_or(result_string);
-                server_error = Some(SideloadError::DeveloperError(code, user_string.to_string()));
+                server_error = Some((code, user_string.to_string()));
 
                 error!(
-                    "Developer request returned error code {}: {} ({})",
-                    code, user_string, result_string
+                    "Developer request {} returned error code {}: {} ({})",
+                    request_id, code, user_string, result_string
                 );
             }
         } else {
@@ -159,14 +381,31 @@ impl DeveloperSession {
         body: impl Into<Option<Dictionary>>,
         response_key: &str,
     ) -> Result<T, Report> {
-        let (dict, server_error) = self.send_dev_request_internal(url, body).await?;
+        let policy = self.retry_policy.clone();
+        self.send_dev_request_with_policy(url, body, response_key, &policy)
+            .await
+    }
+
+    /// Like [`Self::send_dev_request`], retrying failures according to `policy` instead of the session's policy
+    ///
+    /// Use [`RetryPolicy::none`] for requests that must not be sent twice.
+    pub async fn send_dev_request_with_policy<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        body: impl Into<Option<Dictionary>>,
+        response_key: &str,
+        policy: &RetryPolicy,
+    ) -> Result<T, Report> {
+        let (dict, server_error) = self
+            .send_dev_request_internal(url, body.into(), policy)
+            .await?;
 
         let result: Result<T, _> = dict.get_struct(response_key);
 
         if result.is_err()
             && let Some(err) = server_error
         {
-            bail!(err);
+            return Err(err);
         }
 
         Ok(result.context("Failed to extract developer request result")?)
@@ -177,10 +416,25 @@ impl DeveloperSession {
         url: &str,
         body: impl Into<Option<Dictionary>>,
     ) -> Result<Dictionary, Report> {
-        let (dict, server_error) = self.send_dev_request_internal(url, body).await?;
+        let policy = self.retry_policy.clone();
+        self.send_dev_request_no_response_with_policy(url, body, &policy)
+            .await
+    }
+
+    /// Like [`Self::send_dev_request_no_response`], retrying failures according to `policy` instead of the session's
+    /// policy
+    pub async fn send_dev_request_no_response_with_policy(
+        &mut self,
+        url: &str,
+        body: impl Into<Option<Dictionary>>,
+        policy: &RetryPolicy,
+    ) -> Result<Dictionary, Report> {
+        let (dict, server_error) = self
+            .send_dev_request_internal(url, body.into(), policy)
+            .await?;
 
         if let Some(err) = server_error {
-            bail!(err);
+            return Err(err);
         }
 
         Ok(dict)
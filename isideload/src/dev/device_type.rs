@@ -1,9 +1,11 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DeveloperDeviceType {
     Any,
     Ios,
     Tvos,
     Watchos,
+    /// Macs, including Apple Silicon Macs that run iOS apps (Designed for iPad) and Mac Catalyst apps
+    Macos,
 }
 
 impl DeveloperDeviceType {
@@ -13,6 +15,23 @@ impl DeveloperDeviceType {
             DeveloperDeviceType::Ios => "ios/",
             DeveloperDeviceType::Tvos => "tvos/",
             DeveloperDeviceType::Watchos => "watchos/",
+            DeveloperDeviceType::Macos => "mac/",
+        }
+    }
+
+    /// The device type to register a device with, from its hardware model (e.g. `iPhone15,2` or `Mac14,2`)
+    pub fn from_product_type(product_type: &str) -> Self {
+        if product_type.starts_with("AppleTV") {
+            DeveloperDeviceType::Tvos
+        } else if product_type.starts_with("Watch") {
+            DeveloperDeviceType::Watchos
+        } else if product_type.starts_with("Mac")
+            || product_type.starts_with("iMac")
+            || product_type.starts_with("ADP")
+        {
+            DeveloperDeviceType::Macos
+        } else {
+            DeveloperDeviceType::Ios
         }
     }
 }
@@ -1,12 +1,16 @@
+use std::time::Duration;
+
+use crate::SideloadError;
+use crate::auth::retry::RetryPolicy;
 use crate::dev::{
-    developer_session::DeveloperSession,
+    developer_session::{DeveloperSession, is_already_exists_error},
     device_type::{DeveloperDeviceType, dev_url},
     teams::DeveloperTeam,
 };
 use plist_macro::plist;
 use rootcause::prelude::*;
 use serde::Deserialize;
-use tracing::info;
+use tracing::{info, warn};
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -15,6 +19,91 @@ pub struct DeveloperDevice {
     pub device_id: Option<String>,
     pub device_number: String,
     pub status: Option<String>,
+    /// The kind of device, e.g. `iphone`, `ipad`, `watch`, `tvOS` or `mac`
+    #[serde(default)]
+    pub device_class: Option<String>,
+}
+
+impl DeveloperDevice {
+    /// Whether the device has been disabled in the developer portal
+    ///
+    /// Disabled devices still count towards the team's device limit but can't be used in new profiles.
+    pub fn is_disabled(&self) -> bool {
+        matches!(
+            self.status.as_deref(),
+            Some("r") | Some("DISABLED") | Some("disabled")
+        )
+    }
+
+    /// Whether the device is a Mac, registered with [`DeveloperDeviceType::Macos`]
+    pub fn is_mac(&self) -> bool {
+        self.device_class
+            .as_deref()
+            .is_some_and(|class| class.eq_ignore_ascii_case("mac"))
+    }
+}
+
+/// The outcome of [`DevicesApi::ensure_device_registered`]
+#[derive(Debug, Clone)]
+pub enum DeviceRegistration {
+    /// The device was not registered and has been added, consuming one of the team's device slots
+    Added(DeveloperDevice),
+    /// The device was already registered to the team
+    AlreadyRegistered(DeveloperDevice),
+    /// The device was already registered to the team under a different name, and has been renamed
+    Renamed(DeveloperDevice),
+}
+
+impl DeviceRegistration {
+    pub fn device(&self) -> &DeveloperDevice {
+        match self {
+            DeviceRegistration::Added(device)
+            | DeviceRegistration::AlreadyRegistered(device)
+            | DeviceRegistration::Renamed(device) => device,
+        }
+    }
+
+    /// Whether this registration consumed a device slot
+    pub fn was_added(&self) -> bool {
+        matches!(self, DeviceRegistration::Added(_))
+    }
+}
+
+/// The outcome of [`DevicesApi::register_device`]
+#[derive(Debug, Clone)]
+pub struct RegisteredDevice {
+    pub registration: DeviceRegistration,
+    /// How many more devices of the same kind the team can register, if its limit is known
+    ///
+    /// Counted from the team's device list. Deleted devices aren't listed, but still count until the membership
+    /// renews, so Apple may allow fewer.
+    pub remaining_quota: Option<u32>,
+}
+
+impl RegisteredDevice {
+    /// Whether the device was registered to the team before
+    pub fn already_existed(&self) -> bool {
+        !self.registration.was_added()
+    }
+}
+
+/// How long [`DevicesApi::register_many`] waits between adding devices, to stay clear of Apple's rate limits
+pub const BULK_REGISTRATION_PACING: Duration = Duration::from_secs(1);
+
+/// The outcome of [`DevicesApi::register_many`]
+#[derive(Debug, Default)]
+pub struct BulkRegistration {
+    /// The devices that are now registered, in the order they were given
+    pub registered: Vec<DeviceRegistration>,
+    /// The UDIDs of the devices that couldn't be registered, with the reason
+    pub failed: Vec<(String, Report)>,
+}
+
+impl BulkRegistration {
+    /// Whether every device was registered
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
 }
 
 #[async_trait::async_trait]
@@ -54,32 +143,242 @@ pub trait DevicesApi {
 
         let device: DeveloperDevice = self
             .developer_session()
-            .send_dev_request(&dev_url("addDevice", device_type), body, "device")
+            .send_dev_request_with_policy(
+                &dev_url("addDevice", device_type),
+                body,
+                "device",
+                &RetryPolicy::none(),
+            )
             .await
             .context("Failed to add developer device")?;
 
         Ok(device)
     }
 
+    /// Change the name of a registered device
+    async fn update_device_name(
+        &mut self,
+        team: &DeveloperTeam,
+        device: &DeveloperDevice,
+        name: &str,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<DeveloperDevice, Report> {
+        let device_id = device
+            .device_id
+            .as_deref()
+            .ok_or_else(|| report!("Device {} has no device ID", device.device_number))?;
+        let body = plist!(dict {
+            "teamId": &team.team_id,
+            "deviceId": device_id,
+            "deviceNumber": &device.device_number,
+            "name": name,
+        });
+
+        let device: DeveloperDevice = self
+            .developer_session()
+            .send_dev_request_with_policy(
+                &dev_url("updateDevice", device_type),
+                body,
+                "device",
+                &RetryPolicy::none(),
+            )
+            .await
+            .context("Failed to rename developer device")?;
+
+        Ok(device)
+    }
+
+    /// Look up a device registered to the team by its UDID
+    ///
+    /// Returns `None` if the device is not registered.
+    async fn is_device_registered(
+        &mut self,
+        team: &DeveloperTeam,
+        udid: &str,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<Option<DeveloperDevice>, Report> {
+        let devices = self.list_devices(team, device_type).await?;
+
+        Ok(devices.into_iter().find(|d| d.device_number == udid))
+    }
+
     // TODO: This can be skipped if we know the device is already registered
     /// Check if the device is a development device, and add it if not
+    ///
+    /// Devices that are registered but disabled result in a [`SideloadError::DeviceDisabled`] error, since they can't be
+    /// re-enabled until the team's device list is reset. If `rename` is set, a device registered under a different
+    /// name is renamed to `name`.
+    ///
+    /// The returned [`DeviceRegistration`] tells whether the device had to be added.
     async fn ensure_device_registered(
         &mut self,
         team: &DeveloperTeam,
         name: &str,
         udid: &str,
         device_type: impl Into<Option<DeveloperDeviceType>> + Send,
-    ) -> Result<(), Report> {
+        rename: bool,
+    ) -> Result<DeviceRegistration, Report> {
         let device_type = device_type.into();
-        let devices = self.list_devices(team, device_type.clone()).await?;
 
-        if !devices.iter().any(|d| d.device_number == udid) {
-            info!("Registering development device");
-            self.add_device(team, name, udid, device_type).await?;
+        if let Some(device) = self
+            .is_device_registered(team, udid, device_type.clone())
+            .await?
+        {
+            if device.is_disabled() {
+                bail!(SideloadError::DeviceDisabled(udid.to_string()));
+            }
+            info!("Device is a development device");
+
+            if rename && device.name.as_deref() != Some(name) {
+                info!(
+                    "Renaming development device from {} to {}",
+                    device.name.as_deref().unwrap_or("<unnamed>"),
+                    name
+                );
+                let device = self
+                    .update_device_name(team, &device, name, device_type)
+                    .await?;
+                return Ok(DeviceRegistration::Renamed(device));
+            }
+            return Ok(DeviceRegistration::AlreadyRegistered(device));
+        }
+
+        info!("Registering development device");
+        let device = match self.add_device(team, name, udid, device_type.clone()).await {
+            Ok(device) => device,
+            Err(e) => {
+                // the device may have been added by a request whose response was lost, so check before failing
+                let existing = self.is_device_registered(team, udid, device_type).await?;
+                match existing {
+                    Some(device) => {
+                        if !is_already_exists_error(&e) {
+                            warn!("Adding device failed, but it is registered now: {:?}", e);
+                        }
+                        return Ok(DeviceRegistration::AlreadyRegistered(device));
+                    }
+                    None => return Err(e),
+                }
+            }
+        };
+        info!("Device is now a development device");
+
+        Ok(DeviceRegistration::Added(device))
+    }
+
+    /// Register a device to the team without sideloading anything, e.g. to use it with a profile built elsewhere
+    ///
+    /// Like [`Self::ensure_device_registered`], and also reports how much of the team's device limit is left, see
+    /// [`RegisteredDevice::remaining_quota`].
+    async fn register_device(
+        &mut self,
+        team: &DeveloperTeam,
+        name: &str,
+        udid: &str,
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+        rename: bool,
+    ) -> Result<RegisteredDevice, Report> {
+        let device_type = device_type.into();
+        let registration = self
+            .ensure_device_registered(team, name, udid, device_type.clone(), rename)
+            .await?;
+
+        let remaining_quota = match (
+            team.capabilities().device_limit,
+            registration.device().device_class.clone(),
+        ) {
+            (Some(limit), Some(class)) => {
+                let used = self
+                    .list_devices(team, device_type)
+                    .await?
+                    .iter()
+                    .filter(|device| {
+                        device
+                            .device_class
+                            .as_deref()
+                            .is_some_and(|c| c.eq_ignore_ascii_case(&class))
+                    })
+                    .count();
+                Some(limit.saturating_sub(used as u32))
+            }
+            _ => None,
+        };
+
+        Ok(RegisteredDevice {
+            registration,
+            remaining_quota,
+        })
+    }
+
+    /// Register an Apple Silicon Mac, so iOS apps (Designed for iPad) and Mac Catalyst apps can be provisioned to it
+    ///
+    /// `provisioning_udid` is the Mac's "Provisioning UDID" shown by `system_profiler SPHardwareDataType`, not its
+    /// hardware UUID. Registering Macs requires a paid developer team. Once registered, the Mac is included in the
+    /// team's iOS provisioning profiles like any other device.
+    async fn register_mac(
+        &mut self,
+        team: &DeveloperTeam,
+        name: &str,
+        provisioning_udid: &str,
+    ) -> Result<DeviceRegistration, Report> {
+        if !team.capabilities().can_register_macs() {
+            bail!("Macs can only be registered to paid developer teams");
+        }
+        self.ensure_device_registered(
+            team,
+            name,
+            provisioning_udid,
+            DeveloperDeviceType::Macos,
+            false,
+        )
+        .await
+    }
+
+    /// Register many devices at once, e.g. for a workshop, given as `(name, udid)` pairs
+    ///
+    /// The team's devices are listed once, so devices that are already registered don't cost a request. New devices
+    /// are added one at a time, [`BULK_REGISTRATION_PACING`] apart. A device that fails doesn't stop the others.
+    async fn register_many(
+        &mut self,
+        team: &DeveloperTeam,
+        devices: &[(&str, &str)],
+        device_type: impl Into<Option<DeveloperDeviceType>> + Send,
+    ) -> Result<BulkRegistration, Report> {
+        let device_type = device_type.into();
+        let existing = self.list_devices(team, device_type.clone()).await?;
+        let mut result = BulkRegistration::default();
+        let mut sent_request = false;
+
+        for (name, udid) in devices {
+            if let Some(device) = existing.iter().find(|d| d.device_number == *udid) {
+                if device.is_disabled() {
+                    result.failed.push((
+                        udid.to_string(),
+                        report!(SideloadError::DeviceDisabled(udid.to_string())).into_dynamic(),
+                    ));
+                } else {
+                    result
+                        .registered
+                        .push(DeviceRegistration::AlreadyRegistered(device.clone()));
+                }
+                continue;
+            }
+
+            if sent_request {
+                tokio::time::sleep(BULK_REGISTRATION_PACING).await;
+            }
+            sent_request = true;
+
+            info!("Registering development device {} ({})", name, udid);
+            match self.add_device(team, name, udid, device_type.clone()).await {
+                Ok(device) => result.registered.push(DeviceRegistration::Added(device)),
+                Err(e) => {
+                    warn!("Failed to register device {}: {:?}", udid, e);
+                    result.failed.push((udid.to_string(), e));
+                }
+            }
         }
-        info!("Device is a development device");
 
-        Ok(())
+        Ok(result)
     }
 }
 
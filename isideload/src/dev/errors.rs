@@ -0,0 +1,189 @@
+//! Classification of the `resultCode`s returned by the developer services
+//!
+//! Apple doesn't document these codes. Each constant below says where it comes from, and anything not listed is
+//! classified as [`DeveloperServicesError::Other`], so a wrong guess only loses the friendlier error, not the request.
+//! Code that needs to be certain about the account state, like [`crate::dev::app_ids::AppIdsApi::ensure_app_id`],
+//! checks it with a list request instead of relying on a code.
+
+use rootcause::prelude::*;
+
+use crate::{SideloadError, auth::retry::TRANSIENT_RESULT_CODES};
+
+/// Returned when the team already has the maximum number of development certificates. The certificate request code
+/// has relied on it since before the other codes were classified.
+const MAX_CERTIFICATES_CODE: i64 = 7460;
+
+/// Returned with a message saying the maximum App ID limit has been reached, when a free team already registered as
+/// many app IDs as it can within 7 days. AltSign reports it as `ALTAppleAPIErrorMaximumAppIDLimitReached`.
+const APP_ID_LIMIT_CODE: i64 = 9120;
+
+/// Returned with a message saying the team can't register more devices, when a paid team already used all of the
+/// device slots of its membership year
+const DEVICE_LIMIT_CODE: i64 = 8202;
+
+/// Returned with "already exists" messages when adding a device, app ID or app group that is already registered
+const ALREADY_EXISTS_CODES: &[i64] = &[35, 3008];
+
+/// Returned with a message saying the app ID identifier is not available, when another team registered it
+const IDENTIFIER_UNAVAILABLE_CODE: i64 = 9401;
+
+/// Returned with messages asking to accept the Program License Agreement, which use the rest of the 94xx range
+const AGREEMENT_CODES: std::ops::Range<i64> = 9400..9500;
+
+/// Returned with messages saying an identifier or name is invalid
+const INVALID_IDENTIFIER_CODES: std::ops::Range<i64> = 3500..3600;
+
+/// Where the Apple Developer Program License Agreement can be accepted
+pub const LICENSE_AGREEMENT_URL: &str = "https://developer.apple.com/account";
+
+/// How likely a failed developer services request is to succeed if it is sent again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryHint {
+    /// The failure may be temporary, so sending the same request again may succeed
+    Retry,
+    /// The request will keep failing until the user does something, like revoking a certificate or accepting an agreement
+    AfterUserAction,
+    /// The request will never succeed as is
+    Never,
+}
+
+/// A well known error returned by the developer services, classified from its `resultCode`
+///
+/// Failed developer requests have this as the context of their report, wrapping the raw
+/// [`SideloadError::DeveloperError`], so callers can match on it with [`DeveloperServicesError::from_report`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DeveloperServicesError {
+    #[error("The maximum number of development certificates has been reached ({code}): {message}")]
+    MaxCertificatesReached { code: i64, message: String },
+
+    #[error(
+        "The Apple Developer Program License Agreement has not been accepted ({code}): {message}"
+    )]
+    AgreementNotAccepted { code: i64, message: String },
+
+    #[error("The maximum number of app IDs has been reached ({code}): {message}")]
+    AppIdLimitReached { code: i64, message: String },
+
+    #[error("The maximum number of devices has been reached ({code}): {message}")]
+    DeviceLimitReached { code: i64, message: String },
+
+    #[error("Invalid identifier ({code}): {message}")]
+    InvalidIdentifier { code: i64, message: String },
+
+    #[error("The identifier is registered by another developer team ({code}): {message}")]
+    IdentifierUnavailable { code: i64, message: String },
+
+    #[error("Already exists ({code}): {message}")]
+    AlreadyExists { code: i64, message: String },
+
+    #[error("Developer error {code}: {message}")]
+    Other { code: i64, message: String },
+}
+
+impl DeveloperServicesError {
+    /// Classify a `resultCode`, keeping its `userString` as the message
+    ///
+    /// Only the code is used, since the message is localized and can change at any time. Codes that aren't known are
+    /// classified as [`DeveloperServicesError::Other`].
+    pub fn from_result(code: i64, message: &str) -> Self {
+        let message = message.to_string();
+
+        if code == MAX_CERTIFICATES_CODE {
+            DeveloperServicesError::MaxCertificatesReached { code, message }
+        } else if code == APP_ID_LIMIT_CODE {
+            DeveloperServicesError::AppIdLimitReached { code, message }
+        } else if code == DEVICE_LIMIT_CODE {
+            DeveloperServicesError::DeviceLimitReached { code, message }
+        } else if ALREADY_EXISTS_CODES.contains(&code) {
+            DeveloperServicesError::AlreadyExists { code, message }
+        } else if code == IDENTIFIER_UNAVAILABLE_CODE {
+            DeveloperServicesError::IdentifierUnavailable { code, message }
+        } else if AGREEMENT_CODES.contains(&code) {
+            DeveloperServicesError::AgreementNotAccepted { code, message }
+        } else if INVALID_IDENTIFIER_CODES.contains(&code) {
+            DeveloperServicesError::InvalidIdentifier { code, message }
+        } else {
+            DeveloperServicesError::Other { code, message }
+        }
+    }
+
+    /// Find the developer services error that caused the report, if any
+    ///
+    /// Also classifies [`SideloadError::DeveloperError`]s that were reported without this context.
+    pub fn from_report(report: &Report) -> Option<Self> {
+        report.iter_reports().find_map(|node| {
+            if let Some(error) = node.downcast_current_context::<DeveloperServicesError>() {
+                return Some(error.clone());
+            }
+            match node.downcast_current_context::<SideloadError>() {
+                Some(SideloadError::DeveloperError(code, message)) => {
+                    Some(Self::from_result(*code, message))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// The `resultCode` returned by the developer services
+    pub fn code(&self) -> i64 {
+        match self {
+            DeveloperServicesError::MaxCertificatesReached { code, .. }
+            | DeveloperServicesError::AppIdLimitReached { code, .. }
+            | DeveloperServicesError::DeviceLimitReached { code, .. }
+            | DeveloperServicesError::AgreementNotAccepted { code, .. }
+            | DeveloperServicesError::InvalidIdentifier { code, .. }
+            | DeveloperServicesError::IdentifierUnavailable { code, .. }
+            | DeveloperServicesError::AlreadyExists { code, .. }
+            | DeveloperServicesError::Other { code, .. } => *code,
+        }
+    }
+
+    /// The message returned by the developer services
+    pub fn message(&self) -> &str {
+        match self {
+            DeveloperServicesError::MaxCertificatesReached { message, .. }
+            | DeveloperServicesError::AppIdLimitReached { message, .. }
+            | DeveloperServicesError::DeviceLimitReached { message, .. }
+            | DeveloperServicesError::AgreementNotAccepted { message, .. }
+            | DeveloperServicesError::InvalidIdentifier { message, .. }
+            | DeveloperServicesError::IdentifierUnavailable { message, .. }
+            | DeveloperServicesError::AlreadyExists { message, .. }
+            | DeveloperServicesError::Other { message, .. } => message,
+        }
+    }
+
+    /// Whether sending the same request again could succeed, and when
+    ///
+    /// Unknown errors are only retried if their code is one of the [`TRANSIENT_RESULT_CODES`].
+    pub fn retry_hint(&self) -> RetryHint {
+        match self {
+            // app IDs free up as they expire or are deleted, device slots when the membership renews
+            DeveloperServicesError::MaxCertificatesReached { .. }
+            | DeveloperServicesError::AppIdLimitReached { .. }
+            | DeveloperServicesError::DeviceLimitReached { .. }
+            | DeveloperServicesError::AgreementNotAccepted { .. } => RetryHint::AfterUserAction,
+            DeveloperServicesError::InvalidIdentifier { .. }
+            | DeveloperServicesError::IdentifierUnavailable { .. }
+            | DeveloperServicesError::AlreadyExists { .. } => RetryHint::Never,
+            DeveloperServicesError::Other { code, .. } if TRANSIENT_RESULT_CODES.contains(code) => {
+                RetryHint::Retry
+            }
+            DeveloperServicesError::Other { .. } => RetryHint::Never,
+        }
+    }
+
+    /// The page the user has to visit before the request can succeed, if any
+    ///
+    /// Useful for frontends to link to when the [`RetryHint`] is [`RetryHint::AfterUserAction`].
+    pub fn action_url(&self) -> Option<&'static str> {
+        match self {
+            DeveloperServicesError::AgreementNotAccepted { .. } => Some(LICENSE_AGREEMENT_URL),
+            _ => None,
+        }
+    }
+
+    /// Whether the request may succeed if it is sent again right away
+    pub fn is_retryable(&self) -> bool {
+        self.retry_hint() == RetryHint::Retry
+    }
+}
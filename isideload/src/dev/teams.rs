@@ -4,20 +4,138 @@ use crate::dev::{
 };
 use rootcause::prelude::*;
 use serde::Deserialize;
+use std::time::Duration;
+
+/// Where an Apple ID without a developer team can accept the developer agreement to get its personal team
+pub const TEAM_BOOTSTRAP_URL: &str = "https://developer.apple.com/account";
+
+/// How long provisioning profiles of free teams are valid
+pub const FREE_PROFILE_VALIDITY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long provisioning profiles of paid teams are valid
+pub const PAID_PROFILE_VALIDITY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// How many app IDs a free team can register within 7 days
+pub const FREE_APP_ID_LIMIT: u32 = 10;
+
+/// How many devices of each kind (iPhone, iPad, Apple TV, ...) a paid team can register per membership year
+pub const PAID_DEVICE_LIMIT: u32 = 100;
 
 #[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
 #[serde(rename_all = "camelCase")]
 pub struct DeveloperTeam {
     pub name: Option<String>,
     pub team_id: String,
     pub r#type: Option<String>,
     pub status: Option<String>,
+    #[serde(default)]
+    pub memberships: Vec<TeamMembership>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+#[serde(rename_all = "camelCase")]
+pub struct TeamMembership {
+    pub name: Option<String>,
+    pub platform: Option<String>,
+}
+
+impl TeamMembership {
+    /// Whether this is a paid program, rather than Xcode's free provisioning
+    pub fn is_paid(&self) -> bool {
+        !self
+            .name
+            .as_deref()
+            .is_some_and(|name| name.to_lowercase().contains("free"))
+    }
+}
+
+impl DeveloperTeam {
+    /// Whether this is a free (personal) team, which can't use most capabilities
+    pub fn is_free(&self) -> bool {
+        self.r#type.as_deref() == Some("Individual")
+            && self.memberships.len() == 1
+            && self.memberships[0]
+                .name
+                .as_deref()
+                .is_some_and(|name| name.to_lowercase().contains("free"))
+    }
+
+    /// What the team's memberships allow
+    ///
+    /// The team is paid if any of its memberships is a paid program. Teams listed without memberships fall back to
+    /// [`Self::is_free`].
+    pub fn capabilities(&self) -> TeamCapabilities {
+        let paid = if self.memberships.is_empty() {
+            !self.is_free()
+        } else {
+            self.memberships.iter().any(TeamMembership::is_paid)
+        };
+        let mut platforms: Vec<String> = self
+            .memberships
+            .iter()
+            .filter_map(|membership| membership.platform.as_deref())
+            .map(str::to_lowercase)
+            .collect();
+        platforms.sort();
+        platforms.dedup();
+
+        TeamCapabilities {
+            paid,
+            profile_validity: if paid {
+                PAID_PROFILE_VALIDITY
+            } else {
+                FREE_PROFILE_VALIDITY
+            },
+            app_id_limit: (!paid).then_some(FREE_APP_ID_LIMIT),
+            device_limit: paid.then_some(PAID_DEVICE_LIMIT),
+            platforms,
+        }
+    }
+}
+
+/// What a developer team can do, which depends on whether it is a free team or a paid Apple Developer Program
+/// membership
+///
+/// Used to adjust sideloading automatically, e.g. to only enforce the app ID limit on free teams and to allow the
+/// entitlements of paid teams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct TeamCapabilities {
+    /// Whether the team is a paid Apple Developer Program membership
+    pub paid: bool,
+    /// How long the team's provisioning profiles are valid
+    pub profile_validity: Duration,
+    /// How many app IDs the team can register within 7 days, `None` if only the team's available quantity limits it
+    pub app_id_limit: Option<u32>,
+    /// How many devices of each kind the team can register per membership year, `None` for free teams, whose limit
+    /// Apple doesn't publish
+    pub device_limit: Option<u32>,
+    /// The platforms of the team's memberships, e.g. `ios`
+    pub platforms: Vec<String>,
+}
+
+impl TeamCapabilities {
+    /// Whether entitlements that need a paid membership, like push notifications or iCloud, can be used
+    pub fn has_paid_entitlements(&self) -> bool {
+        self.paid
+    }
+
+    /// Whether Macs can be registered to the team
+    pub fn can_register_macs(&self) -> bool {
+        self.paid
+    }
 }
 
 #[async_trait::async_trait]
 pub trait TeamsApi {
     fn developer_session(&mut self) -> &mut DeveloperSession;
 
+    /// List the developer teams of the account
+    ///
+    /// Fails with [`crate::SideloadError::LicenseAgreementNotAccepted`] if Apple requires the account to accept an
+    /// updated license agreement first.
     async fn list_teams(&mut self) -> Result<Vec<DeveloperTeam>, Report> {
         let response: Vec<DeveloperTeam> = self
             .developer_session()
@@ -27,6 +145,20 @@ pub trait TeamsApi {
 
         Ok(response)
     }
+
+    /// Set up the free personal team of an Apple ID that has never been used for development, like Xcode does
+    /// the first time an account is added.
+    ///
+    /// Returns the teams afterwards, which are still empty if the developer agreement has to be accepted
+    /// at [`TEAM_BOOTSTRAP_URL`] first.
+    async fn bootstrap_personal_team(&mut self) -> Result<Vec<DeveloperTeam>, Report> {
+        self.developer_session()
+            .send_dev_request_no_response(&dev_url("viewDeveloper", Any), None)
+            .await
+            .context("Failed to set up personal developer team")?;
+
+        self.list_teams().await
+    }
 }
 
 impl TeamsApi for DeveloperSession {
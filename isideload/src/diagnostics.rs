@@ -0,0 +1,189 @@
+//! Checks of the services isideload depends on, independent of any account
+
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+use crate::auth::grandslam::{GrandSlam, URL_BAG};
+
+/// The developer services endpoint, which answers unauthenticated requests with an error instead of a timeout
+const DEVELOPER_SERVICES_URL: &str = "https://developerservices2.apple.com/services/QH65B2/";
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A service probed by [`check_apple_services`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ProbedService {
+    /// Apple's authentication service, `gsa.apple.com`
+    GrandSlam,
+    /// Apple's developer services, `developerservices2.apple.com`
+    DeveloperServices,
+    /// The anisette server
+    Anisette,
+}
+
+impl Display for ProbedService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbedService::GrandSlam => write!(f, "Apple authentication"),
+            ProbedService::DeveloperServices => write!(f, "Apple developer services"),
+            ProbedService::Anisette => write!(f, "Anisette server"),
+        }
+    }
+}
+
+/// How a service answered its probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(tag = "state", content = "detail", rename_all = "snake_case")
+)]
+pub enum ServiceState {
+    Up,
+    /// The service answered with a server error status, so it is reachable but having problems
+    Degraded(u16),
+    /// The service couldn't be reached, with the connection error
+    Unreachable(String),
+}
+
+/// The result of probing one service
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct ServiceStatus {
+    pub service: ProbedService,
+    pub url: String,
+    pub state: ServiceState,
+    /// How long the service took to answer, if it did
+    pub latency: Option<Duration>,
+}
+
+impl ServiceStatus {
+    pub fn is_up(&self) -> bool {
+        self.state == ServiceState::Up
+    }
+}
+
+impl Display for ServiceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.state {
+            ServiceState::Up => write!(f, "{}: up", self.service)?,
+            ServiceState::Degraded(status) => {
+                write!(f, "{}: degraded (HTTP {})", self.service, status)?
+            }
+            ServiceState::Unreachable(error) => {
+                write!(f, "{}: unreachable ({})", self.service, error)?
+            }
+        }
+        if let Some(latency) = self.latency {
+            write!(f, " in {}ms", latency.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+/// What [`check_apple_services`] found
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct ServicesReport {
+    pub services: Vec<ServiceStatus>,
+}
+
+impl ServicesReport {
+    /// Whether every probed service is up
+    pub fn all_up(&self) -> bool {
+        self.services.iter().all(ServiceStatus::is_up)
+    }
+
+    pub fn get(&self, service: ProbedService) -> Option<&ServiceStatus> {
+        self.services.iter().find(|s| s.service == service)
+    }
+}
+
+/// Check whether Apple's services and the anisette server at `anisette_url` can be reached
+///
+/// Each service gets one lightweight request, without logging in, so frontends can tell an outage or network
+/// problem apart from a broken account or config before starting a job. Services are probed one after another,
+/// each with a 10 second timeout. Pass `None` to skip the anisette server, e.g. with local anisette.
+pub async fn check_apple_services(anisette_url: Option<&str>) -> ServicesReport {
+    let apple_client = match GrandSlam::build_reqwest_client(false) {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(
+                "Failed to build HTTP client with Apple root certificate: {:?}",
+                e
+            );
+            reqwest::Client::new()
+        }
+    };
+
+    let mut services = vec![
+        probe(&apple_client, ProbedService::GrandSlam, URL_BAG, false).await,
+        probe(
+            &apple_client,
+            ProbedService::DeveloperServices,
+            DEVELOPER_SERVICES_URL,
+            false,
+        )
+        .await,
+    ];
+    if let Some(url) = anisette_url {
+        services.push(
+            probe(
+                &reqwest::Client::new(),
+                ProbedService::Anisette,
+                &format!("{}/v3/client_info", url.trim_end_matches('/')),
+                true,
+            )
+            .await,
+        );
+    }
+
+    let report = ServicesReport { services };
+    for status in &report.services {
+        if status.is_up() {
+            info!("{}", status);
+        } else {
+            warn!("{}", status);
+        }
+    }
+    report
+}
+
+/// Send a GET request to `url`, treating any answer below 500 as up unless `require_success` is set
+async fn probe(
+    client: &reqwest::Client,
+    service: ProbedService,
+    url: &str,
+    require_success: bool,
+) -> ServiceStatus {
+    let started = Instant::now();
+    let result = client.get(url).timeout(PROBE_TIMEOUT).send().await;
+    let latency = started.elapsed();
+
+    let (state, latency) = match result {
+        Ok(response) => {
+            let status = response.status();
+            if status.is_server_error() || (require_success && !status.is_success()) {
+                (ServiceState::Degraded(status.as_u16()), Some(latency))
+            } else {
+                (ServiceState::Up, Some(latency))
+            }
+        }
+        Err(e) => (ServiceState::Unreachable(e.to_string()), None),
+    };
+
+    ServiceStatus {
+        service,
+        url: url.to_string(),
+        state,
+        latency,
+    }
+}
@@ -0,0 +1,699 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{LazyLock, RwLock},
+};
+
+use rootcause::prelude::*;
+
+use crate::SideloadError;
+
+/// Well known error conditions that have a user-presentable message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub enum MessageKey {
+    AnisetteNotProvisioned,
+    IncorrectCredentials,
+    AccountLocked,
+    IncorrectVerificationCode,
+    AnisetteRejected,
+    MaxCertificatesReached,
+    InvalidIdentifier,
+    InvalidBundle,
+    AppSignedForAnotherDevice,
+    DeviceCommunication,
+    DeviceDisabled,
+    NeedsTeamBootstrap,
+    LicenseAgreementNotAccepted,
+    AppIdUnavailable,
+    AppNotInstalled,
+    AppIdInUse,
+    DeviceNotTrusted,
+    DeviceLocked,
+    DeveloperModeDisabled,
+    InstallFailed,
+    PlistParse,
+    Unknown,
+}
+
+impl MessageKey {
+    /// Map a crate error to the message key used to look it up in a [`MessageCatalog`]
+    pub fn from_error(error: &SideloadError) -> Self {
+        match error {
+            SideloadError::AnisetteNotProvisioned => MessageKey::AnisetteNotProvisioned,
+            SideloadError::AuthWithMessage(code, _) => match code {
+                -20101 | -22406 => MessageKey::IncorrectCredentials,
+                -20209 | -20283 => MessageKey::AccountLocked,
+                -21669 => MessageKey::IncorrectVerificationCode,
+                -6005 | -45061 => MessageKey::AnisetteRejected,
+                _ => MessageKey::Unknown,
+            },
+            #[cfg(feature = "dev-api")]
+            SideloadError::DeveloperError(code, message) => {
+                use crate::dev::errors::DeveloperServicesError;
+
+                match DeveloperServicesError::from_result(*code, message) {
+                    DeveloperServicesError::MaxCertificatesReached { .. } => {
+                        MessageKey::MaxCertificatesReached
+                    }
+                    DeveloperServicesError::AgreementNotAccepted { .. } => {
+                        MessageKey::LicenseAgreementNotAccepted
+                    }
+                    DeveloperServicesError::IdentifierUnavailable { .. } => {
+                        MessageKey::AppIdUnavailable
+                    }
+                    DeveloperServicesError::InvalidIdentifier { .. } => {
+                        MessageKey::InvalidIdentifier
+                    }
+                    _ => MessageKey::Unknown,
+                }
+            }
+            #[cfg(not(feature = "dev-api"))]
+            SideloadError::DeveloperError(..) => MessageKey::Unknown,
+            SideloadError::InvalidBundle(_) => MessageKey::InvalidBundle,
+            SideloadError::PlistParseError(_) => MessageKey::PlistParse,
+            #[cfg(feature = "install")]
+            SideloadError::IdeviceError(_) => MessageKey::DeviceCommunication,
+            SideloadError::ProfileMismatch(_) => MessageKey::AppSignedForAnotherDevice,
+            SideloadError::DeviceDisabled(_) => MessageKey::DeviceDisabled,
+            SideloadError::NeedsTeamBootstrap(_) => MessageKey::NeedsTeamBootstrap,
+            SideloadError::LicenseAgreementNotAccepted(_) => {
+                MessageKey::LicenseAgreementNotAccepted
+            }
+            SideloadError::AppIdUnavailable(_) => MessageKey::AppIdUnavailable,
+            SideloadError::AppNotInstalled(_) => MessageKey::AppNotInstalled,
+            SideloadError::AppIdInUse(_) => MessageKey::AppIdInUse,
+            #[cfg(feature = "install")]
+            SideloadError::InstallFailed(_) => MessageKey::InstallFailed,
+            #[cfg(feature = "install")]
+            SideloadError::DeveloperModeDisabled => MessageKey::DeveloperModeDisabled,
+            #[cfg(feature = "install")]
+            SideloadError::PairingRequired(state) => match state {
+                crate::util::device::pairing::PairingState::PasswordProtected => {
+                    MessageKey::DeviceLocked
+                }
+                _ => MessageKey::DeviceNotTrusted,
+            },
+        }
+    }
+}
+
+/// A set of translated, user-presentable messages keyed by language and [`MessageKey`]
+///
+/// Languages are matched on their primary subtag, so `"de-AT"` will use the `"de"` messages.
+/// Messages missing from the requested language fall back to English.
+#[derive(Debug, Clone, Default)]
+pub struct MessageCatalog {
+    messages: HashMap<(String, MessageKey), String>,
+}
+
+impl MessageCatalog {
+    /// Create an empty catalog
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a catalog populated with the built-in translations
+    pub fn builtin() -> Self {
+        use MessageKey::*;
+
+        let mut catalog = Self::new();
+        let entries: &[(&str, MessageKey, &str)] = &[
+            (
+                "en",
+                AnisetteNotProvisioned,
+                "The anisette provider is not provisioned yet. Sign in again to provision it, or try a different anisette provider.",
+            ),
+            (
+                "en",
+                IncorrectCredentials,
+                "Your Apple ID or password is incorrect.",
+            ),
+            (
+                "en",
+                AccountLocked,
+                "Your Apple ID has been locked for security reasons. Unlock it at iforgot.apple.com and try again.",
+            ),
+            (
+                "en",
+                IncorrectVerificationCode,
+                "The verification code you entered is incorrect.",
+            ),
+            (
+                "en",
+                AnisetteRejected,
+                "Apple rejected the login request. Try a different anisette server.",
+            ),
+            (
+                "en",
+                MaxCertificatesReached,
+                "You have reached the maximum number of development certificates. Revoke an existing certificate and try again.",
+            ),
+            (
+                "en",
+                InvalidIdentifier,
+                "The app's bundle identifier or one of its app group names is not valid. Change it and try again.",
+            ),
+            (
+                "en",
+                InvalidBundle,
+                "The selected file is not a valid iOS app.",
+            ),
+            (
+                "en",
+                AppSignedForAnotherDevice,
+                "This app was signed for another device or has expired. Sign it with your Apple ID to install it.",
+            ),
+            (
+                "en",
+                DeviceCommunication,
+                "Could not communicate with the device. Make sure it is connected, unlocked and trusts this computer.",
+            ),
+            (
+                "en",
+                DeviceDisabled,
+                "This device has been disabled for your Apple ID. It can be registered again once your device list resets, up to a year after it was added.",
+            ),
+            (
+                "en",
+                NeedsTeamBootstrap,
+                "Your Apple ID has no developer team yet. Sign in at developer.apple.com/account, accept the Apple Developer Agreement and try again. Managed and child Apple IDs can't have developer teams, use a personal Apple ID for those.",
+            ),
+            (
+                "en",
+                LicenseAgreementNotAccepted,
+                "Apple has updated the Apple Developer Program License Agreement. Sign in at developer.apple.com/account, accept the agreement and try again.",
+            ),
+            (
+                "en",
+                AppIdUnavailable,
+                "The app's bundle identifier is already used by another developer. Choose a different bundle identifier and try again.",
+            ),
+            (
+                "en",
+                AppNotInstalled,
+                "The app is no longer installed on the device. Install it again to keep using it.",
+            ),
+            (
+                "en",
+                AppIdInUse,
+                "The app is still installed on a device. Delete it from every device first, then try again.",
+            ),
+            (
+                "en",
+                DeviceNotTrusted,
+                "Your device doesn't trust this computer yet. Unlock it, tap Trust when asked and try again.",
+            ),
+            (
+                "en",
+                DeviceLocked,
+                "Your device is locked. Unlock it and try again.",
+            ),
+            (
+                "en",
+                DeveloperModeDisabled,
+                "Developer Mode is off on your device. Turn it on in Settings > Privacy & Security > Developer Mode, then try again.",
+            ),
+            (
+                "en",
+                InstallFailed,
+                "The device couldn't install the app. Restart the device and try again.",
+            ),
+            (
+                "en",
+                PlistParse,
+                "Received an unexpected response. Please try again.",
+            ),
+            ("en", Unknown, "An unexpected error occurred."),
+            (
+                "es",
+                AnisetteNotProvisioned,
+                "El proveedor de anisette aún no está aprovisionado. Vuelve a iniciar sesión para aprovisionarlo o prueba con otro proveedor de anisette.",
+            ),
+            (
+                "es",
+                IncorrectCredentials,
+                "Tu ID de Apple o contraseña son incorrectos.",
+            ),
+            (
+                "es",
+                AccountLocked,
+                "Tu ID de Apple se ha bloqueado por motivos de seguridad. Desbloquéalo en iforgot.apple.com e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                IncorrectVerificationCode,
+                "El código de verificación introducido es incorrecto.",
+            ),
+            (
+                "es",
+                AnisetteRejected,
+                "Apple rechazó la solicitud de inicio de sesión. Prueba con otro servidor de anisette.",
+            ),
+            (
+                "es",
+                MaxCertificatesReached,
+                "Has alcanzado el número máximo de certificados de desarrollo. Revoca un certificado existente e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                InvalidIdentifier,
+                "El identificador de la app o el nombre de uno de sus grupos de apps no es válido. Cámbialo e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                InvalidBundle,
+                "El archivo seleccionado no es una app de iOS válida.",
+            ),
+            (
+                "es",
+                AppSignedForAnotherDevice,
+                "Esta app se firmó para otro dispositivo o ha caducado. Fírmala con tu ID de Apple para instalarla.",
+            ),
+            (
+                "es",
+                DeviceCommunication,
+                "No se pudo comunicar con el dispositivo. Asegúrate de que está conectado, desbloqueado y confía en este ordenador.",
+            ),
+            (
+                "es",
+                DeviceDisabled,
+                "Este dispositivo se ha desactivado para tu ID de Apple. Podrás registrarlo de nuevo cuando se restablezca tu lista de dispositivos, hasta un año después de añadirlo.",
+            ),
+            (
+                "es",
+                NeedsTeamBootstrap,
+                "Tu ID de Apple aún no tiene un equipo de desarrollo. Inicia sesión en developer.apple.com/account, acepta el Acuerdo de Apple Developer e inténtalo de nuevo. Los ID de Apple gestionados e infantiles no pueden tener equipos de desarrollo, usa un ID de Apple personal en su lugar.",
+            ),
+            (
+                "es",
+                LicenseAgreementNotAccepted,
+                "Apple ha actualizado el Acuerdo de licencia del Apple Developer Program. Inicia sesión en developer.apple.com/account, acepta el acuerdo e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                AppIdUnavailable,
+                "El identificador de la app ya lo usa otro desarrollador. Elige un identificador diferente e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                AppNotInstalled,
+                "La app ya no está instalada en el dispositivo. Instálala de nuevo para seguir usándola.",
+            ),
+            (
+                "es",
+                AppIdInUse,
+                "La app sigue instalada en un dispositivo. Elimínala de todos los dispositivos e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                DeviceNotTrusted,
+                "Tu dispositivo todavía no confía en este ordenador. Desbloquéalo, toca Confiar cuando se te pida e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                DeviceLocked,
+                "Tu dispositivo está bloqueado. Desbloquéalo e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                DeveloperModeDisabled,
+                "El modo de desarrollador está desactivado en tu dispositivo. Actívalo en Ajustes > Privacidad y seguridad > Modo de desarrollador e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                InstallFailed,
+                "El dispositivo no pudo instalar la app. Reinicia el dispositivo e inténtalo de nuevo.",
+            ),
+            (
+                "es",
+                PlistParse,
+                "Se recibió una respuesta inesperada. Inténtalo de nuevo.",
+            ),
+            ("es", Unknown, "Se produjo un error inesperado."),
+            (
+                "de",
+                AnisetteNotProvisioned,
+                "Der Anisette-Anbieter ist noch nicht eingerichtet. Melde dich erneut an, um ihn einzurichten, oder verwende einen anderen Anisette-Anbieter.",
+            ),
+            (
+                "de",
+                IncorrectCredentials,
+                "Deine Apple-ID oder dein Passwort ist falsch.",
+            ),
+            (
+                "de",
+                AccountLocked,
+                "Deine Apple-ID wurde aus Sicherheitsgründen gesperrt. Entsperre sie unter iforgot.apple.com und versuche es erneut.",
+            ),
+            (
+                "de",
+                IncorrectVerificationCode,
+                "Der eingegebene Bestätigungscode ist falsch.",
+            ),
+            (
+                "de",
+                AnisetteRejected,
+                "Apple hat die Anmeldung abgelehnt. Verwende einen anderen Anisette-Server.",
+            ),
+            (
+                "de",
+                MaxCertificatesReached,
+                "Die maximale Anzahl an Entwicklungszertifikaten ist erreicht. Widerrufe ein vorhandenes Zertifikat und versuche es erneut.",
+            ),
+            (
+                "de",
+                InvalidIdentifier,
+                "Die Bundle-ID der App oder der Name einer ihrer App-Gruppen ist ungültig. Ändere sie und versuche es erneut.",
+            ),
+            (
+                "de",
+                InvalidBundle,
+                "Die ausgewählte Datei ist keine gültige iOS-App.",
+            ),
+            (
+                "de",
+                AppSignedForAnotherDevice,
+                "Diese App wurde für ein anderes Gerät signiert oder ist abgelaufen. Signiere sie mit deiner Apple-ID, um sie zu installieren.",
+            ),
+            (
+                "de",
+                DeviceCommunication,
+                "Keine Verbindung zum Gerät möglich. Stelle sicher, dass es verbunden und entsperrt ist und diesem Computer vertraut.",
+            ),
+            (
+                "de",
+                DeviceDisabled,
+                "Dieses Gerät wurde für deine Apple-ID deaktiviert. Es kann erneut registriert werden, sobald deine Geräteliste zurückgesetzt wird, spätestens ein Jahr nach dem Hinzufügen.",
+            ),
+            (
+                "de",
+                NeedsTeamBootstrap,
+                "Deine Apple-ID hat noch kein Entwicklerteam. Melde dich auf developer.apple.com/account an, akzeptiere die Apple Developer Agreement und versuche es erneut. Verwaltete Apple-IDs und Apple-IDs von Kindern können keine Entwicklerteams haben, verwende stattdessen eine persönliche Apple-ID.",
+            ),
+            (
+                "de",
+                LicenseAgreementNotAccepted,
+                "Apple hat die Lizenzvereinbarung des Apple Developer Program aktualisiert. Melde dich auf developer.apple.com/account an, akzeptiere die Vereinbarung und versuche es erneut.",
+            ),
+            (
+                "de",
+                AppIdUnavailable,
+                "Die Bundle-ID der App wird bereits von einem anderen Entwickler verwendet. Wähle eine andere Bundle-ID und versuche es erneut.",
+            ),
+            (
+                "de",
+                AppNotInstalled,
+                "Die App ist nicht mehr auf dem Gerät installiert. Installiere sie erneut, um sie weiter zu verwenden.",
+            ),
+            (
+                "de",
+                AppIdInUse,
+                "Die App ist noch auf einem Gerät installiert. Lösche sie zuerst von allen Geräten und versuche es erneut.",
+            ),
+            (
+                "de",
+                DeviceNotTrusted,
+                "Dein Gerät vertraut diesem Computer noch nicht. Entsperre es, tippe auf Vertrauen, wenn du gefragt wirst, und versuche es erneut.",
+            ),
+            (
+                "de",
+                DeviceLocked,
+                "Dein Gerät ist gesperrt. Entsperre es und versuche es erneut.",
+            ),
+            (
+                "de",
+                DeveloperModeDisabled,
+                "Der Entwicklermodus ist auf deinem Gerät deaktiviert. Aktiviere ihn unter Einstellungen > Datenschutz & Sicherheit > Entwicklermodus und versuche es erneut.",
+            ),
+            (
+                "de",
+                InstallFailed,
+                "Das Gerät konnte die App nicht installieren. Starte das Gerät neu und versuche es erneut.",
+            ),
+            (
+                "de",
+                PlistParse,
+                "Es wurde eine unerwartete Antwort empfangen. Bitte versuche es erneut.",
+            ),
+            ("de", Unknown, "Ein unerwarteter Fehler ist aufgetreten."),
+            (
+                "fr",
+                AnisetteNotProvisioned,
+                "Le fournisseur anisette n'est pas encore provisionné. Reconnectez-vous pour le provisionner ou essayez un autre fournisseur anisette.",
+            ),
+            (
+                "fr",
+                IncorrectCredentials,
+                "Votre identifiant Apple ou votre mot de passe est incorrect.",
+            ),
+            (
+                "fr",
+                AccountLocked,
+                "Votre identifiant Apple a été verrouillé pour des raisons de sécurité. Déverrouillez-le sur iforgot.apple.com puis réessayez.",
+            ),
+            (
+                "fr",
+                IncorrectVerificationCode,
+                "Le code de vérification saisi est incorrect.",
+            ),
+            (
+                "fr",
+                AnisetteRejected,
+                "Apple a refusé la demande de connexion. Essayez un autre serveur anisette.",
+            ),
+            (
+                "fr",
+                MaxCertificatesReached,
+                "Vous avez atteint le nombre maximal de certificats de développement. Révoquez un certificat existant puis réessayez.",
+            ),
+            (
+                "fr",
+                InvalidIdentifier,
+                "L'identifiant de l'app ou le nom de l'un de ses groupes d'apps n'est pas valide. Modifiez-le puis réessayez.",
+            ),
+            (
+                "fr",
+                InvalidBundle,
+                "Le fichier sélectionné n'est pas une app iOS valide.",
+            ),
+            (
+                "fr",
+                AppSignedForAnotherDevice,
+                "Cette app a été signée pour un autre appareil ou a expiré. Signez-la avec votre identifiant Apple pour l'installer.",
+            ),
+            (
+                "fr",
+                DeviceCommunication,
+                "Impossible de communiquer avec l'appareil. Vérifiez qu'il est connecté, déverrouillé et qu'il fait confiance à cet ordinateur.",
+            ),
+            (
+                "fr",
+                DeviceDisabled,
+                "Cet appareil a été désactivé pour votre identifiant Apple. Il pourra être enregistré à nouveau lorsque votre liste d'appareils sera réinitialisée, jusqu'à un an après son ajout.",
+            ),
+            (
+                "fr",
+                NeedsTeamBootstrap,
+                "Votre identifiant Apple n'a pas encore d'équipe de développement. Connectez-vous sur developer.apple.com/account, acceptez l'Apple Developer Agreement puis réessayez. Les identifiants Apple gérés et ceux d'enfants ne peuvent pas avoir d'équipe de développement, utilisez plutôt un identifiant Apple personnel.",
+            ),
+            (
+                "fr",
+                LicenseAgreementNotAccepted,
+                "Apple a mis à jour le contrat de licence de l'Apple Developer Program. Connectez-vous sur developer.apple.com/account, acceptez le contrat puis réessayez.",
+            ),
+            (
+                "fr",
+                AppIdUnavailable,
+                "L'identifiant de l'app est déjà utilisé par un autre développeur. Choisissez un autre identifiant puis réessayez.",
+            ),
+            (
+                "fr",
+                AppNotInstalled,
+                "L'app n'est plus installée sur l'appareil. Réinstallez-la pour continuer à l'utiliser.",
+            ),
+            (
+                "fr",
+                AppIdInUse,
+                "L'app est toujours installée sur un appareil. Supprimez-la de tous les appareils puis réessayez.",
+            ),
+            (
+                "fr",
+                DeviceNotTrusted,
+                "Votre appareil ne fait pas encore confiance à cet ordinateur. Déverrouillez-le, touchez Se fier lorsque cela vous est demandé puis réessayez.",
+            ),
+            (
+                "fr",
+                DeviceLocked,
+                "Votre appareil est verrouillé. Déverrouillez-le puis réessayez.",
+            ),
+            (
+                "fr",
+                DeveloperModeDisabled,
+                "Le mode développeur est désactivé sur votre appareil. Activez-le dans Réglages > Confidentialité et sécurité > Mode développeur, puis réessayez.",
+            ),
+            (
+                "fr",
+                InstallFailed,
+                "L'appareil n'a pas pu installer l'app. Redémarrez l'appareil puis réessayez.",
+            ),
+            (
+                "fr",
+                PlistParse,
+                "Une réponse inattendue a été reçue. Veuillez réessayer.",
+            ),
+            ("fr", Unknown, "Une erreur inattendue s'est produite."),
+        ];
+
+        for (lang, key, message) in entries {
+            catalog.insert(lang, *key, message);
+        }
+        catalog
+    }
+
+    /// Add or replace a message for the given language
+    pub fn insert(&mut self, lang: &str, key: MessageKey, message: &str) {
+        self.messages
+            .insert((primary_language(lang), key), message.to_string());
+    }
+
+    /// Look up the message for a key, falling back to English if the language is missing
+    pub fn get(&self, key: MessageKey, lang: &str) -> Option<&str> {
+        self.messages
+            .get(&(primary_language(lang), key))
+            .or_else(|| self.messages.get(&("en".to_string(), key)))
+            .map(|s| s.as_str())
+    }
+
+    /// Get a user-presentable message for the error
+    ///
+    /// Errors without a catalog entry fall back to the message Apple sent, if any.
+    pub fn message(&self, error: &SideloadError, lang: &str) -> String {
+        let key = MessageKey::from_error(error);
+        if key == MessageKey::Unknown {
+            match error {
+                SideloadError::AuthWithMessage(_, message)
+                | SideloadError::DeveloperError(_, message) => return message.clone(),
+                _ => {}
+            }
+        }
+
+        self.get(key, lang)
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| error.to_string())
+    }
+}
+
+static CATALOG: LazyLock<RwLock<MessageCatalog>> =
+    LazyLock::new(|| RwLock::new(MessageCatalog::builtin()));
+
+/// Add or replace a message in the global catalog used by [`user_message`]
+pub fn register_message(lang: &str, key: MessageKey, message: &str) {
+    CATALOG
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(lang, key, message);
+}
+
+/// Get a friendly, translated message for an error, suitable for showing to users
+///
+/// # Arguments
+/// - `error`: The error to describe
+/// - `lang`: A language tag such as `"en"` or `"de-AT"`
+pub fn user_message(error: &SideloadError, lang: &str) -> String {
+    CATALOG
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .message(error, lang)
+}
+
+/// Get a friendly message for the first [`SideloadError`] found in a report
+///
+/// Returns `None` if the report does not contain a typed crate error.
+pub fn user_message_for_report(report: &Report, lang: &str) -> Option<String> {
+    report
+        .iter_reports()
+        .find_map(|node| node.downcast_current_context::<SideloadError>())
+        .map(|error| user_message(error, lang))
+}
+
+/// The Apple service a [`RequestId`] was sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RequestService {
+    GrandSlam,
+    DeveloperServices,
+}
+
+/// The ID of a request to Apple, attached to the reports of failed requests
+///
+/// Developer services requests carry the `requestId` sent to Apple, so it can be matched up with Apple's own logs.
+/// GrandSlam requests have an ID generated locally that is only logged by isideload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct RequestId {
+    pub service: RequestService,
+    pub id: String,
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.service {
+            RequestService::GrandSlam => write!(f, "GrandSlam request ID: {}", self.id),
+            RequestService::DeveloperServices => {
+                write!(f, "Developer services request ID: {}", self.id)
+            }
+        }
+    }
+}
+
+/// Get the ID of the failed request to Apple that caused the report, if any
+///
+/// Useful for including in bug reports, since the rest of the report doesn't say which request failed.
+pub fn request_id_for_report(report: &Report) -> Option<&RequestId> {
+    report.iter_reports().find_map(|node| {
+        node.attachments()
+            .iter()
+            .find_map(|attachment| attachment.downcast_inner::<RequestId>())
+    })
+}
+
+/// What went wrong in a failed operation, for frontends that can't inspect reports
+///
+/// With the `json-events` feature this serializes to JSON, so errors can be sent along with the events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct ErrorSummary {
+    /// The report's outermost message
+    pub message: String,
+    /// The key of the first crate error in the report, to look up a translated message with
+    pub key: Option<MessageKey>,
+    pub request_id: Option<RequestId>,
+    /// The message of every error in the report, outermost first
+    pub causes: Vec<String>,
+}
+
+impl ErrorSummary {
+    pub fn from_report(report: &Report) -> Self {
+        ErrorSummary {
+            message: report.format_current_context().to_string(),
+            key: report
+                .iter_reports()
+                .find_map(|node| node.downcast_current_context::<SideloadError>())
+                .map(MessageKey::from_error),
+            request_id: request_id_for_report(report).cloned(),
+            causes: report
+                .iter_reports()
+                .map(|node| node.format_current_context().to_string())
+                .collect(),
+        }
+    }
+}
+
+fn primary_language(lang: &str) -> String {
+    lang.split(['-', '_']).next().unwrap_or(lang).to_lowercase()
+}
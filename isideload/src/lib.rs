@@ -1,12 +1,24 @@
+#[cfg(feature = "install")]
 use idevice::IdeviceError;
-use rootcause::{
-    hooks::{Hooks, context_formatter::ContextFormatterHook},
-    prelude::*,
-};
+#[cfg(feature = "auth")]
+use rootcause::hooks::context_formatter::ContextFormatterHook;
+use rootcause::{hooks::Hooks, prelude::*};
 
+// These modules are public so advanced users can reach the raw Apple APIs, but they change often.
+// The stable surface is re-exported from `prelude`.
+#[cfg(feature = "auth")]
+#[cfg_attr(not(feature = "unstable-api"), doc(hidden))]
 pub mod anisette;
+#[cfg(feature = "auth")]
+#[cfg_attr(not(feature = "unstable-api"), doc(hidden))]
 pub mod auth;
+#[cfg(feature = "dev-api")]
+#[cfg_attr(not(feature = "unstable-api"), doc(hidden))]
 pub mod dev;
+#[cfg(feature = "auth")]
+pub mod diagnostics;
+pub mod error;
+pub mod prelude;
 pub mod sideload;
 pub mod util;
 
@@ -27,13 +39,53 @@ pub enum SideloadError {
     #[error("Invalid bundle: {0}")]
     InvalidBundle(String),
 
+    #[cfg(feature = "install")]
     #[error("{0}")]
     IdeviceError(#[from] IdeviceError),
+
+    #[error("Device {0} is disabled in the developer portal")]
+    DeviceDisabled(String),
+
+    #[error(
+        "This Apple ID has no developer team yet. Sign in at {0} and accept the Apple Developer Agreement, then try again (managed and child Apple IDs can't have a team)"
+    )]
+    NeedsTeamBootstrap(String),
+
+    #[error(
+        "The Apple Developer Program License Agreement has not been accepted. Accept it at {0}, then try again"
+    )]
+    LicenseAgreementNotAccepted(String),
+
+    #[error("The app ID {0} is already registered by another developer team")]
+    AppIdUnavailable(String),
+
+    #[error("{0} is not installed on the device")]
+    AppNotInstalled(String),
+
+    #[error("The app ID {0} is still used by an installed app")]
+    AppIdInUse(String),
+
+    #[cfg(feature = "install")]
+    #[error("Installation failed: {0}")]
+    InstallFailed(sideload::install::InstallStatus),
+
+    #[cfg(feature = "install")]
+    #[error("Developer Mode is disabled on the device")]
+    DeveloperModeDisabled,
+
+    #[cfg(feature = "install")]
+    #[error("The device does not trust this computer: {0}")]
+    PairingRequired(util::device::pairing::PairingState),
+
+    #[error("Prebuilt app cannot be installed: {0}")]
+    ProfileMismatch(sideload::profile::ProfileDiagnosis),
 }
 
 // The default reqwest error formatter sucks and provides no info
+#[cfg(feature = "auth")]
 struct ReqwestErrorFormatter;
 
+#[cfg(feature = "auth")]
 impl ContextFormatterHook<reqwest::Error> for ReqwestErrorFormatter {
     fn display(
         &self,
@@ -51,8 +103,10 @@ impl ContextFormatterHook<reqwest::Error> for ReqwestErrorFormatter {
 }
 
 pub fn init() -> Result<(), Report> {
-    Hooks::new()
-        .context_formatter::<reqwest::Error, _>(ReqwestErrorFormatter)
+    let hooks = Hooks::new();
+    #[cfg(feature = "auth")]
+    let hooks = hooks.context_formatter::<reqwest::Error, _>(ReqwestErrorFormatter);
+    hooks
         .install()
         .context("Failed to install error reporting hooks")?;
     Ok(())
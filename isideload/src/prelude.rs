@@ -0,0 +1,107 @@
+//! The stable, high-level API of isideload
+//!
+//! Everything exported here follows semver. The lower level `auth`, `dev` and `anisette` modules
+//! are still public, but they are considered unstable and may change in any release. Enable the
+//! `unstable-api` feature to show them in the documentation.
+
+pub use crate::SideloadError;
+#[cfg(all(feature = "anisette-macos", target_os = "macos"))]
+pub use crate::anisette::macos::MacosAnisetteProvider;
+#[cfg(feature = "anisette-remote")]
+pub use crate::anisette::remote_v3::RemoteV3AnisetteProvider;
+#[cfg(feature = "auth")]
+pub use crate::anisette::{AnisetteClientInfo, AnisetteData, AnisetteProvider};
+#[cfg(feature = "auth")]
+pub use crate::auth::{
+    account_manager::AccountManager,
+    apple_account::{AppToken, AppleAccount, TwoFactorMethod, TwoFactorPolicy},
+    builder::AppleAccountBuilder,
+    health::{AccountHealthReport, HealthCheck},
+    retry::RetryPolicy,
+};
+#[cfg(feature = "dev-api")]
+pub use crate::dev::{
+    certificates::DevelopmentCertificate,
+    developer_session::{DeveloperRequestTrace, DeveloperSession},
+    devices::{DeviceRegistration, RegisteredDevice},
+    errors::{DeveloperServicesError, RetryHint},
+    teams::{DeveloperTeam, TeamCapabilities},
+};
+#[cfg(feature = "auth")]
+pub use crate::diagnostics::{
+    ProbedService, ServiceState, ServiceStatus, ServicesReport, check_apple_services,
+};
+pub use crate::error::{
+    ErrorSummary, MessageKey, RequestId, RequestService, request_id_for_report, user_message,
+    user_message_for_report,
+};
+#[cfg(all(feature = "sign", feature = "json-events"))]
+pub use crate::sideload::events::json_lines_observer;
+#[cfg(feature = "install")]
+pub use crate::sideload::install::{
+    FailedUpload, InstallMode, InstallOptions, InstallProgress, InstallStatus, ProviderFactory,
+    UploadError, UploadVerification,
+};
+#[cfg(all(feature = "sign", feature = "install"))]
+pub use crate::sideload::sideloader::{
+    AppIdRemoval, BatchInstallResult, InstallOutcome, InstallPhases, RefreshOutcome,
+};
+#[cfg(feature = "sign")]
+pub use crate::sideload::{
+    SideloaderBuilder, TeamSelection,
+    adhoc::{AdhocSigner, sign_adhoc},
+    application::{Application, ExtractCleanup, ExtractOptions, SpecialApp},
+    builder::{AppIdReusePolicy, AppIdSuffixStrategy, ConfigError, MaxCertsBehavior},
+    customization::{BundleCustomization, BundleCustomizations},
+    entitlements::EntitlementPolicy,
+    events::{SideloadEvent, SideloadObserver, channel_observer},
+    imported::{ImportedIdentity, ImportedIdentitySideloader},
+    inject::InjectLibrariesPass,
+    pass::{
+        BundleHook, BundleHookPass, BundlePass, BundlePipeline, EmbedProfilePass, PassContext,
+        SpecialAppPass, SupportedDevicesPass, WriteInfoPass,
+    },
+    platform::TargetPlatform,
+    preflight::{PreflightIssue, PreflightReport, PreflightSeverity},
+    rollback::RollbackReport,
+    sideloader::{BatchSignResult, SideloadOutcome, Sideloader, SignedApp},
+    sign::{DroppedCapability, SigningIdentity},
+    signing_report::{BundleSigningReport, SigningReport},
+    thinning::ThinningReport,
+    validation::{ValidationIssue, ValidationReport, validate_signed_app},
+    wwdr::{ChainStatus, WWDR_INTERMEDIATE_URLS, WwdrIntermediates},
+};
+pub use crate::sideload::{
+    bundle::Bundle,
+    extensions::{AppClipBehavior, ExtensionFilter, ExtensionMatcher},
+    itunes_metadata::ITunesMetadataBehavior,
+    profile::ProfileDiagnosis,
+    sanitize::SupportedDevicesBehavior,
+};
+pub use crate::util::clock::{Clock, FixedClock, SystemClock};
+#[cfg(feature = "install")]
+pub use crate::util::device::{
+    aliases::{device_alias, device_aliases, set_device_alias},
+    app_manager::{InstalledApp, list_installed_apps, lookup_app, uninstall_app},
+    developer_mode::{
+        DeveloperModeStatus, accept_developer_mode, developer_mode_status, enable_developer_mode,
+        reveal_developer_mode_option,
+    },
+    discovery::{
+        ConnectionType, DiscoveredDevice, list_devices, list_devices_at, list_devices_with_aliases,
+    },
+    export_pairing_file,
+    pairing::{PairingState, pair, pairing_state},
+    profiles::{
+        InstalledProfile, install_profile, list_profiles, remove_profile, remove_stale_profiles,
+    },
+};
+#[cfg(feature = "encrypted-fs-storage")]
+pub use crate::util::encrypted_fs_storage::EncryptedFsStorage;
+#[cfg(feature = "fs-storage")]
+pub use crate::util::fs_storage::FsStorage;
+#[cfg(feature = "sign")]
+pub use crate::util::ipa_cache::{CachedIpa, IpaCache};
+#[cfg(feature = "keyring-storage")]
+pub use crate::util::keyring_storage::KeyringStorage;
+pub use crate::util::storage::{NamespacedStorage, SideloadingStorage};
@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+
+use apple_codesign::{SettingsScope, SigningSettings, UnifiedSigner};
+use plist_macro::plist_to_xml_string;
+use rootcause::prelude::*;
+use tracing::{debug, info, warn};
+
+use crate::sideload::{
+    application::Application,
+    customization::{BundleCustomization, BundleCustomizations},
+    entitlements::requested_entitlements,
+    events::{SideloadEvent, SideloadObserver},
+    package,
+    sign::{SigningIdentity, relative_bundle_path, signing_settings},
+    signing_report::{BundleSigningReport, SigningReport},
+};
+
+/// An identity without a certificate, which makes apple-codesign write ad-hoc signatures
+struct AdhocIdentity;
+
+impl SigningIdentity for AdhocIdentity {
+    fn setup_signing_settings<'a>(
+        &'a self,
+        _settings: &mut SigningSettings<'a>,
+    ) -> Result<(), Report> {
+        Ok(())
+    }
+}
+
+/// Fake-signs apps with ad-hoc signatures, without an Apple ID, certificate or provisioning profile
+///
+/// Ad-hoc signed apps only run on jailbroken devices or through TrollStore, which don't check who signed an app.
+/// Every bundle keeps the entitlements it was signed with before, since there is no profile to limit them.
+#[derive(Default)]
+pub struct AdhocSigner {
+    bundle_id: Option<String>,
+    observers: Vec<SideloadObserver>,
+    bundle_customizations: BundleCustomizations,
+    injected_libraries: Vec<PathBuf>,
+}
+
+impl AdhocSigner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Change the bundle identifier of the app, and of its extensions that share its prefix, before signing
+    pub fn bundle_id(mut self, bundle_id: impl Into<String>) -> Self {
+        self.bundle_id = Some(bundle_id.into());
+        self
+    }
+
+    /// Add an observer that is notified of signing progress
+    pub fn observer(mut self, observer: SideloadObserver) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Customize the bundle at `path` inside the app right before it is signed, or `""` for the main app
+    ///
+    /// See [`BundleCustomization`] for details. Entitlement overrides apply to the entitlements the bundle was
+    /// signed with before.
+    pub fn bundle_customization(
+        mut self,
+        path: impl Into<String>,
+        customization: BundleCustomization,
+    ) -> Self {
+        self.bundle_customizations
+            .insert(path.into(), customization);
+        self
+    }
+
+    /// Inject a dylib or framework, like a tweak, into the app before it is signed
+    ///
+    /// See [`Application::inject_library`]. Can be called multiple times.
+    pub fn inject_library(mut self, path: PathBuf) -> Self {
+        self.injected_libraries.push(path);
+        self
+    }
+
+    fn emit(&self, event: SideloadEvent) {
+        for observer in &self.observers {
+            observer(&event);
+        }
+    }
+
+    /// Sign the app at the provided path and return the path to the signed app bundle (in a temp dir for IPAs)
+    pub fn sign_app(&self, app_path: PathBuf) -> Result<(PathBuf, SigningReport), Report> {
+        let (app, report) = self.sign(app_path)?;
+        Ok((app.bundle.bundle_dir, report))
+    }
+
+    /// Sign the app at the provided path and package it as an IPA at `output_path`
+    pub fn sign_to_ipa(
+        &self,
+        app_path: PathBuf,
+        output_path: &Path,
+    ) -> Result<SigningReport, Report> {
+        let (app, report) = self.sign(app_path)?;
+        let result = package::write_ipa(
+            &app.bundle.bundle_dir,
+            output_path,
+            None,
+            app.itunes_metadata.as_ref(),
+        );
+
+        if let Some(temp_path) = &app.temp_path
+            && let Err(e) = std::fs::remove_dir_all(temp_path)
+        {
+            warn!("Failed to remove temporary signed app file: {}", e);
+        }
+        result.context("Failed to package signed app")?;
+        self.emit(SideloadEvent::Done);
+
+        Ok(report)
+    }
+
+    fn sign(&self, app_path: PathBuf) -> Result<(Application, SigningReport), Report> {
+        let mut app = Application::new(app_path)?;
+
+        if let Some(bundle_id) = &self.bundle_id {
+            let main_bundle_id = app.main_bundle_id()?;
+            if main_bundle_id != *bundle_id {
+                info!("Renaming {} to {}", main_bundle_id, bundle_id);
+                app.update_bundle_id(&main_bundle_id, bundle_id)?;
+            }
+        }
+        for library in &self.injected_libraries {
+            app.inject_library(library)?;
+        }
+        app.bundle.write_info_recursive()?;
+
+        let bundles = app.bundle.collect_bundles_sorted();
+        let mut report = SigningReport::default();
+        for bundle in bundles {
+            let relative_path = relative_bundle_path(&app.bundle.bundle_dir, &bundle.bundle_dir);
+            let customization = self.bundle_customizations.get(&relative_path);
+            if let Some(customization) = customization {
+                customization.copy_files(&bundle.bundle_dir)?;
+            }
+
+            let mut entitlements = match requested_entitlements(&bundle) {
+                Ok(entitlements) => entitlements.unwrap_or_default(),
+                Err(e) => {
+                    warn!(
+                        "Failed to read entitlements of {}: {:?}",
+                        bundle.bundle_dir.display(),
+                        e
+                    );
+                    Default::default()
+                }
+            };
+            if let Some(customization) = customization {
+                customization.apply_entitlements(&mut entitlements);
+            }
+
+            let bundle_name = bundle
+                .bundle_dir
+                .file_name()
+                .unwrap_or(bundle.bundle_dir.as_os_str())
+                .to_string_lossy()
+                .to_string();
+            info!("Ad-hoc signing {}", bundle_name);
+            self.emit(SideloadEvent::Signing {
+                bundle: bundle_name,
+            });
+
+            let mut settings = signing_settings(&AdhocIdentity)?;
+            if !entitlements.is_empty() {
+                settings
+                    .set_entitlements_xml(SettingsScope::Main, plist_to_xml_string(&entitlements))
+                    .context("Failed to set entitlements XML")?;
+            }
+            UnifiedSigner::new(settings)
+                .sign_path_in_place(&bundle.bundle_dir)
+                .context(format!(
+                    "Failed to sign bundle: {}",
+                    bundle.bundle_dir.display()
+                ))
+                .attach(report.clone())?;
+
+            match BundleSigningReport::from_signed_bundle(&bundle, relative_path, entitlements) {
+                Ok(bundle_report) => {
+                    debug!("{}", bundle_report);
+                    report.bundles.push(bundle_report);
+                }
+                Err(e) => warn!(
+                    "Failed to summarize signature of {}: {:?}",
+                    bundle.bundle_dir.display(),
+                    e
+                ),
+            }
+        }
+
+        info!("App signed!");
+        Ok((app, report))
+    }
+}
+
+/// Ad-hoc sign the app at `app_path` and package it as an IPA at `output_path`
+///
+/// See [`AdhocSigner`] to change the bundle identifier or customize bundles.
+pub fn sign_adhoc(app_path: PathBuf, output_path: &Path) -> Result<SigningReport, Report> {
+    AdhocSigner::new().sign_to_ipa(app_path, output_path)
+}
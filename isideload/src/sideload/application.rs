@@ -2,86 +2,257 @@
 // I'm planning on redoing this later to better handle entitlements, extensions, etc, but it will do for now
 
 use crate::SideloadError;
-use crate::dev::app_ids::{AppId, AppIdsApi};
+use crate::dev::app_ids::{AppId, AppIdsApi, ListAppIdsResponse};
 use crate::dev::developer_session::DeveloperSession;
+use crate::dev::device_type::DeveloperDeviceType;
 use crate::dev::teams::DeveloperTeam;
+use crate::sideload::builder::AppIdReusePolicy;
 use crate::sideload::bundle::Bundle;
 use crate::sideload::cert_identity::CertificateIdentity;
+use crate::sideload::sanitize::fix_bundle_id_references;
+use plist::Dictionary;
 use rootcause::option_ext::OptionExt;
 use rootcause::prelude::*;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use tokio::io::AsyncWriteExt;
-use tracing::info;
+use tracing::{info, warn};
 use zip::ZipArchive;
 
+/// What to do with the extracted files when loading an IPA fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExtractCleanup {
+    /// Remove the partially extracted files. Once the app is loaded, its files are left to the caller, see
+    /// [`Application::temp_path`].
+    #[default]
+    OnError,
+    /// Keep the extracted files, e.g. to inspect an IPA that fails to load
+    Never,
+}
+
+/// How an IPA is extracted by [`Application::with_options`] and [`Application::from_reader_with_options`]
+///
+/// Only what signing needs is extracted: the `.app` in `Payload` (or at the root of the archive, as some tools
+/// produce), `iTunesMetadata.plist` and, unless [`Self::skip_symbols`] is set, the `Symbols` directory. Entries are
+/// streamed to disk one at a time, so extracting doesn't need more memory for bigger IPAs, and support directories
+/// like `SwiftSupport` and `__MACOSX` resource forks aren't written at all.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// The directory IPAs are extracted in, the system's temporary directory if `None`
+    pub working_dir: Option<PathBuf>,
+    /// Skip the `Symbols` directory and `.dSYM` bundles, which are only kept when re-packaging the app as an IPA
+    pub skip_symbols: bool,
+    pub cleanup: ExtractCleanup,
+}
+
+impl ExtractOptions {
+    pub fn working_dir(mut self, dir: PathBuf) -> Self {
+        self.working_dir = Some(dir);
+        self
+    }
+
+    pub fn skip_symbols(mut self, skip: bool) -> Self {
+        self.skip_symbols = skip;
+        self
+    }
+
+    pub fn cleanup(mut self, cleanup: ExtractCleanup) -> Self {
+        self.cleanup = cleanup;
+        self
+    }
+
+    /// Whether the archive entry at `path` is needed to load and sign the app
+    fn wants(&self, path: &Path) -> bool {
+        let components: Vec<_> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        // AppleDouble files, e.g. from archives made with the Finder
+        if components.iter().any(|c| c.starts_with("._"))
+            || (self.skip_symbols && components.iter().any(|c| c.ends_with(".dSYM")))
+        {
+            return false;
+        }
+        match components.as_slice() {
+            [payload, app, ..] if payload == "Payload" => app.ends_with(".app"),
+            [app, _, ..] if app.ends_with(".app") => true,
+            [metadata] if metadata == "iTunesMetadata.plist" => true,
+            [symbols, ..] if symbols == "Symbols" => !self.skip_symbols,
+            _ => false,
+        }
+    }
+}
+
 pub struct Application {
     pub bundle: Bundle,
-    //pub temp_path: PathBuf,
+    /// The `iTunesMetadata.plist` found at the root of the IPA, if any
+    pub itunes_metadata: Option<Dictionary>,
+    /// The directory the IPA was extracted to, if the app was provided as an archive
+    pub temp_path: Option<PathBuf>,
 }
 
 impl Application {
+    /// Load an app from a `.app` directory or an IPA, which is extracted to a temporary directory
     pub fn new(path: PathBuf) -> Result<Self, Report> {
+        Self::with_options(path, &ExtractOptions::default())
+    }
+
+    /// Like [`Self::new`], extracting IPAs as configured by `options`
+    pub fn with_options(path: PathBuf, options: &ExtractOptions) -> Result<Self, Report> {
         if !path.exists() {
             bail!(SideloadError::InvalidBundle(
                 "Application path does not exist".to_string(),
             ));
         }
 
-        let mut bundle_path = path.clone();
-        //let mut temp_path = PathBuf::new();
-
         if path.is_file() {
-            let temp_dir = std::env::temp_dir();
-            let temp_path = temp_dir.join(
-                path.file_name()
-                    .ok_or_report()?
-                    .to_string_lossy()
-                    .to_string()
-                    + "_extracted",
-            );
-            if temp_path.exists() {
-                std::fs::remove_dir_all(&temp_path)
-                    .context("Failed to remove existing temporary directory")?;
-            }
-            std::fs::create_dir_all(&temp_path).context("Failed to create temporary directory")?;
-
             let file = File::open(&path).context("Failed to open application archive")?;
-            let mut archive =
-                ZipArchive::new(file).context("Failed to open application archive")?;
-            archive
-                .extract(&temp_path)
-                .context("Failed to extract application archive")?;
-
-            let payload_folder = temp_path.join("Payload");
-            if payload_folder.exists() && payload_folder.is_dir() {
-                let app_dirs: Vec<_> = std::fs::read_dir(&payload_folder)
-                    .context("Failed to read Payload directory")?
-                    .filter_map(Result::ok)
-                    .filter(|entry| entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
-                    .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "app"))
-                    .collect();
-                if app_dirs.len() == 1 {
-                    bundle_path = app_dirs[0].path();
-                } else if app_dirs.is_empty() {
-                    bail!(SideloadError::InvalidBundle(
-                        "No .app directory found in Payload".to_string(),
-                    ));
-                } else {
-                    bail!(SideloadError::InvalidBundle(
-                        "Multiple .app directories found in Payload".to_string(),
-                    ));
+            let name = path.file_name().ok_or_report()?.to_string_lossy();
+            return Self::from_reader_with_options(file, &name, options);
+        }
+
+        let bundle = Bundle::new(path)?;
+        Ok(Application {
+            bundle,
+            itunes_metadata: None,
+            temp_path: None,
+        })
+    }
+
+    /// Load a copy of the `.app` at `path`, made in a new temporary directory, so signing leaves the original as it is
+    ///
+    /// The copy is placed in [`ExtractOptions::working_dir`], and symlinks in the bundle are copied as symlinks.
+    pub fn copy_of(path: &Path, options: &ExtractOptions) -> Result<Self, Report> {
+        let name = path.file_name().ok_or_report()?;
+        let working_dir = options
+            .working_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        std::fs::create_dir_all(&working_dir).context("Failed to create temporary directory")?;
+        let prefix = format!("{}_", temp_dir_prefix(&name.to_string_lossy()));
+        let temp_path = tempfile::Builder::new()
+            .prefix(&prefix)
+            .suffix("_copy")
+            .tempdir_in(&working_dir)
+            .context("Failed to create temporary directory")?
+            .keep();
+
+        let bundle_dir = temp_path.join(name);
+        let result = copy_dir(path, &bundle_dir)
+            .context(format!("Failed to copy {}", path.display()))
+            .map_err(|e| e.into_dynamic())
+            .and_then(|_| Bundle::new(bundle_dir));
+        match result {
+            Ok(bundle) => Ok(Application {
+                bundle,
+                itunes_metadata: None,
+                temp_path: Some(temp_path),
+            }),
+            Err(e) => {
+                if let Err(e) = std::fs::remove_dir_all(&temp_path) {
+                    warn!("Failed to remove copied application: {}", e);
                 }
-            } else {
-                bail!(SideloadError::InvalidBundle(
-                    "No Payload directory found in the application archive".to_string(),
-                ));
+                Err(e)
             }
         }
+    }
+
+    /// Load an app from an IPA held in memory, e.g. one uploaded to a server
+    ///
+    /// `name`, usually the IPA's file name, prefixes the temporary directory the IPA is extracted to.
+    pub fn from_bytes(data: impl AsRef<[u8]>, name: &str) -> Result<Self, Report> {
+        Self::from_reader(Cursor::new(data), name)
+    }
+
+    /// Load an app from an IPA read from `reader`, without needing the archive itself on disk
+    ///
+    /// The app is extracted to a new temporary directory prefixed with `name`, since signing works on files.
+    pub fn from_reader(reader: impl Read + Seek, name: &str) -> Result<Self, Report> {
+        Self::from_reader_with_options(reader, name, &ExtractOptions::default())
+    }
+
+    /// Like [`Self::from_reader`], extracting the IPA as configured by `options`
+    pub fn from_reader_with_options(
+        reader: impl Read + Seek,
+        name: &str,
+        options: &ExtractOptions,
+    ) -> Result<Self, Report> {
+        let working_dir = options
+            .working_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        std::fs::create_dir_all(&working_dir).context("Failed to create temporary directory")?;
+        // always a new directory, so extracting never replaces anything that is already there
+        let prefix = format!("{}_", temp_dir_prefix(name));
+        let temp_path = tempfile::Builder::new()
+            .prefix(&prefix)
+            .suffix("_extracted")
+            .tempdir_in(&working_dir)
+            .context("Failed to create temporary directory")?
+            .keep();
+
+        let result = extract_archive(reader, &temp_path, options)
+            .and_then(|top_level| Self::from_extracted(temp_path.clone(), &top_level));
+        if result.is_err()
+            && options.cleanup == ExtractCleanup::OnError
+            && let Err(e) = std::fs::remove_dir_all(&temp_path)
+        {
+            warn!("Failed to remove extracted application archive: {}", e);
+        }
+        result
+    }
+
+    /// Load the app from the `Payload` directory of an extracted IPA, or its root if it has no `Payload`
+    ///
+    /// `top_level` lists what the archive contained, to say what was found instead if there is no app.
+    fn from_extracted(temp_path: PathBuf, top_level: &[String]) -> Result<Self, Report> {
+        let mut itunes_metadata = None;
+        let metadata_path = temp_path.join("iTunesMetadata.plist");
+        if metadata_path.exists() {
+            match plist::from_file(&metadata_path) {
+                Ok(metadata) => itunes_metadata = Some(metadata),
+                Err(e) => warn!("Failed to parse iTunesMetadata.plist, ignoring: {}", e),
+            }
+        }
+
+        let payload_folder = temp_path.join("Payload");
+        let (app_folder, location) = if payload_folder.is_dir() {
+            (payload_folder, "Payload")
+        } else {
+            (temp_path.clone(), "the root of the archive")
+        };
+        let app_dirs: Vec<_> = std::fs::read_dir(&app_folder)
+            .context("Failed to read extracted application archive")?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "app"))
+            .map(|entry| entry.path())
+            .collect();
+        let bundle_path = match app_dirs.as_slice() {
+            [app_dir] => app_dir.clone(),
+            [] => bail!(SideloadError::InvalidBundle(format!(
+                "No .app directory found in Payload or at the root of the archive, it contains {}",
+                describe_entries(top_level)
+            ))),
+            _ => bail!(SideloadError::InvalidBundle(format!(
+                "Multiple .app directories found in {}: {}",
+                location,
+                app_dirs
+                    .iter()
+                    .filter_map(|path| path.file_name())
+                    .map(|name| name.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        };
         let bundle = Bundle::new(bundle_path)?;
 
         Ok(Application {
-            bundle, /*temp_path*/
+            bundle,
+            itunes_metadata,
+            temp_path: Some(temp_path),
         })
     }
 
@@ -140,25 +311,20 @@ impl Application {
         main_app_bundle_id: &str,
         main_app_id_str: &str,
     ) -> Result<(), Report> {
-        let extensions = self.bundle.app_extensions_mut();
-        for ext in extensions.iter_mut() {
-            if let Some(id) = ext.bundle_identifier() {
-                if !(id.starts_with(main_app_bundle_id) && id.len() > main_app_bundle_id.len()) {
-                    bail!(SideloadError::InvalidBundle(format!(
-                        "Extension {} is not part of the main app bundle identifier: {}",
-                        ext.bundle_name().unwrap_or("Unknown"),
-                        id
-                    )));
-                } else {
-                    ext.set_bundle_identifier(&format!(
-                        "{}{}",
-                        main_app_id_str,
-                        &id[main_app_bundle_id.len()..]
-                    ));
-                }
+        let mut renamed = HashMap::new();
+        for ext in self.bundle.app_extensions_and_clips_mut() {
+            rename_nested(ext, main_app_bundle_id, main_app_id_str, &mut renamed)?;
+        }
+        // watch app extensions are prefixed by the watch app's identifier, which is prefixed by the main app's
+        for watch_app in self.bundle.watch_apps_mut() {
+            rename_nested(watch_app, main_app_bundle_id, main_app_id_str, &mut renamed)?;
+            for ext in watch_app.app_extensions_mut() {
+                rename_nested(ext, main_app_bundle_id, main_app_id_str, &mut renamed)?;
             }
         }
         self.bundle.set_bundle_identifier(main_app_id_str);
+        renamed.insert(main_app_bundle_id.to_string(), main_app_id_str.to_string());
+        fix_bundle_id_references(&mut self.bundle, &renamed);
 
         Ok(())
     }
@@ -168,15 +334,59 @@ impl Application {
         //mode: &ExtensionsBehavior,
         dev_session: &mut DeveloperSession,
         team: &DeveloperTeam,
+        reuse_policy: &AppIdReusePolicy,
+        device_type: DeveloperDeviceType,
+    ) -> Result<Vec<AppId>, Report> {
+        self.register_app_ids_tracked(
+            dev_session,
+            team,
+            reuse_policy,
+            device_type,
+            &mut Vec::new(),
+        )
+        .await
+    }
+
+    /// Like [`Self::register_app_ids`], adding the app IDs it registers to `created` as it goes, so they are known
+    /// even if a later registration fails
+    pub(crate) async fn register_app_ids_tracked(
+        &self,
+        dev_session: &mut DeveloperSession,
+        team: &DeveloperTeam,
+        reuse_policy: &AppIdReusePolicy,
+        device_type: DeveloperDeviceType,
+        created: &mut Vec<AppId>,
     ) -> Result<Vec<AppId>, Report> {
-        let extension_refs: Vec<_> = self.bundle.app_extensions().iter().collect();
         let mut bundles_with_app_id = vec![&self.bundle];
-        bundles_with_app_id.extend(extension_refs);
+        bundles_with_app_id.extend(self.bundle.app_id_bundles());
+        // paid teams can register as many app IDs as they need, their available quantity isn't meaningful
+        let limited = team.capabilities().app_id_limit.is_some();
 
-        let list_app_ids_response = dev_session
-            .list_app_ids(team, None)
+        let mut list_app_ids_response = dev_session
+            .list_app_ids(team, device_type.clone())
             .await
             .context("Failed to list app IDs for the developer team")?;
+
+        if limited
+            && *reuse_policy == AppIdReusePolicy::DeleteExpired
+            && let Some(available) = list_app_ids_response.available_quantity
+            && Self::missing_app_ids(&bundles_with_app_id, &list_app_ids_response) > available
+        {
+            let in_use: Vec<_> = bundles_with_app_id
+                .iter()
+                .filter_map(|bundle| bundle.bundle_identifier())
+                .collect();
+            let deleted = dev_session
+                .cleanup_expired(team, &in_use, device_type.clone())
+                .await
+                .context("Failed to clean up expired app IDs")?;
+            info!("Deleted {} expired app IDs", deleted.len());
+            list_app_ids_response = dev_session
+                .list_app_ids(team, device_type.clone())
+                .await
+                .context("Failed to list app IDs for the developer team")?;
+        }
+
         let app_ids_to_register = bundles_with_app_id
             .iter()
             .filter(|bundle| {
@@ -188,7 +398,8 @@ impl Application {
             })
             .collect::<Vec<_>>();
 
-        if let Some(available) = list_app_ids_response.available_quantity
+        if limited
+            && let Some(available) = list_app_ids_response.available_quantity
             && app_ids_to_register.len() > available.try_into()?
         {
             bail!(
@@ -201,9 +412,12 @@ impl Application {
         for bundle in app_ids_to_register {
             let id = bundle.bundle_identifier().unwrap_or("");
             let name = bundle.bundle_name().unwrap_or("");
-            dev_session.add_app_id(team, name, id, None).await?;
+            let app_id = dev_session
+                .ensure_app_id(team, name, id, device_type.clone())
+                .await?;
+            created.push(app_id);
         }
-        let list_app_id_response = dev_session.list_app_ids(team, None).await?;
+        let list_app_id_response = dev_session.list_app_ids(team, device_type).await?;
         let app_ids: Vec<_> = list_app_id_response
             .app_ids
             .into_iter()
@@ -218,11 +432,27 @@ impl Application {
         Ok(app_ids)
     }
 
+    /// How many of the bundles don't have an app ID registered yet
+    fn missing_app_ids(bundles: &[&Bundle], response: &ListAppIdsResponse) -> i64 {
+        bundles
+            .iter()
+            .filter(|bundle| {
+                let bundle_id = bundle.bundle_identifier().unwrap_or("");
+                !response
+                    .app_ids
+                    .iter()
+                    .any(|app_id| app_id.identifier == bundle_id)
+            })
+            .count() as i64
+    }
+
     pub async fn apply_special_app_behavior(
         &mut self,
         special: &Option<SpecialApp>,
         group_identifier: &str,
         cert: &CertificateIdentity,
+        // the target device's pairing file, embedded into SideStore so it can refresh apps on its own
+        pairing_file: Option<&[u8]>,
     ) -> Result<(), Report> {
         let Some(special) = special.as_ref() else {
             return Ok(());
@@ -277,13 +507,281 @@ impl Application {
                 file.write_all(&p12_bytes)
                     .await
                     .context(format!("Failed to write {}", cert_file_name))?;
+
+                if let Some(pairing_file) = pairing_file
+                    && matches!(special, SpecialApp::SideStore | SpecialApp::SideStoreLc)
+                {
+                    info!("Embedding pairing file for {}", special);
+                    target_bundle.app_info.insert(
+                        "ALTPairingFile".to_string(),
+                        plist::Value::String(String::from_utf8_lossy(pairing_file).to_string()),
+                    );
+                    tokio::fs::write(
+                        target_bundle
+                            .bundle_dir
+                            .join("ALTPairingFile.mobiledevicepairing"),
+                        pairing_file,
+                    )
+                    .await
+                    .context("Failed to write ALTPairingFile.mobiledevicepairing")?;
+                }
             }
         }
         Ok(())
     }
 }
 
+/// Removes an app's temporary directory when dropped, so every early return cleans up after it
+///
+/// Call [`Self::keep`] once the directory is handed on, e.g. in a signed app that is installed later.
+pub(crate) struct TempDirGuard(Option<PathBuf>);
+
+impl TempDirGuard {
+    pub(crate) fn new(path: Option<PathBuf>) -> Self {
+        TempDirGuard(path)
+    }
+
+    pub(crate) fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for TempDirGuard {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0
+            && path.exists()
+            && let Err(e) = std::fs::remove_dir_all(path)
+        {
+            warn!("Failed to remove temporary app file: {}", e);
+        }
+    }
+}
+
+/// Replace the main app's bundle identifier prefix of a nested bundle's identifier
+fn rename_nested(
+    bundle: &mut Bundle,
+    main_app_bundle_id: &str,
+    main_app_id_str: &str,
+    renamed: &mut HashMap<String, String>,
+) -> Result<(), Report> {
+    let Some(id) = bundle.bundle_identifier().map(str::to_string) else {
+        return Ok(());
+    };
+    if !(id.starts_with(main_app_bundle_id) && id.len() > main_app_bundle_id.len()) {
+        bail!(SideloadError::InvalidBundle(format!(
+            "Extension {} is not part of the main app bundle identifier: {}",
+            bundle.bundle_name().unwrap_or("Unknown"),
+            id
+        )));
+    }
+    let new_id = format!("{}{}", main_app_id_str, &id[main_app_bundle_id.len()..]);
+    bundle.set_bundle_identifier(&new_id);
+    renamed.insert(id, new_id);
+    Ok(())
+}
+
+/// Extract the entries of the IPA that `options` wants to `dir`, one at a time
+///
+/// Returns the names of the entries at the root of the archive, to describe archives that don't contain an app.
+fn extract_archive(
+    mut reader: impl Read + Seek,
+    dir: &Path,
+    options: &ExtractOptions,
+) -> Result<Vec<String>, Report> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+    // only archives over 4 GB need Zip64, so only then is its absence a likely reason for failing
+    let needs_zip64 = len > u32::MAX as u64;
+    let archive = ZipArchive::new(reader).context("Failed to open application archive");
+    let mut archive = if needs_zip64 {
+        archive.attach("Archives larger than 4 GB must use Zip64")?
+    } else {
+        archive?
+    };
+    // symlinks in the archive are only created if they point inside `dir`, so nothing written can end up outside it
+    let root = dir
+        .canonicalize()
+        .context("Failed to resolve temporary directory")?;
+    let mut extracted = 0;
+    let mut top_level = BTreeSet::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .context("Failed to read application archive entry")?;
+        if let Some(first) = entry.name().split('/').next()
+            && !first.is_empty()
+        {
+            top_level.insert(first.to_string());
+        }
+        let Some(relative) = entry.enclosed_name() else {
+            warn!(
+                "Skipping archive entry outside the archive: {}",
+                entry.name()
+            );
+            continue;
+        };
+        if !options.wants(&relative) {
+            continue;
+        }
+
+        let out_path = dir.join(&relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&out_path).context("Failed to create extracted directory")?;
+            continue;
+        }
+        let parent = out_path.parent().unwrap_or(dir);
+        std::fs::create_dir_all(parent).context("Failed to create extracted directory")?;
+        if depth_below(&root, parent).is_none() {
+            warn!(
+                "Skipping archive entry extracted outside the archive: {}",
+                entry.name()
+            );
+            continue;
+        }
+
+        #[cfg(unix)]
+        if entry.is_symlink() {
+            let mut target = String::new();
+            entry
+                .read_to_string(&mut target)
+                .context("Failed to read symlink target")?;
+            let depth = depth_below(&root, parent).unwrap_or_default();
+            if !symlink_stays_inside(Path::new(&target), depth) {
+                warn!(
+                    "Skipping symlink pointing outside the archive: {} -> {}",
+                    entry.name(),
+                    target
+                );
+                continue;
+            }
+            std::os::unix::fs::symlink(&target, &out_path)
+                .context(format!("Failed to extract {}", relative.display()))?;
+            extracted += 1;
+            continue;
+        }
+
+        let mut file =
+            File::create(&out_path).context(format!("Failed to extract {}", relative.display()))?;
+        let written = std::io::copy(&mut entry, &mut file)
+            .context(format!("Failed to extract {}", relative.display()))?;
+        // a size that doesn't match points at an archive over 4 GB written without Zip64, whose sizes wrapped around
+        if written != entry.size() {
+            bail!(SideloadError::InvalidBundle(format!(
+                "{} is {} bytes, but the archive says it is {} bytes. {}",
+                relative.display(),
+                written,
+                entry.size(),
+                if needs_zip64 {
+                    "Archives larger than 4 GB must use Zip64."
+                } else {
+                    "The archive is corrupted."
+                }
+            )));
+        }
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&out_path, std::fs::Permissions::from_mode(mode & 0o777))
+                .context("Failed to set extracted file permissions")?;
+        }
+        extracted += 1;
+    }
+    info!("Extracted {} files from application archive", extracted);
+
+    Ok(top_level.into_iter().collect())
+}
+
+/// Copy the directory `source` to `target`, copying symlinks as symlinks instead of following them
+fn copy_dir(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(target)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = target.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+            #[cfg(not(unix))]
+            std::fs::copy(entry.path(), &target).map(|_| ())?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// The file stem of `name`, with anything that isn't safe in a directory name replaced
+fn temp_dir_prefix(name: &str) -> String {
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let prefix: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if prefix.is_empty() {
+        "app".to_string()
+    } else {
+        prefix
+    }
+}
+
+/// How many directories `path` is below `root` once symlinks are resolved, or `None` if it isn't inside `root`
+fn depth_below(root: &Path, path: &Path) -> Option<usize> {
+    let path = path.canonicalize().ok()?;
+    Some(path.strip_prefix(root).ok()?.components().count())
+}
+
+/// Whether a relative symlink `target`, created `depth` directories below the extraction directory, resolves inside it
+///
+/// `..` is only accepted before any other component: after one, it would apply to wherever that component resolves
+/// to, which could be another symlink.
+#[cfg(unix)]
+fn symlink_stays_inside(target: &Path, mut depth: usize) -> bool {
+    use std::path::Component;
+
+    let mut descended = false;
+    for component in target.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if !descended && depth > 0 => depth -= 1,
+            Component::Normal(_) => descended = true,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// List archive entries in an error message, leaving out the rest if there are many
+fn describe_entries(entries: &[String]) -> String {
+    const MAX_LISTED: usize = 10;
+
+    if entries.is_empty() {
+        return "nothing".to_string();
+    }
+    let mut description = entries
+        .iter()
+        .take(MAX_LISTED)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if entries.len() > MAX_LISTED {
+        description.push_str(&format!(" and {} more", entries.len() - MAX_LISTED));
+    }
+    description
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
 pub enum SpecialApp {
     SideStore,
     SideStoreLc,
@@ -1,12 +1,36 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    path::PathBuf,
+    sync::{Arc, mpsc::Sender},
+    time::Duration,
+};
+
+use rootcause::prelude::*;
 
+#[cfg(feature = "install")]
+use crate::sideload::install::UploadVerification;
 use crate::{
     dev::{
-        certificates::DevelopmentCertificate, developer_session::DeveloperSession,
-        teams::DeveloperTeam,
+        certificates::DevelopmentCertificate,
+        developer_session::DeveloperSession,
+        teams::{DeveloperTeam, FREE_PROFILE_VALIDITY},
+    },
+    sideload::{
+        application::ExtractOptions,
+        bundle::Bundle,
+        cert_identity::CertificateIdentity,
+        customization::{BundleCustomization, BundleCustomizations},
+        entitlements::EntitlementPolicy,
+        events::{SideloadEvent, SideloadObserver, channel_observer},
+        extensions::{AppClipBehavior, ExtensionFilter},
+        inject::InjectLibrariesPass,
+        itunes_metadata::ITunesMetadataBehavior,
+        pass::{BundleHookPass, BundlePass, BundlePipeline, WriteInfoPass},
+        platform::TargetPlatform,
+        sanitize::SupportedDevicesBehavior,
+        sideloader::{DEFAULT_PROFILE_REFRESH_MARGIN, Sideloader},
     },
-    sideload::sideloader::Sideloader,
-    util::storage::SideloadingStorage,
+    util::{clock::Clock, ipa_cache::IpaCache, storage::SideloadingStorage},
 };
 
 /// Configuration for selecting a developer team during sideloading
@@ -43,6 +67,49 @@ pub enum MaxCertsBehavior {
     Prompt(Box<dyn Fn(&Vec<DevelopmentCertificate>) -> Option<Vec<String>> + Send + Sync>),
 }
 
+/// Behavior when the team doesn't have enough available app IDs for the app
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AppIdReusePolicy {
+    /// Return an error
+    #[default]
+    Error,
+    /// Delete expired app IDs that aren't used by the app being installed, then try again
+    DeleteExpired,
+}
+
+/// Behavior when the app ID identifier is already registered by another developer team
+///
+/// App ID identifiers are unique across all teams, so a popular app may already be taken even with the team id appended.
+/// Before retrying with a suffix, the app IDs that were already registered under the abandoned identifier, like the
+/// main app's when only an extension's identifier is taken, are deleted again.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AppIdSuffixStrategy {
+    /// Return [`crate::SideloadError::AppIdUnavailable`]
+    Error,
+    /// Append an increasing number (`.2`, `.3`, ...) to the identifier, up to the given number of attempts
+    Counter(u32),
+    /// Append `.` and a random hex string to the identifier
+    #[default]
+    Random,
+    /// Append the given suffix to the identifier, once
+    Fixed(String),
+}
+
+impl AppIdSuffixStrategy {
+    /// The suffix to try after `attempt` (starting at 1) identifiers were unavailable, or `None` to give up
+    pub fn suffix(&self, attempt: u32) -> Option<String> {
+        match self {
+            AppIdSuffixStrategy::Error => None,
+            AppIdSuffixStrategy::Counter(max) => {
+                (attempt <= *max).then(|| format!(".{}", attempt + 1))
+            }
+            AppIdSuffixStrategy::Random => (attempt <= 3)
+                .then(|| format!(".{}", &uuid::Uuid::new_v4().simple().to_string()[..6])),
+            AppIdSuffixStrategy::Fixed(suffix) => (attempt == 1).then(|| suffix.clone()),
+        }
+    }
+}
+
 /// The actual behavior choices for extensions (non-prompt variants)
 pub enum ExtensionsBehaviorChoice {
     /// Use the main app id/profile for all sub-bundles
@@ -75,6 +142,28 @@ pub enum ExtensionsBehaviorChoice {
 //     }
 // }
 
+/// A problem with a [`SideloaderBuilder`]'s configuration, returned by [`SideloaderBuilder::try_build`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConfigError {
+    #[error(
+        "No storage was provided and neither the `keyring-storage` nor the `fs-storage` feature is enabled, so nothing would be saved across runs"
+    )]
+    NoPersistentStorage,
+
+    #[error("The machine name is empty")]
+    EmptyMachineName,
+
+    #[error(
+        "The app ID suffix is empty, so the same unavailable identifier would be registered again"
+    )]
+    EmptyAppIdSuffix,
+
+    #[error(
+        "The profile refresh margin of {0:?} is at least as long as a free provisioning profile is valid, so profiles would be downloaded again on every install"
+    )]
+    ProfileRefreshMarginTooLong(Duration),
+}
+
 pub struct SideloaderBuilder {
     developer_session: DeveloperSession,
     apple_email: String,
@@ -84,6 +173,36 @@ pub struct SideloaderBuilder {
     storage: Option<Box<dyn SideloadingStorage>>,
     machine_name: Option<String>,
     delete_app_after_install: bool,
+    itunes_metadata_behavior: ITunesMetadataBehavior,
+    supported_devices_behavior: SupportedDevicesBehavior,
+    platform: TargetPlatform,
+    observers: Vec<SideloadObserver>,
+    rename_registered_devices: bool,
+    skip_if_valid: bool,
+    extension_filter: ExtensionFilter,
+    app_clip_behavior: AppClipBehavior,
+    app_id_reuse_policy: AppIdReusePolicy,
+    app_id_suffix_strategy: AppIdSuffixStrategy,
+    keep_symbols: bool,
+    thin_app: bool,
+    extract_options: ExtractOptions,
+    cache_profiles: bool,
+    profile_refresh_margin: Duration,
+    clock: Option<Arc<dyn Clock>>,
+    embed_pairing_file: bool,
+    ipa_cache: Option<IpaCache>,
+    bundle_customizations: BundleCustomizations,
+    entitlement_policy: Option<EntitlementPolicy>,
+    #[cfg(feature = "install")]
+    upload_verification: UploadVerification,
+    #[cfg(feature = "install")]
+    clean_device_profiles: bool,
+    require_developer_mode: bool,
+    validate_signature: bool,
+    bundle_pipeline: BundlePipeline,
+    injected_libraries: Vec<PathBuf>,
+    rollback_on_failure: bool,
+    legacy_store_dir: Option<PathBuf>,
 }
 
 impl SideloaderBuilder {
@@ -97,6 +216,36 @@ impl SideloaderBuilder {
             apple_email,
             max_certs_behavior: None,
             delete_app_after_install: true,
+            itunes_metadata_behavior: ITunesMetadataBehavior::default(),
+            supported_devices_behavior: SupportedDevicesBehavior::default(),
+            platform: TargetPlatform::default(),
+            observers: Vec::new(),
+            rename_registered_devices: false,
+            skip_if_valid: false,
+            extension_filter: ExtensionFilter::default(),
+            app_clip_behavior: AppClipBehavior::default(),
+            app_id_reuse_policy: AppIdReusePolicy::default(),
+            app_id_suffix_strategy: AppIdSuffixStrategy::default(),
+            keep_symbols: false,
+            thin_app: false,
+            extract_options: ExtractOptions::default(),
+            cache_profiles: true,
+            profile_refresh_margin: DEFAULT_PROFILE_REFRESH_MARGIN,
+            clock: None,
+            embed_pairing_file: true,
+            ipa_cache: None,
+            bundle_customizations: BundleCustomizations::new(),
+            entitlement_policy: None,
+            #[cfg(feature = "install")]
+            upload_verification: UploadVerification::default(),
+            #[cfg(feature = "install")]
+            clean_device_profiles: false,
+            require_developer_mode: false,
+            validate_signature: false,
+            bundle_pipeline: BundlePipeline::default(),
+            injected_libraries: Vec::new(),
+            rollback_on_failure: false,
+            legacy_store_dir: None,
             // extensions_behavior: None,
         }
     }
@@ -123,7 +272,7 @@ impl SideloaderBuilder {
     /// Set the machine name to use for the development certificate
     ///
     /// This has no bearing on functionality but can be useful for users to identify where a certificate came from.
-    /// If not set, a default name of "isideload" will be used.
+    /// If not set, the host's computer name is used, falling back to "isideload" if it can't be determined.
     pub fn machine_name(mut self, machine_name: String) -> Self {
         self.machine_name = Some(machine_name);
         self
@@ -141,24 +290,389 @@ impl SideloaderBuilder {
         self
     }
 
+    /// Set how `iTunesMetadata.plist` is handled. Defaults to [`ITunesMetadataBehavior::Ignore`].
+    ///
+    /// See [`ITunesMetadataBehavior`] for details.
+    pub fn itunes_metadata_behavior(mut self, behavior: ITunesMetadataBehavior) -> Self {
+        self.itunes_metadata_behavior = behavior;
+        self
+    }
+
+    /// Set how the `UISupportedDevices` Info.plist key is handled. Defaults to [`SupportedDevicesBehavior::Keep`].
+    ///
+    /// See [`SupportedDevicesBehavior`] for details.
+    pub fn supported_devices_behavior(mut self, behavior: SupportedDevicesBehavior) -> Self {
+        self.supported_devices_behavior = behavior;
+        self
+    }
+
+    /// Set the platform apps are sideloaded to. Defaults to [`TargetPlatform::Ios`].
+    ///
+    /// Devices and app IDs are registered through the developer services endpoints of the platform, apps that don't
+    /// declare one of its `UIDeviceFamily` values are rejected, and so are devices of another platform.
+    pub fn platform(mut self, platform: TargetPlatform) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Set whether to rename devices that are registered under a different name than the device reports. Defaults to `false`.
+    pub fn rename_registered_devices(mut self, rename: bool) -> Self {
+        self.rename_registered_devices = rename;
+        self
+    }
+
+    /// Set whether to skip signing when installing an app that is already signed with the current certificate,
+    /// with a profile that covers the device and is valid for at least another day. Defaults to `false`.
+    ///
+    /// This makes repeated installs of the same signed build nearly instant.
+    pub fn skip_if_valid(mut self, skip: bool) -> Self {
+        self.skip_if_valid = skip;
+        self
+    }
+
+    /// Set whether to embed the device's pairing file when installing SideStore. Defaults to `true`.
+    ///
+    /// SideStore needs the pairing file to refresh apps on the device, and otherwise asks the user to import one.
+    pub fn embed_pairing_file(mut self, embed: bool) -> Self {
+        self.embed_pairing_file = embed;
+        self
+    }
+
+    /// Set which app extensions, watch apps and app clips to keep. Defaults to [`ExtensionFilter::KeepAll`].
+    ///
+    /// See [`ExtensionFilter`] for details.
+    pub fn extension_filter(mut self, filter: ExtensionFilter) -> Self {
+        self.extension_filter = filter;
+        self
+    }
+
+    /// Set what to do with app clips. Defaults to [`AppClipBehavior::Auto`], which removes them on free teams.
+    ///
+    /// See [`AppClipBehavior`] for details.
+    pub fn app_clip_behavior(mut self, behavior: AppClipBehavior) -> Self {
+        self.app_clip_behavior = behavior;
+        self
+    }
+
+    /// Set what to do when there aren't enough available app IDs. Defaults to [`AppIdReusePolicy::Error`].
+    ///
+    /// See [`AppIdReusePolicy`] for details.
+    pub fn app_id_reuse_policy(mut self, policy: AppIdReusePolicy) -> Self {
+        self.app_id_reuse_policy = policy;
+        self
+    }
+
+    /// Set how the app ID is renamed when its identifier is registered by another team. Defaults to [`AppIdSuffixStrategy::Random`].
+    ///
+    /// A warning is logged whenever the app is installed under a modified identifier.
+    pub fn app_id_suffix_strategy(mut self, strategy: AppIdSuffixStrategy) -> Self {
+        self.app_id_suffix_strategy = strategy;
+        self
+    }
+
+    /// Set whether to cache provisioning profiles in the storage and reuse them for repeat installs. Defaults to `true`.
+    ///
+    /// A cached profile is only reused if it still matches the certificate, app group and device.
+    pub fn cache_profiles(mut self, cache: bool) -> Self {
+        self.cache_profiles = cache;
+        self
+    }
+
+    /// Set how long before expiry a cached provisioning profile is downloaded again. Defaults to 2 days.
+    ///
+    /// Installed apps are also refreshed by [`Sideloader::refresh_app`] once their profile expires within this margin.
+    pub fn profile_refresh_margin(mut self, margin: Duration) -> Self {
+        self.profile_refresh_margin = margin;
+        self
+    }
+
+    /// Set the clock profile, certificate, app ID and token expiry are checked against. Defaults to the clock of the
+    /// developer session, which is the system clock unless it was changed.
+    ///
+    /// The developer session is switched to the same clock, so every check agrees. Tests can pass a
+    /// [`FixedClock`](crate::util::clock::FixedClock) to simulate expiry boundaries.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Keep a copy of the original app in `cache` whenever [`Sideloader::install_app`] installs one. Disabled by default.
+    ///
+    /// Cached apps can be refreshed with [`Sideloader::refresh_app_from_cache`] without asking the user for the IPA again.
+    pub fn ipa_cache(mut self, cache: IpaCache) -> Self {
+        self.ipa_cache = Some(cache);
+        self
+    }
+
+    /// Customize the bundle at `path` inside the app right before it is signed, e.g. `PlugIns/Helper.appex`,
+    /// or `""` for the main app
+    ///
+    /// Calling this again for the same path replaces its customization. See [`BundleCustomization`] for details.
+    pub fn bundle_customization(
+        mut self,
+        path: impl Into<String>,
+        customization: BundleCustomization,
+    ) -> Self {
+        self.bundle_customizations
+            .insert(path.into(), customization);
+        self
+    }
+
+    /// Set which entitlements apps may be signed with. Defaults to [`EntitlementPolicy::for_team`] of the selected team.
+    ///
+    /// Entitlements outside the policy are dropped and reported like ones the provisioning profile doesn't grant.
+    pub fn entitlement_policy(mut self, policy: EntitlementPolicy) -> Self {
+        self.entitlement_policy = Some(policy);
+        self
+    }
+
+    /// Set how uploaded files are checked before the app is installed. Defaults to [`UploadVerification::None`].
+    ///
+    /// See [`UploadVerification`] for details.
+    #[cfg(feature = "install")]
+    pub fn upload_verification(mut self, verification: UploadVerification) -> Self {
+        self.upload_verification = verification;
+        self
+    }
+
+    /// Set whether the device's provisioning profiles are cleaned up before installing. Defaults to `false`.
+    ///
+    /// Profiles from this team for the app and its extensions that are expired, or that aren't the profile the app
+    /// was just signed with, are removed with misagent and the new profile is installed directly. Stale profiles can
+    /// otherwise make the install or launch fail. Failing to clean up only logs a warning.
+    #[cfg(feature = "install")]
+    pub fn clean_device_profiles(mut self, clean: bool) -> Self {
+        self.clean_device_profiles = clean;
+        self
+    }
+
+    /// Set whether installing fails with [`crate::SideloadError::DeveloperModeDisabled`] when Developer Mode is disabled
+    /// on the device. Defaults to `false`, which only emits [`SideloadEvent::DeveloperModeDisabled`] and installs anyway.
+    pub fn require_developer_mode(mut self, require: bool) -> Self {
+        self.require_developer_mode = require;
+        self
+    }
+
+    /// Set whether signed apps are checked with [`crate::sideload::validation::validate_signed_app`] before they are
+    /// packaged or uploaded. Defaults to `false`.
+    ///
+    /// Signing fails with the [`crate::sideload::validation::ValidationReport`] attached if any issue is found.
+    pub fn validate_signature(mut self, validate: bool) -> Self {
+        self.validate_signature = validate;
+        self
+    }
+
+    /// Add a pass that changes apps before they are signed
+    ///
+    /// Added passes run in the order they were added, after the built-in changes and right before `Info.plist` is
+    /// written, see [`BundlePipeline`].
+    pub fn bundle_pass(mut self, pass: impl BundlePass + 'static) -> Self {
+        self.bundle_pipeline
+            .insert_before(WriteInfoPass::NAME, pass);
+        self
+    }
+
+    /// Add a callback that can change the main app and every nested bundle before they are signed
+    ///
+    /// Called after the bundle identifiers are rewritten and before `Info.plist` is written, so changes to
+    /// [`crate::sideload::bundle::Bundle::app_info`], like enabling `UIFileSharingEnabled` or adding URL schemes, are
+    /// signed into the app. Can be called multiple times, hooks run in the order they were added.
+    pub fn modify_bundles(
+        self,
+        hook: impl Fn(&mut Bundle) -> Result<(), Report> + Send + Sync + 'static,
+    ) -> Self {
+        self.bundle_pass(BundleHookPass::new(hook))
+    }
+
+    /// Inject a dylib or framework, like a tweak, into every app before it is signed
+    ///
+    /// See [`crate::sideload::application::Application::inject_library`]. Can be called multiple times, libraries
+    /// are injected in the order they were added.
+    pub fn inject_library(mut self, path: PathBuf) -> Self {
+        self.injected_libraries.push(path);
+        self
+    }
+
+    /// Replace the passes run on apps before they are signed, including the built-in ones
+    pub fn bundle_pipeline(mut self, pipeline: BundlePipeline) -> Self {
+        self.bundle_pipeline = pipeline;
+        self
+    }
+
+    /// Set whether the app IDs and app group created while signing an app are removed again if signing fails.
+    /// Defaults to `false`.
+    ///
+    /// Keeps a failed run from leaving half-registered apps behind, which count against the team's active app ID
+    /// limit. Free teams can still only create 10 app IDs per week, removing them doesn't give those back. Registered
+    /// devices are never removed, since Apple doesn't allow it. What was removed is emitted as
+    /// [`SideloadEvent::RolledBack`] and attached to the error as a [`crate::sideload::rollback::RollbackReport`].
+    pub fn rollback_on_failure(mut self, rollback: bool) -> Self {
+        self.rollback_on_failure = rollback;
+        self
+    }
+
+    /// Import the private key saved by older versions in `<store_dir>/keys/` into the storage when building
+    ///
+    /// `store_dir` is the old config directory. The key is only imported if the storage has none for the account yet,
+    /// see [`CertificateIdentity::migrate_legacy_key`].
+    pub fn legacy_store_dir(mut self, store_dir: PathBuf) -> Self {
+        self.legacy_store_dir = Some(store_dir);
+        self
+    }
+
+    /// Set whether [`Sideloader::sign_to_ipa`] keeps the `Symbols` directory of the original IPA. Defaults to `false`.
+    pub fn keep_symbols(mut self, keep: bool) -> Self {
+        self.keep_symbols = keep;
+        self
+    }
+
+    /// Set whether apps are thinned before they are signed. Defaults to `false`.
+    ///
+    /// Thinning removes every slice but arm64 from universal binaries, `.symbols` files and Watch placeholders, so
+    /// there is less to sign and upload. What was removed is reported in
+    /// [`crate::sideload::signing_report::SigningReport::thinning`] and
+    /// [`SideloadEvent::Thinned`]. See [`crate::sideload::thinning`].
+    pub fn thin_app(mut self, thin: bool) -> Self {
+        self.thin_app = thin;
+        self
+    }
+
+    /// Set where and how IPAs are extracted before signing, e.g. to extract them on a disk with more free space
+    /// than the temporary directory. Whether symbols are extracted follows [`Self::keep_symbols`].
+    pub fn extract_options(mut self, options: ExtractOptions) -> Self {
+        self.extract_options = options;
+        self
+    }
+
+    /// Add a callback to receive [`crate::sideload::events::SideloadEvent`]s, such as the current step and progress
+    ///
+    /// Can be called multiple times to add several callbacks.
+    pub fn observer(mut self, observer: impl Fn(&SideloadEvent) + Send + Sync + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Send every [`crate::sideload::events::SideloadEvent`] to a channel, for frontends that process events on another thread
+    pub fn event_channel(mut self, sender: Sender<SideloadEvent>) -> Self {
+        self.observers.push(channel_observer(sender));
+        self
+    }
+
     // pub fn extensions_behavior(mut self, behavior: ExtensionsBehavior) -> Self {
     //     self.extensions_behavior = Some(behavior);
     //     self
     // }
 
+    /// Check the configuration for settings that would fail or behave unexpectedly, returning every problem found
+    ///
+    /// [`Self::build`] doesn't check anything and falls back to defaults instead.
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        if self.storage.is_none() && !cfg!(any(feature = "keyring-storage", feature = "fs-storage"))
+        {
+            errors.push(ConfigError::NoPersistentStorage);
+        }
+        if self
+            .machine_name
+            .as_deref()
+            .is_some_and(|name| name.trim().is_empty())
+        {
+            errors.push(ConfigError::EmptyMachineName);
+        }
+        if matches!(&self.app_id_suffix_strategy, AppIdSuffixStrategy::Fixed(suffix) if suffix.trim().is_empty())
+        {
+            errors.push(ConfigError::EmptyAppIdSuffix);
+        }
+        if self.cache_profiles && self.profile_refresh_margin >= FREE_PROFILE_VALIDITY {
+            errors.push(ConfigError::ProfileRefreshMarginTooLong(
+                self.profile_refresh_margin,
+            ));
+        }
+
+        errors
+    }
+
+    /// Build the `Sideloader` instance, failing with the first [`ConfigError`] found by [`Self::validate`]
+    pub fn try_build(self) -> Result<Sideloader, Report<ConfigError>> {
+        if let Some(error) = self.validate().into_iter().next() {
+            bail!(error);
+        }
+        Ok(self.build())
+    }
+
     /// Build the `Sideloader` instance with the provided configuration
+    ///
+    /// Invalid settings are not checked, use [`Self::try_build`] to catch them.
     pub fn build(self) -> Sideloader {
-        Sideloader::new(
-            self.developer_session,
+        let storage = self
+            .storage
+            .unwrap_or_else(|| Box::new(crate::util::storage::new_storage()));
+        if let Some(store_dir) = &self.legacy_store_dir
+            && let Err(e) = CertificateIdentity::migrate_legacy_key(
+                store_dir,
+                &self.apple_email,
+                storage.as_ref(),
+            )
+        {
+            tracing::warn!("Failed to migrate legacy private key: {:?}", e);
+        }
+
+        let mut developer_session = self.developer_session;
+        if let Some(clock) = self.clock {
+            developer_session.set_clock(clock);
+        }
+        let clock = developer_session.clock();
+        let mut sideloader = Sideloader::new(
+            developer_session,
             self.apple_email,
             self.team_selection.unwrap_or(TeamSelection::First),
             self.max_certs_behavior.unwrap_or(MaxCertsBehavior::Error),
-            self.machine_name.unwrap_or_else(|| "isideload".to_string()),
-            self.storage
-                .unwrap_or_else(|| Box::new(crate::util::storage::new_storage())),
+            self.machine_name
+                .unwrap_or_else(crate::util::machine_name::default_machine_name),
+            storage,
             // self.extensions_behavior
             //     .unwrap_or(ExtensionsBehavior::RegisterAll),
             self.delete_app_after_install,
-        )
+        );
+        sideloader.itunes_metadata_behavior = self.itunes_metadata_behavior;
+        sideloader.supported_devices_behavior = self.supported_devices_behavior;
+        sideloader.platform = self.platform;
+        sideloader.set_observers(self.observers);
+        sideloader.rename_registered_devices = self.rename_registered_devices;
+        sideloader.skip_if_valid = self.skip_if_valid;
+        sideloader.embed_pairing_file = self.embed_pairing_file;
+        sideloader.extension_filter = self.extension_filter;
+        sideloader.app_clip_behavior = self.app_clip_behavior;
+        sideloader.app_id_reuse_policy = self.app_id_reuse_policy;
+        sideloader.app_id_suffix_strategy = self.app_id_suffix_strategy;
+        sideloader.keep_symbols = self.keep_symbols;
+        sideloader.thin_app = self.thin_app;
+        // the symbols have to be extracted to be kept
+        sideloader.extract_options = self.extract_options.skip_symbols(!self.keep_symbols);
+        sideloader.cache_profiles = self.cache_profiles;
+        sideloader.profile_refresh_margin = self.profile_refresh_margin;
+        sideloader.clock = clock;
+        sideloader.ipa_cache = self.ipa_cache;
+        sideloader.bundle_customizations = self.bundle_customizations;
+        sideloader.entitlement_policy = self.entitlement_policy;
+        sideloader.require_developer_mode = self.require_developer_mode;
+        sideloader.validate_signature = self.validate_signature;
+        sideloader.bundle_pipeline = self.bundle_pipeline;
+        sideloader.rollback_on_failure = self.rollback_on_failure;
+        if !self.injected_libraries.is_empty() {
+            sideloader.bundle_pipeline.insert_before(
+                WriteInfoPass::NAME,
+                InjectLibrariesPass {
+                    libraries: self.injected_libraries,
+                },
+            );
+        }
+        #[cfg(feature = "install")]
+        {
+            sideloader.upload_verification = self.upload_verification;
+            sideloader.clean_device_profiles = self.clean_device_profiles;
+        }
+        sideloader
     }
 }
@@ -16,6 +16,8 @@ pub struct Bundle {
     pub bundle_dir: PathBuf,
 
     app_extensions: Vec<Bundle>,
+    app_clips: Vec<Bundle>,
+    watch_apps: Vec<Bundle>,
     frameworks: Vec<Bundle>,
     _libraries: Vec<String>,
 }
@@ -62,6 +64,42 @@ impl Bundle {
             Vec::new()
         };
 
+        // Load app clips from AppClips directory
+        let app_clips_dir = bundle_path.join("AppClips");
+        let app_clips = if app_clips_dir.exists() {
+            fs::read_dir(&app_clips_dir)
+                .context(SideloadError::InvalidBundle(
+                    "Failed to read AppClips directory".to_string(),
+                ))?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false)
+                        && entry.path().join("Info.plist").exists()
+                })
+                .filter_map(|entry| Bundle::new(entry.path()).ok())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // Load watchOS companion apps from Watch directory
+        let watch_dir = bundle_path.join("Watch");
+        let watch_apps = if watch_dir.exists() {
+            fs::read_dir(&watch_dir)
+                .context(SideloadError::InvalidBundle(
+                    "Failed to read Watch directory".to_string(),
+                ))?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false)
+                        && entry.path().join("Info.plist").exists()
+                })
+                .filter_map(|entry| Bundle::new(entry.path()).ok())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
         // Load frameworks from Frameworks directory
         let frameworks_dir = bundle_path.join("Frameworks");
         let frameworks = if frameworks_dir.exists() {
@@ -87,6 +125,8 @@ impl Bundle {
             app_info,
             bundle_dir: bundle_path,
             app_extensions,
+            app_clips,
+            watch_apps,
             frameworks,
             _libraries: libraries,
         })
@@ -111,6 +151,70 @@ impl Bundle {
             .and_then(|v| v.as_string())
     }
 
+    /// The kind of extension this bundle is, such as `com.apple.widgetkit-extension`
+    ///
+    /// Watch apps and app clips aren't extensions, but are reported as `com.apple.watchkit` and `com.apple.appclip`
+    /// so they can be filtered the same way.
+    pub fn extension_point_identifier(&self) -> Option<&str> {
+        if let Some(point) = self
+            .app_info
+            .get("NSExtension")
+            .and_then(|v| v.as_dictionary())
+            .and_then(|d| d.get("NSExtensionPointIdentifier"))
+            .and_then(|v| v.as_string())
+        {
+            return Some(point);
+        }
+        if self.app_info.contains_key("WKWatchKitApp")
+            || self.app_info.contains_key("WKApplication")
+        {
+            return Some("com.apple.watchkit");
+        }
+        if self.is_app_clip() {
+            return Some("com.apple.appclip");
+        }
+        None
+    }
+
+    /// Remove app extensions, watch apps and app clips rejected by `keep`, deleting them from disk
+    ///
+    /// Returns the bundle identifiers of the removed bundles.
+    pub fn retain_extensions(
+        &mut self,
+        keep: impl Fn(&Bundle) -> bool,
+    ) -> Result<Vec<String>, Report> {
+        let mut removed = Vec::new();
+
+        let (kept, rejected): (Vec<_>, Vec<_>) = std::mem::take(&mut self.app_extensions)
+            .into_iter()
+            .partition(|ext| keep(ext));
+        self.app_extensions = kept;
+        for ext in rejected {
+            fs::remove_dir_all(&ext.bundle_dir).context("Failed to remove app extension")?;
+            removed.push(ext.bundle_identifier().unwrap_or("").to_string());
+        }
+
+        let (kept, rejected): (Vec<_>, Vec<_>) = std::mem::take(&mut self.app_clips)
+            .into_iter()
+            .partition(|clip| keep(clip));
+        self.app_clips = kept;
+        for clip in rejected {
+            fs::remove_dir_all(&clip.bundle_dir).context("Failed to remove app clip")?;
+            removed.push(clip.bundle_identifier().unwrap_or("").to_string());
+        }
+
+        let (kept, rejected): (Vec<_>, Vec<_>) = std::mem::take(&mut self.watch_apps)
+            .into_iter()
+            .partition(|watch_app| keep(watch_app));
+        self.watch_apps = kept;
+        for watch_app in rejected {
+            fs::remove_dir_all(&watch_app.bundle_dir).context("Failed to remove watch app")?;
+            removed.push(watch_app.bundle_identifier().unwrap_or("").to_string());
+        }
+
+        Ok(removed)
+    }
+
     pub fn app_extensions(&self) -> &[Bundle] {
         &self.app_extensions
     }
@@ -119,6 +223,46 @@ impl Bundle {
         &mut self.app_extensions
     }
 
+    /// App clips found in the `AppClips` directory
+    pub fn app_clips(&self) -> &[Bundle] {
+        &self.app_clips
+    }
+
+    pub fn app_clips_mut(&mut self) -> &mut [Bundle] {
+        &mut self.app_clips
+    }
+
+    /// App extensions and app clips, which both need bundle identifiers prefixed by the main app's
+    pub fn app_extensions_and_clips_mut(&mut self) -> impl Iterator<Item = &mut Bundle> {
+        self.app_extensions
+            .iter_mut()
+            .chain(self.app_clips.iter_mut())
+    }
+
+    /// watchOS companion apps found in the `Watch` directory, each with their own nested extensions
+    pub fn watch_apps(&self) -> &[Bundle] {
+        &self.watch_apps
+    }
+
+    pub fn watch_apps_mut(&mut self) -> &mut [Bundle] {
+        &mut self.watch_apps
+    }
+
+    /// Every nested bundle that needs its own app ID: app extensions, app clips, watch apps and the watch apps' extensions
+    pub fn app_id_bundles(&self) -> Vec<&Bundle> {
+        let mut bundles: Vec<&Bundle> = self.app_extensions.iter().chain(&self.app_clips).collect();
+        for watch_app in &self.watch_apps {
+            bundles.push(watch_app);
+            bundles.extend(watch_app.app_extensions());
+        }
+        bundles
+    }
+
+    /// Whether this bundle is an app clip
+    pub fn is_app_clip(&self) -> bool {
+        self.app_info.contains_key("NSAppClip")
+    }
+
     pub fn frameworks(&self) -> &[Bundle] {
         &self.frameworks
     }
@@ -127,6 +271,28 @@ impl Bundle {
         &mut self.frameworks
     }
 
+    /// Pick up a framework or dylib copied into this bundle's `Frameworks` directory after it was loaded, so it is
+    /// signed with the bundle
+    pub fn add_framework(&mut self, path: &Path) -> Result<(), Report> {
+        let libraries = if path.is_dir() {
+            self.frameworks.retain(|f| f.bundle_dir != path);
+            self.frameworks.push(Bundle::new(path.to_path_buf())?);
+            find_dylibs(path, &self.bundle_dir)?
+        } else if let Ok(relative_path) = path.strip_prefix(&self.bundle_dir)
+            && let Some(relative_str) = relative_path.to_str()
+        {
+            vec![relative_str.to_string()]
+        } else {
+            Vec::new()
+        };
+        for library in libraries {
+            if !self._libraries.contains(&library) {
+                self._libraries.push(library);
+            }
+        }
+        Ok(())
+    }
+
     pub fn write_info(&self) -> Result<(), Report> {
         let info_plist_path = self.bundle_dir.join("Info.plist");
         plist::to_file_binary(&info_plist_path, &self.app_info).context(
@@ -135,11 +301,46 @@ impl Bundle {
         Ok(())
     }
 
+    /// Call `f` with this bundle and every nested bundle, outermost first
+    pub fn visit_bundles_mut<E>(
+        &mut self,
+        f: &mut impl FnMut(&mut Bundle) -> Result<(), E>,
+    ) -> Result<(), E> {
+        f(self)?;
+        for bundle in self
+            .app_extensions
+            .iter_mut()
+            .chain(&mut self.app_clips)
+            .chain(&mut self.watch_apps)
+            .chain(&mut self.frameworks)
+        {
+            bundle.visit_bundles_mut(f)?;
+        }
+        Ok(())
+    }
+
+    /// Write the Info.plist of this bundle and every nested bundle
+    pub fn write_info_recursive(&self) -> Result<(), Report> {
+        self.write_info()?;
+        for bundle in self
+            .app_extensions
+            .iter()
+            .chain(&self.app_clips)
+            .chain(&self.watch_apps)
+            .chain(&self.frameworks)
+        {
+            bundle.write_info_recursive()?;
+        }
+        Ok(())
+    }
+
     fn from_dylib_path(dylib_path: PathBuf) -> Self {
         Self {
             app_info: Dictionary::new(),
             bundle_dir: dylib_path,
             app_extensions: Vec::new(),
+            app_clips: Vec::new(),
+            watch_apps: Vec::new(),
             frameworks: Vec::new(),
             _libraries: Vec::new(),
         }
@@ -158,6 +359,16 @@ impl Bundle {
             bundle.collect_nested_bundles_into(bundles);
         }
 
+        for bundle in &self.app_clips {
+            bundles.push(bundle.clone());
+            bundle.collect_nested_bundles_into(bundles);
+        }
+
+        for bundle in &self.watch_apps {
+            bundles.push(bundle.clone());
+            bundle.collect_nested_bundles_into(bundles);
+        }
+
         for bundle in &self.frameworks {
             bundles.push(bundle.clone());
             bundle.collect_nested_bundles_into(bundles);
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use apple_codesign::{
     SigningSettings,
     cryptography::{InMemoryPrivateKey, PrivateKey},
@@ -7,23 +9,25 @@ use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair, PKCS_RSA_SHA2
 use rootcause::{option_ext::OptionExt, prelude::*};
 use rsa::{
     RsaPrivateKey,
-    pkcs1::EncodeRsaPublicKey,
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPublicKey},
     pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding},
 };
 
 use sha1::Sha1;
 use sha2::{Digest, Sha256};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use x509_certificate::CapturedX509Certificate;
 
 use crate::{
     SideloadError,
+    auth::account_manager::account_namespace,
     dev::{
         certificates::{CertificatesApi, DevelopmentCertificate},
         developer_session::DeveloperSession,
+        errors::DeveloperServicesError,
         teams::DeveloperTeam,
     },
-    sideload::builder::MaxCertsBehavior,
+    sideload::{builder::MaxCertsBehavior, sign::SigningIdentity, wwdr::WwdrIntermediates},
     util::storage::SideloadingStorage,
 };
 
@@ -33,6 +37,8 @@ pub struct CertificateIdentity {
     pub certificate: CapturedX509Certificate,
     pub private_key: RsaPrivateKey,
     pub signing_key: InMemoryPrivateKey,
+    /// The WWDR intermediates the certificate is chained to when signing
+    pub intermediates: WwdrIntermediates,
 }
 
 impl CertificateIdentity {
@@ -116,12 +122,14 @@ impl CertificateIdentity {
         let found = Self::find_matching(&pr, machine_name, developer_session, team).await;
         if let Ok(Some((cert, x509_cert))) = found {
             info!("Found matching certificate");
+            let intermediates = WwdrIntermediates::for_certificate(&x509_cert, storage).await;
             return Ok(Self {
                 machine_id: cert.machine_id.clone().unwrap_or_default(),
                 machine_name: cert.machine_name.clone().unwrap_or_default(),
                 certificate: x509_cert,
                 private_key: pr,
                 signing_key,
+                intermediates,
             });
         }
 
@@ -139,6 +147,7 @@ impl CertificateIdentity {
         .await?;
 
         info!("Successfully obtained certificate");
+        let intermediates = WwdrIntermediates::for_certificate(&x509_cert, storage).await;
 
         Ok(Self {
             machine_id: cert.machine_id.clone().unwrap_or_default(),
@@ -146,19 +155,74 @@ impl CertificateIdentity {
             certificate: x509_cert,
             private_key: pr,
             signing_key,
+            intermediates,
         })
     }
 
+    /// Whether the team has a certificate for this machine made with the stored private key
+    ///
+    /// Unlike [`Self::retrieve`], this never creates a private key or requests a certificate.
+    pub async fn has_matching(
+        machine_name: &str,
+        apple_email: &str,
+        developer_session: &mut DeveloperSession,
+        team: &DeveloperTeam,
+        storage: &dyn SideloadingStorage,
+    ) -> Result<bool, Report> {
+        let Some(private_key) = retrieve_stored_key(apple_email, storage)? else {
+            return Ok(false);
+        };
+        let private_key = RsaPrivateKey::from_pkcs8_der(&private_key)?;
+
+        Ok(
+            Self::find_matching(&private_key, machine_name, developer_session, team)
+                .await?
+                .is_some(),
+        )
+    }
+
+    /// Import the private key saved by older versions in `<store_dir>/keys/<sha1 of email>/key.pem`
+    ///
+    /// Reusing the old key lets the certificate it was issued for be found again instead of requesting a new one.
+    /// Nothing is imported if the storage already has a key for the account. The file is renamed to
+    /// `key.pem.migrated` once imported. Returns whether a key was imported.
+    pub fn migrate_legacy_key(
+        store_dir: &Path,
+        apple_email: &str,
+        storage: &dyn SideloadingStorage,
+    ) -> Result<bool, Report> {
+        let key_path = store_dir
+            .join("keys")
+            .join(hex::encode(Sha1::digest(apple_email.as_bytes())))
+            .join("key.pem");
+        if !key_path.exists()
+            || retrieve_stored_key(apple_email, storage)?.is_some_and(|key| !key.is_empty())
+        {
+            return Ok(false);
+        }
+
+        let pem =
+            std::fs::read_to_string(&key_path).context("Failed to read legacy private key")?;
+        let private_key = RsaPrivateKey::from_pkcs8_pem(&pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+            .context("Failed to parse legacy private key")?;
+        storage.store_data(
+            &private_key_storage_key(apple_email),
+            private_key.to_pkcs8_der()?.as_bytes(),
+        )?;
+        info!("Migrated legacy private key from {}", key_path.display());
+
+        if let Err(e) = std::fs::rename(&key_path, key_path.with_extension("pem.migrated")) {
+            warn!("Failed to rename legacy private key: {}", e);
+        }
+        Ok(true)
+    }
+
     async fn retrieve_private_key(
         apple_email: &str,
         storage: &dyn SideloadingStorage,
     ) -> Result<RsaPrivateKey, Report> {
-        let mut hasher = Sha256::new();
-        hasher.update(apple_email.as_bytes());
-        let email_hash = hex::encode(hasher.finalize());
-
-        let private_key = storage.retrieve_data(&format!("{}/key", email_hash))?;
-        if let Some(priv_key) = private_key {
+        if let Some(priv_key) = retrieve_stored_key(apple_email, storage)? {
             info!("Using existing private key from storage");
             return Ok(RsaPrivateKey::from_pkcs8_der(&priv_key)?);
         }
@@ -166,13 +230,15 @@ impl CertificateIdentity {
         let mut rng = rand::rng();
         let private_key = RsaPrivateKey::new(&mut rng, 2048)?;
         storage.store_data(
-            &format!("{}/key", email_hash),
+            &private_key_storage_key(apple_email),
             private_key.to_pkcs8_der()?.as_bytes(),
         )?;
 
         Ok(private_key)
     }
 
+    /// The team's certificate for this machine made with `private_key`, unless it has expired according to the
+    /// developer session's clock
     async fn find_matching(
         private_key: &RsaPrivateKey,
         machine_name: &str,
@@ -184,6 +250,7 @@ impl CertificateIdentity {
             .to_pkcs1_der()?
             .as_bytes()
             .to_vec();
+        let clock = developer_session.clock();
         for cert in developer_session
             .list_ios_certs(team)
             .await?
@@ -198,9 +265,15 @@ impl CertificateIdentity {
                 cert.cert_content.as_ref().ok_or_report()?.as_ref(),
             )?;
 
-            if public_key_der == x509_cert.public_key_data().as_ref() {
-                return Ok(Some((cert.clone(), x509_cert)));
+            if public_key_der != x509_cert.public_key_data().as_ref() {
+                continue;
             }
+            // profiles can't outlive their certificate, so an expired one has to be replaced
+            if cert.is_expired(clock.as_ref()) {
+                info!("Matching certificate has expired, ignoring it");
+                continue;
+            }
+            return Ok(Some((cert.clone(), x509_cert)));
         }
 
         Ok(None)
@@ -247,37 +320,33 @@ impl CertificateIdentity {
                     return Ok((apple_cert.clone(), x509_cert));
                 }
                 Err(e) => {
-                    let error = e
-                        .iter_reports()
-                        .find_map(|node| node.downcast_current_context::<SideloadError>());
-                    if let Some(SideloadError::DeveloperError(code, _)) = error {
-                        if *code == 7460 {
-                            if existing_certs.is_none() {
-                                existing_certs = Some(
-                                    developer_session
-                                        .list_ios_certs(team)
-                                        .await?
-                                        .iter()
-                                        .filter(|c| c.serial_number.is_some())
-                                        .cloned()
-                                        .collect(),
-                                );
-                            }
-                            Self::revoke_others(
-                                developer_session,
-                                team,
-                                max_certs_behavior,
-                                SideloadError::DeveloperError(
-                                    *code,
-                                    "Maximum number of certificates reached".to_string(),
-                                ),
-                                existing_certs.as_mut().ok_or_report()?,
-                            )
-                            .await?;
-                        } else {
-                            return Err(e);
-                        }
+                    let Some(DeveloperServicesError::MaxCertificatesReached { code, .. }) =
+                        DeveloperServicesError::from_report(&e)
+                    else {
+                        return Err(e);
+                    };
+                    if existing_certs.is_none() {
+                        existing_certs = Some(
+                            developer_session
+                                .list_ios_certs(team)
+                                .await?
+                                .iter()
+                                .filter(|c| c.serial_number.is_some())
+                                .cloned()
+                                .collect(),
+                        );
                     }
+                    Self::revoke_others(
+                        developer_session,
+                        team,
+                        max_certs_behavior,
+                        SideloadError::DeveloperError(
+                            code,
+                            "Maximum number of certificates reached".to_string(),
+                        ),
+                        existing_certs.as_mut().ok_or_report()?,
+                    )
+                    .await?;
                 }
             };
         }
@@ -350,8 +419,10 @@ impl CertificateIdentity {
             }
         }
     }
+}
 
-    pub fn setup_signing_settings<'a>(
+impl SigningIdentity for CertificateIdentity {
+    fn setup_signing_settings<'a>(
         &'a self,
         settings: &mut SigningSettings<'a>,
     ) -> Result<(), Report> {
@@ -359,9 +430,49 @@ impl CertificateIdentity {
             self.signing_key.as_key_info_signer(),
             self.certificate.clone(),
         );
-        settings.chain_apple_certificates();
+        self.intermediates.chain_into(settings, &self.certificate);
         settings.set_team_id_from_signing_certificate();
 
         Ok(())
     }
 }
+
+/// Where the private key is stored, in the account's namespace so differently cased emails share it
+pub(crate) fn private_key_storage_key(apple_email: &str) -> String {
+    format!("{}/key", account_namespace(apple_email))
+}
+
+/// Where the private key was stored before it moved to the account's namespace, keyed by the email as typed
+fn unnormalized_private_key_storage_key(apple_email: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(apple_email.as_bytes());
+    format!("{}/key", hex::encode(hasher.finalize()))
+}
+
+/// Read the stored private key, moving it from the key it had before emails were normalized if needed
+fn retrieve_stored_key(
+    apple_email: &str,
+    storage: &dyn SideloadingStorage,
+) -> Result<Option<Vec<u8>>, Report> {
+    let key = private_key_storage_key(apple_email);
+    if let Some(data) = storage.retrieve_data(&key)? {
+        return Ok(Some(data));
+    }
+
+    let old_key = unnormalized_private_key_storage_key(apple_email);
+    if old_key == key {
+        return Ok(None);
+    }
+    let Some(data) = storage.retrieve_data(&old_key)? else {
+        return Ok(None);
+    };
+    storage.store_data(&key, &data)?;
+    if let Err(e) = storage.delete(&old_key) {
+        warn!(
+            "Failed to remove the private key from its old location: {:?}",
+            e
+        );
+    }
+    info!("Moved private key to the account's normalized storage key");
+    Ok(Some(data))
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use rootcause::prelude::*;
+
+pub(crate) const FAT_MAGIC: u32 = 0xcafebabe;
+pub(crate) const FAT_MAGIC_64: u32 = 0xcafebabf;
+pub(crate) const MH_MAGIC: u32 = 0xfeedface;
+pub(crate) const MH_MAGIC_64: u32 = 0xfeedfacf;
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+
+/// Read the embedded code signature of every slice of a Mach-O file
+///
+/// Only the headers, load commands and signatures are read, instead of the whole file, which can be gigabytes for
+/// the executables of large games. The returned blobs can be parsed with
+/// [`apple_codesign::EmbeddedSignature::from_bytes`]. Unsigned slices are skipped.
+pub(crate) fn read_code_signatures(path: &Path) -> Result<Vec<Vec<u8>>, Report> {
+    let mut file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+
+    let mut signatures = Vec::new();
+    for offset in slice_offsets(&mut file)? {
+        if let Some(signature) = read_slice_signature(&mut file, offset)? {
+            signatures.push(signature);
+        }
+    }
+    Ok(signatures)
+}
+
+/// The offsets of the Mach-O slices in a file, which is just `0` for thin files
+pub(crate) fn slice_offsets(file: &mut File) -> Result<Vec<u64>, Report> {
+    file.seek(SeekFrom::Start(0))?;
+    let magic = read_u32_be(file)?;
+    Ok(match magic {
+        FAT_MAGIC | FAT_MAGIC_64 => {
+            let count = read_u32_be(file)?;
+            let mut offsets = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                // cputype and cpusubtype
+                read_u32_be(file)?;
+                read_u32_be(file)?;
+                if magic == FAT_MAGIC_64 {
+                    offsets.push(read_u64_be(file)?);
+                    // size, align and reserved
+                    read_u64_be(file)?;
+                    read_u64_be(file)?;
+                } else {
+                    offsets.push(read_u32_be(file)? as u64);
+                    // size and align
+                    read_u64_be(file)?;
+                }
+            }
+            offsets
+        }
+        _ => vec![0],
+    })
+}
+
+fn read_slice_signature(file: &mut File, offset: u64) -> Result<Option<Vec<u8>>, Report> {
+    file.seek(SeekFrom::Start(offset))?;
+    let magic = read_u32_le(file)?;
+    let header_size = match magic {
+        MH_MAGIC => 28,
+        MH_MAGIC_64 => 32,
+        _ => bail!("Not a Mach-O file (magic {:#x})", magic),
+    };
+
+    file.seek(SeekFrom::Start(offset + 16))?;
+    let command_count = read_u32_le(file)?;
+    let mut command_offset = offset + header_size;
+    for _ in 0..command_count {
+        file.seek(SeekFrom::Start(command_offset))?;
+        let command = read_u32_le(file)?;
+        let command_size = read_u32_le(file)?;
+        if command == LC_CODE_SIGNATURE {
+            let data_offset = read_u32_le(file)?;
+            let data_size = read_u32_le(file)?;
+            file.seek(SeekFrom::Start(offset + data_offset as u64))?;
+            let mut signature = vec![0u8; data_size as usize];
+            file.read_exact(&mut signature)
+                .context("Failed to read code signature")?;
+            return Ok(Some(signature));
+        }
+        if command_size == 0 {
+            bail!("Invalid Mach-O load command size");
+        }
+        command_offset += command_size as u64;
+    }
+
+    Ok(None)
+}
+
+pub(crate) fn read_u32_be(file: &mut File) -> Result<u32, Report> {
+    let mut buf = [0u8; 4];
+    file.read_exact(&mut buf)
+        .context("Failed to read Mach-O header")?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+pub(crate) fn read_u32_le(file: &mut File) -> Result<u32, Report> {
+    let mut buf = [0u8; 4];
+    file.read_exact(&mut buf)
+        .context("Failed to read Mach-O header")?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64_be(file: &mut File) -> Result<u64, Report> {
+    let mut buf = [0u8; 8];
+    file.read_exact(&mut buf)
+        .context("Failed to read Mach-O header")?;
+    Ok(u64::from_be_bytes(buf))
+}
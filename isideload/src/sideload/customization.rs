@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use plist::{Dictionary, Value};
+use rootcause::prelude::*;
+use tracing::debug;
+
+use crate::SideloadError;
+
+/// Customizations for the bundles of an app, keyed by the bundle's path inside the app
+///
+/// Keys use `/` as the separator, e.g. `PlugIns/Helper.appex` or `Frameworks/Helper.framework`. The empty string is
+/// the main app.
+pub type BundleCustomizations = HashMap<String, BundleCustomization>;
+
+/// Changes made to a bundle right before it is signed
+///
+/// Meant for apps like LiveContainer that ship helper bundles with unusual requirements. Extra files are copied in
+/// before the bundle is signed, so they are sealed into its signature.
+#[derive(Debug, Clone, Default)]
+pub struct BundleCustomization {
+    /// Files or directories to copy into the bundle, as `(source, destination relative to the bundle)`
+    pub extra_files: Vec<(PathBuf, PathBuf)>,
+    /// Entitlements to set, replacing the ones from the provisioning profile with the same key
+    pub entitlements: Dictionary,
+    /// Entitlement keys to remove
+    pub removed_entitlements: Vec<String>,
+}
+
+impl BundleCustomization {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy the file or directory at `source` to `destination`, relative to the bundle
+    pub fn with_file(mut self, source: PathBuf, destination: PathBuf) -> Self {
+        self.extra_files.push((source, destination));
+        self
+    }
+
+    /// Sign the bundle with `key` set to `value`
+    pub fn with_entitlement(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.entitlements.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sign the bundle without `key`, even if the provisioning profile grants it
+    pub fn without_entitlement(mut self, key: impl Into<String>) -> Self {
+        self.removed_entitlements.push(key.into());
+        self
+    }
+
+    /// Copy the extra files into `bundle_dir`, replacing existing files
+    pub(crate) fn copy_files(&self, bundle_dir: &Path) -> Result<(), Report> {
+        for (source, destination) in &self.extra_files {
+            if destination.as_os_str().is_empty()
+                || destination
+                    .components()
+                    .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                bail!(SideloadError::InvalidBundle(format!(
+                    "Extra file destination must be a relative path inside the bundle: {}",
+                    destination.display()
+                )));
+            }
+            let target = bundle_dir.join(destination);
+            debug!("Copying {} to {}", source.display(), target.display());
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).context("Failed to create directory in bundle")?;
+            }
+            copy_recursive(source, &target)
+                .context(format!("Failed to copy {} into bundle", source.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the entitlement overrides to the entitlements the bundle would be signed with
+    pub(crate) fn apply_entitlements(&self, entitlements: &mut Dictionary) {
+        for (key, value) in &self.entitlements {
+            entitlements.insert(key.clone(), value.clone());
+        }
+        for key in &self.removed_entitlements {
+            entitlements.remove(key);
+        }
+    }
+}
+
+pub(crate) fn copy_recursive(source: &Path, target: &Path) -> Result<(), Report> {
+    if source.is_dir() {
+        std::fs::create_dir_all(target)?;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &target.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(source, target)?;
+    }
+
+    Ok(())
+}
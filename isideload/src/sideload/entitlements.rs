@@ -0,0 +1,253 @@
+use apple_codesign::EmbeddedSignature;
+use plist::{Dictionary, Value};
+use rootcause::prelude::*;
+
+use crate::{
+    dev::teams::DeveloperTeam,
+    sideload::{bundle::Bundle, code_signature::read_code_signatures},
+};
+
+/// Entitlements that are always taken from the provisioning profile, even if the bundle didn't request them
+///
+/// These identify the team and app, or were requested through the sideloader rather than by the app itself.
+pub const PROFILE_ENTITLEMENTS: &[&str] = &[
+    "application-identifier",
+    "com.apple.developer.team-identifier",
+    "com.apple.security.application-groups",
+    "get-task-allow",
+    "keychain-access-groups",
+    "com.apple.developer.kernel.increased-memory-limit",
+];
+
+/// Entitlements free provisioning profiles can back
+///
+/// Entries ending in `*` match every entitlement starting with the part before it.
+pub const FREE_TEAM_ENTITLEMENTS: &[&str] = &[
+    "application-identifier",
+    "com.apple.developer.team-identifier",
+    "com.apple.security.application-groups",
+    "get-task-allow",
+    "keychain-access-groups",
+    "com.apple.developer.kernel.increased-memory-limit",
+    "com.apple.developer.default-data-protection",
+    "com.apple.developer.game-center",
+    "com.apple.developer.homekit",
+    "com.apple.developer.networking.HotspotConfiguration",
+    "com.apple.developer.networking.multipath",
+    "com.apple.developer.networking.networkextension",
+    "com.apple.developer.networking.vpn.api",
+    "com.apple.developer.networking.wifi-info",
+    "com.apple.developer.siri",
+    "com.apple.external-accessory.wireless-configuration",
+    "inter-app-audio",
+    "com.apple.developer.parent-application-identifiers",
+    "com.apple.developer.on-demand-install-capable",
+];
+
+/// Entitlements paid provisioning profiles can only back if Apple granted them to the team
+pub const RESTRICTED_ENTITLEMENTS: &[&str] = &[
+    "com.apple.developer.carplay*",
+    "com.apple.developer.driverkit*",
+    "com.apple.developer.endpoint-security.client",
+    "com.apple.developer.exposure-notification",
+    "com.apple.developer.family-controls",
+    "com.apple.developer.networking.multicast",
+    "com.apple.developer.usernotifications.critical-alerts",
+];
+
+/// Which entitlements a bundle may be signed with, applied after merging the requested and granted entitlements
+///
+/// installd rejects the whole app if its signature has an entitlement the provisioning profile can't back, so
+/// anything outside the policy is dropped instead. Defaults to [`Self::for_team`], and can be replaced or
+/// adjusted with [`crate::sideload::SideloaderBuilder::entitlement_policy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntitlementPolicy {
+    /// Allowed entitlements, or `None` to allow everything not denied
+    allowed: Option<Vec<String>>,
+    denied: Vec<String>,
+}
+
+impl EntitlementPolicy {
+    /// Only allow the entitlements free provisioning profiles can back
+    pub fn free() -> Self {
+        EntitlementPolicy {
+            allowed: Some(
+                FREE_TEAM_ENTITLEMENTS
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect(),
+            ),
+            denied: Vec::new(),
+        }
+    }
+
+    /// Allow everything except entitlements that need Apple's approval
+    pub fn paid() -> Self {
+        EntitlementPolicy {
+            allowed: None,
+            denied: RESTRICTED_ENTITLEMENTS
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+        }
+    }
+
+    /// Allow everything the provisioning profile grants
+    pub fn unrestricted() -> Self {
+        EntitlementPolicy {
+            allowed: None,
+            denied: Vec::new(),
+        }
+    }
+
+    /// The built-in policy for the team, based on its [`crate::dev::teams::TeamCapabilities`]
+    pub fn for_team(team: &DeveloperTeam) -> Self {
+        if team.capabilities().has_paid_entitlements() {
+            Self::paid()
+        } else {
+            Self::free()
+        }
+    }
+
+    /// Allow an entitlement, or every entitlement with a prefix if it ends in `*`
+    pub fn allow(mut self, entitlement: impl Into<String>) -> Self {
+        let entitlement = entitlement.into();
+        self.denied.retain(|e| *e != entitlement);
+        if let Some(allowed) = &mut self.allowed
+            && !allowed.contains(&entitlement)
+        {
+            allowed.push(entitlement);
+        }
+        self
+    }
+
+    /// Never sign with an entitlement, or any entitlement with a prefix if it ends in `*`
+    pub fn deny(mut self, entitlement: impl Into<String>) -> Self {
+        let entitlement = entitlement.into();
+        if let Some(allowed) = &mut self.allowed {
+            allowed.retain(|e| *e != entitlement);
+        }
+        if !self.denied.contains(&entitlement) {
+            self.denied.push(entitlement);
+        }
+        self
+    }
+
+    pub fn is_allowed(&self, entitlement: &str) -> bool {
+        if self.denied.iter().any(|p| pattern_matches(p, entitlement)) {
+            return false;
+        }
+        match &self.allowed {
+            Some(allowed) => allowed.iter().any(|p| pattern_matches(p, entitlement)),
+            None => true,
+        }
+    }
+
+    /// Remove the entitlements outside the policy, returning their keys
+    pub fn apply(&self, entitlements: &mut Dictionary) -> Vec<String> {
+        let removed: Vec<String> = entitlements
+            .keys()
+            .filter(|key| !self.is_allowed(key))
+            .cloned()
+            .collect();
+        for key in &removed {
+            entitlements.remove(key);
+        }
+        removed
+    }
+}
+
+/// Whether the entitlement is one of the [`RESTRICTED_ENTITLEMENTS`]
+pub fn is_restricted_entitlement(entitlement: &str) -> bool {
+    RESTRICTED_ENTITLEMENTS
+        .iter()
+        .any(|p| pattern_matches(p, entitlement))
+}
+
+fn pattern_matches(pattern: &str, entitlement: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => entitlement.starts_with(prefix),
+        None => pattern == entitlement,
+    }
+}
+
+/// Build the entitlements to sign a bundle with
+///
+/// Only entitlements the bundle originally requested and the profile grants are kept, so an extension doesn't
+/// gain the capabilities of the main app. Values come from the profile, except for lists the profile grants
+/// more of than requested, which are narrowed down to the requested entries. Lists without any requested entry
+/// granted are dropped.
+///
+/// If the bundle's original entitlements are unknown, everything the profile grants is used.
+pub fn merge_entitlements(requested: Option<&Dictionary>, granted: &Dictionary) -> Dictionary {
+    let Some(requested) = requested else {
+        return granted.clone();
+    };
+
+    let mut merged = Dictionary::new();
+    for (key, granted_value) in granted {
+        if PROFILE_ENTITLEMENTS.contains(&key.as_str()) {
+            merged.insert(key.clone(), granted_value.clone());
+            continue;
+        }
+        if let Some(value) = requested
+            .get(key)
+            .and_then(|requested_value| merge_value(requested_value, granted_value))
+        {
+            merged.insert(key.clone(), value);
+        }
+    }
+
+    merged
+}
+
+/// The value of an entitlement both requested and granted, `None` if it has to be dropped
+///
+/// Lists are narrowed down to the requested entries. If none of them are granted the entitlement is dropped, since
+/// signing with the profile's whole list would give the bundle access it never asked for.
+pub(crate) fn merge_value(requested: &Value, granted: &Value) -> Option<Value> {
+    match (requested.as_array(), granted.as_array()) {
+        (Some(requested), Some(granted)) => {
+            let narrowed: Vec<Value> = granted
+                .iter()
+                .filter(|v| requested.contains(v))
+                .cloned()
+                .collect();
+            (!narrowed.is_empty()).then_some(Value::Array(narrowed))
+        }
+        _ => Some(granted.clone()),
+    }
+}
+
+/// Read the entitlements embedded in the code signature of the bundle's main executable
+///
+/// Returns `None` if the bundle has no executable, or the executable isn't signed with entitlements.
+pub fn requested_entitlements(bundle: &Bundle) -> Result<Option<Dictionary>, Report> {
+    let Some(executable) = bundle
+        .app_info
+        .get("CFBundleExecutable")
+        .and_then(|v| v.as_string())
+    else {
+        return Ok(None);
+    };
+    let path = bundle.bundle_dir.join(executable);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let Some(data) = read_code_signatures(&path)?.into_iter().next() else {
+        return Ok(None);
+    };
+    let signature =
+        EmbeddedSignature::from_bytes(&data).context("Failed to read code signature")?;
+    let Some(blob) = signature
+        .entitlements()
+        .context("Failed to read entitlements")?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(
+        plist::from_bytes(blob.as_str().as_bytes()).context("Failed to parse entitlements")?,
+    ))
+}
@@ -0,0 +1,90 @@
+use std::sync::mpsc::Sender;
+
+use crate::dev::{developer_session::DeveloperRequestTrace, teams::DeveloperTeam};
+
+/// Events emitted by [`crate::sideload::sideloader::Sideloader`] so frontends can follow along without extra queries
+///
+/// The step events are emitted in pipeline order, so a frontend can show the latest one as the current step.
+///
+/// With the `json-events` feature, events serialize as `{"event": "signing", "data": {"bundle": "Widget.appex"}}`.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(tag = "event", content = "data", rename_all = "snake_case")
+)]
+pub enum SideloadEvent {
+    /// Fetching the developer teams, the first request made with the developer session
+    Authenticating,
+    /// A developer team was selected according to the configured [`crate::sideload::TeamSelection`]
+    TeamSelected(DeveloperTeam),
+    /// Registering the target device with the developer team
+    RegisteringDevice {
+        udid: String,
+        /// The name the device reports
+        name: String,
+        /// The label the user gave the device, see [`crate::util::device::aliases`]
+        alias: Option<String>,
+    },
+    /// Retrieving or creating the development certificate
+    RequestingCertificate,
+    /// Registering the app IDs and app group of the app
+    RegisteringAppIds,
+    /// Unused architectures and files were stripped from the app, see
+    /// [`crate::sideload::SideloaderBuilder::thin_app`]
+    Thinned { bytes_saved: u64 },
+    /// Signing a bundle of the app, identified by its directory name (e.g. `Widget.appex`)
+    Signing { bundle: String },
+    /// An entitlement requested by the app was dropped because the provisioning profile doesn't grant it,
+    /// so the feature it enables won't work
+    CapabilityDropped { entitlement: String, reason: String },
+    /// Signing failed, and the app IDs and app groups it had created were removed again, see
+    /// [`crate::sideload::SideloaderBuilder::rollback_on_failure`]
+    RolledBack {
+        app_ids: Vec<String>,
+        app_groups: Vec<String>,
+    },
+    /// Developer Mode is disabled on the device, so the app will install but won't launch until it is enabled
+    DeveloperModeDisabled,
+    /// Uploading the signed app to the device
+    Uploading { percent: u64 },
+    /// The device is installing the uploaded app
+    Installing { percent: u64 },
+    /// installd's full status message, sent along with every [`SideloadEvent::Installing`]
+    #[cfg(feature = "install")]
+    InstallStatus(crate::sideload::install::InstallStatus),
+    /// A request was sent to the developer services. Its `request_id` is also attached to the error report if it fails.
+    DeveloperRequest(DeveloperRequestTrace),
+    /// The app was signed (and installed, if requested) successfully
+    Done,
+}
+
+/// Callback that receives every [`SideloadEvent`]
+pub type SideloadObserver = Box<dyn Fn(&SideloadEvent) + Send + Sync>;
+
+/// Create an observer that forwards every event to a channel
+///
+/// Events are dropped silently once the receiver is gone.
+pub fn channel_observer(sender: Sender<SideloadEvent>) -> SideloadObserver {
+    Box::new(move |event| {
+        let _ = sender.send(event.clone());
+    })
+}
+
+/// Create an observer that writes every event to `writer` as one line of JSON, e.g. to stdout for a frontend process
+#[cfg(feature = "json-events")]
+pub fn json_lines_observer(writer: impl std::io::Write + Send + 'static) -> SideloadObserver {
+    let writer = std::sync::Mutex::new(writer);
+    Box::new(move |event| {
+        let mut writer = writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let result = serde_json::to_writer(&mut *writer, event)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(writer))
+            .and_then(|_| writer.flush());
+        if let Err(e) = result {
+            tracing::warn!("Failed to write event as JSON: {}", e);
+        }
+    })
+}
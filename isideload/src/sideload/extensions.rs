@@ -0,0 +1,107 @@
+use rootcause::prelude::*;
+use tracing::info;
+
+use crate::sideload::bundle::Bundle;
+
+/// Identifies a nested bundle (app extension, watch app or app clip)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionMatcher {
+    /// Matches bundles whose identifier ends with this suffix, e.g. `.widget`
+    BundleSuffix(String),
+    /// Matches bundles by their `NSExtensionPointIdentifier`, e.g. `com.apple.widgetkit-extension`
+    ///
+    /// Watch apps and app clips match `com.apple.watchkit` and `com.apple.appclip`.
+    ExtensionPoint(String),
+}
+
+impl ExtensionMatcher {
+    pub fn matches(&self, bundle: &Bundle) -> bool {
+        match self {
+            ExtensionMatcher::BundleSuffix(suffix) => bundle
+                .bundle_identifier()
+                .is_some_and(|id| id.ends_with(suffix.as_str())),
+            ExtensionMatcher::ExtensionPoint(point) => {
+                bundle.extension_point_identifier() == Some(point.as_str())
+            }
+        }
+    }
+}
+
+/// Which nested bundles to keep when sideloading an app
+///
+/// Every kept extension needs its own app ID, so dropping the ones you don't need saves app ID slots.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ExtensionFilter {
+    /// Keep all nested bundles
+    #[default]
+    KeepAll,
+    /// Only keep nested bundles matching one of the matchers
+    Allow(Vec<ExtensionMatcher>),
+    /// Remove nested bundles matching any of the matchers
+    Deny(Vec<ExtensionMatcher>),
+}
+
+impl ExtensionFilter {
+    /// Whether the nested bundle should be kept
+    pub fn keeps(&self, bundle: &Bundle) -> bool {
+        match self {
+            ExtensionFilter::KeepAll => true,
+            ExtensionFilter::Allow(matchers) => matchers.iter().any(|m| m.matches(bundle)),
+            ExtensionFilter::Deny(matchers) => !matchers.iter().any(|m| m.matches(bundle)),
+        }
+    }
+
+    /// Remove the nested bundles of the app that this filter rejects
+    ///
+    /// Returns the bundle identifiers of the removed bundles.
+    pub fn apply(&self, bundle: &mut Bundle) -> Result<Vec<String>, Report> {
+        if *self == ExtensionFilter::KeepAll {
+            return Ok(Vec::new());
+        }
+        let removed = bundle.retain_extensions(|ext| self.keeps(ext))?;
+        for id in &removed {
+            info!("Removed {} due to extension filter", id);
+        }
+        Ok(removed)
+    }
+}
+
+/// What to do with app clips (`AppClips/*.app`) inside the app
+///
+/// App clips need their own app ID and entitlements that free teams can't get, so they are removed by default
+/// on free teams.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AppClipBehavior {
+    /// Remove app clips on free teams, and register them on paid teams
+    #[default]
+    Auto,
+    /// Always remove app clips
+    Remove,
+    /// Always register an app ID for app clips and sign them with the parent app entitlements
+    Register,
+}
+
+impl AppClipBehavior {
+    /// Whether app clips are removed when sideloading to a free or paid team
+    pub fn removes_clips(&self, free_team: bool) -> bool {
+        match self {
+            AppClipBehavior::Auto => free_team,
+            AppClipBehavior::Remove => true,
+            AppClipBehavior::Register => false,
+        }
+    }
+
+    /// Remove the app clips of the app if this behavior doesn't keep them on the team
+    ///
+    /// Returns the bundle identifiers of the removed app clips.
+    pub fn apply(&self, bundle: &mut Bundle, free_team: bool) -> Result<Vec<String>, Report> {
+        if !self.removes_clips(free_team) || bundle.app_clips().is_empty() {
+            return Ok(Vec::new());
+        }
+        let removed = bundle.retain_extensions(|ext| !ext.is_app_clip())?;
+        for id in &removed {
+            info!("Removed app clip {}", id);
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,307 @@
+use std::path::{Path, PathBuf};
+
+use apple_codesign::{
+    SigningSettings,
+    cryptography::{InMemoryPrivateKey, PrivateKey},
+};
+use rootcause::{option_ext::OptionExt, prelude::*};
+use tracing::info;
+use x509_certificate::CapturedX509Certificate;
+
+#[cfg(feature = "install")]
+use crate::sideload::install::{InstallOptions, InstallProgress, install_app_with_options};
+#[cfg(feature = "install")]
+use crate::util::device::IdeviceInfo;
+#[cfg(feature = "install")]
+use idevice::provider::IdeviceProvider;
+
+use crate::{
+    SideloadError,
+    sideload::{
+        application::{Application, TempDirGuard},
+        customization::{BundleCustomization, BundleCustomizations},
+        entitlements::EntitlementPolicy,
+        events::{SideloadEvent, SideloadObserver},
+        profile::ProvisioningProfileInfo,
+        sign::{self, SigningIdentity},
+        signing_report::SigningReport,
+        wwdr::WwdrIntermediates,
+    },
+    util::clock::SystemClock,
+};
+
+/// A signing certificate and private key imported from a PKCS#12 (.p12) file
+///
+/// Usually exported from Keychain Access or another sideloading tool. Unlike
+/// [`CertificateIdentity`](crate::sideload::cert_identity::CertificateIdentity), it is never
+/// created or revoked through the developer services.
+pub struct ImportedIdentity {
+    pub certificate: CapturedX509Certificate,
+    pub signing_key: InMemoryPrivateKey,
+    /// The WWDR intermediates the certificate is chained to, the ones bundled with apple-codesign by default
+    pub intermediates: WwdrIntermediates,
+}
+
+impl ImportedIdentity {
+    /// Import the first certificate and private key pair of a PKCS#12 archive
+    pub fn from_p12(data: &[u8], password: &str) -> Result<Self, Report> {
+        let keystore = p12_keystore::KeyStore::from_pkcs12(
+            data,
+            password,
+            p12_keystore::Pkcs12ImportPolicy::default(),
+        )
+        .map_err(|e| report!("Failed to read p12 file: {:?}", e))?;
+
+        let (_, chain) = keystore
+            .private_key_chain()
+            .ok_or_report()
+            .context("p12 file does not contain a private key")?;
+        let certificate = chain
+            .certs()
+            .first()
+            .ok_or_report()
+            .context("p12 file does not contain a certificate for its private key")?;
+
+        Ok(Self {
+            certificate: CapturedX509Certificate::from_der(certificate.as_der().to_vec())
+                .context("Failed to parse certificate from p12 file")?,
+            signing_key: InMemoryPrivateKey::from_pkcs8_der(chain.key().as_der())
+                .context("Failed to parse private key from p12 file")?,
+            intermediates: WwdrIntermediates::default(),
+        })
+    }
+
+    /// Read and import a PKCS#12 archive from disk
+    pub fn from_p12_file(path: &Path, password: &str) -> Result<Self, Report> {
+        let data = std::fs::read(path).context("Failed to read p12 file")?;
+        Self::from_p12(&data, password)
+    }
+}
+
+impl SigningIdentity for ImportedIdentity {
+    fn setup_signing_settings<'a>(
+        &'a self,
+        settings: &mut SigningSettings<'a>,
+    ) -> Result<(), Report> {
+        settings.set_signing_key(
+            self.signing_key.as_key_info_signer(),
+            self.certificate.clone(),
+        );
+        self.intermediates.chain_into(settings, &self.certificate);
+        settings.set_team_id_from_signing_certificate();
+
+        Ok(())
+    }
+}
+
+/// Signs and installs apps with an imported identity and provisioning profile, without an Apple ID
+///
+/// Nothing is registered with the developer services, so the profile must already allow the certificate
+/// and cover the devices the app is installed on.
+pub struct ImportedIdentitySideloader {
+    identity: ImportedIdentity,
+    profile: Vec<u8>,
+    profile_info: ProvisioningProfileInfo,
+    observers: Vec<SideloadObserver>,
+    bundle_customizations: BundleCustomizations,
+    entitlement_policy: EntitlementPolicy,
+}
+
+impl ImportedIdentitySideloader {
+    /// Create a sideloader from an identity and the raw bytes of a `.mobileprovision` file
+    pub fn new(identity: ImportedIdentity, profile: Vec<u8>) -> Result<Self, Report> {
+        let profile_info = ProvisioningProfileInfo::parse(&profile)
+            .context("Failed to parse provisioning profile")?;
+
+        let certificate_der = identity.certificate.encode_der()?;
+        if !profile_info.allows_certificate(&certificate_der) {
+            bail!(SideloadError::InvalidBundle(
+                "The provisioning profile does not allow the imported certificate".to_string(),
+            ));
+        }
+        if profile_info.is_expired(&SystemClock) {
+            bail!(SideloadError::InvalidBundle(
+                "The provisioning profile has expired".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            identity,
+            profile,
+            profile_info,
+            observers: Vec::new(),
+            bundle_customizations: BundleCustomizations::new(),
+            entitlement_policy: EntitlementPolicy::paid(),
+        })
+    }
+
+    /// Load the identity from a p12 file and the profile from a `.mobileprovision` file
+    pub fn from_files(
+        p12_path: &Path,
+        p12_password: &str,
+        profile_path: &Path,
+    ) -> Result<Self, Report> {
+        let identity = ImportedIdentity::from_p12_file(p12_path, p12_password)?;
+        let profile = std::fs::read(profile_path).context("Failed to read provisioning profile")?;
+        Self::new(identity, profile)
+    }
+
+    /// Add an observer that is notified of signing and installation progress
+    pub fn observer(mut self, observer: SideloadObserver) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Customize the bundle at `path` inside the app right before it is signed, or `""` for the main app
+    ///
+    /// See [`BundleCustomization`] for details.
+    pub fn bundle_customization(
+        mut self,
+        path: impl Into<String>,
+        customization: BundleCustomization,
+    ) -> Self {
+        self.bundle_customizations
+            .insert(path.into(), customization);
+        self
+    }
+
+    /// Set which entitlements apps may be signed with. Defaults to [`EntitlementPolicy::paid`], since imported
+    /// identities usually belong to paid teams.
+    pub fn entitlement_policy(mut self, policy: EntitlementPolicy) -> Self {
+        self.entitlement_policy = policy;
+        self
+    }
+
+    pub fn profile_info(&self) -> &ProvisioningProfileInfo {
+        &self.profile_info
+    }
+
+    fn emit(&self, event: SideloadEvent) {
+        for observer in &self.observers {
+            observer(&event);
+        }
+    }
+
+    /// Sign the app at the provided path and return the signed app
+    ///
+    /// If the profile is for an explicit app ID, the app and its extensions are renamed to match it. IPAs are signed
+    /// in the temporary directory they are extracted to, [`Application::temp_path`], which is removed if signing fails
+    /// and is up to the caller to remove otherwise.
+    pub fn sign_app(&self, app_path: PathBuf) -> Result<(Application, SigningReport), Report> {
+        let mut app = Application::new(app_path)?;
+        let temp_dir = TempDirGuard::new(app.temp_path.clone());
+        let signing_report = self.sign_loaded(&mut app)?;
+        temp_dir.keep();
+        Ok((app, signing_report))
+    }
+
+    fn sign_loaded(&self, app: &mut Application) -> Result<SigningReport, Report> {
+        let team_id = self
+            .profile_info
+            .team_id
+            .clone()
+            .ok_or_report()
+            .context("Provisioning profile does not have a team identifier")?;
+
+        let special = app.get_special_app();
+
+        let profile_bundle_id = self
+            .profile_info
+            .entitlements
+            .get("application-identifier")
+            .and_then(|v| v.as_string())
+            .and_then(|id| id.strip_prefix(&format!("{}.", team_id)))
+            .map(str::to_string)
+            .ok_or_report()
+            .context("Provisioning profile does not have an application identifier")?;
+        if !profile_bundle_id.ends_with('*') {
+            let main_bundle_id = app.main_bundle_id()?;
+            if main_bundle_id != profile_bundle_id {
+                info!(
+                    "Renaming {} to {} to match the provisioning profile",
+                    main_bundle_id, profile_bundle_id
+                );
+                app.update_bundle_id(&main_bundle_id, &profile_bundle_id)?;
+            }
+        }
+
+        app.bundle.write_info_recursive()?;
+
+        std::fs::write(
+            app.bundle.bundle_dir.join("embedded.mobileprovision"),
+            &self.profile,
+        )
+        .context("Failed to write embedded.mobileprovision")?;
+
+        let signing_report = sign::sign(
+            app,
+            &self.identity,
+            &self.profile,
+            &special,
+            &team_id,
+            &self.bundle_customizations,
+            &self.entitlement_policy,
+            &|event| self.emit(event),
+        )
+        .context("Failed to sign app")?;
+        for capability in &signing_report.dropped_capabilities {
+            self.emit(SideloadEvent::CapabilityDropped {
+                entitlement: capability.entitlement.clone(),
+                reason: capability.reason.clone(),
+            });
+        }
+
+        info!("App signed!");
+
+        Ok(signing_report)
+    }
+
+    /// Sign the app and install it on the device
+    ///
+    /// Fails before signing if the profile doesn't cover the device. The signed app is removed afterwards.
+    #[cfg(feature = "install")]
+    pub async fn install_app(
+        &self,
+        device_provider: &impl IdeviceProvider,
+        app_path: PathBuf,
+    ) -> Result<(), Report> {
+        let device_info = IdeviceInfo::from_device(device_provider).await?;
+        if !self.profile_info.covers_device(&device_info.udid) {
+            bail!(SideloadError::InvalidBundle(format!(
+                "The provisioning profile does not include device {}",
+                device_info.udid
+            )));
+        }
+
+        let mut app = Application::new(app_path)?;
+        let _temp_dir = TempDirGuard::new(app.temp_path.clone());
+        self.sign_loaded(&mut app)?;
+
+        install_app_with_options(
+            device_provider,
+            &app.bundle.bundle_dir,
+            &InstallOptions::default(),
+            |progress| match progress {
+                InstallProgress::Uploading {
+                    bytes_done,
+                    bytes_total,
+                    ..
+                } => self.emit(SideloadEvent::Uploading {
+                    percent: bytes_done * 100 / bytes_total.max(1),
+                }),
+                InstallProgress::Installing(status) => {
+                    info!("Installing: {}", status);
+                    self.emit(SideloadEvent::Installing {
+                        percent: status.percent,
+                    });
+                    self.emit(SideloadEvent::InstallStatus(status));
+                }
+            },
+        )
+        .await
+        .context("Failed to install app")?;
+        self.emit(SideloadEvent::Done);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use plist::Value;
+use rootcause::{option_ext::OptionExt, prelude::*};
+use tracing::{info, warn};
+
+use crate::{
+    SideloadError,
+    sideload::{
+        application::Application,
+        bundle::Bundle,
+        code_signature::{MH_MAGIC, MH_MAGIC_64, slice_offsets},
+        customization::copy_recursive,
+        pass::{BundlePass, PassContext},
+    },
+};
+
+const LC_SEGMENT: u32 = 0x1;
+const LC_LOAD_DYLIB: u32 = 0xc;
+const LC_ID_DYLIB: u32 = 0xd;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_LOAD_WEAK_DYLIB: u32 = 0x8000_0018;
+
+impl Application {
+    /// Add a tweak or other dylib or framework to the app, so it is loaded by the main executable and signed with it
+    ///
+    /// The library is copied into the app's `Frameworks` directory, replacing an existing one with the same name,
+    /// its install name is changed to match, and a load command for it is added to the main executable, like
+    /// Azule-style patching does. Returns the install name. Injecting the same library twice doesn't add a second
+    /// load command.
+    pub fn inject_library(&mut self, source: &Path) -> Result<String, Report> {
+        let file_name = source
+            .file_name()
+            .ok_or_report()
+            .context("Library path has no file name")?
+            .to_string_lossy()
+            .to_string();
+        let frameworks_dir = self.bundle.bundle_dir.join("Frameworks");
+        let target = frameworks_dir.join(&file_name);
+        if target.exists() {
+            warn!("Replacing {} already in the app", file_name);
+            if target.is_dir() {
+                std::fs::remove_dir_all(&target)
+            } else {
+                std::fs::remove_file(&target)
+            }
+            .context(format!("Failed to remove existing {}", file_name))?;
+        }
+        std::fs::create_dir_all(&frameworks_dir)
+            .context("Failed to create Frameworks directory")?;
+        copy_recursive(source, &target)
+            .context(format!("Failed to copy {} into app", source.display()))?;
+
+        let (binary, install_name) = if target.is_dir() {
+            let executable = bundle_executable(&Bundle::new(target.clone())?)?;
+            (
+                target.join(&executable),
+                format!("@executable_path/Frameworks/{}/{}", file_name, executable),
+            )
+        } else {
+            (
+                target.clone(),
+                format!("@executable_path/Frameworks/{}", file_name),
+            )
+        };
+
+        // dyld loads libraries by the path in the load command, so a stale install name only matters to code
+        // linking against the library
+        if let Err(e) = set_install_name(&binary, &install_name) {
+            warn!("Failed to change install name of {}: {:?}", file_name, e);
+        }
+
+        let main_executable = self
+            .bundle
+            .bundle_dir
+            .join(bundle_executable(&self.bundle)?);
+        add_load_command(&main_executable, &install_name)
+            .context(format!("Failed to add load command for {}", file_name))?;
+        self.bundle.add_framework(&target)?;
+
+        info!("Injected {} as {}", file_name, install_name);
+        Ok(install_name)
+    }
+}
+
+/// Injects dylibs and frameworks into the app with [`Application::inject_library`]
+pub struct InjectLibrariesPass {
+    pub libraries: Vec<PathBuf>,
+}
+
+impl InjectLibrariesPass {
+    pub const NAME: &str = "inject-libraries";
+}
+
+#[async_trait::async_trait]
+impl BundlePass for InjectLibrariesPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn run(&self, context: &mut PassContext<'_>) -> Result<(), Report> {
+        for library in &self.libraries {
+            context.app.inject_library(library)?;
+        }
+        Ok(())
+    }
+}
+
+fn bundle_executable(bundle: &Bundle) -> Result<String, Report> {
+    Ok(bundle
+        .app_info
+        .get("CFBundleExecutable")
+        .and_then(Value::as_string)
+        .ok_or_report()
+        .context(SideloadError::InvalidBundle(format!(
+            "{} has no CFBundleExecutable",
+            bundle.bundle_dir.display()
+        )))?
+        .to_string())
+}
+
+/// Replace the `LC_ID_DYLIB` name of every slice of a dylib, which has to fit in the space of the current name
+fn set_install_name(path: &Path, name: &str) -> Result<(), Report> {
+    let mut file = open_rw(path)?;
+    for offset in slice_offsets(&mut file)? {
+        let mut slice = LoadCommands::read(&mut file, offset)?;
+        let Some(&(command_offset, _, command_size)) = slice
+            .commands()?
+            .iter()
+            .find(|(_, command, _)| *command == LC_ID_DYLIB)
+        else {
+            bail!("{} is not a dylib", path.display());
+        };
+
+        let name_offset = command_offset + slice.u32_at(command_offset + 8)? as usize;
+        let end = command_offset + command_size as usize;
+        if name_offset + name.len() + 1 > end {
+            bail!(
+                "Install name {} is longer than the space for it in {}",
+                name,
+                path.display()
+            );
+        }
+        slice.data[name_offset..end].fill(0);
+        slice.data[name_offset..name_offset + name.len()].copy_from_slice(name.as_bytes());
+        slice.write(&mut file)?;
+    }
+    Ok(())
+}
+
+/// Add an `LC_LOAD_DYLIB` command for `name` to every slice of an executable that doesn't load it yet
+///
+/// The command goes in the padding between the load commands and the first section, which linkers leave for this.
+fn add_load_command(path: &Path, name: &str) -> Result<(), Report> {
+    let mut file = open_rw(path)?;
+    for offset in slice_offsets(&mut file)? {
+        let mut slice = LoadCommands::read(&mut file, offset)?;
+        let commands = slice.commands()?;
+
+        let mut loaded = false;
+        for &(command_offset, command, command_size) in &commands {
+            if (command == LC_LOAD_DYLIB || command == LC_LOAD_WEAK_DYLIB)
+                && slice.dylib_name(command_offset, command_size)? == name
+            {
+                loaded = true;
+            }
+        }
+        if loaded {
+            continue;
+        }
+
+        let alignment = if slice.is_64 { 8 } else { 4 };
+        let command_size = (24 + name.len() + 1).div_ceil(alignment) * alignment;
+        let first_section = slice.first_section_offset(&commands)?;
+        if (slice.data.len() + command_size) as u64 > first_section {
+            bail!(
+                "Not enough space for another load command in {}",
+                path.display()
+            );
+        }
+
+        let mut command = Vec::with_capacity(command_size);
+        command.extend_from_slice(&LC_LOAD_DYLIB.to_le_bytes());
+        command.extend_from_slice(&(command_size as u32).to_le_bytes());
+        // name offset, timestamp, current version and compatibility version
+        command.extend_from_slice(&24u32.to_le_bytes());
+        command.extend_from_slice(&2u32.to_le_bytes());
+        command.extend_from_slice(&0x10000u32.to_le_bytes());
+        command.extend_from_slice(&0x10000u32.to_le_bytes());
+        command.extend_from_slice(name.as_bytes());
+        command.resize(command_size, 0);
+
+        let command_count = slice.u32_at(16)? + 1;
+        let commands_size = slice.u32_at(20)? + command_size as u32;
+        slice.data[16..20].copy_from_slice(&command_count.to_le_bytes());
+        slice.data[20..24].copy_from_slice(&commands_size.to_le_bytes());
+        slice.data.extend_from_slice(&command);
+        slice.write(&mut file)?;
+    }
+    Ok(())
+}
+
+fn open_rw(path: &Path) -> Result<File, Report> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .context(format!("Failed to open {}", path.display()))?)
+}
+
+/// The header and load commands of one Mach-O slice
+struct LoadCommands {
+    offset: u64,
+    is_64: bool,
+    data: Vec<u8>,
+}
+
+impl LoadCommands {
+    fn read(file: &mut File, offset: u64) -> Result<Self, Report> {
+        let mut header = [0u8; 32];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)
+            .context("Failed to read Mach-O header")?;
+        let is_64 = match u32::from_le_bytes([header[0], header[1], header[2], header[3]]) {
+            MH_MAGIC => false,
+            MH_MAGIC_64 => true,
+            magic => bail!("Not a Mach-O file (magic {:#x})", magic),
+        };
+        let header_size = if is_64 { 32 } else { 28 };
+        let commands_size = u32::from_le_bytes([header[20], header[21], header[22], header[23]]);
+
+        let mut data = vec![0u8; header_size + commands_size as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)
+            .context("Failed to read Mach-O load commands")?;
+        Ok(Self {
+            offset,
+            is_64,
+            data,
+        })
+    }
+
+    fn write(&self, file: &mut File) -> Result<(), Report> {
+        file.seek(SeekFrom::Start(self.offset))?;
+        file.write_all(&self.data)
+            .context("Failed to write Mach-O load commands")?;
+        Ok(())
+    }
+
+    fn u32_at(&self, at: usize) -> Result<u32, Report> {
+        let bytes = self
+            .data
+            .get(at..at + 4)
+            .ok_or_report()
+            .context("Mach-O load command is out of bounds")?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// The offset, type and size of every load command
+    fn commands(&self) -> Result<Vec<(usize, u32, u32)>, Report> {
+        let count = self.u32_at(16)?;
+        let mut offset = if self.is_64 { 32 } else { 28 };
+        let mut commands = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let command = self.u32_at(offset)?;
+            let size = self.u32_at(offset + 4)?;
+            if size == 0 {
+                bail!("Invalid Mach-O load command size");
+            }
+            commands.push((offset, command, size));
+            offset += size as usize;
+        }
+        Ok(commands)
+    }
+
+    fn dylib_name(&self, command_offset: usize, command_size: u32) -> Result<String, Report> {
+        let start = command_offset + self.u32_at(command_offset + 8)? as usize;
+        let name = self
+            .data
+            .get(start..command_offset + command_size as usize)
+            .ok_or_report()
+            .context("Mach-O dylib name is out of bounds")?;
+        let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        Ok(String::from_utf8_lossy(&name[..end]).to_string())
+    }
+
+    /// The file offset of the first section with contents, relative to the slice, where the load commands must end
+    fn first_section_offset(&self, commands: &[(usize, u32, u32)]) -> Result<u64, Report> {
+        let mut first = u64::MAX;
+        for &(command_offset, command, _) in commands {
+            let (sections_offset, section_size, offset_field) = match command {
+                LC_SEGMENT_64 => (72, 80, 48),
+                LC_SEGMENT => (56, 68, 40),
+                _ => continue,
+            };
+            let section_count = self.u32_at(command_offset + sections_offset - 8)?;
+            for i in 0..section_count as usize {
+                let section = command_offset + sections_offset + i * section_size;
+                let offset = self.u32_at(section + offset_field)? as u64;
+                // zero-fill sections like __bss have no contents in the file
+                if offset != 0 {
+                    first = first.min(offset);
+                }
+            }
+        }
+        Ok(first)
+    }
+}
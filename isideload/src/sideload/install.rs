@@ -1,14 +1,185 @@
 use idevice::{
-    IdeviceService, afc::AfcClient, installation_proxy::InstallationProxyClient,
+    Idevice, IdeviceError, IdeviceService,
+    afc::{AfcClient, FileInfo, file::FileDescriptor},
+    installation_proxy::InstallationProxyClient,
     provider::IdeviceProvider,
 };
+use plist::Dictionary;
 use plist_macro::plist;
 use rootcause::option_ext::OptionExt;
 use rootcause::prelude::*;
 
 use crate::SideloadError as Error;
-use std::pin::Pin;
-use std::{future::Future, path::Path};
+use futures_util::future::try_join_all;
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{info, warn};
+
+/// Progress reported while installing an app
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum InstallProgress {
+    /// Part of a file was copied to the device's staging directory
+    Uploading {
+        /// The path of the file, relative to the app bundle
+        file: String,
+        files_done: usize,
+        files_total: usize,
+        /// Bytes uploaded so far across all files
+        bytes_done: u64,
+        bytes_total: u64,
+    },
+    /// installd reported progress
+    Installing(InstallStatus),
+}
+
+/// A status message from installd, sent through the installation proxy while an app is installed
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct InstallStatus {
+    /// The phase installd is in, such as `CreatingStagingDirectory`, `VerifyingApplication` or `Complete`
+    pub phase: String,
+    pub percent: u64,
+    /// installd's error code, e.g. `ApplicationVerificationFailed`, if the install failed
+    pub error: Option<String>,
+    /// installd's own description of the error
+    pub error_description: Option<String>,
+    /// installd's numeric error detail, which distinguishes causes that share an error code
+    pub error_detail: Option<u64>,
+    /// The message as installd sent it
+    pub dictionary: Dictionary,
+}
+
+impl InstallStatus {
+    /// Parse a status message, returning `None` for messages that have neither a status nor an error
+    pub fn from_dictionary(dictionary: Dictionary) -> Option<Self> {
+        let string = |key: &str| {
+            dictionary
+                .get(key)
+                .and_then(|v| v.as_string())
+                .map(str::to_string)
+        };
+        let phase = string("Status");
+        let error = string("Error");
+        if phase.is_none() && error.is_none() {
+            return None;
+        }
+
+        let percent = if phase.as_deref() == Some("Complete") {
+            100
+        } else {
+            dictionary
+                .get("PercentComplete")
+                .and_then(|v| v.as_unsigned_integer())
+                .unwrap_or(0)
+        };
+        Some(Self {
+            phase: phase.unwrap_or_default(),
+            percent,
+            error_description: string("ErrorDescription"),
+            error_detail: dictionary
+                .get("ErrorDetail")
+                .and_then(|v| v.as_unsigned_integer()),
+            error,
+            dictionary,
+        })
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.phase == "Complete"
+    }
+}
+
+impl std::fmt::Display for InstallStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            Some(error) => {
+                write!(
+                    f,
+                    "{} ({})",
+                    error,
+                    self.error_description
+                        .as_deref()
+                        .unwrap_or("no description")
+                )?;
+                if let Some(detail) = self.error_detail {
+                    write!(f, " [detail {}]", detail)?;
+                }
+                Ok(())
+            }
+            None => write!(f, "{} ({}%)", self.phase, self.percent),
+        }
+    }
+}
+
+/// The installation proxy command used to install an app
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InstallMode {
+    /// Use `Upgrade` if an app with the same bundle ID is already installed, otherwise `Install`
+    #[default]
+    Auto,
+    /// Always use `Install`
+    Install,
+    /// Always use `Upgrade`, which keeps the existing app's data
+    Upgrade,
+}
+
+/// How uploaded files are checked before installd is asked to install the app
+///
+/// AFC has no way to hash a file on the device, so files are checked by their size and modification time,
+/// and the most corruption sensitive files can be read back and compared with the local copies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UploadVerification {
+    /// Don't check the uploaded files
+    #[default]
+    None,
+    /// Check that every file has the expected size and wasn't modified after it was uploaded
+    Size,
+    /// Like [`UploadVerification::Size`], and read back every `Info.plist`, the files in `_CodeSignature` and the
+    /// main executable to compare them with the local files
+    Critical,
+}
+
+/// Additional options for installing an app
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// The `iTunesMetadata.plist` to hand to installd alongside the app
+    pub itunes_metadata: Option<Dictionary>,
+    pub mode: InstallMode,
+    /// How many files to upload at once, each over its own AFC connection. `0` and `1` upload one file at a time.
+    ///
+    /// Apps made of many small files upload noticeably faster with a few connections.
+    pub upload_concurrency: usize,
+    pub verification: UploadVerification,
+}
+
+impl InstallOptions {
+    fn client_options(&self) -> Result<Dictionary, Report> {
+        let mut options = plist!(dict {
+            "PackageType": "Developer"
+        });
+
+        if let Some(metadata) = &self.itunes_metadata {
+            let mut buf = Vec::new();
+            plist::to_writer_binary(&mut buf, metadata)
+                .context("Failed to serialize iTunesMetadata")?;
+            options.insert("iTunesMetadata".to_string(), plist::Value::Data(buf));
+        }
+
+        Ok(options)
+    }
+}
 
 /// Installs an ***already signed*** app onto your device.
 /// To sign and install an app, see [`crate::sideload::sideload_app`]
@@ -17,80 +188,730 @@ pub async fn install_app(
     app_path: &Path,
     progress_callback: impl Fn(u64),
 ) -> Result<(), Report> {
-    let mut afc_client = AfcClient::connect(provider)
-        .await
-        .map_err(Error::IdeviceError)?;
+    install_app_with_options(provider, app_path, &InstallOptions::default(), |progress| {
+        if let InstallProgress::Installing(status) = progress {
+            progress_callback(status.percent);
+        }
+    })
+    .await
+}
 
+/// Installs an ***already signed*** app onto your device with the given [`InstallOptions`].
+///
+/// Unlike [`install_app`], the callback receives every uploaded file and installd's status phase,
+/// which is useful for showing what is happening during the long verification step of large apps.
+pub async fn install_app_with_options(
+    provider: &impl IdeviceProvider,
+    app_path: &Path,
+    install_options: &InstallOptions,
+    progress_callback: impl Fn(InstallProgress),
+) -> Result<(), Report> {
+    install_inner(
+        provider,
+        app_path,
+        install_options,
+        false,
+        &progress_callback,
+    )
+    .await
+}
+
+/// Creates fresh connections to a device, so an install can recover from a dropped connection
+///
+/// Closures returning a boxed provider implement this trait, e.g. one that looks the device up in usbmuxd again.
+#[async_trait::async_trait]
+pub trait ProviderFactory: Send + Sync {
+    async fn connect(&self) -> Result<Box<dyn IdeviceProvider>, Report>;
+}
+
+#[async_trait::async_trait]
+impl<F> ProviderFactory for F
+where
+    F: Fn() -> Result<Box<dyn IdeviceProvider>, Report> + Send + Sync,
+{
+    async fn connect(&self) -> Result<Box<dyn IdeviceProvider>, Report> {
+        self()
+    }
+}
+
+/// Installs an ***already signed*** app, re-acquiring the connection from `factory` if it drops
+///
+/// Up to `max_attempts` connections are made. When retrying, files that were already uploaded
+/// completely are skipped instead of being sent again.
+pub async fn install_app_with_reconnect(
+    factory: &dyn ProviderFactory,
+    app_path: &Path,
+    install_options: &InstallOptions,
+    max_attempts: u32,
+    progress_callback: impl Fn(InstallProgress),
+) -> Result<(), Report> {
+    let mut attempt = 1;
+    loop {
+        let provider = factory
+            .connect()
+            .await
+            .context("Failed to connect to device")?;
+        let result = install_inner(
+            provider.as_ref(),
+            app_path,
+            install_options,
+            attempt > 1,
+            &progress_callback,
+        )
+        .await;
+
+        match result {
+            Err(e) if attempt < max_attempts && is_connection_error(&e) => {
+                warn!(
+                    "Lost connection to device (attempt {}/{}), reconnecting: {}",
+                    attempt, max_attempts, e
+                );
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_connection_error(report: &Report) -> bool {
+    report.iter_reports().any(|node| {
+        matches!(
+            node.downcast_current_context::<Error>(),
+            Some(Error::IdeviceError(
+                IdeviceError::Socket(_) | IdeviceError::NoEstablishedConnection
+            ))
+        )
+    })
+}
+
+async fn install_inner(
+    provider: &dyn IdeviceProvider,
+    app_path: &Path,
+    install_options: &InstallOptions,
+    skip_existing: bool,
+    progress_callback: &impl Fn(InstallProgress),
+) -> Result<(), Report> {
     let dir = format!(
         "PublicStaging/{}",
         app_path.file_name().ok_or_report()?.to_string_lossy()
     );
-    afc_upload_dir(&mut afc_client, app_path, &dir).await?;
+    afc_upload_dir(
+        provider,
+        app_path,
+        &dir,
+        skip_existing,
+        install_options.upload_concurrency.max(1),
+        install_options.verification,
+        progress_callback,
+    )
+    .await?;
 
     let mut instproxy_client = InstallationProxyClient::connect(provider)
         .await
         .map_err(Error::IdeviceError)?;
 
-    let options = plist!(dict {
-        "PackageType": "Developer"
+    let options = install_options.client_options()?;
+
+    let upgrade = match install_options.mode {
+        InstallMode::Install => false,
+        InstallMode::Upgrade => true,
+        InstallMode::Auto => is_installed(&mut instproxy_client, app_path).await?,
+    };
+    if upgrade {
+        info!("App is already installed, upgrading to preserve its data");
+    }
+
+    let command_name = if upgrade { "Upgrade" } else { "Install" };
+
+    // InstallationProxyClient only exposes the percentage, so the command is sent by hand to see the status phases
+    let command = plist!(dict {
+        "Command": command_name,
+        "ClientOptions": options,
+        "PackagePath": dir,
     });
+    send_plist(&mut instproxy_client.idevice, &command).await?;
+
+    loop {
+        let response = read_plist(&mut instproxy_client.idevice).await?;
+        let Some(status) = InstallStatus::from_dictionary(response) else {
+            continue;
+        };
+
+        if status.error.is_some() {
+            bail!(Error::InstallFailed(status));
+        }
+        let complete = status.is_complete();
+        progress_callback(InstallProgress::Installing(status));
+        if complete {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check whether an app with the same bundle ID as the one at `app_path` is installed
+async fn is_installed(
+    instproxy_client: &mut InstallationProxyClient,
+    app_path: &Path,
+) -> Result<bool, Report> {
+    let info: Dictionary = plist::from_file(app_path.join("Info.plist"))
+        .context("Failed to read Info.plist of the app to install")?;
+    let Some(bundle_id) = info.get("CFBundleIdentifier").and_then(|v| v.as_string()) else {
+        return Ok(false);
+    };
+
+    let apps = instproxy_client
+        .get_apps(Some("User"), Some(vec![bundle_id.to_string()]))
+        .await
+        .map_err(Error::IdeviceError)?;
+    Ok(apps.contains_key(bundle_id))
+}
+
+async fn send_plist(idevice: &mut Idevice, message: &Dictionary) -> Result<(), Report> {
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, message)?;
+    idevice
+        .send_raw(&(buf.len() as u32).to_be_bytes())
+        .await
+        .map_err(Error::IdeviceError)?;
+    idevice.send_raw(&buf).await.map_err(Error::IdeviceError)?;
+    Ok(())
+}
+
+async fn read_plist(idevice: &mut Idevice) -> Result<Dictionary, Report> {
+    let len = idevice.read_raw(4).await.map_err(Error::IdeviceError)?;
+    let len = u32::from_be_bytes(len.try_into().map_err(|_| report!("Invalid length"))?);
+    let buf = idevice
+        .read_raw(len as usize)
+        .await
+        .map_err(Error::IdeviceError)?;
+    Ok(plist::from_bytes(&buf)?)
+}
+
+/// Size of the chunks files are streamed to the device in, matching the largest AFC write
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+/// How many times a file is sent before it is reported in [`UploadError`]
+const MAX_FILE_ATTEMPTS: u32 = 3;
+// far beyond any real app, these only stop symlink loops and similar from walking forever
+const MAX_UPLOAD_DEPTH: usize = 64;
+const MAX_UPLOAD_FILES: usize = 250_000;
+
+/// Files that couldn't be uploaded to the device, even after retrying each of them a few times
+///
+/// Uploads that fail this way have it as the context of their report, see [`UploadError::from_report`]. A lost
+/// connection fails the upload right away instead, so it can be retried with a new connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct UploadError {
+    pub failed: Vec<FailedUpload>,
+    /// How many files the app has, including the failed ones
+    pub files_total: usize,
+}
+
+/// A file that couldn't be uploaded, see [`UploadError`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct FailedUpload {
+    /// The path of the file, relative to the app bundle
+    pub path: String,
+    /// The error of the last attempt
+    pub error: String,
+}
+
+impl UploadError {
+    /// Find the [`UploadError`] in a failed install's report
+    pub fn from_report(report: &Report) -> Option<&Self> {
+        report
+            .iter_reports()
+            .find_map(|node| node.downcast_current_context::<UploadError>())
+    }
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to upload {} of {} files",
+            self.failed.len(),
+            self.files_total
+        )?;
+        for failed in &self.failed {
+            write!(f, "\n  {}: {}", failed.path, failed.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+/// A file waiting to be uploaded
+struct UploadEntry {
+    local_path: PathBuf,
+    remote_path: String,
+    relative_path: PathBuf,
+    size: u64,
+}
+
+/// An uploaded file to check, see [`UploadVerification`]
+struct VerifyEntry {
+    local_path: PathBuf,
+    remote_path: String,
+    size: u64,
+    /// Whether to read the file back from the device and compare it with the local file
+    read_back: bool,
+}
+
+/// Upload progress shared between the upload workers
+struct UploadProgress<'a, F: Fn(InstallProgress)> {
+    files_done: AtomicUsize,
+    files_total: usize,
+    bytes_done: AtomicU64,
+    bytes_total: u64,
+    callback: &'a F,
+}
+
+impl<F: Fn(InstallProgress)> UploadProgress<'_, F> {
+    fn report(&self, file: &Path, bytes: u64, finished: bool) {
+        let bytes_done = self.bytes_done.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let files_done = if finished {
+            self.files_done.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            self.files_done.load(Ordering::Relaxed)
+        };
+        (self.callback)(InstallProgress::Uploading {
+            file: file.to_string_lossy().to_string(),
+            files_done,
+            files_total: self.files_total,
+            bytes_done,
+            bytes_total: self.bytes_total,
+        });
+    }
+}
+
+async fn afc_upload_dir(
+    provider: &dyn IdeviceProvider,
+    path: &Path,
+    afc_path: &str,
+    skip_existing: bool,
+    concurrency: usize,
+    verification: UploadVerification,
+    progress_callback: &impl Fn(InstallProgress),
+) -> Result<(), Report> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    collect_upload_entries(path, afc_path, Path::new(""), 0, &mut dirs, &mut files)?;
+
+    let mut afc_client = AfcClient::connect(provider)
+        .await
+        .map_err(Error::IdeviceError)?;
+    for dir in &dirs {
+        afc_client.mk_dir(dir).await.map_err(Error::IdeviceError)?;
+    }
 
-    instproxy_client
-        .install_with_callback(
-            dir,
-            Some(plist::Value::Dictionary(options)),
-            async |(percentage, _)| {
-                progress_callback(percentage);
-            },
-            (),
+    let files_total = files.len();
+    let progress = UploadProgress {
+        files_done: AtomicUsize::new(0),
+        files_total,
+        bytes_done: AtomicU64::new(0),
+        bytes_total: files.iter().map(|f| f.size).sum(),
+        callback: progress_callback,
+    };
+    // largest first, so a big file doesn't end up alone at the end while the other connections sit idle
+    files.sort_by_key(|f| std::cmp::Reverse(f.size));
+    let workers = concurrency.min(files.len());
+    let critical = match verification {
+        UploadVerification::Critical => critical_files(path),
+        _ => Vec::new(),
+    };
+    let to_verify: Vec<_> = match verification {
+        UploadVerification::None => Vec::new(),
+        _ => files
+            .iter()
+            .map(|f| VerifyEntry {
+                local_path: f.local_path.clone(),
+                remote_path: f.remote_path.clone(),
+                size: f.size,
+                read_back: critical.contains(&f.relative_path),
+            })
+            .collect(),
+    };
+    let queue = Mutex::new(VecDeque::from(files));
+    // the device's file info right after each upload, to detect files that change afterwards
+    let uploaded = (verification != UploadVerification::None).then(|| Mutex::new(HashMap::new()));
+
+    let mut clients = vec![afc_client];
+    for _ in 1..workers {
+        clients.push(
+            AfcClient::connect(provider)
+                .await
+                .map_err(Error::IdeviceError)?,
+        );
+    }
+
+    let failed = Mutex::new(Vec::new());
+    let mut clients = try_join_all(clients.into_iter().map(|client| {
+        upload_worker(
+            client,
+            &queue,
+            skip_existing,
+            uploaded.as_ref(),
+            &failed,
+            &progress,
+        )
+    }))
+    .await?;
+
+    let mut failed = failed
+        .into_inner()
+        .map_err(|_| report!("Upload failure lock poisoned"))?;
+    if !failed.is_empty() {
+        failed.sort_by(|a: &FailedUpload, b| a.path.cmp(&b.path));
+        bail!(UploadError {
+            failed,
+            files_total,
+        });
+    }
+
+    if let Some(uploaded) = uploaded {
+        let uploaded = uploaded
+            .into_inner()
+            .map_err(|_| report!("Upload record lock poisoned"))?;
+        verify_uploads(&mut clients[0], &to_verify, &uploaded).await?;
+    }
+
+    Ok(())
+}
+
+/// Check the uploaded files against the local ones, see [`UploadVerification`]
+async fn verify_uploads(
+    afc_client: &mut AfcClient,
+    files: &[VerifyEntry],
+    uploaded: &HashMap<String, FileInfo>,
+) -> Result<(), Report> {
+    for VerifyEntry {
+        local_path,
+        remote_path,
+        size,
+        read_back,
+    } in files
+    {
+        let info = afc_client
+            .get_file_info(remote_path.as_str())
+            .await
+            .map_err(Error::IdeviceError)
+            .context(format!("Failed to verify {}", remote_path))?;
+        if info.size as u64 != *size {
+            bail!(
+                "Uploaded file {} has {} bytes instead of {}",
+                remote_path,
+                info.size,
+                size
+            );
+        }
+        if uploaded
+            .get(remote_path)
+            .is_some_and(|written| written.modified != info.modified)
+        {
+            bail!("Uploaded file {} was modified after upload", remote_path);
+        }
+
+        if *read_back {
+            let mut local = tokio::fs::File::open(local_path)
+                .await
+                .context(format!("Failed to open {}", local_path.display()))?;
+            let mut file_handle = afc_client
+                .open(
+                    remote_path.clone(),
+                    idevice::afc::opcode::AfcFopenMode::RdOnly,
+                )
+                .await
+                .map_err(Error::IdeviceError)?;
+            let matches = contents_match(&mut local, &mut file_handle, *size)
+                .await
+                .context(format!("Failed to read back {}", remote_path))?;
+            file_handle.close().await.map_err(Error::IdeviceError)?;
+            if !matches {
+                bail!(
+                    "Uploaded file {} does not match the local file",
+                    remote_path
+                );
+            }
+        }
+    }
+    info!("Verified {} uploaded files", files.len());
+
+    Ok(())
+}
+
+/// The files of the app most likely to break installation or launch if corrupted, relative to the app bundle
+fn critical_files(app_path: &Path) -> Vec<PathBuf> {
+    let mut critical = Vec::new();
+    if let Ok(info) = plist::from_file::<_, Dictionary>(app_path.join("Info.plist"))
+        && let Some(executable) = info.get("CFBundleExecutable").and_then(|v| v.as_string())
+    {
+        critical.push(PathBuf::from(executable));
+    }
+    collect_critical_files(app_path, Path::new(""), &mut critical);
+    critical
+}
+
+fn collect_critical_files(dir: &Path, relative_path: &Path, critical: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = entry.file_name();
+        let relative = relative_path.join(&name);
+        if path.is_dir() {
+            collect_critical_files(&path, &relative, critical);
+        } else if name == "Info.plist"
+            || relative
+                .components()
+                .any(|c| c.as_os_str() == "_CodeSignature")
+        {
+            critical.push(relative);
+        }
+    }
+}
+
+/// Upload files from the queue until it is empty, then hand the connection back
+///
+/// Files that fail are retried, and added to `failed` if they still fail. If `uploaded` is set, the device's file
+/// info for every file is recorded in it.
+async fn upload_worker(
+    mut afc_client: AfcClient,
+    queue: &Mutex<VecDeque<UploadEntry>>,
+    skip_existing: bool,
+    uploaded: Option<&Mutex<HashMap<String, FileInfo>>>,
+    failed: &Mutex<Vec<FailedUpload>>,
+    progress: &UploadProgress<'_, impl Fn(InstallProgress)>,
+) -> Result<AfcClient, Report> {
+    loop {
+        // the lock must be released before awaiting, since the other workers share the queue
+        let next = queue
+            .lock()
+            .map_err(|_| report!("Upload queue lock poisoned"))?
+            .pop_front();
+        let Some(entry) = next else {
+            return Ok(afc_client);
+        };
+
+        let mut attempt = 1;
+        loop {
+            let mut sent = 0;
+            let result = upload_file(
+                &mut afc_client,
+                &entry,
+                skip_existing,
+                uploaded,
+                progress,
+                &mut sent,
+            )
+            .await;
+            let Err(e) = result else {
+                break;
+            };
+            // the next attempt starts the file over
+            progress.bytes_done.fetch_sub(sent, Ordering::Relaxed);
+            if is_connection_error(&e) {
+                return Err(e
+                    .context(format!(
+                        "Failed to upload {}",
+                        entry.relative_path.display()
+                    ))
+                    .into_dynamic());
+            }
+            if attempt < MAX_FILE_ATTEMPTS {
+                warn!(
+                    "Failed to upload {} (attempt {}/{}), retrying: {}",
+                    entry.relative_path.display(),
+                    attempt,
+                    MAX_FILE_ATTEMPTS,
+                    e
+                );
+                attempt += 1;
+                continue;
+            }
+            failed
+                .lock()
+                .map_err(|_| report!("Upload failure lock poisoned"))?
+                .push(FailedUpload {
+                    path: entry.relative_path.to_string_lossy().to_string(),
+                    error: e.to_string(),
+                });
+            break;
+        }
+    }
+}
+
+/// Upload one file, adding the bytes reported as progress to `sent`
+async fn upload_file(
+    afc_client: &mut AfcClient,
+    entry: &UploadEntry,
+    skip_existing: bool,
+    uploaded: Option<&Mutex<HashMap<String, FileInfo>>>,
+    progress: &UploadProgress<'_, impl Fn(InstallProgress)>,
+    sent: &mut u64,
+) -> Result<(), Report> {
+    if skip_existing && is_uploaded(afc_client, &entry.local_path, &entry.remote_path).await {
+        record_upload(afc_client, &entry.remote_path, uploaded).await?;
+        progress.report(&entry.relative_path, entry.size, true);
+        return Ok(());
+    }
+
+    let mut local_file = tokio::fs::File::open(&entry.local_path)
+        .await
+        .context(format!("Failed to open {}", entry.local_path.display()))?;
+    let mut file_handle = afc_client
+        .open(
+            entry.remote_path.clone(),
+            idevice::afc::opcode::AfcFopenMode::WrOnly,
         )
         .await
         .map_err(Error::IdeviceError)?;
 
+    let written = write_file(&mut file_handle, &mut local_file, entry, progress, sent).await;
+    // closed even if writing failed, dropping the handle only closes it on a multi-threaded runtime and the retry
+    // would open the same file again
+    let closed = file_handle.close().await.map_err(Error::IdeviceError);
+    written?;
+    closed?;
+    record_upload(afc_client, &entry.remote_path, uploaded).await?;
+
+    progress.report(&entry.relative_path, 0, true);
     Ok(())
 }
 
-fn afc_upload_dir<'a>(
-    afc_client: &'a mut AfcClient,
-    path: &'a Path,
-    afc_path: &'a str,
-) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'a>> {
-    Box::pin(async move {
-        let entries = std::fs::read_dir(path)?;
-        afc_client
-            .mk_dir(afc_path)
+/// Copy the local file to the open remote file, adding the bytes reported as progress to `sent`
+async fn write_file(
+    file_handle: &mut FileDescriptor<'_>,
+    local_file: &mut tokio::fs::File,
+    entry: &UploadEntry,
+    progress: &UploadProgress<'_, impl Fn(InstallProgress)>,
+    sent: &mut u64,
+) -> Result<(), Report> {
+    let mut buf = vec![0u8; UPLOAD_CHUNK_SIZE.min(entry.size.max(1) as usize)];
+    loop {
+        let read = local_file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        file_handle
+            .write_entire(&buf[..read])
             .await
             .map_err(Error::IdeviceError)?;
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                let new_afc_path = format!(
-                    "{}/{}",
-                    afc_path,
-                    path.file_name().ok_or_report()?.to_string_lossy()
-                );
-                afc_upload_dir(afc_client, &path, &new_afc_path).await?;
-            } else {
-                let mut file_handle = afc_client
-                    .open(
-                        format!(
-                            "{}/{}",
-                            afc_path,
-                            path.file_name().ok_or_report()?.to_string_lossy()
-                        ),
-                        idevice::afc::opcode::AfcFopenMode::WrOnly,
-                    )
-                    .await
-                    .map_err(Error::IdeviceError)?;
-                let bytes = std::fs::read(&path)?;
-                file_handle
-                    .write_entire(&bytes)
-                    .await
-                    .map_err(Error::IdeviceError)?;
-                file_handle.close().await.map_err(Error::IdeviceError)?;
+        *sent += read as u64;
+        // only report progress within a file for large files, small ones finish in one chunk anyway
+        if entry.size > UPLOAD_CHUNK_SIZE as u64 {
+            progress.report(&entry.relative_path, read as u64, false);
+        } else {
+            progress
+                .bytes_done
+                .fetch_add(read as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Compare two files of `size` bytes chunk by chunk, so huge executables are never held in memory whole
+async fn contents_match(
+    local: &mut (impl AsyncRead + Unpin),
+    remote: &mut (impl AsyncRead + Unpin),
+    size: u64,
+) -> std::io::Result<bool> {
+    let chunk_size = UPLOAD_CHUNK_SIZE.min(size.max(1) as usize);
+    let mut local_buf = vec![0u8; chunk_size];
+    let mut remote_buf = vec![0u8; chunk_size];
+    let mut remaining = size;
+    while remaining > 0 {
+        let len = chunk_size.min(remaining as usize);
+        local.read_exact(&mut local_buf[..len]).await?;
+        remote.read_exact(&mut remote_buf[..len]).await?;
+        if local_buf[..len] != remote_buf[..len] {
+            return Ok(false);
+        }
+        remaining -= len as u64;
+    }
+    Ok(true)
+}
+
+async fn record_upload(
+    afc_client: &mut AfcClient,
+    remote_path: &str,
+    uploaded: Option<&Mutex<HashMap<String, FileInfo>>>,
+) -> Result<(), Report> {
+    let Some(uploaded) = uploaded else {
+        return Ok(());
+    };
+    let info = afc_client
+        .get_file_info(remote_path)
+        .await
+        .map_err(Error::IdeviceError)?;
+    uploaded
+        .lock()
+        .map_err(|_| report!("Upload record lock poisoned"))?
+        .insert(remote_path.to_string(), info);
+    Ok(())
+}
+
+async fn is_uploaded(afc_client: &mut AfcClient, local_path: &Path, remote_path: &str) -> bool {
+    let Ok(metadata) = std::fs::metadata(local_path) else {
+        return false;
+    };
+    afc_client
+        .get_file_info(remote_path)
+        .await
+        .is_ok_and(|info| info.size as u64 == metadata.len())
+}
+
+/// Walk the app bundle, collecting the directories to create (parents first) and the files to upload
+fn collect_upload_entries(
+    path: &Path,
+    afc_path: &str,
+    relative_path: &Path,
+    depth: usize,
+    dirs: &mut Vec<String>,
+    files: &mut Vec<UploadEntry>,
+) -> Result<(), Report> {
+    if depth > MAX_UPLOAD_DEPTH {
+        bail!(Error::InvalidBundle(format!(
+            "{} is nested more than {} directories deep, is there a symlink loop?",
+            relative_path.display(),
+            MAX_UPLOAD_DEPTH
+        )));
+    }
+    dirs.push(afc_path.to_string());
+    let entries = std::fs::read_dir(path).context(format!("Failed to read {}", path.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let file_name = path.file_name().ok_or_report()?;
+        let new_afc_path = format!("{}/{}", afc_path, file_name.to_string_lossy());
+        let new_relative_path = relative_path.join(file_name);
+        if path.is_dir() {
+            collect_upload_entries(
+                &path,
+                &new_afc_path,
+                &new_relative_path,
+                depth + 1,
+                dirs,
+                files,
+            )?;
+        } else {
+            if files.len() >= MAX_UPLOAD_FILES {
+                bail!(Error::InvalidBundle(format!(
+                    "The app has more than {} files",
+                    MAX_UPLOAD_FILES
+                )));
             }
+            files.push(UploadEntry {
+                size: entry.metadata()?.len(),
+                local_path: path,
+                remote_path: new_afc_path,
+                relative_path: new_relative_path,
+            });
         }
-        Ok(())
-    })
+    }
+    Ok(())
 }
@@ -0,0 +1,95 @@
+use plist::Date;
+use rootcause::prelude::*;
+use serde::{Deserialize, Serialize};
+use x509_certificate::CapturedX509Certificate;
+
+use crate::{sideload::profile::ProvisioningProfileInfo, util::storage::SideloadingStorage};
+
+/// An app [`crate::sideload::sideloader::Sideloader`] installed on a device, saved in [`SideloadingStorage`]
+/// so the app can be refreshed before its profile expires
+#[derive(Serialize, Deserialize)]
+pub(crate) struct InstallRecord {
+    pub team_id: String,
+    pub expiration_date: Date,
+    /// The common names of the certificates the profile allows, to recognize the app's `SignerIdentity`
+    pub signer_identities: Vec<String>,
+}
+
+impl InstallRecord {
+    /// Build a record from the profile the app was signed with, if it has a team and an expiration date
+    pub fn from_profile(profile: &ProvisioningProfileInfo) -> Option<Self> {
+        Some(Self {
+            team_id: profile.team_id.clone()?,
+            expiration_date: profile.expiration_date?.into(),
+            signer_identities: profile
+                .developer_certificates
+                .iter()
+                .filter_map(|der| CapturedX509Certificate::from_der(der.clone()).ok())
+                .filter_map(|cert| cert.subject_common_name())
+                .collect(),
+        })
+    }
+
+    /// Whether an installed app with the given `SignerIdentity` is still the one this record describes
+    ///
+    /// If the device didn't report a signer, the app is assumed to be unchanged.
+    pub fn signed_by(&self, signer_identity: Option<&str>) -> bool {
+        signer_identity.is_none_or(|signer| self.signer_identities.iter().any(|s| s == signer))
+    }
+
+    pub fn load(
+        storage: &dyn SideloadingStorage,
+        udid: &str,
+        bundle_id: &str,
+    ) -> Result<Option<Self>, Report> {
+        let Some(data) = storage.retrieve_data(&storage_key(udid, bundle_id))? else {
+            return Ok(None);
+        };
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(
+            plist::from_bytes(&data).context("Failed to parse install record")?,
+        ))
+    }
+
+    pub fn save(
+        &self,
+        storage: &dyn SideloadingStorage,
+        udid: &str,
+        bundle_id: &str,
+    ) -> Result<(), Report> {
+        let mut buf = Vec::new();
+        plist::to_writer_binary(&mut buf, self).context("Failed to serialize install record")?;
+        storage.store_data(&storage_key(udid, bundle_id), &buf)
+    }
+
+    pub fn delete(
+        storage: &dyn SideloadingStorage,
+        udid: &str,
+        bundle_id: &str,
+    ) -> Result<(), Report> {
+        storage.delete(&storage_key(udid, bundle_id))
+    }
+
+    /// The UDIDs of the devices the app with `bundle_id` was recorded as installed on
+    pub fn devices(
+        storage: &dyn SideloadingStorage,
+        bundle_id: &str,
+    ) -> Result<Vec<String>, Report> {
+        Ok(storage
+            .list(STORAGE_PREFIX)?
+            .into_iter()
+            .filter_map(|key| {
+                let (udid, id) = key.strip_prefix(STORAGE_PREFIX)?.split_once('/')?;
+                (id == bundle_id).then(|| udid.to_string())
+            })
+            .collect())
+    }
+}
+
+const STORAGE_PREFIX: &str = "installed/";
+
+fn storage_key(udid: &str, bundle_id: &str) -> String {
+    format!("{}{}/{}", STORAGE_PREFIX, udid, bundle_id)
+}
@@ -0,0 +1,93 @@
+use plist::Dictionary;
+use plist_macro::plist;
+use tracing::{info, warn};
+
+use crate::sideload::bundle::Bundle;
+
+/// Behavior for the `iTunesMetadata.plist` shipped alongside some IPAs
+///
+/// Some apps check for this file on first launch, and store-ripped IPAs often contain one that references
+/// the Apple ID of whoever originally downloaded the app.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ITunesMetadataBehavior {
+    /// Don't send any metadata to the device
+    #[default]
+    Ignore,
+    /// Forward the metadata from the IPA, unless it references a different Apple ID, in which case it is stripped
+    StripForeign,
+    /// Generate a minimal metadata file for the signing Apple ID, replacing any existing one
+    Generate,
+}
+
+impl ITunesMetadataBehavior {
+    /// Resolve the metadata to send to the device
+    ///
+    /// # Arguments
+    /// - `existing`: The metadata found in the IPA, if any
+    /// - `bundle`: The main app bundle, after bundle id rewriting
+    /// - `apple_id`: The Apple ID used to sign the app
+    pub fn resolve(
+        &self,
+        existing: Option<&Dictionary>,
+        bundle: &Bundle,
+        apple_id: &str,
+    ) -> Option<Dictionary> {
+        match self {
+            ITunesMetadataBehavior::Ignore => None,
+            ITunesMetadataBehavior::StripForeign => {
+                let existing = existing?;
+                match metadata_apple_id(existing) {
+                    Some(id) if !id.eq_ignore_ascii_case(apple_id) => {
+                        warn!("Stripping iTunesMetadata.plist that references another Apple ID");
+                        None
+                    }
+                    _ => Some(existing.clone()),
+                }
+            }
+            ITunesMetadataBehavior::Generate => {
+                info!("Generating iTunesMetadata.plist");
+                Some(generate_metadata(bundle, apple_id))
+            }
+        }
+    }
+}
+
+/// Generate a minimal `iTunesMetadata.plist` for the bundle, attributed to the given Apple ID
+pub fn generate_metadata(bundle: &Bundle, apple_id: &str) -> Dictionary {
+    let name = bundle.bundle_name().unwrap_or("");
+    let string_value = |key: &str| {
+        bundle
+            .app_info
+            .get(key)
+            .and_then(|v| v.as_string())
+            .unwrap_or("")
+            .to_string()
+    };
+
+    plist!(dict {
+        "appleId": apple_id,
+        "artistName": name,
+        "bundleShortVersionString": string_value("CFBundleShortVersionString"),
+        "bundleVersion": string_value("CFBundleVersion"),
+        "com.apple.iTunesStore.downloadInfo": {
+            "accountInfo": {
+                "AppleID": apple_id,
+            },
+        },
+        "itemId": 0,
+        "itemName": name,
+        "kind": "software",
+        "softwareVersionBundleId": bundle.bundle_identifier().unwrap_or(""),
+    })
+}
+
+fn metadata_apple_id(metadata: &Dictionary) -> Option<&str> {
+    metadata
+        .get("com.apple.iTunesStore.downloadInfo")
+        .and_then(|v| v.as_dictionary())
+        .and_then(|d| d.get("accountInfo"))
+        .and_then(|v| v.as_dictionary())
+        .and_then(|d| d.get("AppleID"))
+        .or_else(|| metadata.get("appleId"))
+        .and_then(|v| v.as_string())
+}
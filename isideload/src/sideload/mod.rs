@@ -1,9 +1,55 @@
+#[cfg(feature = "sign")]
+pub mod adhoc;
+#[cfg(feature = "sign")]
 pub mod application;
+#[cfg(feature = "sign")]
 pub mod builder;
 pub mod bundle;
+#[cfg(feature = "sign")]
 pub mod cert_identity;
+#[cfg(feature = "sign")]
+pub(crate) mod code_signature;
+#[cfg(feature = "sign")]
+pub mod customization;
+#[cfg(feature = "sign")]
+pub mod entitlements;
+#[cfg(feature = "sign")]
+pub mod events;
+pub mod extensions;
+#[cfg(feature = "sign")]
+pub mod imported;
+#[cfg(feature = "sign")]
+pub mod inject;
 #[cfg(feature = "install")]
 pub mod install;
+#[cfg(all(feature = "sign", feature = "install"))]
+pub(crate) mod install_record;
+pub mod itunes_metadata;
+#[cfg(feature = "sign")]
+pub mod package;
+#[cfg(feature = "sign")]
+pub mod pass;
+#[cfg(feature = "sign")]
+pub mod platform;
+#[cfg(feature = "sign")]
+pub mod preflight;
+pub mod profile;
+#[cfg(feature = "sign")]
+pub mod profile_cache;
+#[cfg(feature = "sign")]
+pub mod rollback;
+pub mod sanitize;
+#[cfg(feature = "sign")]
 pub mod sideloader;
+#[cfg(feature = "sign")]
 pub mod sign;
+#[cfg(feature = "sign")]
+pub mod signing_report;
+#[cfg(feature = "sign")]
+pub mod thinning;
+#[cfg(feature = "sign")]
+pub mod validation;
+#[cfg(feature = "sign")]
+pub mod wwdr;
+#[cfg(feature = "sign")]
 pub use builder::{SideloaderBuilder, TeamSelection};
@@ -0,0 +1,123 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use plist::Dictionary;
+use rootcause::prelude::*;
+use tracing::info;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+/// Package a signed app bundle into an IPA at `output_path`
+///
+/// # Arguments
+/// - `bundle_dir`: The signed `.app` directory, stored as `Payload/<name>.app`
+/// - `output_path`: Where to write the IPA, replacing any existing file
+/// - `symbols_dir`: A `Symbols` directory to store next to `Payload`, if any
+/// - `itunes_metadata`: An `iTunesMetadata.plist` to store at the root of the IPA, if any
+pub fn write_ipa(
+    bundle_dir: &Path,
+    output_path: &Path,
+    symbols_dir: Option<&Path>,
+    itunes_metadata: Option<&Dictionary>,
+) -> Result<(), Report> {
+    let bundle_name = bundle_dir
+        .file_name()
+        .ok_or_else(|| report!("Invalid app bundle path: {}", bundle_dir.display()))?
+        .to_string_lossy()
+        .to_string();
+
+    let file = File::create(output_path).context("Failed to create IPA file")?;
+    let mut zip = ZipWriter::new(file);
+
+    add_dir_entry(&mut zip, "Payload")?;
+    add_dir_recursive(&mut zip, bundle_dir, &format!("Payload/{}", bundle_name))?;
+
+    if let Some(symbols_dir) = symbols_dir
+        && symbols_dir.is_dir()
+    {
+        add_dir_recursive(&mut zip, symbols_dir, "Symbols")?;
+    }
+
+    if let Some(metadata) = itunes_metadata {
+        let mut data = Vec::new();
+        plist::to_writer_xml(&mut data, metadata)
+            .context("Failed to serialize iTunesMetadata.plist")?;
+        zip.start_file(
+            "iTunesMetadata.plist",
+            file_options(0o644, data.len() as u64),
+        )
+        .context("Failed to add iTunesMetadata.plist to IPA")?;
+        zip.write_all(&data)
+            .context("Failed to add iTunesMetadata.plist to IPA")?;
+    }
+
+    zip.finish().context("Failed to finish IPA")?;
+    info!("Wrote IPA to {}", output_path.display());
+    Ok(())
+}
+
+fn add_dir_recursive(
+    zip: &mut ZipWriter<File>,
+    dir: &Path,
+    archive_path: &str,
+) -> Result<(), Report> {
+    add_dir_entry(zip, archive_path)?;
+
+    let mut entries = std::fs::read_dir(dir)
+        .context(format!("Failed to read directory {}", dir.display()))?
+        .collect::<Result<Vec<_>, _>>()?;
+    // sorted so the same bundle always produces the same archive
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = format!("{}/{}", archive_path, entry.file_name().to_string_lossy());
+        let metadata = std::fs::symlink_metadata(&path)?;
+
+        if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(&path)?;
+            zip.add_symlink(name, target.to_string_lossy(), SimpleFileOptions::default())
+                .context(format!("Failed to add symlink {}", path.display()))?;
+        } else if metadata.is_dir() {
+            add_dir_recursive(zip, &path, &name)?;
+        } else {
+            zip.start_file(name, file_options(permissions(&metadata), metadata.len()))
+                .context(format!("Failed to add {} to IPA", path.display()))?;
+            let mut file = File::open(&path)?;
+            io::copy(&mut file, zip).context(format!("Failed to add {} to IPA", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn add_dir_entry(zip: &mut ZipWriter<File>, archive_path: &str) -> Result<(), Report> {
+    zip.add_directory(
+        format!("{}/", archive_path),
+        SimpleFileOptions::default().unix_permissions(0o755),
+    )
+    .context(format!("Failed to add directory {} to IPA", archive_path))?;
+    Ok(())
+}
+
+fn file_options(mode: u32, size: u64) -> SimpleFileOptions {
+    SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(mode)
+        .large_file(size >= u32::MAX as u64)
+}
+
+/// Keep the executable bit, since installd refuses apps whose main executable isn't executable
+#[cfg(unix)]
+fn permissions(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+// permissions can't be read on windows, so mark everything executable to be safe
+#[cfg(not(unix))]
+fn permissions(_metadata: &std::fs::Metadata) -> u32 {
+    0o755
+}
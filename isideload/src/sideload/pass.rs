@@ -0,0 +1,256 @@
+use plist::Dictionary;
+use rootcause::prelude::*;
+use tracing::debug;
+
+use crate::{
+    sideload::{
+        application::{Application, SpecialApp},
+        bundle::Bundle,
+        cert_identity::CertificateIdentity,
+        sanitize::{SupportedDevicesBehavior, sanitize_supported_devices},
+    },
+    util::plist::diff as plist_diff,
+};
+
+/// What a [`BundlePass`] can read and change
+///
+/// Passes run after the bundle identifiers are rewritten and the provisioning profile is acquired, and before the
+/// app is signed, so anything they change is sealed into the signature.
+pub struct PassContext<'a> {
+    pub app: &'a mut Application,
+    pub special: &'a Option<SpecialApp>,
+    pub team_id: &'a str,
+    /// The app group every app ID was assigned to
+    pub group_identifier: &'a str,
+    pub certificate: &'a CertificateIdentity,
+    pub provisioning_profile: &'a [u8],
+    /// The main app's `Info.plist` before it was changed for sideloading
+    pub original_info: &'a Dictionary,
+    /// The hardware model of the target device, e.g. `iPhone15,2`, if signing for a device
+    pub product_type: Option<&'a str>,
+    pub(crate) pairing_file: Option<&'a [u8]>,
+    pub(crate) supported_devices_behavior: &'a SupportedDevicesBehavior,
+}
+
+/// One step of preparing an app bundle for signing
+///
+/// Implement this to change apps before they are signed, e.g. to inject a config file or strip an SDK, and add it
+/// with [`crate::sideload::SideloaderBuilder::bundle_pass`].
+#[async_trait::async_trait]
+pub trait BundlePass: Send + Sync {
+    /// A short name, used to attribute errors and to position other passes
+    fn name(&self) -> &str;
+
+    async fn run(&self, context: &mut PassContext<'_>) -> Result<(), Report>;
+}
+
+/// The ordered passes run on every app before it is signed
+///
+/// The default pipeline runs [`SpecialAppPass`], [`SupportedDevicesPass`], [`WriteInfoPass`] and
+/// [`EmbedProfilePass`], in that order. Passes that change `Info.plist` in memory need to run before
+/// [`WriteInfoPass`].
+pub struct BundlePipeline {
+    passes: Vec<Box<dyn BundlePass>>,
+}
+
+impl Default for BundlePipeline {
+    fn default() -> Self {
+        BundlePipeline {
+            passes: vec![
+                Box::new(SpecialAppPass),
+                Box::new(SupportedDevicesPass),
+                Box::new(WriteInfoPass),
+                Box::new(EmbedProfilePass),
+            ],
+        }
+    }
+}
+
+impl BundlePipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A pipeline without the built-in passes
+    ///
+    /// Apps signed without [`WriteInfoPass`] and [`EmbedProfilePass`] won't install, so only use this to rebuild
+    /// the pipeline from scratch.
+    pub fn empty() -> Self {
+        BundlePipeline { passes: Vec::new() }
+    }
+
+    /// Add a pass to the end of the pipeline
+    pub fn push(&mut self, pass: impl BundlePass + 'static) {
+        self.passes.push(Box::new(pass));
+    }
+
+    /// Add a pass right before the pass named `before`, or to the end if there is no such pass
+    pub fn insert_before(&mut self, before: &str, pass: impl BundlePass + 'static) {
+        let index = self
+            .passes
+            .iter()
+            .position(|p| p.name() == before)
+            .unwrap_or(self.passes.len());
+        self.passes.insert(index, Box::new(pass));
+    }
+
+    /// Remove the pass named `name`, returning whether it was in the pipeline
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.passes.len();
+        self.passes.retain(|p| p.name() != name);
+        self.passes.len() != len
+    }
+
+    /// The names of the passes, in the order they run
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    pub(crate) async fn run(&self, context: &mut PassContext<'_>) -> Result<(), Report> {
+        for pass in &self.passes {
+            debug!("Running bundle pass {}", pass.name());
+            pass.run(context)
+                .await
+                .context(format!("Bundle pass {} failed", pass.name()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Callback that changes a bundle before it is signed, see [`BundleHookPass`]
+pub type BundleHook = Box<dyn Fn(&mut Bundle) -> Result<(), Report> + Send + Sync>;
+
+/// Calls a hook with the main app and every nested bundle, to change their `Info.plist` or files
+///
+/// Runs after the bundle identifiers are rewritten, so hooks see the final identifiers. Changes to
+/// [`Bundle::app_info`] are written by [`WriteInfoPass`].
+pub struct BundleHookPass {
+    hook: BundleHook,
+}
+
+impl BundleHookPass {
+    pub const NAME: &str = "bundle-hook";
+
+    pub fn new(hook: impl Fn(&mut Bundle) -> Result<(), Report> + Send + Sync + 'static) -> Self {
+        Self {
+            hook: Box::new(hook),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BundlePass for BundleHookPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn run(&self, context: &mut PassContext<'_>) -> Result<(), Report> {
+        context.app.bundle.visit_bundles_mut(&mut |bundle| {
+            (self.hook)(bundle).context(format!(
+                "Bundle hook failed for {}",
+                bundle.bundle_dir.display()
+            ))?;
+            Ok::<_, Report>(())
+        })
+    }
+}
+
+/// Applies the changes special apps like SideStore and LiveContainer need, see [`SpecialApp`]
+pub struct SpecialAppPass;
+
+impl SpecialAppPass {
+    pub const NAME: &str = "special-app";
+}
+
+#[async_trait::async_trait]
+impl BundlePass for SpecialAppPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn run(&self, context: &mut PassContext<'_>) -> Result<(), Report> {
+        context
+            .app
+            .apply_special_app_behavior(
+                context.special,
+                context.group_identifier,
+                context.certificate,
+                context.pairing_file,
+            )
+            .await
+            .context("Failed to modify app bundle")?;
+        Ok(())
+    }
+}
+
+/// Applies the sideloader's [`SupportedDevicesBehavior`]
+pub struct SupportedDevicesPass;
+
+impl SupportedDevicesPass {
+    pub const NAME: &str = "supported-devices";
+}
+
+#[async_trait::async_trait]
+impl BundlePass for SupportedDevicesPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn run(&self, context: &mut PassContext<'_>) -> Result<(), Report> {
+        sanitize_supported_devices(
+            &mut context.app.bundle,
+            context.supported_devices_behavior,
+            context.product_type,
+        );
+        Ok(())
+    }
+}
+
+/// Writes the in-memory `Info.plist` of every bundle back to disk
+pub struct WriteInfoPass;
+
+impl WriteInfoPass {
+    pub const NAME: &str = "write-info";
+}
+
+#[async_trait::async_trait]
+impl BundlePass for WriteInfoPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn run(&self, context: &mut PassContext<'_>) -> Result<(), Report> {
+        for change in plist_diff(context.original_info, &context.app.bundle.app_info) {
+            debug!("Info.plist: {}", change);
+        }
+        context.app.bundle.write_info_recursive()
+    }
+}
+
+/// Writes the provisioning profile to the main app's `embedded.mobileprovision`
+pub struct EmbedProfilePass;
+
+impl EmbedProfilePass {
+    pub const NAME: &str = "embed-profile";
+}
+
+#[async_trait::async_trait]
+impl BundlePass for EmbedProfilePass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn run(&self, context: &mut PassContext<'_>) -> Result<(), Report> {
+        tokio::fs::write(
+            context
+                .app
+                .bundle
+                .bundle_dir
+                .join("embedded.mobileprovision"),
+            context.provisioning_profile,
+        )
+        .await
+        .context("Failed to write embedded.mobileprovision")?;
+        Ok(())
+    }
+}
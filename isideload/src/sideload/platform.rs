@@ -0,0 +1,97 @@
+use std::fmt::Display;
+
+use plist::Value;
+use rootcause::prelude::*;
+
+use crate::{SideloadError, dev::device_type::DeveloperDeviceType, sideload::bundle::Bundle};
+
+const DEVICE_FAMILY_KEY: &str = "UIDeviceFamily";
+
+/// The platform apps are sideloaded to
+///
+/// The platform decides which developer services endpoints the device and app IDs are registered through, and which
+/// `UIDeviceFamily` values the app must declare to be accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TargetPlatform {
+    /// iPhones, iPads and iPods
+    #[default]
+    Ios,
+    /// Apple TVs
+    Tvos,
+}
+
+impl TargetPlatform {
+    /// The device type to use with the developer services for this platform
+    pub fn device_type(&self) -> DeveloperDeviceType {
+        match self {
+            TargetPlatform::Ios => DeveloperDeviceType::Ios,
+            TargetPlatform::Tvos => DeveloperDeviceType::Tvos,
+        }
+    }
+
+    /// The `UIDeviceFamily` values of this platform, an app needs at least one of them
+    pub fn device_families(&self) -> &'static [u64] {
+        match self {
+            // iPhone and iPad
+            TargetPlatform::Ios => &[1, 2],
+            TargetPlatform::Tvos => &[3],
+        }
+    }
+
+    /// The platform of a device from its hardware model (e.g. `iPhone15,2` or `AppleTV11,1`), `None` for devices
+    /// apps can't be sideloaded to
+    pub fn from_product_type(product_type: &str) -> Option<Self> {
+        match DeveloperDeviceType::from_product_type(product_type) {
+            DeveloperDeviceType::Ios => Some(TargetPlatform::Ios),
+            DeveloperDeviceType::Tvos => Some(TargetPlatform::Tvos),
+            _ => None,
+        }
+    }
+
+    /// Check that the app declares a device family of this platform in its `UIDeviceFamily`
+    ///
+    /// Apps without the key are treated as iPhone apps, like installd does.
+    pub fn check_bundle(&self, bundle: &Bundle) -> Result<(), Report> {
+        let families = device_families(bundle);
+        if !families
+            .iter()
+            .any(|family| self.device_families().contains(family))
+        {
+            bail!(SideloadError::InvalidBundle(format!(
+                "{} doesn't support {} (UIDeviceFamily is {:?})",
+                bundle.bundle_name().unwrap_or("The app"),
+                self,
+                families
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Display for TargetPlatform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetPlatform::Ios => write!(f, "iOS"),
+            TargetPlatform::Tvos => write!(f, "tvOS"),
+        }
+    }
+}
+
+/// The `UIDeviceFamily` of a bundle, which can be an array or a single value, and numbers or strings
+fn device_families(bundle: &Bundle) -> Vec<u64> {
+    let family = |value: &Value| {
+        value
+            .as_unsigned_integer()
+            .or_else(|| value.as_string().and_then(|s| s.parse().ok()))
+    };
+    match bundle.app_info.get(DEVICE_FAMILY_KEY) {
+        Some(Value::Array(values)) => values.iter().filter_map(family).collect(),
+        Some(value) => family(value).into_iter().collect(),
+        None => vec![1],
+    }
+}
@@ -0,0 +1,164 @@
+use std::{fmt::Display, path::Path};
+
+use crate::dev::teams::DeveloperTeam;
+
+/// How much a [`PreflightIssue`] gets in the way of sideloading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum PreflightSeverity {
+    /// Sideloading will work, but the user may want to know first, e.g. because a device will be registered
+    Warning,
+    /// Sideloading will fail
+    Error,
+}
+
+/// A problem found by [`crate::sideload::sideloader::Sideloader::preflight`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(tag = "issue", content = "details", rename_all = "snake_case")
+)]
+pub enum PreflightIssue {
+    /// The app couldn't be read, or is missing required parts like a bundle identifier
+    InvalidBundle(String),
+    /// The team doesn't have enough app IDs left for the app and its extensions
+    NotEnoughAppIds { required: usize, available: i64 },
+    /// No certificate for this machine was found, so a new one will be requested
+    ///
+    /// This can revoke one of the `existing` certificates, depending on the
+    /// [`crate::sideload::builder::MaxCertsBehavior`].
+    CertificateWillBeRequested { existing: usize },
+    /// The device isn't registered to the team yet, so it will be registered
+    DeviceNotRegistered { udid: String },
+    /// The device is registered but disabled, and can't be provisioned until the team's device list is reset
+    DeviceDisabled { udid: String },
+    /// The device doesn't have enough free space for the app to be uploaded
+    InsufficientSpace { required: u64, available: u64 },
+    /// The account has no developer team yet, so the team checks were skipped
+    ///
+    /// Sideloading tries to set up a personal team first. If that fails, the user has to sign in at `url` and accept
+    /// the Apple Developer Agreement.
+    NeedsTeamBootstrap { url: String },
+}
+
+impl PreflightIssue {
+    pub fn severity(&self) -> PreflightSeverity {
+        match self {
+            PreflightIssue::CertificateWillBeRequested { .. }
+            | PreflightIssue::DeviceNotRegistered { .. }
+            | PreflightIssue::NeedsTeamBootstrap { .. } => PreflightSeverity::Warning,
+            PreflightIssue::InvalidBundle(_)
+            | PreflightIssue::NotEnoughAppIds { .. }
+            | PreflightIssue::DeviceDisabled { .. }
+            | PreflightIssue::InsufficientSpace { .. } => PreflightSeverity::Error,
+        }
+    }
+}
+
+impl Display for PreflightIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreflightIssue::InvalidBundle(reason) => write!(f, "Invalid app: {}", reason),
+            PreflightIssue::NotEnoughAppIds {
+                required,
+                available,
+            } => write!(
+                f,
+                "{} new app IDs are required, but only {} are available",
+                required, available
+            ),
+            PreflightIssue::CertificateWillBeRequested { existing } => write!(
+                f,
+                "A new certificate will be requested, the team has {} already",
+                existing
+            ),
+            PreflightIssue::DeviceNotRegistered { udid } => {
+                write!(f, "Device {} will be registered to the team", udid)
+            }
+            PreflightIssue::DeviceDisabled { udid } => {
+                write!(f, "Device {} is disabled for the team", udid)
+            }
+            PreflightIssue::InsufficientSpace {
+                required,
+                available,
+            } => write!(
+                f,
+                "The app needs {} bytes, but the device only has {} bytes free",
+                required, available
+            ),
+            PreflightIssue::NeedsTeamBootstrap { url } => write!(
+                f,
+                "The account has no developer team yet, a personal team will be set up (or accept the agreement at {})",
+                url
+            ),
+        }
+    }
+}
+
+/// What [`crate::sideload::sideloader::Sideloader::preflight`] found out about sideloading an app, without changing
+/// anything on the team or the device
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct PreflightReport {
+    /// The team the app would be signed for
+    pub team: Option<DeveloperTeam>,
+    /// The bundle identifier of the app before it is changed for the team
+    pub bundle_id: Option<String>,
+    /// How many app IDs the app and its kept extensions need in total
+    pub app_ids: usize,
+    /// How many of those aren't registered yet
+    pub new_app_ids: usize,
+    /// How many more app IDs the team can register, if the developer services report it
+    pub available_app_ids: Option<i64>,
+    /// Whether a certificate for this machine that can be reused was found
+    pub has_certificate: bool,
+    /// Whether the device is registered to the team, if a device was checked
+    pub device_registered: Option<bool>,
+    /// The size of the app's files, roughly how much is uploaded to the device
+    pub upload_size: u64,
+    /// The free space on the device, if a device was checked
+    pub device_free_space: Option<u64>,
+    pub issues: Vec<PreflightIssue>,
+}
+
+impl PreflightReport {
+    /// Whether sideloading is expected to succeed, i.e. no issue is an error
+    pub fn can_proceed(&self) -> bool {
+        !self
+            .issues
+            .iter()
+            .any(|issue| issue.severity() == PreflightSeverity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &PreflightIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity() == PreflightSeverity::Warning)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &PreflightIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity() == PreflightSeverity::Error)
+    }
+}
+
+/// The total size of the files under `path`
+pub(crate) fn directory_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += directory_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
@@ -0,0 +1,253 @@
+use std::{
+    fmt::Display,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use plist::{Dictionary, Value};
+use rootcause::{option_ext::OptionExt, prelude::*};
+
+use crate::util::{clock::Clock, plist::PlistDataExtract};
+
+/// The parts of a provisioning profile relevant to sideloading
+#[derive(Debug, Clone)]
+pub struct ProvisioningProfileInfo {
+    pub name: Option<String>,
+    /// The profile's UUID, which identifies it when installed on a device
+    pub uuid: Option<String>,
+    pub team_id: Option<String>,
+    pub team_name: Option<String>,
+    pub expiration_date: Option<SystemTime>,
+    /// The UDIDs this profile is valid for. Empty if `provisions_all_devices` is set.
+    pub provisioned_devices: Vec<String>,
+    pub provisions_all_devices: bool,
+    pub entitlements: Dictionary,
+    /// DER encoded certificates allowed to sign apps with this profile
+    pub developer_certificates: Vec<Vec<u8>>,
+}
+
+impl ProvisioningProfileInfo {
+    /// Parse a provisioning profile from its raw (CMS signed) bytes
+    pub fn parse(data: &[u8]) -> Result<Self, Report> {
+        let profile = profile_plist(data)?;
+
+        let string = |key: &str| {
+            profile
+                .get(key)
+                .and_then(|v| v.as_string())
+                .map(String::from)
+        };
+
+        Ok(Self {
+            name: string("Name"),
+            uuid: string("UUID"),
+            team_id: profile
+                .get("TeamIdentifier")
+                .and_then(|v| v.as_array())
+                .and_then(|a| a.first())
+                .and_then(|v| v.as_string())
+                .map(String::from),
+            team_name: string("TeamName"),
+            expiration_date: profile
+                .get("ExpirationDate")
+                .and_then(|v| v.as_date())
+                .map(SystemTime::from),
+            provisioned_devices: profile
+                .get("ProvisionedDevices")
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_string().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            provisions_all_devices: profile
+                .get("ProvisionsAllDevices")
+                .and_then(|v| v.as_boolean())
+                .unwrap_or(false),
+            entitlements: profile.get_dict("Entitlements")?.clone(),
+            developer_certificates: profile
+                .get("DeveloperCertificates")
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|v| v.as_data().map(|d| d.to_vec()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    /// The bundle identifier the profile is for, without the team prefix, e.g. `com.example.app` or `*`
+    pub fn bundle_identifier(&self) -> Option<&str> {
+        let identifier = self
+            .entitlements
+            .get("application-identifier")
+            .and_then(|v| v.as_string())?;
+        Some(
+            identifier
+                .split_once('.')
+                .map_or(identifier, |(_, bundle_id)| bundle_id),
+        )
+    }
+
+    /// Read and parse the `embedded.mobileprovision` of an app bundle, if it has one
+    pub fn from_bundle_dir(bundle_dir: &Path) -> Result<Option<Self>, Report> {
+        let path = bundle_dir.join("embedded.mobileprovision");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path).context("Failed to read embedded.mobileprovision")?;
+        Ok(Some(
+            Self::parse(&data).context("Failed to parse embedded.mobileprovision")?,
+        ))
+    }
+
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        self.expires_within(clock, Duration::ZERO)
+    }
+
+    /// Whether the profile expires within the given duration from now
+    pub fn expires_within(&self, clock: &dyn Clock, duration: Duration) -> bool {
+        self.expiration_date
+            .is_some_and(|expiration| clock.expires_within(expiration, duration))
+    }
+
+    /// Whether the certificate (DER encoded) is allowed to sign apps with this profile
+    pub fn allows_certificate(&self, certificate_der: &[u8]) -> bool {
+        self.developer_certificates
+            .iter()
+            .any(|c| c.as_slice() == certificate_der)
+    }
+
+    pub fn covers_device(&self, udid: &str) -> bool {
+        self.provisions_all_devices
+            || self
+                .provisioned_devices
+                .iter()
+                .any(|d| d.eq_ignore_ascii_case(udid))
+    }
+
+    /// Check whether an app signed by `team_id` with the DER encoded `certificate_der` may use this profile
+    ///
+    /// Returns [`ProfileDiagnosis::SignerNotAllowed`] if the team differs or the profile doesn't allow the
+    /// certificate, and [`ProfileDiagnosis::Valid`] otherwise.
+    pub fn diagnose_signer(
+        &self,
+        team_id: Option<&str>,
+        certificate_der: &[u8],
+    ) -> ProfileDiagnosis {
+        if team_id.is_some()
+            && team_id == self.team_id.as_deref()
+            && self.allows_certificate(certificate_der)
+        {
+            ProfileDiagnosis::Valid
+        } else {
+            ProfileDiagnosis::SignerNotAllowed {
+                team_id: self.team_id.clone(),
+                team_name: self.team_name.clone(),
+            }
+        }
+    }
+
+    /// Check whether this profile allows the app to be installed on the given device
+    pub fn diagnose(&self, udid: &str, clock: &dyn Clock) -> ProfileDiagnosis {
+        if self.is_expired(clock) {
+            ProfileDiagnosis::Expired {
+                team_name: self.team_name.clone(),
+            }
+        } else if !self.covers_device(udid) {
+            ProfileDiagnosis::DeviceNotProvisioned {
+                team_id: self.team_id.clone(),
+                team_name: self.team_name.clone(),
+            }
+        } else {
+            ProfileDiagnosis::Valid
+        }
+    }
+}
+
+/// The result of checking a prebuilt app's embedded provisioning profile against a device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileDiagnosis {
+    /// The profile covers the device and has not expired
+    Valid,
+    /// The app has no embedded provisioning profile
+    MissingProfile,
+    /// The profile has expired
+    Expired { team_name: Option<String> },
+    /// The profile was made for other devices, usually because it was signed by someone else
+    DeviceNotProvisioned {
+        team_id: Option<String>,
+        team_name: Option<String>,
+    },
+    /// The app isn't signed with a certificate of the profile's team that the profile allows, so the device would
+    /// reject it
+    SignerNotAllowed {
+        team_id: Option<String>,
+        team_name: Option<String>,
+    },
+}
+
+impl ProfileDiagnosis {
+    /// Whether the app must be re-signed before it can be installed
+    pub fn needs_resign(&self) -> bool {
+        !matches!(self, ProfileDiagnosis::Valid)
+    }
+}
+
+impl Display for ProfileDiagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileDiagnosis::Valid => write!(f, "the app is signed for this device"),
+            ProfileDiagnosis::MissingProfile => {
+                write!(f, "the app has no embedded provisioning profile")
+            }
+            ProfileDiagnosis::Expired { team_name } => write!(
+                f,
+                "the app's provisioning profile{} has expired",
+                team_name
+                    .as_ref()
+                    .map(|t| format!(" (team {})", t))
+                    .unwrap_or_default()
+            ),
+            ProfileDiagnosis::DeviceNotProvisioned { team_id, team_name } => write!(
+                f,
+                "the app was signed for another device by team {}",
+                team_name
+                    .as_deref()
+                    .or(team_id.as_deref())
+                    .unwrap_or("<unknown>")
+            ),
+            ProfileDiagnosis::SignerNotAllowed { team_id, team_name } => write!(
+                f,
+                "the app isn't signed with a certificate its provisioning profile from team {} allows",
+                team_name
+                    .as_deref()
+                    .or(team_id.as_deref())
+                    .unwrap_or("<unknown>")
+            ),
+        }
+    }
+}
+
+/// Extract the plist embedded in a CMS signed provisioning profile
+pub fn profile_plist(data: &[u8]) -> Result<Dictionary, Report> {
+    let start = data
+        .windows(6)
+        .position(|w| w == b"<plist")
+        .ok_or_report()
+        .context("Provisioning profile does not contain a plist")?;
+    let end = data
+        .windows(8)
+        .rposition(|w| w == b"</plist>")
+        .ok_or_report()
+        .context("Provisioning profile does not contain a plist")?
+        + 8;
+    let plist = Value::from_reader_xml(&data[start..end])?;
+
+    Ok(plist
+        .into_dictionary()
+        .ok_or_report()
+        .context("Provisioning profile plist is not a dictionary")?)
+}
@@ -0,0 +1,50 @@
+use plist::{Data, Date};
+use rootcause::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{dev::app_ids::Profile, util::storage::SideloadingStorage};
+
+/// A team provisioning profile saved in [`SideloadingStorage`], so repeat installs don't need to download it again
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedProfile {
+    pub encoded_profile: Data,
+    pub date_expire: Date,
+}
+
+impl CachedProfile {
+    pub fn load(
+        storage: &dyn SideloadingStorage,
+        team_id: &str,
+        app_id_id: &str,
+    ) -> Result<Option<Self>, Report> {
+        let Some(data) = storage.retrieve_data(&storage_key(team_id, app_id_id))? else {
+            return Ok(None);
+        };
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(
+            plist::from_bytes(&data).context("Failed to parse cached provisioning profile")?,
+        ))
+    }
+
+    pub fn save(
+        profile: &Profile,
+        storage: &dyn SideloadingStorage,
+        team_id: &str,
+        app_id_id: &str,
+    ) -> Result<(), Report> {
+        let cached = CachedProfile {
+            encoded_profile: profile.encoded_profile.clone(),
+            date_expire: profile.date_expire,
+        };
+        let mut buf = Vec::new();
+        plist::to_writer_binary(&mut buf, &cached)
+            .context("Failed to serialize provisioning profile")?;
+        storage.store_data(&storage_key(team_id, app_id_id), &buf)
+    }
+}
+
+fn storage_key(team_id: &str, app_id_id: &str) -> String {
+    format!("profiles/{}/{}", team_id, app_id_id)
+}
@@ -0,0 +1,92 @@
+use std::fmt::Display;
+
+use tracing::{info, warn};
+
+use crate::dev::{
+    app_groups::{AppGroup, AppGroupsApi},
+    app_ids::{AppId, AppIdsApi},
+    developer_session::DeveloperSession,
+    device_type::DeveloperDeviceType,
+    teams::DeveloperTeam,
+};
+
+/// The app IDs and app groups created in the developer account while signing one app
+#[derive(Debug, Default)]
+pub(crate) struct Transaction {
+    pub(crate) app_ids: Vec<AppId>,
+    pub(crate) app_groups: Vec<AppGroup>,
+}
+
+/// What was removed after a sideload failed, see [`crate::sideload::SideloaderBuilder::rollback_on_failure`]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct RollbackReport {
+    /// Identifiers of the app IDs that were removed
+    pub removed_app_ids: Vec<String>,
+    /// Identifiers of the app groups that were removed
+    pub removed_app_groups: Vec<String>,
+    /// Identifiers of the app IDs and app groups that couldn't be removed, with the error
+    pub failed: Vec<(String, String)>,
+}
+
+impl Display for RollbackReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Rolled back {} app IDs and {} app groups",
+            self.removed_app_ids.len(),
+            self.removed_app_groups.len()
+        )?;
+        for (identifier, error) in &self.failed {
+            write!(f, "\n- Failed to remove {}: {}", identifier, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Transaction {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.app_ids.is_empty() && self.app_groups.is_empty()
+    }
+
+    /// Remove everything that was created, continuing past failures
+    ///
+    /// App IDs are removed first, since a group can't be removed while it is assigned to one.
+    pub(crate) async fn rollback(
+        self,
+        dev_session: &mut DeveloperSession,
+        team: &DeveloperTeam,
+        device_type: DeveloperDeviceType,
+    ) -> RollbackReport {
+        let mut report = RollbackReport::default();
+
+        for app_id in self.app_ids {
+            match dev_session
+                .delete_app_id(team, &app_id.app_id_id, device_type.clone())
+                .await
+            {
+                Ok(()) => report.removed_app_ids.push(app_id.identifier),
+                Err(e) => {
+                    warn!("Failed to remove app ID {}: {:?}", app_id.identifier, e);
+                    report.failed.push((app_id.identifier, e.to_string()));
+                }
+            }
+        }
+
+        for group in self.app_groups {
+            match dev_session
+                .delete_app_group(team, &group, device_type.clone())
+                .await
+            {
+                Ok(()) => report.removed_app_groups.push(group.identifier),
+                Err(e) => {
+                    warn!("Failed to remove app group {}: {:?}", group.identifier, e);
+                    report.failed.push((group.identifier, e.to_string()));
+                }
+            }
+        }
+
+        info!("{}", report);
+        report
+    }
+}
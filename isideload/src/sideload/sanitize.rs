@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use plist::{Dictionary, Value};
+use tracing::{debug, warn};
+
+use crate::sideload::bundle::Bundle;
+
+const SUPPORTED_DEVICES_KEY: &str = "UISupportedDevices";
+
+/// Top level Info.plist keys that hold the bundle identifier of another bundle in the app
+const BUNDLE_ID_REFERENCE_KEYS: &[&str] =
+    &["WKCompanionAppBundleIdentifier", "WKAppBundleIdentifier"];
+
+/// Behavior for the `UISupportedDevices` Info.plist key
+///
+/// Store-ripped IPAs often restrict the app to the devices it was thinned for,
+/// which makes installs on other devices fail with `DeviceFamilyNotSupported`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SupportedDevicesBehavior {
+    /// Leave the key untouched
+    #[default]
+    Keep,
+    /// Remove the key entirely
+    Remove,
+    /// Add the target device's product type (e.g. `iPhone15,2`) to the list if it is missing.
+    /// Falls back to removing the key if the target device is unknown.
+    AddTargetDevice,
+}
+
+/// Apply the [`SupportedDevicesBehavior`] to the bundle and all of its nested bundles
+///
+/// # Arguments
+/// - `bundle`: The main app bundle
+/// - `behavior`: The behavior to apply
+/// - `product_type`: The product type of the device the app will be installed to, if known
+pub fn sanitize_supported_devices(
+    bundle: &mut Bundle,
+    behavior: &SupportedDevicesBehavior,
+    product_type: Option<&str>,
+) {
+    if *behavior == SupportedDevicesBehavior::Keep {
+        return;
+    }
+
+    sanitize_bundle(bundle, behavior, product_type);
+    for ext in bundle.app_extensions_mut() {
+        sanitize_bundle(ext, behavior, product_type);
+    }
+}
+
+fn sanitize_bundle(
+    bundle: &mut Bundle,
+    behavior: &SupportedDevicesBehavior,
+    product_type: Option<&str>,
+) {
+    let Some(devices) = bundle
+        .app_info
+        .get(SUPPORTED_DEVICES_KEY)
+        .and_then(|v| v.as_array())
+    else {
+        return;
+    };
+    let name = bundle.bundle_name().unwrap_or("Unknown").to_string();
+
+    match (behavior, product_type) {
+        (SupportedDevicesBehavior::AddTargetDevice, Some(product_type)) => {
+            if devices.iter().any(|d| d.as_string() == Some(product_type)) {
+                return;
+            }
+            warn!(
+                "{} restricts supported devices, adding {} to {}",
+                name, product_type, SUPPORTED_DEVICES_KEY
+            );
+            let mut devices = devices.clone();
+            devices.push(Value::String(product_type.to_string()));
+            bundle
+                .app_info
+                .insert(SUPPORTED_DEVICES_KEY.to_string(), Value::Array(devices));
+        }
+        _ => {
+            warn!(
+                "{} restricts supported devices, removing {}",
+                name, SUPPORTED_DEVICES_KEY
+            );
+            bundle.app_info.remove(SUPPORTED_DEVICES_KEY);
+        }
+    }
+}
+
+/// Point references to renamed bundles at their new bundle identifiers
+///
+/// After extension bundle identifiers are rewritten, keys like `WKCompanionAppBundleIdentifier` and
+/// the `NSExtension` attributes can still hold the old identifiers, which makes the system reject the extension.
+/// Only exact matches inside the `NSExtension` dictionary and a few known keys are replaced, since other values
+/// that happen to equal a bundle identifier (like URL schemes) are usually also hardcoded in the binary.
+///
+/// # Arguments
+/// - `bundle`: The main app bundle, after bundle id rewriting
+/// - `renamed`: Map of old bundle identifiers to new ones
+pub fn fix_bundle_id_references(bundle: &mut Bundle, renamed: &HashMap<String, String>) {
+    if renamed.is_empty() {
+        return;
+    }
+
+    fix_references(bundle, renamed);
+    for ext in bundle.app_extensions_and_clips_mut() {
+        fix_references(ext, renamed);
+    }
+    for watch_app in bundle.watch_apps_mut() {
+        fix_references(watch_app, renamed);
+        for ext in watch_app.app_extensions_mut() {
+            fix_references(ext, renamed);
+        }
+    }
+}
+
+fn fix_references(bundle: &mut Bundle, renamed: &HashMap<String, String>) {
+    let name = bundle.bundle_name().unwrap_or("Unknown").to_string();
+
+    for key in BUNDLE_ID_REFERENCE_KEYS {
+        if let Some(value) = bundle.app_info.get_mut(key)
+            && replace_references(value, renamed)
+        {
+            debug!("Updated {} in {}", key, name);
+        }
+    }
+
+    if let Some(extension) = bundle.app_info.get_mut("NSExtension")
+        && replace_references(extension, renamed)
+    {
+        debug!("Updated NSExtension references in {}", name);
+    }
+}
+
+/// Recursively replace string values that exactly match a renamed bundle identifier
+///
+/// Returns whether anything was replaced.
+fn replace_references(value: &mut Value, renamed: &HashMap<String, String>) -> bool {
+    match value {
+        Value::String(s) => match renamed.get(s.as_str()) {
+            Some(new_id) => {
+                *s = new_id.clone();
+                true
+            }
+            None => false,
+        },
+        Value::Array(values) => {
+            let mut changed = false;
+            for v in values {
+                changed |= replace_references(v, renamed);
+            }
+            changed
+        }
+        Value::Dictionary(dict) => replace_in_dict(dict, renamed),
+        _ => false,
+    }
+}
+
+fn replace_in_dict(dict: &mut Dictionary, renamed: &HashMap<String, String>) -> bool {
+    let mut changed = false;
+    for v in dict.values_mut() {
+        changed |= replace_references(v, renamed);
+    }
+    changed
+}
@@ -1,26 +1,88 @@
+#[cfg(feature = "install")]
+use crate::{
+    SideloadError,
+    dev::devices::DeviceRegistration,
+    sideload::{
+        install::{ProviderFactory, UploadVerification},
+        install_record::InstallRecord,
+        profile::ProfileDiagnosis,
+        validation::bundle_signer,
+    },
+    util::device::{
+        IdeviceInfo,
+        aliases::{device_alias, set_device_alias},
+        app_manager::{InstalledApp, list_installed_apps, lookup_app},
+        developer_mode::{DeveloperModeStatus, developer_mode_status},
+        export_pairing_file,
+        profiles::{install_profile, remove_stale_profiles},
+    },
+};
 use crate::{
     dev::{
         app_groups::AppGroupsApi,
-        app_ids::AppIdsApi,
-        developer_session::DeveloperSession,
-        devices::DevicesApi,
-        teams::{DeveloperTeam, TeamsApi},
+        app_ids::{AppId, AppIdsApi},
+        certificates::CertificatesApi,
+        developer_session::{
+            DeveloperRequestHook, DeveloperSession, is_identifier_unavailable_error,
+        },
+        devices::{DevicesApi, RegisteredDevice},
+        teams::{DeveloperTeam, TEAM_BOOTSTRAP_URL, TeamsApi},
     },
     sideload::{
         TeamSelection,
-        application::{Application, SpecialApp},
-        builder::MaxCertsBehavior,
+        application::{Application, ExtractOptions, SpecialApp, TempDirGuard},
+        builder::{AppIdReusePolicy, AppIdSuffixStrategy, MaxCertsBehavior},
         cert_identity::CertificateIdentity,
+        customization::BundleCustomizations,
+        entitlements::EntitlementPolicy,
+        events::{SideloadEvent, SideloadObserver},
+        extensions::{AppClipBehavior, ExtensionFilter},
+        itunes_metadata::ITunesMetadataBehavior,
+        package,
+        pass::{BundlePipeline, PassContext},
+        platform::TargetPlatform,
+        preflight::{PreflightIssue, PreflightReport, directory_size},
+        profile::ProvisioningProfileInfo,
+        profile_cache::CachedProfile,
+        rollback::Transaction,
+        sanitize::SupportedDevicesBehavior,
         sign,
+        signing_report::SigningReport,
+        thinning::{ThinningReport, thin_app},
+        validation::{ValidationReport, validate_signed_app},
+        wwdr::WwdrIntermediates,
+    },
+    util::{
+        clock::{Clock, SystemClock},
+        ipa_cache::IpaCache,
+        storage::SideloadingStorage,
     },
-    util::{device::IdeviceInfo, storage::SideloadingStorage},
 };
 
-use std::path::PathBuf;
+#[cfg(feature = "install")]
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
+#[cfg(feature = "install")]
 use idevice::provider::IdeviceProvider;
+use plist::Dictionary;
 use rootcause::{option_ext::OptionExt, prelude::*};
-use tracing::info;
+use tracing::{debug, info};
+
+/// How long before expiry a cached provisioning profile is downloaded again
+pub(crate) const DEFAULT_PROFILE_REFRESH_MARGIN: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// How long the embedded profile must stay valid for [`crate::sideload::SideloaderBuilder::skip_if_valid`] to skip signing
+#[cfg(feature = "install")]
+const SKIP_IF_VALID_MIN_VALIDITY: Duration = Duration::from_secs(24 * 60 * 60);
 
 pub struct Sideloader {
     team_selection: TeamSelection,
@@ -30,8 +92,295 @@ pub struct Sideloader {
     apple_email: String,
     max_certs_behavior: MaxCertsBehavior,
     //extensions_behavior: ExtensionsBehavior,
+    #[cfg(feature = "install")]
     delete_app_after_install: bool,
     team: Option<DeveloperTeam>,
+    teams: Option<Vec<DeveloperTeam>>,
+    observers: Arc<Vec<SideloadObserver>>,
+    pub(crate) rename_registered_devices: bool,
+    pub(crate) skip_if_valid: bool,
+    pub(crate) embed_pairing_file: bool,
+    pub(crate) extension_filter: ExtensionFilter,
+    pub(crate) app_clip_behavior: AppClipBehavior,
+    pub(crate) app_id_reuse_policy: AppIdReusePolicy,
+    pub(crate) app_id_suffix_strategy: AppIdSuffixStrategy,
+    pub(crate) keep_symbols: bool,
+    pub(crate) thin_app: bool,
+    pub(crate) extract_options: ExtractOptions,
+    pub(crate) cache_profiles: bool,
+    pub(crate) profile_refresh_margin: Duration,
+    /// Checked against for every profile and certificate expiry, shared with the developer session
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) ipa_cache: Option<IpaCache>,
+    pub(crate) bundle_customizations: BundleCustomizations,
+    /// Overrides the built-in [`EntitlementPolicy::for_team`]
+    pub(crate) entitlement_policy: Option<EntitlementPolicy>,
+    #[cfg(feature = "install")]
+    pub(crate) upload_verification: UploadVerification,
+    #[cfg(feature = "install")]
+    pub(crate) clean_device_profiles: bool,
+    pub(crate) require_developer_mode: bool,
+    pub(crate) validate_signature: bool,
+    pub(crate) bundle_pipeline: BundlePipeline,
+    pub(crate) rollback_on_failure: bool,
+    /// Temporary files from installs that haven't finished yet, removed on shutdown
+    staging_paths: Vec<PathBuf>,
+    last_signing_report: Option<SigningReport>,
+    pub(crate) itunes_metadata_behavior: ITunesMetadataBehavior,
+    pub(crate) supported_devices_behavior: SupportedDevicesBehavior,
+    pub(crate) platform: TargetPlatform,
+}
+
+/// The outcome of signing one app with [`Sideloader::sign_all`]
+pub struct BatchSignResult {
+    /// The IPA or `.app` that was found
+    pub source: PathBuf,
+    /// The signed IPA and what was signed, or why the app couldn't be signed
+    pub result: Result<(PathBuf, SigningReport), Report>,
+}
+
+#[cfg(feature = "json-events")]
+impl serde::Serialize for BatchSignResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("BatchSignResult", 4)?;
+        state.serialize_field("source", &self.source)?;
+        match &self.result {
+            Ok((output, signing_report)) => {
+                state.serialize_field("output", output)?;
+                state.serialize_field("signing_report", signing_report)?;
+                state.serialize_field("error", &None::<crate::error::ErrorSummary>)?;
+            }
+            Err(e) => {
+                state.serialize_field("output", &None::<PathBuf>)?;
+                state.serialize_field("signing_report", &None::<SigningReport>)?;
+                state
+                    .serialize_field("error", &Some(crate::error::ErrorSummary::from_report(e)))?;
+            }
+        }
+        state.end()
+    }
+}
+
+/// The outcome of installing one app with [`Sideloader::install_batch`]
+#[cfg(feature = "install")]
+pub struct BatchInstallResult {
+    /// The IPA or `.app` that was installed
+    pub source: PathBuf,
+    pub result: Result<InstallOutcome, Report>,
+}
+
+#[cfg(all(feature = "install", feature = "json-events"))]
+impl serde::Serialize for BatchInstallResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("BatchInstallResult", 3)?;
+        state.serialize_field("source", &self.source)?;
+        match &self.result {
+            Ok(outcome) => {
+                state.serialize_field("outcome", outcome)?;
+                state.serialize_field("error", &None::<crate::error::ErrorSummary>)?;
+            }
+            Err(e) => {
+                state.serialize_field("outcome", &None::<InstallOutcome>)?;
+                state
+                    .serialize_field("error", &Some(crate::error::ErrorSummary::from_report(e)))?;
+            }
+        }
+        state.end()
+    }
+}
+
+/// An app of [`Sideloader::install_batch`] waiting to be installed
+#[cfg(feature = "install")]
+enum PendingApp {
+    /// Already signed for the device, see [`crate::sideload::SideloaderBuilder::skip_if_valid`]
+    Signed(SignedApp),
+    Unsigned(Application),
+}
+
+/// The device an app is being signed for
+struct TargetDevice<'a> {
+    udid: &'a str,
+    /// The hardware model, e.g. `iPhone15,2`
+    product_type: Option<&'a str>,
+    /// The device's pairing file, embedded into SideStore
+    pairing_file: Option<&'a [u8]>,
+}
+
+/// The result of [`Sideloader::refresh_app`]
+#[cfg(feature = "install")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub enum RefreshOutcome {
+    /// The app was re-signed and reinstalled
+    Refreshed(Option<SpecialApp>),
+    /// The app's profile stays valid for longer than the profile refresh margin, so nothing was done
+    NotNeeded { expires: SystemTime },
+    /// The app wasn't installed by this `Sideloader`, or was re-signed by something else since
+    NotManaged,
+}
+
+/// What [`Sideloader::remove_app_id_for`] removed from the developer team
+#[cfg(feature = "install")]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct AppIdRemoval {
+    /// Identifiers of the app IDs that were removed, for the app and its extensions
+    pub removed_app_ids: Vec<String>,
+    /// Identifiers of the app groups that were removed because no other app ID is assigned to them
+    pub removed_app_groups: Vec<String>,
+}
+
+/// An app that has been signed by [`Sideloader`]
+pub struct SignedApp {
+    /// Path to the signed app bundle
+    pub bundle_dir: PathBuf,
+    pub special_app: Option<SpecialApp>,
+    /// The `iTunesMetadata.plist` to send to the device, according to the configured [`ITunesMetadataBehavior`]
+    pub itunes_metadata: Option<Dictionary>,
+    /// The temporary directory the app was extracted to, if it was provided as an IPA
+    pub temp_path: Option<PathBuf>,
+    /// What was signed, including the entitlements the app requested that the provisioning profile doesn't grant
+    pub signing_report: SigningReport,
+    /// The bundle identifier of the main app, after it was changed for the team
+    pub bundle_id: String,
+    pub team_id: String,
+    /// The serial number of the certificate the app was signed with
+    pub certificate_serial: String,
+    /// When the provisioning profile, and with it the app, expires
+    pub profile_expiration: Option<SystemTime>,
+    /// The identifiers of the app IDs registered for the app and its extensions
+    pub app_ids: Vec<String>,
+}
+
+impl SignedApp {
+    fn outcome(&self, started: Instant) -> SideloadOutcome {
+        SideloadOutcome {
+            bundle_id: self.bundle_id.clone(),
+            team_id: self.team_id.clone(),
+            certificate_serial: self.certificate_serial.clone(),
+            profile_expiration: self.profile_expiration,
+            app_ids: self.app_ids.clone(),
+            special_app: self.special_app.clone(),
+            bundle_dir: self.bundle_dir.clone(),
+            duration: started.elapsed(),
+            signing_report: self.signing_report.clone(),
+        }
+    }
+}
+
+/// What [`Sideloader::sign_app`] did, or how [`Sideloader::install_app`] signed the app, for frontends to record and
+/// show, e.g. "expires on ..."
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct SideloadOutcome {
+    /// The bundle identifier the app was signed with, after it was changed for the team
+    pub bundle_id: String,
+    pub team_id: String,
+    /// The serial number of the certificate the app was signed with
+    pub certificate_serial: String,
+    /// When the provisioning profile, and with it the app, expires
+    pub profile_expiration: Option<SystemTime>,
+    /// The identifiers of the app IDs registered for the app and its extensions. Empty if an already signed app
+    /// was installed as is.
+    pub app_ids: Vec<String>,
+    pub special_app: Option<SpecialApp>,
+    /// The signed app bundle. It is removed after installing unless
+    /// [`crate::sideload::SideloaderBuilder::delete_app_after_install`] is disabled.
+    pub bundle_dir: PathBuf,
+    /// How long signing, and installing if the app was installed, took
+    pub duration: Duration,
+    pub signing_report: SigningReport,
+}
+
+/// What [`Sideloader::install_app`] did, with everything a frontend needs to show a "done" screen without querying
+/// the device again
+#[cfg(feature = "install")]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct InstallOutcome {
+    /// The bundle identifier the app was installed as
+    pub bundle_id: String,
+    /// The name shown on the home screen
+    pub display_name: Option<String>,
+    /// When the provisioning profile, and with it the app, expires
+    pub profile_expiration: Option<SystemTime>,
+    /// How many bytes were uploaded to the device
+    pub bytes_transferred: u64,
+    pub phases: InstallPhases,
+    /// Problems that didn't stop the install, like dropped capabilities or Developer Mode being disabled
+    pub warnings: Vec<String>,
+    /// The details of how the app was signed
+    pub sideload: SideloadOutcome,
+}
+
+/// How long each phase of [`Sideloader::install_app`] took
+#[cfg(feature = "install")]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct InstallPhases {
+    /// Registering the device and signing the app, or checking that an installed app could be reused
+    pub signing: Duration,
+    /// Uploading the app to the device
+    pub upload: Duration,
+    /// Waiting for installd to install the uploaded app
+    pub install: Duration,
+}
+
+/// Tracks the upload while an app is installed, from the install progress
+#[cfg(feature = "install")]
+struct InstallTracker {
+    started: Instant,
+    bytes_transferred: AtomicU64,
+    upload_finished: Mutex<Option<Instant>>,
+}
+
+#[cfg(feature = "install")]
+impl InstallTracker {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            bytes_transferred: AtomicU64::new(0),
+            upload_finished: Mutex::new(None),
+        }
+    }
+
+    fn record(&self, progress: &crate::sideload::install::InstallProgress) {
+        use crate::sideload::install::InstallProgress;
+
+        match progress {
+            InstallProgress::Uploading { bytes_done, .. } => {
+                self.bytes_transferred
+                    .fetch_max(*bytes_done, Ordering::Relaxed);
+            }
+            InstallProgress::Installing(_) => {
+                self.upload_finished
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    fn bytes_transferred(&self) -> u64 {
+        self.bytes_transferred.load(Ordering::Relaxed)
+    }
+
+    fn phases(&self, signing: Duration) -> InstallPhases {
+        let upload_finished = self
+            .upload_finished
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .unwrap_or_else(Instant::now);
+        InstallPhases {
+            signing,
+            upload: upload_finished.duration_since(self.started),
+            install: upload_finished.elapsed(),
+        }
+    }
 }
 
 impl Sideloader {
@@ -48,6 +397,9 @@ impl Sideloader {
         //extensions_behavior: ExtensionsBehavior,
         delete_app_after_install: bool,
     ) -> Self {
+        // only installing leaves an app behind to delete
+        #[cfg(not(feature = "install"))]
+        let _ = delete_app_after_install;
         Sideloader {
             team_selection,
             storage,
@@ -56,8 +408,40 @@ impl Sideloader {
             apple_email,
             max_certs_behavior,
             //extensions_behavior,
+            #[cfg(feature = "install")]
             delete_app_after_install,
             team: None,
+            teams: None,
+            observers: Arc::new(Vec::new()),
+            rename_registered_devices: false,
+            skip_if_valid: false,
+            embed_pairing_file: true,
+            extension_filter: ExtensionFilter::default(),
+            app_clip_behavior: AppClipBehavior::default(),
+            app_id_reuse_policy: AppIdReusePolicy::default(),
+            app_id_suffix_strategy: AppIdSuffixStrategy::default(),
+            keep_symbols: false,
+            thin_app: false,
+            extract_options: ExtractOptions::default().skip_symbols(true),
+            cache_profiles: true,
+            profile_refresh_margin: DEFAULT_PROFILE_REFRESH_MARGIN,
+            clock: Arc::new(SystemClock),
+            ipa_cache: None,
+            bundle_customizations: BundleCustomizations::new(),
+            entitlement_policy: None,
+            #[cfg(feature = "install")]
+            upload_verification: UploadVerification::default(),
+            #[cfg(feature = "install")]
+            clean_device_profiles: false,
+            require_developer_mode: false,
+            validate_signature: false,
+            bundle_pipeline: BundlePipeline::default(),
+            rollback_on_failure: false,
+            staging_paths: Vec::new(),
+            last_signing_report: None,
+            itunes_metadata_behavior: ITunesMetadataBehavior::default(),
+            supported_devices_behavior: SupportedDevicesBehavior::default(),
+            platform: TargetPlatform::default(),
         }
     }
 
@@ -68,35 +452,610 @@ impl Sideloader {
         team: Option<DeveloperTeam>,
         // this will be replaced with proper entitlement handling later
         increased_memory_limit: bool,
-    ) -> Result<(PathBuf, Option<SpecialApp>), Report> {
-        let team = match team {
-            Some(t) => t,
-            None => self.get_team().await?,
+    ) -> Result<SideloadOutcome, Report> {
+        let started = Instant::now();
+        let signed = self
+            .sign_app_inner(
+                Application::with_options(app_path, &self.extract_options)?,
+                team,
+                None,
+                increased_memory_limit,
+            )
+            .await?;
+        self.emit(SideloadEvent::Done);
+
+        Ok(signed.outcome(started))
+    }
+
+    /// Sign the app at the provided path and package it as an IPA at `output_path`, without needing a device.
+    ///
+    /// The IPA contains the app, the `iTunesMetadata.plist` resolved by the configured [`ITunesMetadataBehavior`], and
+    /// the `Symbols` directory of the original IPA if [`crate::sideload::SideloaderBuilder::keep_symbols`] is set.
+    /// Returns what was signed, including the entitlements that were dropped while signing.
+    pub async fn sign_to_ipa(
+        &mut self,
+        app_path: PathBuf,
+        output_path: PathBuf,
+        team: Option<DeveloperTeam>,
+        increased_memory_limit: bool,
+    ) -> Result<SigningReport, Report> {
+        self.sign_application_to_ipa(
+            Application::with_options(app_path, &self.extract_options)?,
+            output_path,
+            team,
+            increased_memory_limit,
+        )
+        .await
+    }
+
+    /// Like [`Self::sign_to_ipa`], for an app that was already loaded, e.g. with [`Application::from_bytes`]
+    ///
+    /// This lets servers sign uploaded IPAs without writing the upload to disk first.
+    pub async fn sign_application_to_ipa(
+        &mut self,
+        app: Application,
+        output_path: PathBuf,
+        team: Option<DeveloperTeam>,
+        increased_memory_limit: bool,
+    ) -> Result<SigningReport, Report> {
+        let signed = self
+            .sign_app_inner(app, team, None, increased_memory_limit)
+            .await?;
+
+        package_signed_app(&signed, &output_path, self.keep_symbols)?;
+        self.emit(SideloadEvent::Done);
+
+        Ok(signed.signing_report)
+    }
+
+    /// Sign every IPA and `.app` under `dir` and package each one as an IPA in `output_dir`
+    ///
+    /// All apps are signed for the same team with the same certificate, so the developer services are only asked
+    /// for them once. The developer services set up one app at a time, while signing and packaging run in parallel,
+    /// with at most one app per CPU core on disk at once. `.app` bundles are copied before they are signed, so the
+    /// originals are left as they are. Symlinked directories under `dir` aren't followed.
+    ///
+    /// Returns the outcome for every app found, in path order. A failed app doesn't stop the others, only failing
+    /// to find the apps, to select a team or to get a certificate fails the whole batch.
+    pub async fn sign_all(
+        &mut self,
+        dir: &Path,
+        output_dir: &Path,
+    ) -> Result<Vec<BatchSignResult>, Report> {
+        let mut sources = Vec::new();
+        find_apps(dir, output_dir, &mut sources).context("Failed to find apps to sign")?;
+        sources.sort();
+        info!("Found {} apps to sign in {}", sources.len(), dir.display());
+        std::fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+
+        let team = self.get_team().await?;
+        self.emit(SideloadEvent::RequestingCertificate);
+        let identity = self.signing_identity(&team).await?;
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let mut output_names = HashSet::new();
+
+        let mut results = Vec::with_capacity(sources.len());
+        for chunk in sources.chunks(workers) {
+            let mut apps = Vec::new();
+            let mut outputs = Vec::new();
+            for source in chunk {
+                let name = unique_ipa_name(source, &mut output_names);
+                let app = if source.is_file() {
+                    std::fs::File::open(source)
+                        .context("Failed to open application archive")
+                        .map_err(|e| e.into_dynamic())
+                        .and_then(|file| {
+                            Application::from_reader_with_options(
+                                file,
+                                &name,
+                                &self.extract_options,
+                            )
+                        })
+                } else {
+                    Application::copy_of(source, &self.extract_options)
+                };
+                match app {
+                    Ok(app) => {
+                        apps.push((app, Transaction::default()));
+                        outputs.push((source.clone(), output_dir.join(&name)));
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to load {}: {:?}", source.display(), e);
+                        results.push(BatchSignResult {
+                            source: source.clone(),
+                            result: Err(e),
+                        });
+                    }
+                }
+            }
+
+            let signed = self.sign_batch(apps, &team, &identity, None).await;
+            let keep_symbols = self.keep_symbols;
+            // zipping is blocking work, so it runs off the runtime's worker threads
+            let mut packaging = tokio::task::JoinSet::new();
+            for ((source, output_path), signed) in outputs.into_iter().zip(signed) {
+                packaging.spawn_blocking(move || {
+                    let result = signed.and_then(|app| {
+                        package_signed_app(&app, &output_path, keep_symbols)
+                            .map(|_| (output_path, app.signing_report))
+                    });
+                    (source, result)
+                });
+            }
+            while let Some(joined) = packaging.join_next().await {
+                let (source, result) = joined.context("Packaging task panicked")?;
+                if let Err(e) = &result {
+                    tracing::warn!("Failed to sign {}: {:?}", source.display(), e);
+                }
+                results.push(BatchSignResult { source, result });
+            }
+        }
+        results.sort_by(|a, b| a.source.cmp(&b.source));
+        self.emit(SideloadEvent::Done);
+
+        Ok(results)
+    }
+
+    /// Check whether an app can be sideloaded, without changing anything on the team or a device
+    ///
+    /// Validates the app, counts the app IDs it needs against what the team has left, and checks whether a
+    /// certificate has to be requested and whether the device with `device_udid` is registered. Problems that would
+    /// make sideloading fail or that the user may want to know about are listed in [`PreflightReport::issues`],
+    /// only failing to run the checks, e.g. because the developer services can't be reached, returns an error.
+    ///
+    /// IPAs are extracted to a temporary directory, which is removed before returning.
+    pub async fn preflight(
+        &mut self,
+        app_path: PathBuf,
+        device_udid: Option<&str>,
+    ) -> Result<PreflightReport, Report> {
+        let mut report = PreflightReport::default();
+        let app = match Application::with_options(app_path, &self.extract_options) {
+            Ok(app) => app,
+            Err(e) => {
+                report
+                    .issues
+                    .push(PreflightIssue::InvalidBundle(e.to_string()));
+                return Ok(report);
+            }
+        };
+
+        let result = self.preflight_app(&app, device_udid, &mut report).await;
+        if let Some(temp_path) = &app.temp_path
+            && let Err(e) = std::fs::remove_dir_all(temp_path)
+        {
+            tracing::warn!("Failed to remove temporary app file: {}", e);
+        }
+        result?;
+
+        Ok(report)
+    }
+
+    /// Like [`Self::preflight`], also checking the connected device's registration and free space
+    #[cfg(feature = "install")]
+    pub async fn preflight_for_device(
+        &mut self,
+        app_path: PathBuf,
+        device_provider: &impl IdeviceProvider,
+    ) -> Result<PreflightReport, Report> {
+        let device_info = IdeviceInfo::from_device(device_provider).await?;
+        let mut report = self.preflight(app_path, Some(&device_info.udid)).await?;
+
+        // `ProviderFactory` is in scope too, so the service's `connect` has to be named
+        let mut afc_client =
+            <idevice::afc::AfcClient as idevice::IdeviceService>::connect(device_provider)
+                .await
+                .context("Failed to connect to AFC")?;
+        let free_space = afc_client
+            .get_device_info()
+            .await
+            .context("Failed to get device storage info")?
+            .free_bytes as u64;
+        report.device_free_space = Some(free_space);
+        if report.upload_size > free_space {
+            report.issues.push(PreflightIssue::InsufficientSpace {
+                required: report.upload_size,
+                available: free_space,
+            });
+        }
+
+        Ok(report)
+    }
+
+    async fn preflight_app(
+        &mut self,
+        app: &Application,
+        device_udid: Option<&str>,
+        report: &mut PreflightReport,
+    ) -> Result<(), Report> {
+        report.upload_size =
+            directory_size(&app.bundle.bundle_dir).context("Failed to measure app size")?;
+        let main_bundle_id = match app.main_bundle_id() {
+            Ok(id) => id,
+            Err(e) => {
+                report
+                    .issues
+                    .push(PreflightIssue::InvalidBundle(e.to_string()));
+                return Ok(());
+            }
+        };
+        report.bundle_id = Some(main_bundle_id.clone());
+        if let Err(e) = self.platform.check_bundle(&app.bundle) {
+            report
+                .issues
+                .push(PreflightIssue::InvalidBundle(e.to_string()));
+        }
+
+        let Some(team) = self.existing_team().await? else {
+            report.issues.push(PreflightIssue::NeedsTeamBootstrap {
+                url: TEAM_BOOTSTRAP_URL.to_string(),
+            });
+            return Ok(());
         };
-        let cert_identity = CertificateIdentity::retrieve(
+        report.team = Some(team.clone());
+
+        let identifiers: Vec<String> = self
+            .planned_app_ids(app, &main_bundle_id, &team)
+            .into_iter()
+            .map(|(identifier, _)| identifier)
+            .collect();
+        let app_ids = self
+            .dev_session
+            .list_app_ids(&team, self.platform.device_type())
+            .await
+            .context("Failed to list app IDs for the developer team")?;
+        report.app_ids = identifiers.len();
+        report.new_app_ids = identifiers
+            .iter()
+            .filter(|id| {
+                !app_ids
+                    .app_ids
+                    .iter()
+                    .any(|app_id| app_id.identifier == **id)
+            })
+            .count();
+        report.available_app_ids = app_ids.available_quantity;
+        if team.capabilities().app_id_limit.is_some()
+            && let Some(available) = app_ids.available_quantity
+            && report.new_app_ids as i64 > available
+        {
+            report.issues.push(PreflightIssue::NotEnoughAppIds {
+                required: report.new_app_ids,
+                available,
+            });
+        }
+
+        report.has_certificate = CertificateIdentity::has_matching(
             &self.machine_name,
             &self.apple_email,
             &mut self.dev_session,
             &team,
             self.storage.as_ref(),
+        )
+        .await
+        .context("Failed to check for a matching certificate")?;
+        if !report.has_certificate {
+            let existing = self.dev_session.list_ios_certs(&team).await?.len();
+            report
+                .issues
+                .push(PreflightIssue::CertificateWillBeRequested { existing });
+        }
+
+        if let Some(udid) = device_udid {
+            let device = self
+                .dev_session
+                .is_device_registered(&team, udid, self.platform.device_type())
+                .await
+                .context("Failed to check device registration")?;
+            report.device_registered = Some(device.is_some());
+            match device {
+                Some(device) if device.is_disabled() => {
+                    report.issues.push(PreflightIssue::DeviceDisabled {
+                        udid: udid.to_string(),
+                    })
+                }
+                Some(_) => {}
+                None => report.issues.push(PreflightIssue::DeviceNotRegistered {
+                    udid: udid.to_string(),
+                }),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The identifiers and names of the app IDs that signing `app` for `team` registers
+    ///
+    /// These are the same app IDs `sign_app_inner` registers, after the extension filter and app clip behavior are
+    /// applied, as long as the main app ID isn't taken by another team.
+    fn planned_app_ids(
+        &self,
+        app: &Application,
+        main_bundle_id: &str,
+        team: &DeveloperTeam,
+    ) -> Vec<(String, String)> {
+        let remove_clips = self.app_clip_behavior.removes_clips(team.is_free());
+        let main_app_id = format!("{}.{}", main_bundle_id, team.team_id);
+        let main_name = app.bundle.bundle_name().unwrap_or("").to_string();
+        std::iter::once((main_app_id.clone(), main_name))
+            .chain(
+                app.bundle
+                    .app_id_bundles()
+                    .into_iter()
+                    .filter(|b| {
+                        self.extension_filter.keeps(b) && !(remove_clips && b.is_app_clip())
+                    })
+                    .filter_map(|b| {
+                        let id = b.bundle_identifier()?;
+                        let identifier = match id.strip_prefix(main_bundle_id) {
+                            Some(rest) => format!("{}{}", main_app_id, rest),
+                            None => id.to_string(),
+                        };
+                        Some((identifier, b.bundle_name().unwrap_or("").to_string()))
+                    }),
+            )
+            .collect()
+    }
+
+    /// The certificate to sign with, retrieved once and shared by the apps of a batch
+    async fn signing_identity(
+        &mut self,
+        team: &DeveloperTeam,
+    ) -> Result<Arc<CertificateIdentity>, Report> {
+        let identity = CertificateIdentity::retrieve(
+            &self.machine_name,
+            &self.apple_email,
+            &mut self.dev_session,
+            team,
+            self.storage.as_ref(),
             &self.max_certs_behavior,
         )
         .await
         .context("Failed to retrieve certificate identity")?;
+        Ok(Arc::new(identity))
+    }
+
+    async fn sign_app_inner(
+        &mut self,
+        app: Application,
+        team: Option<DeveloperTeam>,
+        device: Option<&TargetDevice<'_>>,
+        increased_memory_limit: bool,
+    ) -> Result<SignedApp, Report> {
+        let team = match team {
+            Some(t) => t,
+            None => self.get_team().await?,
+        };
+        self.emit(SideloadEvent::RequestingCertificate);
+        let identity = self.signing_identity(&team).await?;
+        self.sign_app_with(app, &team, identity, device, increased_memory_limit)
+            .await
+    }
+
+    /// Like [`Self::sign_app_inner`], with a certificate that was already retrieved
+    async fn sign_app_with(
+        &mut self,
+        app: Application,
+        team: &DeveloperTeam,
+        identity: Arc<CertificateIdentity>,
+        device: Option<&TargetDevice<'_>>,
+        increased_memory_limit: bool,
+    ) -> Result<SignedApp, Report> {
+        let temp_dir = TempDirGuard::new(app.temp_path.clone());
+        let mut transaction = Transaction::default();
+        let result = async {
+            let prepared = self
+                .prepare_signing(
+                    app,
+                    team,
+                    identity,
+                    device,
+                    increased_memory_limit,
+                    &mut transaction,
+                )
+                .await?;
+            let signed = sign_prepared(prepared, &self.signing_config(team));
+            self.finish_signing(signed).await
+        }
+        .await;
+        if result.is_ok() {
+            temp_dir.keep();
+        }
+        self.rollback_failed(result, transaction, team).await
+    }
+
+    /// Sign several apps for the same team with the same certificate, returning the result for each in order
+    ///
+    /// The developer services can only set up one app at a time, but once an app is set up it is signed on a blocking
+    /// thread while the next one is, with up to one app per CPU core being signed at once. `transactions` holds
+    /// what was already created in the developer account for each app, rolled back with the rest if it fails.
+    async fn sign_batch(
+        &mut self,
+        apps: Vec<(Application, Transaction)>,
+        team: &DeveloperTeam,
+        identity: &Arc<CertificateIdentity>,
+        device: Option<&TargetDevice<'_>>,
+    ) -> Vec<Result<SignedApp, Report>> {
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let config = Arc::new(self.signing_config(team));
+        let mut results: Vec<_> = apps.iter().map(|_| None).collect();
+        let mut signing = VecDeque::new();
+
+        // removed if the app fails, nothing else will need its files
+        let temp_dirs: Vec<_> = apps
+            .iter()
+            .map(|(app, _)| TempDirGuard::new(app.temp_path.clone()))
+            .collect();
+
+        for (index, (app, mut transaction)) in apps.into_iter().enumerate() {
+            if signing.len() >= workers
+                && let Some((index, transaction, handle)) = signing.pop_front()
+            {
+                results[index] = Some(self.join_signing(handle, transaction, team).await);
+            }
+            match self
+                .prepare_signing(app, team, identity.clone(), device, false, &mut transaction)
+                .await
+            {
+                Ok(prepared) => {
+                    let config = config.clone();
+                    let handle =
+                        tokio::task::spawn_blocking(move || sign_prepared(prepared, &config));
+                    signing.push_back((index, transaction, handle));
+                }
+                Err(e) => {
+                    results[index] = Some(self.rollback_failed(Err(e), transaction, team).await);
+                }
+            }
+        }
+        while let Some((index, transaction, handle)) = signing.pop_front() {
+            results[index] = Some(self.join_signing(handle, transaction, team).await);
+        }
+
+        for (result, temp_dir) in results.iter().zip(temp_dirs) {
+            if let Some(Ok(_)) = result {
+                temp_dir.keep();
+            }
+        }
+        results.into_iter().flatten().collect()
+    }
+
+    async fn join_signing(
+        &mut self,
+        handle: tokio::task::JoinHandle<Result<SignedOutput, Report>>,
+        transaction: Transaction,
+        team: &DeveloperTeam,
+    ) -> Result<SignedApp, Report> {
+        let result = match handle.await {
+            Ok(Ok(signed)) => self.finish_signing(Ok(signed)).await,
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(report!("Signing thread failed: {}", e)),
+        };
+        self.rollback_failed(result, transaction, team).await
+    }
+
+    /// Remove what was created in the developer account for an app that failed, if
+    /// [`crate::sideload::SideloaderBuilder::rollback_on_failure`] is set
+    async fn rollback_failed(
+        &mut self,
+        result: Result<SignedApp, Report>,
+        transaction: Transaction,
+        team: &DeveloperTeam,
+    ) -> Result<SignedApp, Report> {
+        match result {
+            Err(e) if self.rollback_on_failure && !transaction.is_empty() => {
+                let rollback = transaction
+                    .rollback(&mut self.dev_session, team, self.platform.device_type())
+                    .await;
+                self.emit(SideloadEvent::RolledBack {
+                    app_ids: rollback.removed_app_ids.clone(),
+                    app_groups: rollback.removed_app_groups.clone(),
+                });
+                Err(e).attach(rollback)
+            }
+            result => result,
+        }
+    }
+
+    /// Everything signing `team`'s apps needs from the sideloader
+    fn signing_config(&self, team: &DeveloperTeam) -> SigningConfig {
+        SigningConfig {
+            team_id: team.team_id.clone(),
+            customizations: self.bundle_customizations.clone(),
+            entitlement_policy: self
+                .entitlement_policy
+                .clone()
+                .unwrap_or_else(|| EntitlementPolicy::for_team(team)),
+            validate_signature: self.validate_signature,
+            itunes_metadata_behavior: self.itunes_metadata_behavior.clone(),
+            apple_email: self.apple_email.clone(),
+            observers: self.observers.clone(),
+        }
+    }
 
-        let mut app = Application::new(app_path)?;
+    /// Register the app IDs, app group and provisioning profile for an app, and prepare its bundles for signing
+    async fn prepare_signing(
+        &mut self,
+        mut app: Application,
+        team: &DeveloperTeam,
+        cert_identity: Arc<CertificateIdentity>,
+        device: Option<&TargetDevice<'_>>,
+        increased_memory_limit: bool,
+        transaction: &mut Transaction,
+    ) -> Result<PreparedApp, Report> {
+        self.platform.check_bundle(&app.bundle)?;
+        let original_info = app.bundle.app_info.clone();
         let special = app.get_special_app();
+        self.extension_filter.apply(&mut app.bundle)?;
+        self.app_clip_behavior
+            .apply(&mut app.bundle, team.is_free())?;
+        let thinning = if self.thin_app {
+            let report = thin_app(&app.bundle.bundle_dir).context("Failed to thin app")?;
+            info!("App thinning {}", report);
+            self.emit(SideloadEvent::Thinned {
+                bytes_saved: report.bytes_saved,
+            });
+            Some(report)
+        } else {
+            None
+        };
 
         let main_bundle_id = app.main_bundle_id()?;
         let main_app_name = app.main_app_name()?;
-        let main_app_id_str = format!("{}.{}", main_bundle_id, team.team_id);
+        let base_app_id_str = format!("{}.{}", main_bundle_id, team.team_id);
+        let mut main_app_id_str = base_app_id_str.clone();
         app.update_bundle_id(&main_bundle_id, &main_app_id_str)?;
-        let mut app_ids = app
-            .register_app_ids(
-                /*&self.extensions_behavior, */ &mut self.dev_session,
-                &team,
-            )
-            .await?;
+        self.emit(SideloadEvent::RegisteringAppIds);
+        let mut attempt = 0;
+        let mut app_ids = loop {
+            let attempt_start = transaction.app_ids.len();
+            let error = match app
+                .register_app_ids_tracked(
+                    /*&self.extensions_behavior, */ &mut self.dev_session,
+                    team,
+                    &self.app_id_reuse_policy,
+                    self.platform.device_type(),
+                    &mut transaction.app_ids,
+                )
+                .await
+            {
+                Ok(app_ids) => break app_ids,
+                Err(e) => e,
+            };
+            if !is_identifier_unavailable_error(&error) {
+                return Err(error);
+            }
+
+            attempt += 1;
+            let Some(suffix) = self.app_id_suffix_strategy.suffix(attempt) else {
+                return Err(error
+                    .context(crate::SideloadError::AppIdUnavailable(main_app_id_str))
+                    .into_dynamic());
+            };
+            // the app IDs registered before the taken one was hit belong to the abandoned identifier and would
+            // otherwise count against the team's limit
+            let abandoned = Transaction {
+                app_ids: transaction.app_ids.split_off(attempt_start),
+                app_groups: Vec::new(),
+            };
+            if !abandoned.is_empty() {
+                abandoned
+                    .rollback(&mut self.dev_session, team, self.platform.device_type())
+                    .await;
+            }
+            let new_app_id_str = format!("{}{}", base_app_id_str, suffix);
+            tracing::warn!(
+                "App ID {} is registered by another team, using {} instead",
+                main_app_id_str,
+                new_app_id_str
+            );
+            app.update_bundle_id(&main_app_id_str, &new_app_id_str)?;
+            main_app_id_str = new_app_id_str;
+        };
         let main_app_id = match app_ids
             .iter()
             .find(|app_id| app_id.identifier == main_app_id_str)
@@ -120,68 +1079,107 @@ impl Sideloader {
             }
         );
 
-        let app_group = self
+        let (app_group, created) = self
             .dev_session
-            .ensure_app_group(&team, &main_app_name, &group_identifier, None)
+            .ensure_app_group_tracked(
+                team,
+                &main_app_name,
+                &group_identifier,
+                self.platform.device_type(),
+            )
             .await?;
+        if created {
+            transaction.app_groups.push(app_group.clone());
+        }
 
         for app_id in app_ids.iter_mut() {
             app_id
-                .ensure_group_feature(&mut self.dev_session, &team)
+                .ensure_group_feature(&mut self.dev_session, team)
                 .await?;
 
             self.dev_session
-                .assign_app_group(&team, &app_group, app_id, None)
+                .ensure_app_group_assigned(team, &app_group, app_id, self.platform.device_type())
                 .await?;
 
             if increased_memory_limit {
                 self.dev_session
-                    .add_increased_memory_limit(&team, app_id)
+                    .add_increased_memory_limit(team, app_id)
                     .await?;
             }
         }
 
         info!("App IDs configured");
 
-        app.apply_special_app_behavior(&special, &group_identifier, &cert_identity)
-            .await
-            .context("Failed to modify app bundle")?;
-
         let provisioning_profile = self
-            .dev_session
-            .download_team_provisioning_profile(&team, &main_app_id, None)
+            .provisioning_profile(
+                team,
+                &main_app_id,
+                &cert_identity,
+                &group_identifier,
+                increased_memory_limit,
+                device.map(|d| d.udid),
+            )
             .await?;
 
         info!("Acquired provisioning profile");
 
-        app.bundle.write_info()?;
-        for ext in app.bundle.app_extensions_mut() {
-            ext.write_info()?;
-        }
-        for ext in app.bundle.frameworks_mut() {
-            ext.write_info()?;
-        }
-
-        tokio::fs::write(
-            app.bundle.bundle_dir.join("embedded.mobileprovision"),
-            provisioning_profile.encoded_profile.as_ref(),
-        )
-        .await?;
+        self.bundle_pipeline
+            .run(&mut PassContext {
+                app: &mut app,
+                special: &special,
+                team_id: &team.team_id,
+                group_identifier: &group_identifier,
+                certificate: &cert_identity,
+                provisioning_profile: &provisioning_profile,
+                original_info: &original_info,
+                product_type: device.and_then(|d| d.product_type),
+                pairing_file: device.and_then(|d| d.pairing_file),
+                supported_devices_behavior: &self.supported_devices_behavior,
+            })
+            .await?;
 
-        sign::sign(
-            &mut app,
-            &cert_identity,
-            &provisioning_profile,
-            &special,
-            &team,
-        )
-        .context("Failed to sign app")?;
+        Ok(PreparedApp {
+            app,
+            identity: cert_identity,
+            provisioning_profile,
+            special,
+            thinning,
+            bundle_id: main_app_id_str,
+            app_ids: app_ids
+                .iter()
+                .map(|app_id| app_id.identifier.clone())
+                .collect(),
+        })
+    }
 
-        info!("App signed!");
+    /// Check the outcome of [`sign_prepared`], refreshing the WWDR intermediates if the signature's chain wasn't trusted
+    async fn finish_signing(
+        &mut self,
+        signed: Result<SignedOutput, Report>,
+    ) -> Result<SignedApp, Report> {
+        let (signed, validation) = signed?;
+        self.last_signing_report = Some(signed.signing_report.clone());
+        for capability in &signed.signing_report.dropped_capabilities {
+            self.emit(SideloadEvent::CapabilityDropped {
+                entitlement: capability.entitlement.clone(),
+                reason: capability.reason.clone(),
+            });
+        }
 
-        Ok((app.bundle.bundle_dir.clone(), special))
+        if let Some(validation) = validation {
+            if validation.has_untrusted_chain() {
+                // the next signature will be chained to the refreshed intermediates
+                match WwdrIntermediates::refresh(self.storage.as_ref()).await {
+                    Ok(_) => tracing::warn!(
+                        "Refreshed WWDR intermediates after an untrusted chain, sideloading again should fix it"
+                    ),
+                    Err(e) => tracing::warn!("Failed to refresh WWDR intermediates: {:?}", e),
+                }
+            }
+            bail!(report!("Signed app failed validation").attach(validation));
+        }
+        Ok(signed)
     }
-
     #[cfg(feature = "install")]
     /// Sign and install an app to a device.
     pub async fn install_app(
@@ -190,33 +1188,1192 @@ impl Sideloader {
         app_path: PathBuf,
         // this is gross but will be replaced with proper entitlement handling later
         increased_memory_limit: bool,
-    ) -> Result<Option<SpecialApp>, Report> {
-        let device_info = IdeviceInfo::from_device(device_provider).await?;
-
-        let team = self.get_team().await?;
-        self.dev_session
-            .ensure_device_registered(&team, &device_info.name, &device_info.udid, None)
-            .await?;
-
-        let (signed_app_path, special_app) = self
-            .sign_app(app_path, Some(team), increased_memory_limit)
+    ) -> Result<InstallOutcome, Report> {
+        let started = Instant::now();
+        if let Some(cache) = &self.ipa_cache
+            && let Err(e) = cache.insert(&app_path)
+        {
+            tracing::warn!("Failed to cache app: {:?}", e);
+        }
+        let (device_info, signed, warnings) = self
+            .sign_for_device(device_provider, app_path, increased_memory_limit)
             .await?;
 
-        info!("Transferring App...");
+        self.install_signed(device_provider, &device_info, signed, warnings, started)
+            .await
+    }
 
-        crate::sideload::install::install_app(device_provider, &signed_app_path, |progress| {
-            info!("Installing: {}%", progress);
-        })
+    /// Sign and install several apps to a device, sharing everything that doesn't depend on the app
+    ///
+    /// The device is checked and registered, and the team, certificate and anisette data are fetched, once for the
+    /// whole batch. Before anything is signed, the app IDs of all apps are counted against what the team has left
+    /// and registered together, so a batch that doesn't fit fails before any app is installed instead of halfway.
+    /// The apps are then signed concurrently, with one app per CPU core being signed at once while the developer
+    /// services set up the next, and installed one after another, as the device handles one install at a time.
+    ///
+    /// Returns the outcome for every app, in the order given. A failed app doesn't stop the others, only failing to
+    /// prepare the device, get a certificate or register the app IDs fails the whole batch.
+    #[cfg(feature = "install")]
+    pub async fn install_batch(
+        &mut self,
+        device_provider: &impl IdeviceProvider,
+        app_paths: Vec<PathBuf>,
+    ) -> Result<Vec<BatchInstallResult>, Report> {
+        let (device_info, team, warnings) = self.prepare_device(device_provider).await?;
+        self.emit(SideloadEvent::RequestingCertificate);
+        let identity = self.signing_identity(&team).await?;
+
+        self.install_batch_inner(
+            device_provider,
+            &device_info,
+            &team,
+            &identity,
+            app_paths,
+            warnings,
+        )
+        .await
+    }
+
+    #[cfg(feature = "install")]
+    async fn install_batch_inner(
+        &mut self,
+        device_provider: &impl IdeviceProvider,
+        device_info: &IdeviceInfo,
+        team: &DeveloperTeam,
+        identity: &Arc<CertificateIdentity>,
+        app_paths: Vec<PathBuf>,
+        device_warnings: Vec<String>,
+    ) -> Result<Vec<BatchInstallResult>, Report> {
+        let mut results: Vec<Option<BatchInstallResult>> = Vec::new();
+        let mut pending = Vec::new();
+        // removed if the batch fails before the apps are handed on to signing and installing
+        let mut temp_dirs = Vec::new();
+        for source in app_paths {
+            if let Some(cache) = &self.ipa_cache
+                && let Err(e) = cache.insert(&source)
+            {
+                tracing::warn!("Failed to cache app: {:?}", e);
+            }
+            let app =
+                Application::with_options(source.clone(), &self.extract_options).and_then(|app| {
+                    let temp_dir = TempDirGuard::new(app.temp_path.clone());
+                    let reused = if self.skip_if_valid {
+                        self.reuse_if_valid(&app, team, device_info, identity)?
+                    } else {
+                        None
+                    };
+                    temp_dirs.push(temp_dir);
+                    Ok(match reused {
+                        Some(signed) => PendingApp::Signed(signed),
+                        None => PendingApp::Unsigned(app),
+                    })
+                });
+            match app {
+                Ok(app) => {
+                    pending.push((results.len(), source, app));
+                    results.push(None);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to load {}: {:?}", source.display(), e);
+                    results.push(Some(BatchInstallResult {
+                        source,
+                        result: Err(e),
+                    }));
+                }
+            }
+        }
+
+        let unsigned: Vec<&Application> = pending
+            .iter()
+            .filter_map(|(_, _, app)| match app {
+                PendingApp::Unsigned(app) => Some(app),
+                PendingApp::Signed(_) => None,
+            })
+            .collect();
+        let mut created = self
+            .register_batch_app_ids(team, &unsigned)
+            .await?
+            .into_iter();
+
+        let pairing_file = if self.embed_pairing_file {
+            Some(export_pairing_file(device_provider).await?)
+        } else {
+            None
+        };
+        temp_dirs.into_iter().for_each(TempDirGuard::keep);
+        let device = TargetDevice {
+            udid: &device_info.udid,
+            product_type: device_info.product_type.as_deref(),
+            pairing_file: pairing_file.as_deref(),
+        };
+        let mut to_sign = Vec::new();
+        let mut signing_sources = Vec::new();
+        let mut signed_apps = Vec::new();
+        for (index, source, app) in pending {
+            match app {
+                PendingApp::Signed(signed) => {
+                    signed_apps.push((index, source, Ok(signed), Duration::ZERO))
+                }
+                PendingApp::Unsigned(app) => match created.next().unwrap_or(Ok(Vec::new())) {
+                    Ok(app_ids) => {
+                        let transaction = Transaction {
+                            app_ids,
+                            ..Default::default()
+                        };
+                        to_sign.push((app, transaction));
+                        signing_sources.push((index, source));
+                    }
+                    Err(error) => {
+                        if let Some(temp_path) = &app.temp_path
+                            && let Err(e) = std::fs::remove_dir_all(temp_path)
+                        {
+                            tracing::warn!("Failed to remove temporary app file: {}", e);
+                        }
+                        signed_apps.push((index, source, Err(error), Duration::ZERO));
+                    }
+                },
+            }
+        }
+        let signing_started = Instant::now();
+        let signed = self
+            .sign_batch(to_sign, team, identity, Some(&device))
+            .await;
+        let signing_took = signing_started.elapsed();
+        signed_apps.extend(
+            signing_sources
+                .into_iter()
+                .zip(signed)
+                .map(|((index, source), signed)| (index, source, signed, signing_took)),
+        );
+        signed_apps.sort_by_key(|(index, ..)| *index);
+
+        for (index, source, signed, signing_took) in signed_apps {
+            // the apps were signed together, so each one took as long to sign as the whole batch
+            let started = Instant::now()
+                .checked_sub(signing_took)
+                .unwrap_or_else(Instant::now);
+            let result = match signed {
+                Ok(signed) => {
+                    let mut warnings = device_warnings.clone();
+                    self.stage_signed(&signed, &mut warnings);
+                    self.install_signed(device_provider, device_info, signed, warnings, started)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                tracing::warn!("Failed to install {}: {:?}", source.display(), e);
+            }
+            results[index] = Some(BatchInstallResult { source, result });
+        }
+
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Register the app IDs that signing every app of a batch needs, if they fit in what the team has left
+    ///
+    /// Returns the app IDs created for each app, so they can be rolled back if signing that app fails. App IDs
+    /// that are taken by another team are left for signing to resolve with the app ID suffix strategy. An app whose
+    /// app IDs can't be planned, e.g. because it has no bundle identifier, gets an error and the rest are registered.
+    #[cfg(feature = "install")]
+    async fn register_batch_app_ids(
+        &mut self,
+        team: &DeveloperTeam,
+        apps: &[&Application],
+    ) -> Result<Vec<Result<Vec<AppId>, Report>>, Report> {
+        let mut planned = Vec::with_capacity(apps.len());
+        let mut failed = Vec::with_capacity(apps.len());
+        for app in apps {
+            match app.main_bundle_id() {
+                Ok(main_bundle_id) => {
+                    planned.push(self.planned_app_ids(app, &main_bundle_id, team));
+                    failed.push(None);
+                }
+                Err(e) => {
+                    planned.push(Vec::new());
+                    failed.push(Some(e));
+                }
+            }
+        }
+        let in_use: Vec<&str> = planned
+            .iter()
+            .flatten()
+            .map(|(identifier, _)| identifier.as_str())
+            .collect();
+        // paid teams can register as many app IDs as they need, their available quantity isn't meaningful
+        let limited = team.capabilities().app_id_limit.is_some();
+
+        let mut app_ids = self
+            .dev_session
+            .list_app_ids(team, self.platform.device_type())
+            .await
+            .context("Failed to list app IDs for the developer team")?;
+        let missing = |app_ids: &crate::dev::app_ids::ListAppIdsResponse| {
+            let mut seen = HashSet::new();
+            planned
+                .iter()
+                .map(|identifiers| {
+                    identifiers
+                        .iter()
+                        .filter(|(identifier, _)| {
+                            !app_ids.app_ids.iter().any(|a| a.identifier == *identifier)
+                                && seen.insert(identifier.clone())
+                        })
+                        .cloned()
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let required =
+            |missing: &[Vec<(String, String)>]| missing.iter().map(Vec::len).sum::<usize>();
+
+        let mut to_register = missing(&app_ids);
+        if limited
+            && self.app_id_reuse_policy == AppIdReusePolicy::DeleteExpired
+            && let Some(available) = app_ids.available_quantity
+            && required(&to_register) as i64 > available
+        {
+            let deleted = self
+                .dev_session
+                .cleanup_expired(team, &in_use, self.platform.device_type())
+                .await
+                .context("Failed to clean up expired app IDs")?;
+            info!("Deleted {} expired app IDs", deleted.len());
+            app_ids = self
+                .dev_session
+                .list_app_ids(team, self.platform.device_type())
+                .await
+                .context("Failed to list app IDs for the developer team")?;
+            to_register = missing(&app_ids);
+        }
+        if limited
+            && let Some(available) = app_ids.available_quantity
+            && required(&to_register) as i64 > available
+        {
+            bail!(
+                "Not enough available app IDs for {} apps. {} are required, but only {} are available.",
+                apps.len(),
+                required(&to_register),
+                available
+            );
+        }
+
+        self.emit(SideloadEvent::RegisteringAppIds);
+        let mut created: Vec<Vec<AppId>> = Vec::with_capacity(to_register.len());
+        for identifiers in to_register {
+            let mut app_created = Vec::new();
+            for (identifier, name) in identifiers {
+                match self
+                    .dev_session
+                    .ensure_app_id(team, &name, &identifier, self.platform.device_type())
+                    .await
+                {
+                    Ok(app_id) => app_created.push(app_id),
+                    Err(e) if is_identifier_unavailable_error(&e) => {
+                        debug!("{} is taken, leaving it to signing", identifier);
+                    }
+                    Err(e) => {
+                        if self.rollback_on_failure {
+                            created.push(app_created);
+                            Transaction {
+                                app_ids: created.into_iter().flatten().collect(),
+                                ..Default::default()
+                            }
+                            .rollback(&mut self.dev_session, team, self.platform.device_type())
+                            .await;
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            created.push(app_created);
+        }
+        info!("Registered app IDs for {} apps", apps.len());
+
+        Ok(created
+            .into_iter()
+            .zip(failed)
+            .map(|(app_ids, failed)| match failed {
+                Some(e) => Err(e),
+                None => Ok(app_ids),
+            })
+            .collect())
+    }
+
+    #[cfg(feature = "install")]
+    async fn install_signed(
+        &mut self,
+        device_provider: &impl IdeviceProvider,
+        device_info: &IdeviceInfo,
+        signed: SignedApp,
+        mut warnings: Vec<String>,
+        started: Instant,
+    ) -> Result<InstallOutcome, Report> {
+        if self.clean_device_profiles
+            && let Err(e) = self.prepare_device_profiles(device_provider, &signed).await
+        {
+            tracing::warn!(
+                "Failed to clean up provisioning profiles on device: {:?}",
+                e
+            );
+            warnings.push(format!(
+                "Failed to clean up provisioning profiles on the device: {}",
+                e
+            ));
+        }
+
+        info!("Transferring App...");
+
+        let signing = started.elapsed();
+        let tracker = InstallTracker::new();
+        crate::sideload::install::install_app_with_options(
+            device_provider,
+            &signed.bundle_dir,
+            &self.install_options(&signed),
+            |progress| {
+                tracker.record(&progress);
+                self.report_install_progress(progress)
+            },
+        )
+        .await
+        .context("Failed to install app on device")?;
+
+        Ok(self
+            .finish_install(
+                signed,
+                &device_info.udid,
+                started,
+                tracker.phases(signing),
+                tracker.bytes_transferred(),
+                warnings,
+            )
+            .await)
+    }
+
+    /// Remove the stale profiles of the app from the device and install the one it was signed with
+    #[cfg(feature = "install")]
+    async fn prepare_device_profiles(
+        &self,
+        device_provider: &impl IdeviceProvider,
+        signed: &SignedApp,
+    ) -> Result<(), Report> {
+        let profile = std::fs::read(signed.bundle_dir.join("embedded.mobileprovision"))
+            .context("Failed to read embedded.mobileprovision")?;
+        let uuid = ProvisioningProfileInfo::parse(&profile)?.uuid;
+
+        let removed = remove_stale_profiles(
+            device_provider,
+            &signed.team_id,
+            &signed.app_ids,
+            uuid.as_deref(),
+            self.clock.as_ref(),
+        )
+        .await?;
+        if !removed.is_empty() {
+            info!("Removed {} stale provisioning profiles", removed.len());
+        }
+        install_profile(device_provider, &profile).await
+    }
+
+    #[cfg(feature = "install")]
+    /// List the apps this `Sideloader` installed on the device whose profile expires within the profile refresh margin
+    ///
+    /// Apps that were re-signed by something else since, or installed before isideload kept track of installs,
+    /// aren't included.
+    pub async fn apps_needing_refresh(
+        &self,
+        device_provider: &impl IdeviceProvider,
+    ) -> Result<Vec<InstalledApp>, Report> {
+        let device_info = IdeviceInfo::from_device(device_provider).await?;
+        let mut apps = Vec::new();
+        for app in list_installed_apps(device_provider).await? {
+            if self
+                .installed_app_expiry(&device_info.udid, &app)?
+                .is_some_and(|expires| self.expires_soon(expires))
+            {
+                apps.push(app);
+            }
+        }
+
+        Ok(apps)
+    }
+
+    #[cfg(feature = "install")]
+    /// Re-sign and reinstall an app this `Sideloader` installed, if its profile expires within the profile refresh margin
+    ///
+    /// `app_path` is the original app or IPA the installed app was made from. Free developer accounts get profiles
+    /// that are only valid for 7 days, so frontends typically call this for every app from
+    /// [`Self::apps_needing_refresh`] in the background.
+    pub async fn refresh_app(
+        &mut self,
+        device_provider: &impl IdeviceProvider,
+        bundle_id: &str,
+        app_path: PathBuf,
+    ) -> Result<RefreshOutcome, Report> {
+        let device_info = IdeviceInfo::from_device(device_provider).await?;
+        let installed = lookup_app(device_provider, bundle_id)
+            .await?
+            .ok_or_else(|| report!(SideloadError::AppNotInstalled(bundle_id.to_string())))?;
+
+        let Some(expires) = self.installed_app_expiry(&device_info.udid, &installed)? else {
+            info!(
+                "{} was not installed by isideload, not refreshing",
+                bundle_id
+            );
+            return Ok(RefreshOutcome::NotManaged);
+        };
+        if !self.expires_soon(expires) {
+            return Ok(RefreshOutcome::NotNeeded { expires });
+        }
+
+        info!("Refreshing {}", bundle_id);
+        let increased_memory_limit = installed
+            .info
+            .get("Entitlements")
+            .and_then(|v| v.as_dictionary())
+            .is_some_and(|e| e.contains_key("com.apple.developer.kernel.increased-memory-limit"));
+        let started = Instant::now();
+        let (device_info, signed, warnings) = self
+            .sign_for_device(device_provider, app_path, increased_memory_limit)
+            .await?;
+        let temp_dir = TempDirGuard::new(signed.temp_path.clone());
+        let signed_bundle_id = signed_bundle_id(&signed.bundle_dir)?;
+        if signed_bundle_id != bundle_id {
+            bail!(SideloadError::InvalidBundle(format!(
+                "The app was signed as {}, which is not the installed {}",
+                signed_bundle_id, bundle_id
+            )));
+        }
+        temp_dir.keep();
+
+        let outcome = self
+            .install_signed(device_provider, &device_info, signed, warnings, started)
+            .await?;
+        Ok(RefreshOutcome::Refreshed(outcome.sideload.special_app))
+    }
+
+    #[cfg(feature = "install")]
+    /// Like [`Self::refresh_app`], using the original app from the [`IpaCache`] configured with
+    /// [`crate::sideload::SideloaderBuilder::ipa_cache`]
+    pub async fn refresh_app_from_cache(
+        &mut self,
+        device_provider: &impl IdeviceProvider,
+        bundle_id: &str,
+    ) -> Result<RefreshOutcome, Report> {
+        let cache = self
+            .ipa_cache
+            .as_ref()
+            .ok_or_else(|| report!("No IPA cache is configured"))?;
+        let installed = lookup_app(device_provider, bundle_id)
+            .await?
+            .ok_or_else(|| report!(SideloadError::AppNotInstalled(bundle_id.to_string())))?;
+        let Some(cached) = cache.find_installed(bundle_id, installed.version.as_deref())? else {
+            info!("{} is not in the IPA cache, not refreshing", bundle_id);
+            return Ok(RefreshOutcome::NotManaged);
+        };
+
+        self.refresh_app(device_provider, bundle_id, cached.path)
+            .await
+    }
+
+    #[cfg(feature = "install")]
+    /// Remove the app IDs that sideloading `app_path` registered, e.g. after the app was deleted from the device
+    ///
+    /// The app IDs are found the same way signing registers them, `<bundle id>.<team id>` for the app and its
+    /// extensions. Nothing is removed, and [`SideloadError::AppIdInUse`] is returned, if the app is still installed on
+    /// the connected device or was installed on another device by this `Sideloader`, since it stops launching once its
+    /// app ID is gone. App groups the app IDs were assigned to are removed too, unless another app ID uses them.
+    pub async fn remove_app_id_for(
+        &mut self,
+        device_provider: &impl IdeviceProvider,
+        app_path: PathBuf,
+    ) -> Result<AppIdRemoval, Report> {
+        let team = self.get_team().await?;
+        let app = Application::with_options(app_path, &self.extract_options)?;
+        let identifiers = app
+            .main_bundle_id()
+            .map(|main_bundle_id| self.planned_app_ids(&app, &main_bundle_id, &team));
+        if let Some(temp_path) = &app.temp_path
+            && let Err(e) = std::fs::remove_dir_all(temp_path)
+        {
+            tracing::warn!("Failed to remove temporary app file: {}", e);
+        }
+        let identifiers: Vec<String> = identifiers?
+            .into_iter()
+            .map(|(identifier, _)| identifier)
+            .collect();
+        let main_app_id = identifiers[0].clone();
+
+        let device_info = IdeviceInfo::from_device(device_provider).await?;
+        if list_installed_apps(device_provider)
+            .await?
+            .iter()
+            .any(|installed| identifiers.contains(&installed.bundle_id))
+        {
+            bail!(SideloadError::AppIdInUse(main_app_id));
+        }
+        for udid in InstallRecord::devices(self.storage.as_ref(), &main_app_id)
+            .context("Failed to check which devices the app is installed on")?
+        {
+            if udid != device_info.udid {
+                bail!(
+                    report!(SideloadError::AppIdInUse(main_app_id))
+                        .attach(format!("Installed on {}", udid))
+                );
+            }
+            // the app isn't on the device anymore, so the record is stale
+            InstallRecord::delete(self.storage.as_ref(), &udid, &main_app_id)?;
+        }
+
+        let app_ids = self
+            .dev_session
+            .list_app_ids(&team, self.platform.device_type())
+            .await
+            .context("Failed to list app IDs for the developer team")?
+            .app_ids;
+        let (to_remove, remaining): (Vec<AppId>, Vec<AppId>) = app_ids
+            .into_iter()
+            .partition(|app_id| identifiers.contains(&app_id.identifier));
+        let mut removal = AppIdRemoval::default();
+        if to_remove.is_empty() {
+            info!("No app IDs registered for {}", main_app_id);
+            return Ok(removal);
+        }
+
+        // the assignments have to be read before the app IDs are gone
+        let mut groups: Vec<crate::dev::app_groups::AppGroup> = Vec::new();
+        for app_id in &to_remove {
+            match self
+                .dev_session
+                .list_group_assignments(&team, app_id, self.platform.device_type())
+                .await
+            {
+                Ok(assigned) => {
+                    for group in assigned {
+                        if !groups
+                            .iter()
+                            .any(|g| g.application_group == group.application_group)
+                        {
+                            groups.push(group);
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!(
+                    "Failed to list app groups of {}, keeping them: {:?}",
+                    app_id.identifier,
+                    e
+                ),
+            }
+        }
+
+        for app_id in to_remove {
+            self.dev_session
+                .delete_app_id(&team, &app_id.app_id_id, self.platform.device_type())
+                .await?;
+            removal.removed_app_ids.push(app_id.identifier);
+        }
+
+        'groups: for group in groups {
+            for app_id in &remaining {
+                match self
+                    .dev_session
+                    .list_group_assignments(&team, app_id, self.platform.device_type())
+                    .await
+                {
+                    Ok(assigned)
+                        if !assigned
+                            .iter()
+                            .any(|g| g.application_group == group.application_group) => {}
+                    Ok(_) => continue 'groups,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to list app groups of {}, keeping {}: {:?}",
+                            app_id.identifier,
+                            group.identifier,
+                            e
+                        );
+                        continue 'groups;
+                    }
+                }
+            }
+            self.dev_session
+                .delete_app_group(&team, &group, self.platform.device_type())
+                .await?;
+            removal.removed_app_groups.push(group.identifier);
+        }
+        info!(
+            "Removed {} app IDs and {} app groups of {}",
+            removal.removed_app_ids.len(),
+            removal.removed_app_groups.len(),
+            main_app_id
+        );
+
+        Ok(removal)
+    }
+
+    /// When the profile of an app this `Sideloader` installed expires, or `None` if it isn't managed by us
+    #[cfg(feature = "install")]
+    fn installed_app_expiry(
+        &self,
+        udid: &str,
+        app: &InstalledApp,
+    ) -> Result<Option<SystemTime>, Report> {
+        let Some(record) = InstallRecord::load(self.storage.as_ref(), udid, &app.bundle_id)? else {
+            return Ok(None);
+        };
+        if !record.signed_by(app.signer_identity.as_deref()) {
+            return Ok(None);
+        }
+        Ok(Some(record.expiration_date.into()))
+    }
+
+    #[cfg(feature = "install")]
+    fn expires_soon(&self, expires: SystemTime) -> bool {
+        self.clock
+            .expires_within(expires, self.profile_refresh_margin)
+    }
+
+    /// Remember the profile an installed app was signed with, so it can be refreshed before it expires
+    #[cfg(feature = "install")]
+    fn record_install(&self, udid: &str, signed: &SignedApp) -> Result<(), Report> {
+        let Some(record) = ProvisioningProfileInfo::from_bundle_dir(&signed.bundle_dir)?
+            .as_ref()
+            .and_then(InstallRecord::from_profile)
+        else {
+            return Ok(());
+        };
+        record.save(
+            self.storage.as_ref(),
+            udid,
+            &signed_bundle_id(&signed.bundle_dir)?,
+        )
+    }
+
+    #[cfg(feature = "install")]
+    /// Sign and install an app to a device, re-acquiring the connection from `factory` if it drops during the install.
+    ///
+    /// See [`crate::sideload::install::install_app_with_reconnect`] for details.
+    pub async fn install_app_with_reconnect(
+        &mut self,
+        factory: &dyn ProviderFactory,
+        app_path: PathBuf,
+        increased_memory_limit: bool,
+        max_attempts: u32,
+    ) -> Result<InstallOutcome, Report> {
+        let started = Instant::now();
+        let provider = factory
+            .connect()
+            .await
+            .context("Failed to connect to device")?;
+        let (device_info, signed, warnings) = self
+            .sign_for_device(provider.as_ref(), app_path, increased_memory_limit)
+            .await?;
+        drop(provider);
+
+        info!("Transferring App...");
+
+        let signing = started.elapsed();
+        let tracker = InstallTracker::new();
+        crate::sideload::install::install_app_with_reconnect(
+            factory,
+            &signed.bundle_dir,
+            &self.install_options(&signed),
+            max_attempts,
+            |progress| {
+                tracker.record(&progress);
+                self.report_install_progress(progress)
+            },
+        )
+        .await
+        .context("Failed to install app on device")?;
+
+        Ok(self
+            .finish_install(
+                signed,
+                &device_info.udid,
+                started,
+                tracker.phases(signing),
+                tracker.bytes_transferred(),
+                warnings,
+            )
+            .await)
+    }
+
+    #[cfg(feature = "install")]
+    async fn sign_for_device(
+        &mut self,
+        device_provider: &dyn IdeviceProvider,
+        app_path: PathBuf,
+        increased_memory_limit: bool,
+    ) -> Result<(IdeviceInfo, SignedApp, Vec<String>), Report> {
+        let app = Application::with_options(app_path, &self.extract_options)?;
+        self.sign_loaded_for_device(device_provider, app, increased_memory_limit)
+            .await
+    }
+
+    /// Like [`Self::sign_for_device`], with an app that was already loaded
+    ///
+    /// The app's temporary directory is removed if anything fails, otherwise it belongs to the signed app.
+    #[cfg(feature = "install")]
+    async fn sign_loaded_for_device(
+        &mut self,
+        device_provider: &dyn IdeviceProvider,
+        app: Application,
+        increased_memory_limit: bool,
+    ) -> Result<(IdeviceInfo, SignedApp, Vec<String>), Report> {
+        let temp_dir = TempDirGuard::new(app.temp_path.clone());
+        let (device_info, team, mut warnings) = self.prepare_device(device_provider).await?;
+
+        self.emit(SideloadEvent::RequestingCertificate);
+        let identity = self.signing_identity(&team).await?;
+        let reused = if self.skip_if_valid {
+            self.reuse_if_valid(&app, &team, &device_info, &identity)?
+        } else {
+            None
+        };
+        let signed = match reused {
+            Some(signed) => signed,
+            None => {
+                let pairing_file = if self.embed_pairing_file {
+                    Some(export_pairing_file(device_provider).await?)
+                } else {
+                    None
+                };
+                let device = TargetDevice {
+                    udid: &device_info.udid,
+                    product_type: device_info.product_type.as_deref(),
+                    pairing_file: pairing_file.as_deref(),
+                };
+                self.sign_app_with(app, &team, identity, Some(&device), increased_memory_limit)
+                    .await?
+            }
+        };
+        temp_dir.keep();
+        self.stage_signed(&signed, &mut warnings);
+
+        Ok((device_info, signed, warnings))
+    }
+
+    /// Check the device can be sideloaded to and register it to the team
+    ///
+    /// Returns the device, the team and the warnings about the device, like Developer Mode being disabled.
+    #[cfg(feature = "install")]
+    async fn prepare_device(
+        &mut self,
+        device_provider: &dyn IdeviceProvider,
+    ) -> Result<(IdeviceInfo, DeveloperTeam, Vec<String>), Report> {
+        let mut warnings = Vec::new();
+        let mut device_info = IdeviceInfo::from_device(device_provider).await?;
+        device_info.alias =
+            device_alias(self.storage.as_ref(), &device_info.udid).unwrap_or_else(|e| {
+                tracing::warn!("Failed to read the alias of {}: {:?}", device_info.udid, e);
+                None
+            });
+        if developer_mode_status(device_provider).await? == DeveloperModeStatus::Disabled {
+            if self.require_developer_mode {
+                bail!(SideloadError::DeveloperModeDisabled);
+            }
+            tracing::warn!(
+                "Developer Mode is disabled on {}, the app won't launch until it is enabled",
+                device_info.display_name()
+            );
+            self.emit(SideloadEvent::DeveloperModeDisabled);
+            warnings.push(format!(
+                "Developer Mode is disabled on {}, the app won't launch until it is enabled",
+                device_info.display_name()
+            ));
+        }
+
+        if let Some(platform) = device_info
+            .product_type
+            .as_deref()
+            .and_then(TargetPlatform::from_product_type)
+            && platform != self.platform
+        {
+            bail!(
+                "{} is a {} device, but the sideloader targets {}",
+                device_info.display_name(),
+                platform,
+                self.platform
+            );
+        }
+
+        let team = self.get_team().await?;
+        self.emit(SideloadEvent::RegisteringDevice {
+            udid: device_info.udid.clone(),
+            name: device_info.name.clone(),
+            alias: device_info.alias.clone(),
+        });
+        // the alias is what the user calls the device, so it is also the name it gets in the team
+        let registration = self
+            .dev_session
+            .ensure_device_registered(
+                &team,
+                device_info.display_name(),
+                &device_info.udid,
+                self.platform.device_type(),
+                self.rename_registered_devices,
+            )
+            .await?;
+        match &registration {
+            DeviceRegistration::Added(_) => {
+                info!(
+                    "Registered {} to team {}",
+                    device_info.display_name(),
+                    team.team_id
+                )
+            }
+            DeviceRegistration::Renamed(_) => {
+                info!("Renamed device to {}", device_info.display_name())
+            }
+            DeviceRegistration::AlreadyRegistered(_) => {}
+        }
+
+        Ok((device_info, team, warnings))
+    }
+
+    /// Remember to remove the signed app after installing it, and warn about the capabilities it lost
+    #[cfg(feature = "install")]
+    fn stage_signed(&mut self, signed: &SignedApp, warnings: &mut Vec<String>) {
+        if self.delete_app_after_install {
+            self.staging_paths.push(signed.bundle_dir.clone());
+            self.staging_paths.extend(signed.temp_path.clone());
+        }
+        for capability in &signed.signing_report.dropped_capabilities {
+            warnings.push(format!(
+                "Dropped {}: {}",
+                capability.entitlement, capability.reason
+            ));
+        }
+    }
+
+    /// Get the team provisioning profile for the app ID, reusing the cached one if it is still usable
+    ///
+    /// The cached profile is refreshed when it expires within the configured margin, or when it doesn't match
+    /// the certificate, app group or device it is needed for.
+    async fn provisioning_profile(
+        &mut self,
+        team: &DeveloperTeam,
+        app_id: &AppId,
+        cert_identity: &CertificateIdentity,
+        group_identifier: &str,
+        increased_memory_limit: bool,
+        udid: Option<&str>,
+    ) -> Result<Vec<u8>, Report> {
+        if self.cache_profiles {
+            match CachedProfile::load(self.storage.as_ref(), &team.team_id, &app_id.app_id_id) {
+                Ok(Some(cached)) => {
+                    if self.cached_profile_usable(
+                        &cached,
+                        cert_identity,
+                        group_identifier,
+                        increased_memory_limit,
+                        udid,
+                    )? {
+                        info!("Using cached provisioning profile");
+                        return Ok(cached.encoded_profile.into());
+                    }
+                    debug!("Cached provisioning profile can't be used, downloading a new one");
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to load cached provisioning profile: {:?}", e),
+            }
+        }
+
+        let profile = self
+            .dev_session
+            .download_team_provisioning_profile(team, app_id, self.platform.device_type())
+            .await?;
+
+        if self.cache_profiles
+            && let Err(e) = CachedProfile::save(
+                &profile,
+                self.storage.as_ref(),
+                &team.team_id,
+                &app_id.app_id_id,
+            )
+        {
+            tracing::warn!("Failed to cache provisioning profile: {:?}", e);
+        }
+
+        Ok(profile.encoded_profile.into())
+    }
+
+    fn cached_profile_usable(
+        &self,
+        cached: &CachedProfile,
+        cert_identity: &CertificateIdentity,
+        group_identifier: &str,
+        increased_memory_limit: bool,
+        udid: Option<&str>,
+    ) -> Result<bool, Report> {
+        if self
+            .clock
+            .expires_within(cached.date_expire.into(), self.profile_refresh_margin)
+        {
+            return Ok(false);
+        }
+
+        let info = match ProvisioningProfileInfo::parse(cached.encoded_profile.as_ref()) {
+            Ok(info) => info,
+            Err(e) => {
+                tracing::warn!("Failed to parse cached provisioning profile: {:?}", e);
+                return Ok(false);
+            }
+        };
+        let has_group = info
+            .entitlements
+            .get("com.apple.security.application-groups")
+            .and_then(|v| v.as_array())
+            .is_some_and(|groups| {
+                groups
+                    .iter()
+                    .any(|g| g.as_string() == Some(group_identifier))
+            });
+
+        Ok(
+            info.allows_certificate(&cert_identity.certificate.encode_der()?)
+                && udid.is_none_or(|udid| info.covers_device(udid))
+                && has_group
+                && (!increased_memory_limit
+                    || info
+                        .entitlements
+                        .contains_key("com.apple.developer.kernel.increased-memory-limit")),
+        )
+    }
+
+    /// Check whether the app is already signed with our certificate and a profile that covers the device
+    /// and isn't about to expire, in which case it can be installed without signing it again.
+    ///
+    /// The returned signed app uses the app's files, so the app must not be signed afterwards.
+    #[cfg(feature = "install")]
+    fn reuse_if_valid(
+        &self,
+        app: &Application,
+        team: &DeveloperTeam,
+        device_info: &IdeviceInfo,
+        cert_identity: &CertificateIdentity,
+    ) -> Result<Option<SignedApp>, Report> {
+        let Some(profile) = ProvisioningProfileInfo::from_bundle_dir(&app.bundle.bundle_dir)?
+        else {
+            return Ok(None);
+        };
+
+        if profile.team_id.as_deref() != Some(team.team_id.as_str())
+            || !profile.covers_device(&device_info.udid)
+            || profile.expires_within(self.clock.as_ref(), SKIP_IF_VALID_MIN_VALIDITY)
+        {
+            info!("App is not validly signed for this device, signing");
+            return Ok(None);
+        }
+
+        if !profile.allows_certificate(&cert_identity.certificate.encode_der()?) {
+            info!("App was signed with a different certificate, signing");
+            return Ok(None);
+        }
+
+        info!("App is already signed for this device, skipping signing");
+        let itunes_metadata = self.itunes_metadata_behavior.resolve(
+            app.itunes_metadata.as_ref(),
+            &app.bundle,
+            &self.apple_email,
+        );
+        Ok(Some(SignedApp {
+            bundle_dir: app.bundle.bundle_dir.clone(),
+            special_app: app.get_special_app(),
+            itunes_metadata,
+            temp_path: app.temp_path.clone(),
+            signing_report: SigningReport::default(),
+            bundle_id: app.main_bundle_id()?,
+            team_id: team.team_id.clone(),
+            certificate_serial: cert_identity.get_serial_number(),
+            profile_expiration: profile.expiration_date,
+            app_ids: Vec::new(),
+        }))
+    }
+
+    #[cfg(feature = "install")]
+    fn install_options(&self, signed: &SignedApp) -> crate::sideload::install::InstallOptions {
+        crate::sideload::install::InstallOptions {
+            itunes_metadata: signed.itunes_metadata.clone(),
+            verification: self.upload_verification,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "install")]
+    async fn finish_install(
+        &mut self,
+        signed: SignedApp,
+        udid: &str,
+        started: Instant,
+        phases: InstallPhases,
+        bytes_transferred: u64,
+        mut warnings: Vec<String>,
+    ) -> InstallOutcome {
+        if let Err(e) = self.record_install(udid, &signed) {
+            tracing::warn!("Failed to record installed app: {:?}", e);
+            warnings.push(format!(
+                "Failed to record the install, so it can't be refreshed automatically: {}",
+                e
+            ));
+        }
+        // read before the signed app is removed
+        let display_name = bundle_display_name(&signed.bundle_dir);
+        self.remove_staging_paths();
+        self.emit(SideloadEvent::Done);
+
+        let sideload = signed.outcome(started);
+        InstallOutcome {
+            bundle_id: sideload.bundle_id.clone(),
+            display_name,
+            profile_expiration: sideload.profile_expiration,
+            bytes_transferred,
+            phases,
+            warnings,
+            sideload,
+        }
+    }
+
+    fn remove_staging_paths(&mut self) {
+        for path in self.staging_paths.drain(..) {
+            if path.exists()
+                && let Err(e) = std::fs::remove_dir_all(&path)
+            {
+                tracing::warn!("Failed to remove temporary signed app file: {}", e);
+            }
+        }
+    }
+
+    /// Flush storage, save the anisette state and remove temporary files left behind by unfinished installs.
+    ///
+    /// Dropping the `Sideloader` does the same on a best-effort basis, but can't save the anisette state
+    /// or report errors, so prefer calling this before your program exits.
+    pub async fn shutdown(mut self) -> Result<(), Report> {
+        self.remove_staging_paths();
+        self.dev_session
+            .persist_anisette_state()
+            .await
+            .context("Failed to save anisette state")?;
+        self.storage.flush().context("Failed to flush storage")?;
+        Ok(())
+    }
+
+    #[cfg(feature = "install")]
+    /// Install an app that was already signed elsewhere, without re-signing it.
+    ///
+    /// The embedded provisioning profile is checked against the device, and the app's signature against the profile's
+    /// team and certificates. If the profile does not cover the device, has expired or doesn't allow the signature, the
+    /// app is re-signed and installed like [`Self::install_app`] does when `resign_if_invalid` is set, otherwise a
+    /// [`crate::SideloadError::ProfileMismatch`] error describing the problem is returned.
+    pub async fn install_prebuilt_app(
+        &mut self,
+        device_provider: &impl IdeviceProvider,
+        app_path: PathBuf,
+        resign_if_invalid: bool,
+    ) -> Result<Option<SpecialApp>, Report> {
+        let started = Instant::now();
+        let device_info = IdeviceInfo::from_device(device_provider).await?;
+        let app = Application::with_options(app_path.clone(), &self.extract_options)?;
+        let temp_dir = TempDirGuard::new(app.temp_path.clone());
+
+        let diagnosis = match ProvisioningProfileInfo::from_bundle_dir(&app.bundle.bundle_dir)? {
+            Some(profile) => match profile.diagnose(&device_info.udid, self.clock.as_ref()) {
+                ProfileDiagnosis::Valid => {
+                    // an unsigned app has no team, so it is never allowed
+                    let signer = bundle_signer(&app.bundle)?;
+                    let (team_id, certificate) = signer
+                        .as_ref()
+                        .map(|signer| (signer.team_id.as_deref(), signer.certificate.as_slice()))
+                        .unwrap_or_default();
+                    profile.diagnose_signer(team_id, certificate)
+                }
+                diagnosis => diagnosis,
+            },
+            None => ProfileDiagnosis::MissingProfile,
+        };
+
+        if diagnosis.needs_resign() {
+            if !resign_if_invalid {
+                bail!(crate::SideloadError::ProfileMismatch(diagnosis));
+            }
+            tracing::warn!("Re-signing prebuilt app: {}", diagnosis);
+            if let Some(cache) = &self.ipa_cache
+                && let Err(e) = cache.insert(&app_path)
+            {
+                tracing::warn!("Failed to cache app: {:?}", e);
+            }
+            // signing removes the extracted app itself if it fails
+            temp_dir.keep();
+            let (device_info, signed, warnings) = self
+                .sign_loaded_for_device(device_provider, app, false)
+                .await?;
+            return self
+                .install_signed(device_provider, &device_info, signed, warnings, started)
+                .await
+                .map(|outcome| outcome.sideload.special_app);
+        }
+
+        info!("Prebuilt app is signed for this device, installing as-is");
+        crate::sideload::install::install_app_with_options(
+            device_provider,
+            &app.bundle.bundle_dir,
+            &Default::default(),
+            |progress| self.report_install_progress(progress),
+        )
         .await
         .context("Failed to install app on device")?;
 
-        if self.delete_app_after_install
-            && let Err(e) = tokio::fs::remove_dir_all(signed_app_path).await
+        Ok(app.get_special_app())
+    }
+
+    /// Get the developer teams available to the account
+    ///
+    /// The list is fetched once and cached, use [`Self::refresh_teams`] to fetch it again.
+    pub async fn teams(&mut self) -> Result<&[DeveloperTeam], Report> {
+        if self.teams.is_none() {
+            return self.refresh_teams().await;
+        }
+        Ok(self.teams.as_deref().unwrap_or_default())
+    }
+
+    /// Fetch the developer teams again, replacing the cached list
+    ///
+    /// If the previously selected team is no longer available, it will be selected again on the next [`Self::get_team`] call.
+    pub async fn refresh_teams(&mut self) -> Result<&[DeveloperTeam], Report> {
+        self.emit(SideloadEvent::Authenticating);
+        let mut teams = self.dev_session.list_teams().await?;
+        if teams.is_empty() {
+            info!("No developer teams found, trying to set up a personal team");
+            teams = self.dev_session.bootstrap_personal_team().await?;
+            if teams.is_empty() {
+                bail!(crate::SideloadError::NeedsTeamBootstrap(
+                    TEAM_BOOTSTRAP_URL.to_string()
+                ));
+            }
+        }
+        if let Some(team) = &self.team
+            && !teams.iter().any(|t| t.team_id == team.team_id)
         {
-            tracing::warn!("Failed to remove temporary signed app file: {}", e);
+            info!("Selected team {} is no longer available", team.team_id);
+            self.team = None;
         }
+        Ok(self.teams.insert(teams).as_slice())
+    }
 
-        Ok(special_app)
+    /// Get the team like [`Self::get_team`], without setting up a personal team if the account has none
+    ///
+    /// Returns `None` if the account has no teams yet.
+    async fn existing_team(&mut self) -> Result<Option<DeveloperTeam>, Report> {
+        if self.team.is_none() && self.teams.is_none() {
+            self.emit(SideloadEvent::Authenticating);
+            let teams = self.dev_session.list_teams().await?;
+            if teams.is_empty() {
+                return Ok(None);
+            }
+            self.teams = Some(teams);
+        }
+        self.get_team().await.map(Some)
     }
 
     /// Get the developer team according to the configured team selection behavior
@@ -224,7 +2381,7 @@ impl Sideloader {
         if let Some(team) = &self.team {
             return Ok(team.clone());
         }
-        let teams = self.dev_session.list_teams().await?;
+        let teams = self.teams().await?.to_vec();
         let team = match teams.len() {
             0 => {
                 bail!("No developer teams available")
@@ -252,14 +2409,306 @@ impl Sideloader {
         if !matches!(&self.team_selection, TeamSelection::PromptAlways(_)) {
             self.team = Some(team.clone());
         }
+        self.emit(SideloadEvent::TeamSelected(team.clone()));
         Ok(team)
     }
 
+    /// Log install progress and forward it to the observers
+    #[cfg(feature = "install")]
+    fn report_install_progress(&self, progress: crate::sideload::install::InstallProgress) {
+        use crate::sideload::install::InstallProgress;
+
+        match progress {
+            InstallProgress::Uploading {
+                file,
+                files_done,
+                files_total,
+                bytes_done,
+                bytes_total,
+            } => {
+                tracing::debug!("Uploading {} ({}/{})", file, files_done, files_total);
+                self.emit(SideloadEvent::Uploading {
+                    percent: bytes_done * 100 / bytes_total.max(1),
+                });
+            }
+            InstallProgress::Installing(status) => {
+                info!("Installing: {}", status);
+                self.emit(SideloadEvent::Installing {
+                    percent: status.percent,
+                });
+                self.emit(SideloadEvent::InstallStatus(status));
+            }
+        }
+    }
+
+    /// Set the observers and forward the developer session's requests to them
+    pub(crate) fn set_observers(&mut self, observers: Vec<SideloadObserver>) {
+        self.observers = Arc::new(observers);
+        let observers = self.observers.clone();
+        let hook: DeveloperRequestHook = Arc::new(move |trace| {
+            let event = SideloadEvent::DeveloperRequest(trace.clone());
+            for observer in observers.iter() {
+                observer(&event);
+            }
+        });
+        self.dev_session.set_request_hook(Some(hook));
+    }
+
+    pub(crate) fn emit(&self, event: SideloadEvent) {
+        for observer in self.observers.iter() {
+            observer(&event);
+        }
+    }
+
+    /// Register a device to the selected team without sideloading anything, e.g. to use it with a profile built
+    /// elsewhere
+    ///
+    /// The device is registered for the sideloader's [`TargetPlatform`], and renamed to `name` if
+    /// [`crate::sideload::SideloaderBuilder::rename_registered_devices`] is set.
+    pub async fn register_device(
+        &mut self,
+        udid: &str,
+        name: &str,
+    ) -> Result<RegisteredDevice, Report> {
+        let team = self.get_team().await?;
+        self.emit(SideloadEvent::RegisteringDevice {
+            udid: udid.to_string(),
+            name: name.to_string(),
+            alias: None,
+        });
+        let registered = self
+            .dev_session
+            .register_device(
+                &team,
+                name,
+                udid,
+                self.platform.device_type(),
+                self.rename_registered_devices,
+            )
+            .await?;
+        if let Some(remaining) = registered.remaining_quota {
+            info!(
+                "{} devices of this kind can still be registered to team {}",
+                remaining, team.team_id
+            );
+        }
+        Ok(registered)
+    }
+
+    /// What was signed by the most recent sign or install call that signed an app
+    pub fn last_signing_report(&self) -> Option<&SigningReport> {
+        self.last_signing_report.as_ref()
+    }
+
     pub fn get_dev_session(&mut self) -> &mut DeveloperSession {
         &mut self.dev_session
     }
 
+    /// Remember `alias` as the name of the device with `udid` in this `Sideloader`'s storage, or forget it if `None`
+    ///
+    /// The alias is used in progress events and as the device's name when it is registered to a team.
+    #[cfg(feature = "install")]
+    pub fn set_device_alias(&self, udid: &str, alias: Option<&str>) -> Result<(), Report> {
+        set_device_alias(self.storage.as_ref(), udid, alias)
+    }
+
+    /// The alias remembered for the device with `udid`, see [`Self::set_device_alias`]
+    #[cfg(feature = "install")]
+    pub fn device_alias(&self, udid: &str) -> Result<Option<String>, Report> {
+        device_alias(self.storage.as_ref(), udid)
+    }
+
     pub fn get_email(&self) -> &str {
         &self.apple_email
     }
 }
+
+impl Drop for Sideloader {
+    fn drop(&mut self) {
+        self.remove_staging_paths();
+        if let Err(e) = self.storage.flush() {
+            tracing::warn!("Failed to flush storage: {:?}", e);
+        }
+    }
+}
+
+/// Package a signed app as an IPA and remove its temporary directory
+fn package_signed_app(
+    signed: &SignedApp,
+    output_path: &Path,
+    keep_symbols: bool,
+) -> Result<(), Report> {
+    let symbols_dir = signed
+        .temp_path
+        .as_ref()
+        .filter(|_| keep_symbols)
+        .map(|p| p.join("Symbols"));
+    let result = package::write_ipa(
+        &signed.bundle_dir,
+        output_path,
+        symbols_dir.as_deref(),
+        signed.itunes_metadata.as_ref(),
+    );
+
+    if let Some(temp_path) = &signed.temp_path
+        && let Err(e) = std::fs::remove_dir_all(temp_path)
+    {
+        tracing::warn!("Failed to remove temporary signed app file: {}", e);
+    }
+    result.context("Failed to package signed app")?;
+    Ok(())
+}
+
+/// What signing an app needs from the [`Sideloader`], so it can happen on another thread
+struct SigningConfig {
+    team_id: String,
+    customizations: BundleCustomizations,
+    entitlement_policy: EntitlementPolicy,
+    validate_signature: bool,
+    itunes_metadata_behavior: ITunesMetadataBehavior,
+    apple_email: String,
+    observers: Arc<Vec<SideloadObserver>>,
+}
+
+/// An app whose app IDs and provisioning profile are ready, see [`Sideloader::prepare_signing`]
+struct PreparedApp {
+    app: Application,
+    identity: Arc<CertificateIdentity>,
+    provisioning_profile: Vec<u8>,
+    special: Option<SpecialApp>,
+    thinning: Option<ThinningReport>,
+    /// The bundle identifier of the main app, after it was changed for the team
+    bundle_id: String,
+    app_ids: Vec<String>,
+}
+
+/// A signed app, and the validation report if [`crate::sideload::SideloaderBuilder::validate_signature`] found a
+/// problem with its signature
+type SignedOutput = (SignedApp, Option<ValidationReport>);
+
+/// Sign a prepared app, without needing the [`Sideloader`] itself
+fn sign_prepared(prepared: PreparedApp, config: &SigningConfig) -> Result<SignedOutput, Report> {
+    let PreparedApp {
+        mut app,
+        identity,
+        provisioning_profile,
+        special,
+        thinning,
+        bundle_id,
+        app_ids,
+    } = prepared;
+
+    let mut signing_report = sign::sign(
+        &mut app,
+        identity.as_ref(),
+        &provisioning_profile,
+        &special,
+        &config.team_id,
+        &config.customizations,
+        &config.entitlement_policy,
+        &|event| {
+            for observer in config.observers.iter() {
+                observer(&event);
+            }
+        },
+    )
+    .context("Failed to sign app")?;
+    signing_report.thinning = thinning;
+    info!("App signed!");
+
+    let validation = if config.validate_signature {
+        let validation = validate_signed_app(&app.bundle.bundle_dir, &config.team_id)?;
+        if validation.is_valid() {
+            debug!("Signed app validated: {}", validation);
+            None
+        } else {
+            Some(validation)
+        }
+    } else {
+        None
+    };
+
+    let itunes_metadata = config.itunes_metadata_behavior.resolve(
+        app.itunes_metadata.as_ref(),
+        &app.bundle,
+        &config.apple_email,
+    );
+
+    let signed = SignedApp {
+        bundle_dir: app.bundle.bundle_dir.clone(),
+        special_app: special,
+        itunes_metadata,
+        temp_path: app.temp_path.clone(),
+        signing_report,
+        bundle_id,
+        team_id: config.team_id.clone(),
+        certificate_serial: identity.get_serial_number(),
+        profile_expiration: ProvisioningProfileInfo::parse(&provisioning_profile)
+            .ok()
+            .and_then(|profile| profile.expiration_date),
+        app_ids,
+    };
+    Ok((signed, validation))
+}
+
+/// Collect the IPAs and `.app` bundles under `dir`, without looking inside bundles or `skip` or following symlinks
+fn find_apps(dir: &Path, skip: &Path, apps: &mut Vec<PathBuf>) -> Result<(), Report> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // the file type of the entry itself, so symlinks aren't followed out of `dir` or into a loop
+        let file_type = entry.file_type()?;
+        if path == skip || file_type.is_symlink() {
+            continue;
+        }
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        if file_type.is_dir() {
+            if extension.as_deref() == Some("app") {
+                apps.push(path);
+            } else {
+                find_apps(&path, skip, apps)?;
+            }
+        } else if extension.as_deref() == Some("ipa") {
+            apps.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The file name of the signed IPA for `source`, numbered if another app in the batch has the same name
+fn unique_ipa_name(source: &Path, used: &mut HashSet<String>) -> String {
+    let stem = source
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "app".to_string());
+    let mut name = format!("{}.ipa", stem);
+    let mut number = 2;
+    while !used.insert(name.clone()) {
+        name = format!("{}-{}.ipa", stem, number);
+        number += 1;
+    }
+    name
+}
+
+/// The name the app shows on the home screen
+#[cfg(feature = "install")]
+fn bundle_display_name(bundle_dir: &Path) -> Option<String> {
+    let info: Dictionary = plist::from_file(bundle_dir.join("Info.plist")).ok()?;
+    info.get("CFBundleDisplayName")
+        .or_else(|| info.get("CFBundleName"))
+        .and_then(|v| v.as_string())
+        .map(str::to_string)
+}
+
+/// The bundle identifier of a signed app bundle
+#[cfg(feature = "install")]
+fn signed_bundle_id(bundle_dir: &Path) -> Result<String, Report> {
+    let info: Dictionary =
+        plist::from_file(bundle_dir.join("Info.plist")).context("Failed to read Info.plist")?;
+    Ok(info
+        .get("CFBundleIdentifier")
+        .and_then(|v| v.as_string())
+        .ok_or_report()
+        .context("Info.plist has no CFBundleIdentifier")?
+        .to_string())
+}
@@ -1,92 +1,211 @@
+use std::path::Path;
+
 use apple_codesign::{SigningSettings, UnifiedSigner};
 use plist::Dictionary;
 use plist_macro::plist_to_xml_string;
-use rootcause::{option_ext::OptionExt, prelude::*};
-use tracing::info;
+use rootcause::prelude::*;
+use tracing::{debug, info, warn};
 
 use crate::{
-    dev::{app_ids::Profile, teams::DeveloperTeam},
     sideload::{
         application::{Application, SpecialApp},
-        cert_identity::CertificateIdentity,
+        bundle::Bundle,
+        customization::BundleCustomizations,
+        entitlements::{
+            EntitlementPolicy, PROFILE_ENTITLEMENTS, is_restricted_entitlement, merge_entitlements,
+            merge_value, requested_entitlements,
+        },
+        events::SideloadEvent,
+        profile::profile_plist,
+        signing_report::{BundleSigningReport, SigningReport},
     },
-    util::plist::PlistDataExtract,
+    util::plist::{PlistDataExtract, diff as plist_diff},
 };
 
+/// An entitlement the app asked for that the provisioning profile doesn't grant
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct DroppedCapability {
+    /// The entitlement key, e.g. `aps-environment`
+    pub entitlement: String,
+    /// Why the entitlement couldn't be kept
+    pub reason: String,
+}
+
+/// A certificate and private key that can sign apps
+///
+/// Implemented by the [`CertificateIdentity`](crate::sideload::cert_identity::CertificateIdentity) managed through
+/// the developer services and by [`ImportedIdentity`](crate::sideload::imported::ImportedIdentity) loaded from a p12 file.
+pub trait SigningIdentity {
+    fn setup_signing_settings<'a>(
+        &'a self,
+        settings: &mut SigningSettings<'a>,
+    ) -> Result<(), Report>;
+}
+
+/// Sign the app and all of its nested bundles
+///
+/// `provisioning_profile` is the raw (CMS signed) profile the app is signed for, and `team_id` the team it belongs to.
+/// `customizations` are applied to each bundle right before it is signed, and entitlements outside `policy` are
+/// dropped after that.
+/// Returns a summary of each signed bundle, and the entitlements that were requested by the app but dropped because
+/// the profile doesn't grant them. If signing fails, the summary of the bundles signed so far is attached to the error.
+#[allow(clippy::too_many_arguments)]
 pub fn sign(
     app: &mut Application,
-    cert_identity: &CertificateIdentity,
-    provisioning_profile: &Profile,
+    identity: &dyn SigningIdentity,
+    provisioning_profile: &[u8],
     special: &Option<SpecialApp>,
-    team: &DeveloperTeam,
-) -> Result<(), Report> {
-    let mut settings = signing_settings(cert_identity)?;
-    let entitlements: Dictionary = entitlements_from_prov(
-        provisioning_profile.encoded_profile.as_ref(),
-        special,
-        team,
-    )?;
-
-    settings
-        .set_entitlements_xml(
-            apple_codesign::SettingsScope::Main,
-            plist_to_xml_string(&entitlements),
-        )
-        .context("Failed to set entitlements XML")?;
-    let signer = UnifiedSigner::new(settings);
-
-    for bundle in app.bundle.collect_bundles_sorted() {
-        info!(
-            "Signing {}",
-            bundle
-                .bundle_dir
-                .file_name()
-                .unwrap_or(bundle.bundle_dir.as_os_str())
-                .to_string_lossy()
-        );
-        signer
+    team_id: &str,
+    customizations: &BundleCustomizations,
+    policy: &EntitlementPolicy,
+    on_event: &dyn Fn(SideloadEvent),
+) -> Result<SigningReport, Report> {
+    let entitlements: Dictionary = entitlements_from_prov(provisioning_profile, special, team_id)?;
+    let parent_app_id = format!(
+        "{}.{}",
+        team_id,
+        app.bundle.bundle_identifier().unwrap_or("")
+    );
+
+    let bundles = app.bundle.collect_bundles_sorted();
+    let relative_paths: Vec<String> = bundles
+        .iter()
+        .map(|bundle| relative_bundle_path(&app.bundle.bundle_dir, &bundle.bundle_dir))
+        .collect();
+    for path in customizations.keys() {
+        if !relative_paths.contains(path) {
+            warn!("No bundle at {} to customize, ignoring", path);
+        }
+    }
+
+    let mut report = SigningReport::default();
+    for (bundle, relative_path) in bundles.into_iter().zip(relative_paths) {
+        let customization = customizations.get(&relative_path);
+        if let Some(customization) = customization {
+            customization.copy_files(&bundle.bundle_dir)?;
+        }
+
+        let requested = match requested_entitlements(&bundle) {
+            Ok(requested) => requested,
+            Err(e) => {
+                warn!(
+                    "Failed to read entitlements of {}: {:?}",
+                    bundle.bundle_dir.display(),
+                    e
+                );
+                None
+            }
+        };
+        for capability in dropped_capabilities(&bundle, requested.as_ref(), &entitlements) {
+            if !report.dropped_capabilities.contains(&capability) {
+                warn!(
+                    "Dropping entitlement {}: {}",
+                    capability.entitlement, capability.reason
+                );
+                report.dropped_capabilities.push(capability);
+            }
+        }
+
+        let bundle_name = bundle
+            .bundle_dir
+            .file_name()
+            .unwrap_or(bundle.bundle_dir.as_os_str())
+            .to_string_lossy()
+            .to_string();
+        info!("Signing {}", bundle_name);
+        on_event(SideloadEvent::Signing {
+            bundle: bundle_name,
+        });
+        let mut bundle_entitlements = merge_entitlements(requested.as_ref(), &entitlements);
+        if bundle.is_app_clip() {
+            bundle_entitlements =
+                app_clip_entitlements(&bundle_entitlements, &bundle, &parent_app_id, team_id);
+        }
+        if let Some(customization) = customization {
+            customization.apply_entitlements(&mut bundle_entitlements);
+        }
+        for entitlement in policy.apply(&mut bundle_entitlements) {
+            let capability = DroppedCapability {
+                reason: policy_drop_reason(&entitlement, policy).to_string(),
+                entitlement,
+            };
+            if !report.dropped_capabilities.contains(&capability) {
+                warn!(
+                    "Dropping entitlement {}: {}",
+                    capability.entitlement, capability.reason
+                );
+                report.dropped_capabilities.push(capability);
+            }
+        }
+        let mut settings = signing_settings(identity)?;
+        // standalone dylibs, like injected tweaks, are signed with the same identity but can't have entitlements.
+        // Bundles always get theirs set, even if empty, so the entitlements of the original signature aren't kept.
+        if bundle.bundle_dir.is_file() {
+            bundle_entitlements = Dictionary::new();
+        } else {
+            settings
+                .set_entitlements_xml(
+                    apple_codesign::SettingsScope::Main,
+                    plist_to_xml_string(&bundle_entitlements),
+                )
+                .context("Failed to set entitlements XML")?;
+        }
+        UnifiedSigner::new(settings)
             .sign_path_in_place(&bundle.bundle_dir)
             .context(format!(
                 "Failed to sign bundle: {}",
                 bundle.bundle_dir.display()
-            ))?;
+            ))
+            .attach(report.clone())?;
+
+        match BundleSigningReport::from_signed_bundle(&bundle, relative_path, bundle_entitlements) {
+            Ok(bundle_report) => {
+                debug!("{}", bundle_report);
+                report.bundles.push(bundle_report);
+            }
+            Err(e) => warn!(
+                "Failed to summarize signature of {}: {:?}",
+                bundle.bundle_dir.display(),
+                e
+            ),
+        }
     }
 
-    Ok(())
+    Ok(report)
 }
 
-pub fn signing_settings<'a>(cert: &'a CertificateIdentity) -> Result<SigningSettings<'a>, Report> {
+pub fn signing_settings<'a>(
+    identity: &'a dyn SigningIdentity,
+) -> Result<SigningSettings<'a>, Report> {
     let mut settings = SigningSettings::default();
 
-    cert.setup_signing_settings(&mut settings)?;
+    identity.setup_signing_settings(&mut settings)?;
     settings.set_for_notarization(false);
     settings.set_shallow(true);
 
     Ok(settings)
 }
 
+/// The path of a nested bundle inside the main app, with `/` separators
+pub(crate) fn relative_bundle_path(app_dir: &Path, bundle_dir: &Path) -> String {
+    bundle_dir
+        .strip_prefix(app_dir)
+        .map(|p| {
+            p.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .unwrap_or_default()
+}
+
 fn entitlements_from_prov(
     data: &[u8],
     special: &Option<SpecialApp>,
-    team: &DeveloperTeam,
+    team_id: &str,
 ) -> Result<Dictionary, Report> {
-    let start = data
-        .windows(6)
-        .position(|w| w == b"<plist")
-        .ok_or_report()?;
-    let end = data
-        .windows(8)
-        .rposition(|w| w == b"</plist>")
-        .ok_or_report()?
-        + 8;
-    let plist_data = &data[start..end];
-    let plist = plist::Value::from_reader_xml(plist_data)?;
-
-    let mut entitlements = plist
-        .as_dictionary()
-        .ok_or_report()?
-        .get_dict("Entitlements")?
-        .clone();
+    let mut entitlements = profile_plist(data)?.get_dict("Entitlements")?.clone();
 
     if matches!(
         special,
@@ -94,13 +213,13 @@ fn entitlements_from_prov(
     ) {
         let mut keychain_access = vec![plist::Value::String(format!(
             "{}.com.kdt.livecontainer.shared",
-            team.team_id
+            team_id
         ))];
 
         for number in 1..128 {
             keychain_access.push(plist::Value::String(format!(
                 "{}.com.kdt.livecontainer.shared.{}",
-                team.team_id, number
+                team_id, number
             )));
         }
 
@@ -112,3 +231,102 @@ fn entitlements_from_prov(
 
     Ok(entitlements)
 }
+
+/// App clips must declare their parent app and use their own application identifier
+fn app_clip_entitlements(
+    entitlements: &Dictionary,
+    clip: &Bundle,
+    parent_app_id: &str,
+    team_id: &str,
+) -> Dictionary {
+    let mut entitlements = entitlements.clone();
+    entitlements.insert(
+        "application-identifier".to_string(),
+        plist::Value::String(format!(
+            "{}.{}",
+            team_id,
+            clip.bundle_identifier().unwrap_or("")
+        )),
+    );
+    entitlements.insert(
+        "com.apple.developer.parent-application-identifiers".to_string(),
+        plist::Value::Array(vec![plist::Value::String(parent_app_id.to_string())]),
+    );
+    entitlements.insert(
+        "com.apple.developer.on-demand-install-capable".to_string(),
+        plist::Value::Boolean(true),
+    );
+    entitlements
+}
+
+/// Compare the entitlements the bundle was originally signed with against the ones it will be signed with
+fn dropped_capabilities(
+    bundle: &Bundle,
+    requested: Option<&Dictionary>,
+    granted: &Dictionary,
+) -> Vec<DroppedCapability> {
+    let Some(requested) = requested else {
+        return Vec::new();
+    };
+
+    for change in plist_diff(requested, granted) {
+        debug!(
+            "Entitlements of {}: {}",
+            bundle.bundle_dir.display(),
+            change
+        );
+    }
+
+    requested
+        .iter()
+        .filter(|(key, requested_value)| {
+            granted
+                .get(key.as_str())
+                .and_then(|granted_value| merge_value(requested_value, granted_value))
+                .is_none()
+        })
+        .map(|(key, _)| key)
+        .filter(|key| !PROFILE_ENTITLEMENTS.contains(&key.as_str()))
+        .map(|key| DroppedCapability {
+            entitlement: key.clone(),
+            reason: drop_reason(key).to_string(),
+        })
+        .collect()
+}
+
+fn drop_reason(entitlement: &str) -> &'static str {
+    match entitlement {
+        "aps-environment" => "Push notifications require a paid developer account",
+        e if e.starts_with("com.apple.developer.carplay") => {
+            "CarPlay entitlements must be granted to the team by Apple"
+        }
+        e if e.starts_with("com.apple.developer.nfc") => {
+            "NFC tag reading requires a paid developer account"
+        }
+        "com.apple.developer.icloud-container-identifiers"
+        | "com.apple.developer.icloud-services"
+        | "com.apple.developer.ubiquity-container-identifiers"
+        | "com.apple.developer.ubiquity-kvstore-identifier" => {
+            "iCloud requires a paid developer account"
+        }
+        "com.apple.developer.associated-domains" => {
+            "Associated domains require a paid developer account"
+        }
+        "com.apple.developer.in-app-payments" => "Apple Pay requires a paid developer account",
+        "com.apple.developer.healthkit" | "com.apple.developer.healthkit.access" => {
+            "HealthKit is not enabled for the app ID"
+        }
+        "com.apple.developer.siri" => "SiriKit is not enabled for the app ID",
+        _ => "Not granted by the provisioning profile",
+    }
+}
+
+fn policy_drop_reason(entitlement: &str, policy: &EntitlementPolicy) -> &'static str {
+    if is_restricted_entitlement(entitlement) {
+        "Must be granted to the team by Apple"
+    } else if *policy == EntitlementPolicy::free() {
+        "Not available to free developer accounts"
+    } else {
+        "Not allowed by the entitlement policy"
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt::Display;
+
+use apple_codesign::EmbeddedSignature;
+use plist::{Dictionary, Value};
+use rootcause::prelude::*;
+
+use crate::sideload::{
+    bundle::Bundle, code_signature::read_code_signatures, sign::DroppedCapability,
+    thinning::ThinningReport,
+};
+
+/// A summary of what was signed, to trace signing problems like unsealed resources to a specific file
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct SigningReport {
+    /// The signed bundles, innermost first, in the order they were signed
+    pub bundles: Vec<BundleSigningReport>,
+    /// Entitlements the app requested that the provisioning profile doesn't grant
+    pub dropped_capabilities: Vec<DroppedCapability>,
+    /// What was stripped from the app before signing, if [`crate::sideload::SideloaderBuilder::thin_app`] is set
+    pub thinning: Option<ThinningReport>,
+}
+
+/// What was written into the signature of one bundle
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct BundleSigningReport {
+    /// The bundle's path inside the app, e.g. `PlugIns/Widget.appex`. Empty for the main app.
+    pub bundle: String,
+    /// The identifier in the main executable's code directory
+    pub identifier: Option<String>,
+    /// Resource files sealed into `_CodeSignature/CodeResources`, relative to the bundle
+    pub sealed_files: Vec<String>,
+    /// Nested bundles and executables sealed by their own signature, relative to the bundle
+    pub nested_code: Vec<String>,
+    /// The number of code pages hashed in the main executable, across all architectures
+    pub code_slots: usize,
+    /// The special slots of the main executable's code directory, e.g. `Info (1)` or `Entitlements (5)`
+    pub special_slots: Vec<String>,
+    /// The entitlements the bundle was signed with
+    pub entitlements: Dictionary,
+}
+
+impl BundleSigningReport {
+    /// Summarize the signature of a bundle that was just signed
+    pub(crate) fn from_signed_bundle(
+        bundle: &Bundle,
+        relative_path: String,
+        entitlements: Dictionary,
+    ) -> Result<Self, Report> {
+        let mut report = Self {
+            bundle: relative_path,
+            entitlements,
+            ..Default::default()
+        };
+
+        let code_resources = bundle
+            .bundle_dir
+            .join("_CodeSignature")
+            .join("CodeResources");
+        if code_resources.exists() {
+            let resources: Dictionary = plist::from_file(&code_resources)
+                .context("Failed to read _CodeSignature/CodeResources")?;
+            if let Some(files) = resources.get("files2").and_then(|v| v.as_dictionary()) {
+                for (path, entry) in files {
+                    if entry
+                        .as_dictionary()
+                        .is_some_and(|e| e.contains_key("cdhash"))
+                    {
+                        report.nested_code.push(path.clone());
+                    } else {
+                        report.sealed_files.push(path.clone());
+                    }
+                }
+            }
+        }
+
+        let Some(executable) = bundle
+            .app_info
+            .get("CFBundleExecutable")
+            .and_then(Value::as_string)
+        else {
+            return Ok(report);
+        };
+        let path = bundle.bundle_dir.join(executable);
+        if !path.exists() {
+            return Ok(report);
+        }
+        for data in read_code_signatures(&path)? {
+            let Some(code_directory) = EmbeddedSignature::from_bytes(&data)
+                .context("Failed to read code signature")?
+                .code_directory()
+                .context("Failed to read code directory")?
+            else {
+                continue;
+            };
+            report
+                .identifier
+                .get_or_insert_with(|| code_directory.ident.to_string());
+            report.code_slots += code_directory.code_digests.len();
+            for slot in code_directory.special_digests.keys() {
+                let slot = format!("{:?}", slot);
+                if !report.special_slots.contains(&slot) {
+                    report.special_slots.push(slot);
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl Display for BundleSigningReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} files sealed, {} nested, {} code pages, slots [{}]",
+            if self.bundle.is_empty() {
+                "<main app>"
+            } else {
+                &self.bundle
+            },
+            self.sealed_files.len(),
+            self.nested_code.len(),
+            self.code_slots,
+            self.special_slots.join(", ")
+        )
+    }
+}
+
+impl Display for SigningReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Signed {} bundles", self.bundles.len())?;
+        if let Some(thinning) = &self.thinning {
+            write!(f, " after app thinning {}", thinning)?;
+        }
+        for bundle in &self.bundles {
+            write!(f, "\n  {}", bundle)?;
+        }
+        Ok(())
+    }
+}
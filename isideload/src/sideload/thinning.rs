@@ -0,0 +1,268 @@
+//! Stripping what a device never uses from an app before it is signed
+//!
+//! Universal binaries carry slices for architectures no iOS device runs anymore, like `armv7` or the simulator's
+//! `x86_64`, and App Store exports can contain symbol files and Watch placeholders. All of it is hashed while signing
+//! and uploaded to the device, so removing it first makes both faster. See
+//! [`crate::sideload::SideloaderBuilder::thin_app`].
+
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use rootcause::prelude::*;
+use tracing::{debug, warn};
+
+use crate::sideload::code_signature::{FAT_MAGIC, FAT_MAGIC_64, read_u32_be, read_u64_be};
+
+const CPU_TYPE_ARM64: u32 = 0x0100000c;
+// Java class files share the fat magic, but their version number is always far above this
+const MAX_FAT_ARCHS: u32 = 32;
+const WATCH_PLACEHOLDER: &str = "com.apple.WatchPlaceholder";
+
+/// What was removed from an app by [`thin_app`]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct ThinningReport {
+    /// Mach-O files that had their non-arm64 slices removed, relative to the app
+    pub thinned_binaries: Vec<String>,
+    /// Symbol files and Watch placeholders that were deleted, relative to the app
+    pub removed: Vec<String>,
+    /// How many bytes smaller the app is
+    pub bytes_saved: u64,
+}
+
+impl Display for ThinningReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "thinned {} binaries and removed {} files, saving {:.1} MB",
+            self.thinned_binaries.len(),
+            self.removed.len(),
+            self.bytes_saved as f64 / 1_000_000.0
+        )
+    }
+}
+
+struct FatArch {
+    cpu_type: u32,
+    cpu_subtype: u32,
+    offset: u64,
+    size: u64,
+    align: u32,
+}
+
+/// Remove the non-arm64 slices of every universal binary, `.symbols` files and Watch placeholders from the app at
+/// `app_dir`
+///
+/// Binaries without an arm64 slice are left alone, as are the binaries of Watch apps, which run `arm64_32`.
+pub fn thin_app(app_dir: &Path) -> Result<ThinningReport, Report> {
+    let mut report = ThinningReport::default();
+    thin_dir(app_dir, app_dir, true, &mut report)?;
+    debug!("App thinning {}", report);
+    Ok(report)
+}
+
+fn thin_dir(
+    app_dir: &Path,
+    dir: &Path,
+    thin_binaries: bool,
+    report: &mut ThinningReport,
+) -> Result<(), Report> {
+    let entries =
+        fs::read_dir(dir).context(format!("Failed to read directory {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.context("Failed to read directory entry")?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let relative = path
+            .strip_prefix(app_dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .to_string();
+
+        if file_type.is_dir() {
+            if name == WATCH_PLACEHOLDER {
+                report.bytes_saved += dir_size(&path)?;
+                fs::remove_dir_all(&path)
+                    .context(format!("Failed to remove {}", path.display()))?;
+                report.removed.push(relative);
+            } else {
+                thin_dir(app_dir, &path, thin_binaries && name != "Watch", report)?;
+            }
+        } else if file_type.is_file() {
+            if name.ends_with(".symbols") {
+                report.bytes_saved += entry.metadata()?.len();
+                fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+                report.removed.push(relative);
+            } else if thin_binaries && let Some(saved) = thin_binary(&path)? {
+                report.bytes_saved += saved;
+                report.thinned_binaries.push(relative);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the universal binary at `path` with only its arm64 slices, returning how many bytes that saved
+///
+/// Returns `None` without changing anything if `path` isn't a universal binary, or thinning wouldn't remove a slice.
+fn thin_binary(path: &Path) -> Result<Option<u64>, Report> {
+    let mut file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let metadata = file.metadata()?;
+    let Some((magic, archs)) = read_fat_archs(&mut file, metadata.len())? else {
+        return Ok(None);
+    };
+    let (kept, removed): (Vec<_>, Vec<_>) = archs
+        .into_iter()
+        .partition(|arch| arch.cpu_type == CPU_TYPE_ARM64);
+    if removed.is_empty() {
+        return Ok(None);
+    }
+    if kept.is_empty() {
+        warn!("{} has no arm64 slice, leaving it as it is", path.display());
+        return Ok(None);
+    }
+
+    let thin_path = path.with_file_name(format!(
+        "{}.thin",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let result = write_thinned(&mut file, &thin_path, magic, &kept)
+        .and_then(|_| {
+            fs::set_permissions(&thin_path, metadata.permissions())?;
+            fs::rename(&thin_path, path)?;
+            Ok(())
+        })
+        .context(format!("Failed to thin {}", path.display()));
+    if result.is_err() {
+        let _ = fs::remove_file(&thin_path);
+    }
+    result?;
+
+    let new_len = fs::metadata(path)?.len();
+    Ok(Some(metadata.len().saturating_sub(new_len)))
+}
+
+/// The slices of a universal binary, or `None` if the file isn't one
+fn read_fat_archs(file: &mut File, len: u64) -> Result<Option<(u32, Vec<FatArch>)>, Report> {
+    if len < 8 {
+        return Ok(None);
+    }
+    let magic = read_u32_be(file)?;
+    if magic != FAT_MAGIC && magic != FAT_MAGIC_64 {
+        return Ok(None);
+    }
+    let count = read_u32_be(file)?;
+    if count == 0 || count > MAX_FAT_ARCHS {
+        return Ok(None);
+    }
+
+    let mut archs = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let cpu_type = read_u32_be(file)?;
+        let cpu_subtype = read_u32_be(file)?;
+        let (offset, size, align) = if magic == FAT_MAGIC_64 {
+            let offset = read_u64_be(file)?;
+            let size = read_u64_be(file)?;
+            let align = read_u32_be(file)?;
+            // reserved
+            read_u32_be(file)?;
+            (offset, size, align)
+        } else {
+            (
+                read_u32_be(file)? as u64,
+                read_u32_be(file)? as u64,
+                read_u32_be(file)?,
+            )
+        };
+        if offset.saturating_add(size) > len || align > 31 {
+            return Ok(None);
+        }
+        archs.push(FatArch {
+            cpu_type,
+            cpu_subtype,
+            offset,
+            size,
+            align,
+        });
+    }
+    Ok(Some((magic, archs)))
+}
+
+/// Write the kept slices to `thin_path`, as a thin binary if only one is left
+fn write_thinned(
+    file: &mut File,
+    thin_path: &Path,
+    magic: u32,
+    kept: &[FatArch],
+) -> Result<(), Report> {
+    let mut out = io::BufWriter::new(File::create(thin_path)?);
+    if let [arch] = kept {
+        copy_slice(file, &mut out, arch)?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    let arch_size: u64 = if magic == FAT_MAGIC_64 { 32 } else { 20 };
+    let mut position = 8 + arch_size * kept.len() as u64;
+    let mut offsets = Vec::with_capacity(kept.len());
+    for arch in kept {
+        position = position.next_multiple_of(1 << arch.align);
+        offsets.push(position);
+        position += arch.size;
+    }
+
+    out.write_all(&magic.to_be_bytes())?;
+    out.write_all(&(kept.len() as u32).to_be_bytes())?;
+    for (arch, offset) in kept.iter().zip(&offsets) {
+        out.write_all(&arch.cpu_type.to_be_bytes())?;
+        out.write_all(&arch.cpu_subtype.to_be_bytes())?;
+        if magic == FAT_MAGIC_64 {
+            out.write_all(&offset.to_be_bytes())?;
+            out.write_all(&arch.size.to_be_bytes())?;
+            out.write_all(&arch.align.to_be_bytes())?;
+            out.write_all(&0u32.to_be_bytes())?;
+        } else {
+            out.write_all(&(*offset as u32).to_be_bytes())?;
+            out.write_all(&(arch.size as u32).to_be_bytes())?;
+            out.write_all(&arch.align.to_be_bytes())?;
+        }
+    }
+
+    let mut written = 8 + arch_size * kept.len() as u64;
+    for (arch, offset) in kept.iter().zip(&offsets) {
+        io::copy(&mut io::repeat(0).take(offset - written), &mut out)?;
+        copy_slice(file, &mut out, arch)?;
+        written = offset + arch.size;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn copy_slice(file: &mut File, out: &mut impl Write, arch: &FatArch) -> Result<(), Report> {
+    file.seek(SeekFrom::Start(arch.offset))?;
+    let copied = io::copy(&mut Read::by_ref(file).take(arch.size), out)?;
+    if copied != arch.size {
+        bail!("Mach-O slice is truncated");
+    }
+    Ok(())
+}
+
+fn dir_size(dir: &Path) -> Result<u64, Report> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
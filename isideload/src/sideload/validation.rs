@@ -0,0 +1,395 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use apple_codesign::{AppleCertificate, EmbeddedSignature, verify_macho_data};
+use plist::{Dictionary, Value};
+use rootcause::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::sideload::{
+    bundle::Bundle,
+    code_signature::read_code_signatures,
+    entitlements::requested_entitlements,
+    profile::profile_plist,
+    sign::relative_bundle_path,
+    wwdr::{ChainStatus, WwdrIntermediates},
+};
+
+/// A problem found by [`validate_signed_app`] that would likely make the app fail to install or launch
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(tag = "issue", rename_all = "snake_case")
+)]
+pub enum ValidationIssue {
+    /// An executable has no signature, or one that doesn't match its contents
+    InvalidSignature { path: String, problem: String },
+    /// An executable is signed for another team, usually a framework or dylib that wasn't re-signed
+    WrongTeam {
+        path: String,
+        team_id: Option<String>,
+    },
+    /// A bundle has no `_CodeSignature/CodeResources`, so its resources aren't sealed
+    MissingCodeResources { bundle: String },
+    /// A file sealed in `CodeResources` doesn't exist anymore
+    MissingSealedFile { bundle: String, file: String },
+    /// A file sealed in `CodeResources` was changed after signing, e.g. a stale `_CodeSignature` was kept
+    ModifiedSealedFile { bundle: String, file: String },
+    /// An executable is signed with an entitlement the embedded provisioning profile doesn't grant
+    UngrantedEntitlement { path: String, entitlement: String },
+    /// The signing certificate doesn't chain to Apple's root through valid intermediates
+    UntrustedChain { path: String, status: ChainStatus },
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationIssue::InvalidSignature { path, problem } => {
+                write!(f, "{} has an invalid signature: {}", path, problem)
+            }
+            ValidationIssue::WrongTeam { path, team_id } => write!(
+                f,
+                "{} is signed for team {}",
+                path,
+                team_id.as_deref().unwrap_or("<none>")
+            ),
+            ValidationIssue::MissingCodeResources { bundle } => {
+                write!(f, "{} has no _CodeSignature/CodeResources", bundle)
+            }
+            ValidationIssue::MissingSealedFile { bundle, file } => {
+                write!(f, "{} is sealed in {} but missing", file, bundle)
+            }
+            ValidationIssue::ModifiedSealedFile { bundle, file } => {
+                write!(f, "{} was modified after {} was signed", file, bundle)
+            }
+            ValidationIssue::UngrantedEntitlement { path, entitlement } => write!(
+                f,
+                "{} is signed with {}, which the provisioning profile doesn't grant",
+                path, entitlement
+            ),
+            ValidationIssue::UntrustedChain { path, status } => {
+                write!(f, "{} has an untrusted certificate chain: {}", path, status)
+            }
+        }
+    }
+}
+
+/// What [`validate_signed_app`] checked, and the problems it found
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json-events", derive(serde::Serialize))]
+pub struct ValidationReport {
+    /// How many executables, including frameworks and dylibs, were checked
+    pub executables: usize,
+    /// How many sealed resource files were checked against their hashes
+    pub sealed_files: usize,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Whether a certificate chain is untrusted, which refreshing the WWDR intermediates may fix
+    pub fn has_untrusted_chain(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|i| matches!(i, ValidationIssue::UntrustedChain { .. }))
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} executables and {} sealed files checked, {} issues",
+            self.executables,
+            self.sealed_files,
+            self.issues.len()
+        )?;
+        for issue in &self.issues {
+            write!(f, "\n- {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check a signed app for signing mistakes before it is uploaded
+///
+/// Every executable, including those of nested bundles, frameworks and dylibs, is verified with apple-codesign and
+/// must be signed for `team_id` with entitlements the app's `embedded.mobileprovision` grants. The resources sealed
+/// in each bundle's `CodeResources` are hashed again to catch files changed after signing, and the certificates
+/// embedded in each signature must chain to Apple's root.
+pub fn validate_signed_app(app_dir: &Path, team_id: &str) -> Result<ValidationReport, Report> {
+    let app = Bundle::new(app_dir.to_path_buf())?;
+    let profile = std::fs::read(app_dir.join("embedded.mobileprovision"))
+        .context("Failed to read embedded.mobileprovision")?;
+    let granted = profile_plist(&profile)?
+        .get("Entitlements")
+        .and_then(Value::as_dictionary)
+        .cloned()
+        .unwrap_or_default();
+
+    let mut report = ValidationReport::default();
+    for bundle in app.collect_bundles_sorted() {
+        let relative_path = relative_bundle_path(app_dir, &bundle.bundle_dir);
+        let name = if relative_path.is_empty() {
+            "<main app>".to_string()
+        } else {
+            relative_path.clone()
+        };
+
+        // dylibs are collected as bundles made of just the executable
+        if bundle.bundle_dir.is_file() {
+            validate_executable(&bundle.bundle_dir, &name, team_id, &mut report)?;
+            continue;
+        }
+
+        if let Some(executable) = bundle
+            .app_info
+            .get("CFBundleExecutable")
+            .and_then(Value::as_string)
+        {
+            let executable_path = bundle.bundle_dir.join(executable);
+            let path = format!(
+                "{}{}",
+                if relative_path.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", relative_path)
+                },
+                executable
+            );
+            validate_executable(&executable_path, &path, team_id, &mut report)?;
+
+            if let Some(entitlements) = requested_entitlements(&bundle)? {
+                for entitlement in ungranted_entitlements(&entitlements, &granted) {
+                    report.issues.push(ValidationIssue::UngrantedEntitlement {
+                        path: path.clone(),
+                        entitlement,
+                    });
+                }
+            }
+        }
+
+        validate_sealed_resources(&bundle.bundle_dir, &name, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+fn validate_executable(
+    path: &Path,
+    name: &str,
+    team_id: &str,
+    report: &mut ValidationReport,
+) -> Result<(), Report> {
+    if !path.exists() {
+        return Ok(());
+    }
+    report.executables += 1;
+
+    let data = std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+    for problem in verify_macho_data(&data) {
+        report.issues.push(ValidationIssue::InvalidSignature {
+            path: name.to_string(),
+            problem: problem.to_string(),
+        });
+    }
+    drop(data);
+
+    for signature in read_code_signatures(path).unwrap_or_default() {
+        let Ok(embedded) = EmbeddedSignature::from_bytes(&signature) else {
+            // already reported by verify_macho_data
+            continue;
+        };
+        let Ok(Some(code_directory)) = embedded.code_directory() else {
+            continue;
+        };
+        if code_directory.team_name.as_deref() != Some(team_id) {
+            report.issues.push(ValidationIssue::WrongTeam {
+                path: name.to_string(),
+                team_id: code_directory.team_name.map(|t| t.to_string()),
+            });
+            break;
+        }
+
+        if let Some(status) = embedded_chain_status(&embedded)
+            && status != ChainStatus::Trusted
+        {
+            report.issues.push(ValidationIssue::UntrustedChain {
+                path: name.to_string(),
+                status,
+            });
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// The chain status of the signing certificate of a CMS signature, `None` for ad-hoc signatures
+fn embedded_chain_status(signature: &EmbeddedSignature) -> Option<ChainStatus> {
+    let signed_data = signature.signed_data().ok()??;
+    let certificates = signed_data.certificates().cloned().collect::<Vec<_>>();
+    let leaf = certificates
+        .iter()
+        .find(|c| !c.is_apple_root_ca() && !c.is_apple_intermediate_ca())?;
+
+    Some(WwdrIntermediates::from_certificates(certificates.clone()).chain_status(leaf))
+}
+
+/// Who signed a bundle's main executable
+#[cfg(feature = "install")]
+pub(crate) struct BundleSigner {
+    pub team_id: Option<String>,
+    /// The DER encoded signing certificate
+    pub certificate: Vec<u8>,
+}
+
+/// The signer of the bundle's main executable
+///
+/// Returns `None` if the bundle has no executable, or the executable isn't signed with a certificate.
+#[cfg(feature = "install")]
+pub(crate) fn bundle_signer(bundle: &Bundle) -> Result<Option<BundleSigner>, Report> {
+    let Some(executable) = bundle
+        .app_info
+        .get("CFBundleExecutable")
+        .and_then(|v| v.as_string())
+    else {
+        return Ok(None);
+    };
+    let path = bundle.bundle_dir.join(executable);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let Some(data) = read_code_signatures(&path)?.into_iter().next() else {
+        return Ok(None);
+    };
+    let signature =
+        EmbeddedSignature::from_bytes(&data).context("Failed to read code signature")?;
+    let team_id = signature
+        .code_directory()
+        .ok()
+        .flatten()
+        .and_then(|code_directory| code_directory.team_name.map(|t| t.to_string()));
+    let Some(signed_data) = signature.signed_data().ok().flatten() else {
+        return Ok(None);
+    };
+    let Some(leaf) = signed_data
+        .certificates()
+        .find(|c| !c.is_apple_root_ca() && !c.is_apple_intermediate_ca())
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(BundleSigner {
+        team_id,
+        certificate: leaf.encode_der()?,
+    }))
+}
+
+fn validate_sealed_resources(
+    bundle_dir: &Path,
+    name: &str,
+    report: &mut ValidationReport,
+) -> Result<(), Report> {
+    let code_resources = bundle_dir.join("_CodeSignature").join("CodeResources");
+    if !code_resources.exists() {
+        report.issues.push(ValidationIssue::MissingCodeResources {
+            bundle: name.to_string(),
+        });
+        return Ok(());
+    }
+    let resources: Dictionary =
+        plist::from_file(&code_resources).context("Failed to read _CodeSignature/CodeResources")?;
+    let Some(files) = resources.get("files2").and_then(Value::as_dictionary) else {
+        return Ok(());
+    };
+
+    for (file, entry) in files {
+        // nested code is checked through its own signature, and symlinks have no contents to hash
+        let Some(entry) = entry.as_dictionary() else {
+            continue;
+        };
+        let Some(expected) = entry.get("hash2").and_then(Value::as_data) else {
+            continue;
+        };
+        let optional = entry
+            .get("optional")
+            .and_then(Value::as_boolean)
+            .unwrap_or(false);
+
+        let path = bundle_dir.join(file);
+        if !path.exists() {
+            if !optional {
+                report.issues.push(ValidationIssue::MissingSealedFile {
+                    bundle: name.to_string(),
+                    file: file.clone(),
+                });
+            }
+            continue;
+        }
+
+        report.sealed_files += 1;
+        if hash_file(&path).context(format!("Failed to read {}", path.display()))? != expected {
+            report.issues.push(ValidationIssue::ModifiedSealedFile {
+                bundle: name.to_string(),
+                file: file.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// SHA-256 of a file, read in chunks so large resources aren't held in memory
+fn hash_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// The entitlements in `entitlements` that `granted` doesn't cover
+///
+/// Profiles grant wildcards like `TEAMID.*` and lists of allowed values, so a value is covered if it matches the
+/// granted value, or each of its items matches one of the granted items.
+fn ungranted_entitlements(entitlements: &Dictionary, granted: &Dictionary) -> Vec<String> {
+    entitlements
+        .iter()
+        .filter(|(key, value)| {
+            !granted
+                .get(key.as_str())
+                .is_some_and(|granted| is_granted(value, granted))
+        })
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+fn is_granted(value: &Value, granted: &Value) -> bool {
+    match (value, granted) {
+        // disabling something is always allowed
+        (Value::Boolean(false), _) => true,
+        (Value::Array(items), _) => items.iter().all(|item| is_granted(item, granted)),
+        (_, Value::Array(allowed)) => allowed.iter().any(|allowed| is_granted(value, allowed)),
+        (Value::String(value), Value::String(granted)) => match granted.strip_suffix('*') {
+            Some(prefix) => value.starts_with(prefix),
+            None => value == granted,
+        },
+        _ => value == granted,
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt::Display;
+
+use apple_codesign::{AppleCertificate, KnownCertificate, SigningSettings};
+use rootcause::prelude::*;
+use tracing::{info, warn};
+use x509_certificate::CapturedX509Certificate;
+
+use crate::util::storage::SideloadingStorage;
+
+/// Where Apple publishes the WWDR intermediate certificates that issue development certificates
+pub const WWDR_INTERMEDIATE_URLS: &[&str] = &[
+    "https://www.apple.com/certificateauthority/AppleWWDRCAG2.cer",
+    "https://www.apple.com/certificateauthority/AppleWWDRCAG3.cer",
+    "https://www.apple.com/certificateauthority/AppleWWDRCAG4.cer",
+    "https://www.apple.com/certificateauthority/AppleWWDRCAG5.cer",
+    "https://www.apple.com/certificateauthority/AppleWWDRCAG6.cer",
+];
+
+const STORAGE_KEY: &str = "wwdr_intermediates";
+
+/// Whether a certificate chains to Apple's root through valid intermediates
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json-events",
+    derive(serde::Serialize),
+    serde(tag = "status", content = "certificate", rename_all = "snake_case")
+)]
+pub enum ChainStatus {
+    Trusted,
+    /// A certificate in the chain has expired, identified by its common name
+    Expired(String),
+    /// No intermediate that issued the certificate is known
+    Untrusted,
+}
+
+impl Display for ChainStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainStatus::Trusted => write!(f, "trusted"),
+            ChainStatus::Expired(name) => write!(f, "{} has expired", name),
+            ChainStatus::Untrusted => write!(f, "not issued by a known Apple intermediate"),
+        }
+    }
+}
+
+/// Apple WWDR intermediate certificates used to chain signing certificates to Apple's root
+///
+/// apple-codesign bundles the intermediates known when it was released. When Apple renews one, signatures chained
+/// to the old one stop being accepted, so the current ones can be downloaded with [`Self::refresh`]. Downloaded
+/// intermediates are preferred over the bundled ones.
+#[derive(Debug, Clone, Default)]
+pub struct WwdrIntermediates {
+    certificates: Vec<CapturedX509Certificate>,
+}
+
+impl WwdrIntermediates {
+    /// The intermediates saved by an earlier [`Self::refresh`], or none if they can't be read
+    pub fn load(storage: &dyn SideloadingStorage) -> Self {
+        let certificates = match storage.retrieve(STORAGE_KEY) {
+            Ok(Some(pem)) => CapturedX509Certificate::from_pem_multiple(pem).unwrap_or_else(|e| {
+                warn!("Failed to parse saved WWDR intermediates: {}", e);
+                Vec::new()
+            }),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                warn!("Failed to read saved WWDR intermediates: {:?}", e);
+                Vec::new()
+            }
+        };
+        Self { certificates }
+    }
+
+    /// Download the current intermediates from Apple and save them to `storage`
+    ///
+    /// Intermediates that fail to download are skipped, it only fails if none could be downloaded.
+    pub async fn refresh(storage: &dyn SideloadingStorage) -> Result<Self, Report> {
+        let client = reqwest::Client::new();
+        let mut certificates = Vec::new();
+        for url in WWDR_INTERMEDIATE_URLS {
+            match download_certificate(&client, url).await {
+                Ok(certificate) => certificates.push(certificate),
+                Err(e) => warn!("Failed to download WWDR intermediate {}: {:?}", url, e),
+            }
+        }
+        if certificates.is_empty() {
+            bail!("Failed to download any WWDR intermediate certificate");
+        }
+
+        let pem = certificates
+            .iter()
+            .map(|c| c.encode_pem())
+            .collect::<Vec<_>>()
+            .join("\n");
+        storage.store(STORAGE_KEY, &pem)?;
+        info!("Downloaded {} WWDR intermediates", certificates.len());
+
+        Ok(Self { certificates })
+    }
+
+    /// Use `certificates` as intermediates, e.g. the ones embedded in a signature
+    pub(crate) fn from_certificates(certificates: Vec<CapturedX509Certificate>) -> Self {
+        Self { certificates }
+    }
+
+    /// The downloaded intermediates
+    pub fn certificates(&self) -> &[CapturedX509Certificate] {
+        &self.certificates
+    }
+
+    /// The intermediates and root that `certificate` chains to, not including itself
+    pub fn chain_for(&self, certificate: &CapturedX509Certificate) -> Vec<CapturedX509Certificate> {
+        let mut chain = Vec::new();
+        for issuer in certificate.resolve_signing_chain(
+            self.certificates
+                .iter()
+                .chain(KnownCertificate::all().iter().copied()),
+        ) {
+            chain.push(issuer.clone());
+            if issuer.is_apple_root_ca() {
+                break;
+            }
+        }
+        chain
+    }
+
+    pub fn chain_status(&self, certificate: &CapturedX509Certificate) -> ChainStatus {
+        let chain = self.chain_for(certificate);
+        if !chain.last().is_some_and(|c| c.is_apple_root_ca()) {
+            return ChainStatus::Untrusted;
+        }
+        match chain.iter().find(|c| !c.time_constraints_valid(None)) {
+            Some(expired) => ChainStatus::Expired(
+                expired
+                    .subject_common_name()
+                    .unwrap_or_else(|| "An intermediate".to_string()),
+            ),
+            None => ChainStatus::Trusted,
+        }
+    }
+
+    /// Load the saved intermediates, downloading them again if they don't give `certificate` a trusted chain
+    pub(crate) async fn for_certificate(
+        certificate: &CapturedX509Certificate,
+        storage: &dyn SideloadingStorage,
+    ) -> Self {
+        let intermediates = Self::load(storage);
+        let status = intermediates.chain_status(certificate);
+        if status == ChainStatus::Trusted {
+            return intermediates;
+        }
+
+        warn!(
+            "Signing certificate chain is not trusted ({}), downloading WWDR intermediates",
+            status
+        );
+        match Self::refresh(storage).await {
+            Ok(refreshed) => refreshed,
+            Err(e) => {
+                warn!("Failed to refresh WWDR intermediates: {:?}", e);
+                intermediates
+            }
+        }
+    }
+
+    /// Add the chain of the signing certificate to `settings`
+    pub(crate) fn chain_into(
+        &self,
+        settings: &mut SigningSettings<'_>,
+        certificate: &CapturedX509Certificate,
+    ) {
+        let chain = self.chain_for(certificate);
+        if !chain.last().is_some_and(|c| c.is_apple_root_ca()) {
+            warn!("Signing certificate doesn't chain to Apple's root, the app may not install");
+        }
+        for issuer in chain {
+            settings.chain_certificate(issuer);
+        }
+    }
+}
+
+async fn download_certificate(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<CapturedX509Certificate, Report> {
+    let data = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(CapturedX509Certificate::from_der(data.to_vec()).context("Failed to parse certificate")?)
+}
@@ -0,0 +1,82 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// A source of the current time, used for every expiry check in the crate
+///
+/// Profiles, certificates, app tokens, app IDs and anisette data are all checked against a clock passed in by their
+/// owner, see [`crate::sideload::SideloaderBuilder::clock`]. A [`FixedClock`] lets tests simulate boundaries like a
+/// free profile's 7 day expiry, and one shared clock keeps the checks consistent with each other if the system clock
+/// is skewed.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// Whether `expiration` has passed, or will within `margin`
+    fn expires_within(&self, expiration: SystemTime, margin: Duration) -> bool {
+        expiration <= self.now() + margin
+    }
+
+    /// How long ago `time` was, `None` if it is in the future, which means the clock went backwards
+    fn age(&self, time: SystemTime) -> Option<Duration> {
+        self.now().duration_since(time).ok()
+    }
+
+    /// The current time in milliseconds since the unix epoch
+    fn now_millis(&self) -> u64 {
+        self.now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// The system clock, used by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to
+///
+/// Clones share the same time, so a clone passed to a sideloader can be advanced afterwards.
+#[derive(Debug, Clone)]
+pub struct FixedClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl FixedClock {
+    pub fn new(now: SystemTime) -> Self {
+        FixedClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *now += duration;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        *self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
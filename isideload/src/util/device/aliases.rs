@@ -0,0 +1,53 @@
+//! Names the user gave devices, remembered in [`SideloadingStorage`] by UDID
+//!
+//! The name a device reports can be changed on the device at any time, and several devices are often called
+//! "iPhone", so frontends can let users pick their own label instead. Aliases are shown in
+//! [`super::discovery::DiscoveredDevice`] listings and in [`crate::sideload::events::SideloadEvent::RegisteringDevice`].
+
+use rootcause::prelude::*;
+
+use crate::util::storage::SideloadingStorage;
+
+const STORAGE_PREFIX: &str = "device_aliases/";
+
+/// The alias the user gave the device with `udid`, if any
+pub fn device_alias(
+    storage: &dyn SideloadingStorage,
+    udid: &str,
+) -> Result<Option<String>, Report> {
+    Ok(storage
+        .retrieve(&storage_key(udid))?
+        .filter(|alias| !alias.is_empty()))
+}
+
+/// Remember `alias` for the device with `udid`, or forget its alias if `alias` is `None` or empty
+pub fn set_device_alias(
+    storage: &dyn SideloadingStorage,
+    udid: &str,
+    alias: Option<&str>,
+) -> Result<(), Report> {
+    match alias.map(str::trim).filter(|alias| !alias.is_empty()) {
+        Some(alias) => storage.store(&storage_key(udid), alias),
+        None => storage.delete(&storage_key(udid)),
+    }
+}
+
+/// Every remembered alias, as `(udid, alias)` pairs
+///
+/// Fails if the storage backend can't list its entries, see [`SideloadingStorage::list`].
+pub fn device_aliases(storage: &dyn SideloadingStorage) -> Result<Vec<(String, String)>, Report> {
+    let mut aliases = Vec::new();
+    for key in storage.list(STORAGE_PREFIX)? {
+        let Some(udid) = key.strip_prefix(STORAGE_PREFIX) else {
+            continue;
+        };
+        if let Some(alias) = device_alias(storage, udid)? {
+            aliases.push((udid.to_string(), alias));
+        }
+    }
+    Ok(aliases)
+}
+
+fn storage_key(udid: &str) -> String {
+    format!("{}{}", STORAGE_PREFIX, udid)
+}
@@ -0,0 +1,106 @@
+//! Listing, looking up and uninstalling the apps installed on a device
+
+use idevice::{
+    IdeviceService, installation_proxy::InstallationProxyClient, provider::IdeviceProvider,
+};
+use plist::{Dictionary, Value};
+use rootcause::prelude::*;
+use tracing::info;
+
+use crate::SideloadError as Error;
+
+/// An app installed on a device, as reported by installd
+#[derive(Debug, Clone)]
+pub struct InstalledApp {
+    pub bundle_id: String,
+    /// The display name, falling back to the bundle name
+    pub name: Option<String>,
+    /// `CFBundleShortVersionString`
+    pub version: Option<String>,
+    /// `CFBundleVersion`
+    pub build: Option<String>,
+    /// The common name of the certificate the app is signed with, e.g. `Apple Development: ... (XXXXXXXXXX)`
+    pub signer_identity: Option<String>,
+    /// Everything installd returned for the app, including its Info.plist
+    pub info: Dictionary,
+}
+
+impl InstalledApp {
+    fn from_info(bundle_id: String, info: Dictionary) -> Self {
+        let string = |key: &str| {
+            info.get(key)
+                .and_then(|v| v.as_string())
+                .map(str::to_string)
+        };
+        Self {
+            name: string("CFBundleDisplayName").or_else(|| string("CFBundleName")),
+            version: string("CFBundleShortVersionString"),
+            build: string("CFBundleVersion"),
+            signer_identity: string("SignerIdentity"),
+            bundle_id,
+            info,
+        }
+    }
+}
+
+/// List the user installed apps on the device, sorted by bundle ID
+pub async fn list_installed_apps(
+    provider: &dyn IdeviceProvider,
+) -> Result<Vec<InstalledApp>, Report> {
+    let mut client = connect(provider).await?;
+    let apps = client
+        .get_apps(Some("User"), None)
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to list installed apps")?;
+
+    let mut apps: Vec<InstalledApp> = apps
+        .into_iter()
+        .filter_map(|(bundle_id, info)| match info {
+            Value::Dictionary(info) => Some(InstalledApp::from_info(bundle_id, info)),
+            _ => None,
+        })
+        .collect();
+    apps.sort_by(|a, b| a.bundle_id.cmp(&b.bundle_id));
+
+    Ok(apps)
+}
+
+/// Look up an installed app by bundle ID, returning `None` if it isn't installed
+pub async fn lookup_app(
+    provider: &dyn IdeviceProvider,
+    bundle_id: &str,
+) -> Result<Option<InstalledApp>, Report> {
+    let mut client = connect(provider).await?;
+    let mut apps = client
+        .get_apps(Some("Any"), Some(vec![bundle_id.to_string()]))
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to look up installed app")?;
+
+    Ok(match apps.remove(bundle_id) {
+        Some(Value::Dictionary(info)) => Some(InstalledApp::from_info(bundle_id.to_string(), info)),
+        _ => None,
+    })
+}
+
+/// Uninstall an app and its data from the device
+pub async fn uninstall_app(provider: &dyn IdeviceProvider, bundle_id: &str) -> Result<(), Report> {
+    let mut client = connect(provider).await?;
+    client
+        .uninstall(bundle_id, None)
+        .await
+        .map_err(Error::IdeviceError)
+        .context(format!("Failed to uninstall {}", bundle_id))?;
+    info!("Uninstalled {}", bundle_id);
+
+    Ok(())
+}
+
+async fn connect(provider: &dyn IdeviceProvider) -> Result<InstallationProxyClient, Report> {
+    let client = InstallationProxyClient::connect(provider)
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to connect to installation proxy")?;
+    Ok(client)
+}
@@ -0,0 +1,110 @@
+//! Checking and enabling Developer Mode, which iOS 16 and later require to launch sideloaded apps
+
+use idevice::{
+    IdeviceService, amfi::AmfiClient, lockdown::LockdownClient, provider::IdeviceProvider,
+};
+use rootcause::prelude::*;
+use tracing::{debug, info};
+
+use crate::{SideloadError as Error, util::device::pairing::pairing_error};
+
+/// Whether Developer Mode is enabled on a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeveloperModeStatus {
+    Enabled,
+    /// Apps install, but won't launch until Developer Mode is enabled
+    Disabled,
+    /// The device runs iOS 15 or earlier, which has no Developer Mode
+    NotRequired,
+    /// The device didn't report the status
+    Unknown,
+}
+
+/// Check whether Developer Mode is enabled on the device
+pub async fn developer_mode_status(
+    provider: &dyn IdeviceProvider,
+) -> Result<DeveloperModeStatus, Report> {
+    let mut lockdown = LockdownClient::connect(provider)
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to connect to device lockdown")?;
+    let pairing = provider
+        .get_pairing_file()
+        .await
+        .context("Failed to get device pairing file")?;
+    lockdown
+        .start_session(&pairing)
+        .await
+        .map_err(pairing_error)
+        .context("Failed to start lockdown session")?;
+
+    let major_version = lockdown
+        .get_value(Some("ProductVersion"), None)
+        .await
+        .ok()
+        .and_then(|v| v.as_string()?.split('.').next()?.parse::<u32>().ok());
+    if major_version.is_some_and(|major| major < 16) {
+        return Ok(DeveloperModeStatus::NotRequired);
+    }
+
+    match lockdown
+        .get_value(
+            Some("DeveloperModeStatus"),
+            Some("com.apple.security.mac.amfi"),
+        )
+        .await
+    {
+        Ok(value) => Ok(match value.as_boolean() {
+            Some(true) => DeveloperModeStatus::Enabled,
+            Some(false) => DeveloperModeStatus::Disabled,
+            None => DeveloperModeStatus::Unknown,
+        }),
+        Err(e) => {
+            debug!("Failed to get Developer Mode status: {}", e);
+            Ok(DeveloperModeStatus::Unknown)
+        }
+    }
+}
+
+/// Show the Developer Mode switch in Settings > Privacy & Security, which is hidden until an app needs it
+pub async fn reveal_developer_mode_option(provider: &dyn IdeviceProvider) -> Result<(), Report> {
+    let mut amfi = connect(provider).await?;
+    amfi.reveal_developer_mode_option_in_ui()
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to reveal the Developer Mode option")?;
+    Ok(())
+}
+
+/// Turn on Developer Mode, which restarts the device
+///
+/// This only works on devices without a passcode. Once the device has restarted and been unlocked, call
+/// [`accept_developer_mode`] to show the prompt that finishes enabling it. On devices with a passcode,
+/// use [`reveal_developer_mode_option`] and let the user enable it in Settings instead.
+pub async fn enable_developer_mode(provider: &dyn IdeviceProvider) -> Result<(), Report> {
+    let mut amfi = connect(provider).await?;
+    amfi.enable_developer_mode()
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to enable Developer Mode")?;
+    info!("Enabled Developer Mode, the device will restart");
+    Ok(())
+}
+
+/// Show the prompt that finishes enabling Developer Mode after the device restarted
+pub async fn accept_developer_mode(provider: &dyn IdeviceProvider) -> Result<(), Report> {
+    let mut amfi = connect(provider).await?;
+    amfi.accept_developer_mode()
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to show the Developer Mode prompt")?;
+    Ok(())
+}
+
+async fn connect(provider: &dyn IdeviceProvider) -> Result<AmfiClient, Report> {
+    let client = AmfiClient::connect(provider)
+        .await
+        .map_err(pairing_error)
+        .context("Failed to connect to AMFI")?;
+    Ok(client)
+}
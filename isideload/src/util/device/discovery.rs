@@ -0,0 +1,183 @@
+//! Finding the devices connected through usbmuxd
+
+use std::{fmt::Display, net::IpAddr};
+
+use idevice::{
+    IdeviceService,
+    lockdown::LockdownClient,
+    provider::UsbmuxdProvider,
+    usbmuxd::{Connection, UsbmuxdAddr, UsbmuxdDevice},
+};
+use rootcause::prelude::*;
+use tracing::{debug, warn};
+
+use crate::{
+    SideloadError as Error,
+    util::{device::aliases::device_alias, storage::SideloadingStorage},
+};
+
+/// The label used for connections made to list devices
+const DISCOVERY_LABEL: &str = "isideload-discovery";
+
+/// How a device is connected to this computer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionType {
+    Usb,
+    /// Wi-Fi sync, with the device's address
+    Network(IpAddr),
+    /// A connection type usbmuxd reported that isn't known yet
+    Unknown(String),
+}
+
+impl From<&Connection> for ConnectionType {
+    fn from(connection: &Connection) -> Self {
+        match connection {
+            Connection::Usb => ConnectionType::Usb,
+            Connection::Network(addr) => ConnectionType::Network(*addr),
+            Connection::Unknown(kind) => ConnectionType::Unknown(kind.clone()),
+        }
+    }
+}
+
+impl Display for ConnectionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionType::Usb => write!(f, "USB"),
+            ConnectionType::Network(addr) => write!(f, "Network ({})", addr),
+            ConnectionType::Unknown(kind) => write!(f, "Unknown ({})", kind),
+        }
+    }
+}
+
+/// A device connected through usbmuxd
+///
+/// A device connected over USB and Wi-Fi at the same time is listed once per connection.
+#[derive(Debug, Clone)]
+pub struct DiscoveredDevice {
+    /// The name the user gave the device, or its UDID if the device didn't report one
+    pub name: String,
+    pub udid: String,
+    /// The hardware model, e.g. `iPhone15,2`
+    pub product_type: Option<String>,
+    /// The iOS version, e.g. `17.4.1`
+    pub ios_version: Option<String>,
+    pub connection_type: ConnectionType,
+    /// The label the user gave the device, see [`crate::util::device::aliases`]
+    pub alias: Option<String>,
+    device: UsbmuxdDevice,
+    addr: UsbmuxdAddr,
+}
+
+impl DiscoveredDevice {
+    /// Create a provider for connecting to the device, which can be passed to `Sideloader::install_app`
+    pub fn provider(&self, label: impl Into<String>) -> UsbmuxdProvider {
+        self.device.to_provider(self.addr.clone(), label)
+    }
+
+    /// The ID usbmuxd assigned to this connection
+    pub fn device_id(&self) -> u32 {
+        self.device.device_id
+    }
+
+    /// The alias the user gave the device, or the name it reports
+    pub fn display_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl Display for DiscoveredDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_name())?;
+        if let Some(product_type) = &self.product_type {
+            write!(f, " ({}", product_type)?;
+            if let Some(version) = &self.ios_version {
+                write!(f, ", iOS {}", version)?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, " [{}]", self.connection_type)
+    }
+}
+
+/// List the devices connected through usbmuxd
+///
+/// Uses the usbmuxd at `USBMUXD_SOCKET_ADDRESS` if it is set, and the platform's default otherwise. Devices that
+/// don't trust this computer are still listed, since their name and version can be read without pairing.
+pub async fn list_devices() -> Result<Vec<DiscoveredDevice>, Report> {
+    let addr = UsbmuxdAddr::from_env_var().context("Invalid USBMUXD_SOCKET_ADDRESS")?;
+    list_devices_at(addr).await
+}
+
+/// Like [`list_devices`], with the aliases remembered in `storage`
+///
+/// Aliases that can't be read are left out, so a broken storage doesn't hide the devices.
+pub async fn list_devices_with_aliases(
+    storage: &dyn SideloadingStorage,
+) -> Result<Vec<DiscoveredDevice>, Report> {
+    let mut devices = list_devices().await?;
+    for device in &mut devices {
+        device.alias = device_alias(storage, &device.udid).unwrap_or_else(|e| {
+            warn!("Failed to read the alias of {}: {:?}", device.udid, e);
+            None
+        });
+    }
+    Ok(devices)
+}
+
+/// List the devices connected through the usbmuxd at `addr`
+pub async fn list_devices_at(addr: UsbmuxdAddr) -> Result<Vec<DiscoveredDevice>, Report> {
+    let mut usbmuxd = addr
+        .connect(0)
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to connect to usbmuxd")?;
+    let devices = usbmuxd
+        .get_devices()
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to list devices")?;
+
+    let mut discovered = Vec::with_capacity(devices.len());
+    for device in devices {
+        discovered.push(describe(device, addr.clone()).await);
+    }
+
+    Ok(discovered)
+}
+
+/// Read the device's name and version, leaving out whatever can't be read
+async fn describe(device: UsbmuxdDevice, addr: UsbmuxdAddr) -> DiscoveredDevice {
+    let provider = device.to_provider(addr.clone(), DISCOVERY_LABEL);
+    let (name, product_type, ios_version) = match LockdownClient::connect(&provider).await {
+        Ok(mut lockdown) => (
+            get_string(&mut lockdown, "DeviceName", &device.udid).await,
+            get_string(&mut lockdown, "ProductType", &device.udid).await,
+            get_string(&mut lockdown, "ProductVersion", &device.udid).await,
+        ),
+        Err(e) => {
+            debug!("Failed to connect to lockdown of {}: {}", device.udid, e);
+            (None, None, None)
+        }
+    };
+
+    DiscoveredDevice {
+        name: name.unwrap_or_else(|| device.udid.clone()),
+        udid: device.udid.clone(),
+        product_type,
+        ios_version,
+        connection_type: ConnectionType::from(&device.connection_type),
+        alias: None,
+        device,
+        addr,
+    }
+}
+
+async fn get_string(lockdown: &mut LockdownClient, key: &str, udid: &str) -> Option<String> {
+    match lockdown.get_value(Some(key), None).await {
+        Ok(value) => value.as_string().map(str::to_string),
+        Err(e) => {
+            debug!("Failed to get {} of {}: {}", key, udid, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,96 @@
+pub mod aliases;
+pub mod app_manager;
+pub mod developer_mode;
+pub mod discovery;
+pub mod pairing;
+pub mod profiles;
+
+use idevice::{IdeviceService, lockdown::LockdownClient, provider::IdeviceProvider};
+use rootcause::prelude::*;
+use tracing::debug;
+
+pub struct IdeviceInfo {
+    pub name: String,
+    pub udid: String,
+    /// The hardware model, e.g. `iPhone15,2`
+    pub product_type: Option<String>,
+    /// The label the user gave the device, see [`aliases`]
+    pub alias: Option<String>,
+}
+
+impl IdeviceInfo {
+    pub fn new(name: String, udid: String) -> Self {
+        Self {
+            name,
+            udid,
+            product_type: None,
+            alias: None,
+        }
+    }
+
+    /// The alias the user gave the device, or the name it reports
+    pub fn display_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    pub async fn from_device(device: &dyn IdeviceProvider) -> Result<Self, Report> {
+        let mut lockdown = LockdownClient::connect(device)
+            .await
+            .context("Failed to connect to device lockdown")?;
+        let pairing = device
+            .get_pairing_file()
+            .await
+            .context("Failed to get device pairing file")?;
+        lockdown
+            .start_session(&pairing)
+            .await
+            .map_err(pairing::pairing_error)
+            .context("Failed to start lockdown session")?;
+        let device_name = lockdown
+            .get_value(Some("DeviceName"), None)
+            .await
+            .context("Failed to get device name")?
+            .as_string()
+            .ok_or_else(|| report!("Device name is not a string"))?
+            .to_string();
+
+        let device_udid = lockdown
+            .get_value(Some("UniqueDeviceID"), None)
+            .await
+            .context("Failed to get device UDID")?
+            .as_string()
+            .ok_or_else(|| report!("Device UDID is not a string"))?
+            .to_string();
+
+        let product_type = lockdown
+            .get_value(Some("ProductType"), None)
+            .await
+            .ok()
+            .and_then(|v| v.as_string().map(|s| s.to_string()));
+
+        Ok(Self {
+            product_type,
+            ..Self::new(device_name, device_udid)
+        })
+    }
+}
+
+/// Retrieve the usbmuxd pairing record of the connected device, serialized as a plist
+///
+/// This is the pairing file SideStore and StikJIT ask users to import. It contains the keys needed to
+/// talk to the device, so only its size is logged.
+pub async fn export_pairing_file(provider: &dyn IdeviceProvider) -> Result<Vec<u8>, Report> {
+    let pairing_file = provider
+        .get_pairing_file()
+        .await
+        .context("Failed to get device pairing file")?
+        .serialize()
+        .context("Failed to serialize device pairing file")?;
+    debug!(
+        "Exported pairing file for {} ({} bytes)",
+        provider.label(),
+        pairing_file.len()
+    );
+
+    Ok(pairing_file)
+}
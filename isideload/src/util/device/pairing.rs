@@ -0,0 +1,123 @@
+//! Checking whether a device trusts this computer, and pairing with it
+
+use std::fmt::Display;
+
+use idevice::{
+    IdeviceError, IdeviceService, lockdown::LockdownClient, pairing_file::PairingFile,
+    provider::IdeviceProvider,
+};
+use rootcause::prelude::*;
+use tracing::{debug, info};
+
+use crate::SideloadError as Error;
+
+/// Whether a device trusts this computer
+///
+/// Anything but [`PairingState::Paired`] means the user has to do something on the device before it can be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingState {
+    /// The device trusts this computer
+    Paired,
+    /// There is no pairing record for the device, or the device no longer accepts it
+    NotPaired,
+    /// The "Trust This Computer?" dialog is showing on the device and hasn't been answered yet
+    AwaitingTrust,
+    /// The user tapped "Don't Trust"
+    TrustDenied,
+    /// The device is locked with a passcode, and has to be unlocked before it can be paired
+    PasswordProtected,
+}
+
+impl PairingState {
+    /// The pairing state a lockdown error indicates, or `None` if the error isn't about pairing
+    pub fn from_idevice_error(error: &IdeviceError) -> Option<Self> {
+        match error {
+            IdeviceError::InvalidHostID => Some(Self::NotPaired),
+            IdeviceError::PairingDialogResponsePending => Some(Self::AwaitingTrust),
+            IdeviceError::UserDeniedPairing => Some(Self::TrustDenied),
+            IdeviceError::PasswordProtected => Some(Self::PasswordProtected),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PairingState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PairingState::Paired => write!(f, "the device is paired"),
+            PairingState::NotPaired => write!(f, "the device is not paired with this computer"),
+            PairingState::AwaitingTrust => {
+                write!(f, "waiting for the user to tap Trust on the device")
+            }
+            PairingState::TrustDenied => write!(f, "the user did not trust this computer"),
+            PairingState::PasswordProtected => {
+                write!(f, "the device is locked, unlock it to continue")
+            }
+        }
+    }
+}
+
+/// Turn a lockdown error into [`crate::SideloadError::PairingRequired`] if it is about pairing
+pub(crate) fn pairing_error(error: IdeviceError) -> Error {
+    match PairingState::from_idevice_error(&error) {
+        Some(state) => Error::PairingRequired(state),
+        None => Error::IdeviceError(error),
+    }
+}
+
+/// Check whether the device trusts this computer, by starting a lockdown session with its pairing record
+pub async fn pairing_state(provider: &dyn IdeviceProvider) -> Result<PairingState, Report> {
+    let pairing_file = match provider.get_pairing_file().await {
+        Ok(pairing_file) => pairing_file,
+        Err(e) => {
+            debug!("No pairing record for {}: {}", provider.label(), e);
+            return Ok(PairingState::NotPaired);
+        }
+    };
+
+    let mut lockdown = LockdownClient::connect(provider)
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to connect to device lockdown")?;
+    match lockdown.start_session(&pairing_file).await {
+        Ok(_) => Ok(PairingState::Paired),
+        Err(e) => match PairingState::from_idevice_error(&e) {
+            Some(state) => Ok(state),
+            None => Err(report!(Error::IdeviceError(e))
+                .context("Failed to start lockdown session")
+                .into()),
+        },
+    }
+}
+
+/// Pair with the device, which shows the "Trust This Computer?" dialog on it
+///
+/// This waits until the user answers the dialog, so callers should tell the user to unlock the device and tap
+/// Trust first, and may want to wrap it in a timeout. `host_id` is a UUID identifying this computer and
+/// `system_buid` the BUID of the usbmuxd the device is connected through.
+///
+/// The returned pairing record isn't saved anywhere. Connections through usbmuxd only work once it has been saved
+/// to usbmuxd, e.g. with `UsbmuxdConnection::save_pair_record`.
+pub async fn pair(
+    provider: &dyn IdeviceProvider,
+    host_id: &str,
+    system_buid: &str,
+    host_name: Option<&str>,
+) -> Result<PairingFile, Report> {
+    let mut lockdown = LockdownClient::connect(provider)
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to connect to device lockdown")?;
+    info!(
+        "Pairing with {}, waiting for the user to tap Trust",
+        provider.label()
+    );
+    let pairing_file = lockdown
+        .pair(host_id, system_buid, host_name)
+        .await
+        .map_err(pairing_error)
+        .context("Failed to pair with device")?;
+    info!("Paired with {}", provider.label());
+
+    Ok(pairing_file)
+}
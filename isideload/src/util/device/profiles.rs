@@ -0,0 +1,117 @@
+//! Listing, installing and removing the provisioning profiles installed on a device, with the misagent service
+//!
+//! Installing an app also installs its embedded profile, but profiles from earlier installs stay on the device.
+//! Expired profiles, or ones issued for another certificate, can make installing or launching the app fail.
+
+use idevice::{IdeviceService, misagent::MisagentClient, provider::IdeviceProvider};
+use rootcause::prelude::*;
+use tracing::{info, warn};
+
+use crate::{
+    SideloadError as Error, sideload::profile::ProvisioningProfileInfo, util::clock::Clock,
+};
+
+/// A provisioning profile installed on a device
+#[derive(Debug, Clone)]
+pub struct InstalledProfile {
+    pub info: ProvisioningProfileInfo,
+    /// The raw (CMS signed) profile
+    pub data: Vec<u8>,
+}
+
+impl InstalledProfile {
+    pub fn uuid(&self) -> Option<&str> {
+        self.info.uuid.as_deref()
+    }
+}
+
+/// List the provisioning profiles installed on the device
+///
+/// Profiles that can't be parsed are skipped.
+pub async fn list_profiles(
+    provider: &dyn IdeviceProvider,
+) -> Result<Vec<InstalledProfile>, Report> {
+    let mut client = connect(provider).await?;
+    let profiles = client
+        .copy_all()
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to list installed provisioning profiles")?;
+
+    Ok(profiles
+        .into_iter()
+        .filter_map(|data| match ProvisioningProfileInfo::parse(&data) {
+            Ok(info) => Some(InstalledProfile { info, data }),
+            Err(e) => {
+                warn!("Failed to parse installed provisioning profile: {:?}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Install a provisioning profile on the device, replacing an installed one with the same UUID
+pub async fn install_profile(provider: &dyn IdeviceProvider, profile: &[u8]) -> Result<(), Report> {
+    let mut client = connect(provider).await?;
+    client
+        .install(profile.to_vec())
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to install provisioning profile")?;
+    info!("Installed provisioning profile");
+
+    Ok(())
+}
+
+/// Remove the provisioning profile with `uuid` from the device
+pub async fn remove_profile(provider: &dyn IdeviceProvider, uuid: &str) -> Result<(), Report> {
+    let mut client = connect(provider).await?;
+    client
+        .remove(uuid)
+        .await
+        .map_err(Error::IdeviceError)
+        .context(format!("Failed to remove provisioning profile {}", uuid))?;
+    info!("Removed provisioning profile {}", uuid);
+
+    Ok(())
+}
+
+/// Remove the profiles of `team_id` for `bundle_ids` that are expired according to `clock` or aren't the profile with
+/// `keep_uuid`
+///
+/// Profiles of other teams, or for other apps, are left alone. Returns the UUIDs of the removed profiles.
+pub async fn remove_stale_profiles(
+    provider: &dyn IdeviceProvider,
+    team_id: &str,
+    bundle_ids: &[String],
+    keep_uuid: Option<&str>,
+    clock: &dyn Clock,
+) -> Result<Vec<String>, Report> {
+    let mut removed = Vec::new();
+    for profile in list_profiles(provider).await? {
+        let Some(uuid) = profile.uuid() else {
+            continue;
+        };
+        let ours = profile.info.team_id.as_deref() == Some(team_id)
+            && profile
+                .info
+                .bundle_identifier()
+                .is_some_and(|id| bundle_ids.iter().any(|b| b == id));
+        if !ours || (Some(uuid) == keep_uuid && !profile.info.is_expired(clock)) {
+            continue;
+        }
+
+        remove_profile(provider, uuid).await?;
+        removed.push(uuid.to_string());
+    }
+
+    Ok(removed)
+}
+
+async fn connect(provider: &dyn IdeviceProvider) -> Result<MisagentClient, Report> {
+    let client = MisagentClient::connect(provider)
+        .await
+        .map_err(Error::IdeviceError)
+        .context("Failed to connect to misagent")?;
+    Ok(client)
+}
@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+
+use aes_gcm::{AeadInOut, Aes256Gcm, KeyInit, aead::Nonce};
+use rootcause::prelude::*;
+use sha2::Sha256;
+use tracing::info;
+
+use crate::util::{fs_storage::FsStorage, storage::SideloadingStorage};
+
+/// Holds the salt for the key derivation and a known value to check the key against
+const METADATA_KEY: &str = ".encryption";
+const FORMAT_VERSION: u8 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const PBKDF2_ROUNDS: u32 = 600_000;
+const CHECK_VALUE: &[u8] = b"isideload";
+
+/// Files that hold the machine ID on Linux, in order of preference
+const MACHINE_ID_PATHS: &[&str] = &["/etc/machine-id", "/var/lib/dbus/machine-id"];
+
+/// Like [`FsStorage`], but every file is encrypted with AES-256-GCM
+///
+/// Meant for platforms without a usable keyring, like headless servers and containers. The key is derived from a
+/// passphrase with PBKDF2, using a random salt saved in the storage directory, and checked when the storage is
+/// opened, so a wrong passphrase fails early instead of on the first read. Each file is authenticated together with
+/// the name it is stored under, so encrypted files can't be swapped between entries.
+pub struct EncryptedFsStorage {
+    inner: FsStorage,
+    cipher: Aes256Gcm,
+}
+
+impl EncryptedFsStorage {
+    /// Open the storage in `path`, deriving the key from `passphrase`
+    ///
+    /// Fails if the directory was created with a different passphrase.
+    pub fn with_passphrase(path: PathBuf, passphrase: &str) -> Result<Self, Report> {
+        Self::open(path, passphrase.as_bytes())
+    }
+
+    /// Open the storage in `path`, deriving the key from the machine ID
+    ///
+    /// This needs no passphrase, but only keeps the files from being read on another machine, anyone who can read
+    /// the machine ID can read them too. Only supported on Linux, where the machine ID is in `/etc/machine-id`.
+    pub fn with_machine_key(path: PathBuf) -> Result<Self, Report> {
+        let machine_id = MACHINE_ID_PATHS
+            .iter()
+            .find_map(|path| std::fs::read_to_string(path).ok())
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+        let Some(machine_id) = machine_id else {
+            bail!("No machine ID is available on this platform, use a passphrase instead");
+        };
+        Self::open(path, machine_id.as_bytes())
+    }
+
+    fn open(path: PathBuf, secret: &[u8]) -> Result<Self, Report> {
+        let inner = FsStorage::new(path);
+        match inner.retrieve_data(METADATA_KEY)? {
+            Some(metadata) => {
+                if metadata.len() < SALT_LENGTH {
+                    bail!("Encrypted storage metadata is corrupted");
+                }
+                let (salt, check) = metadata.split_at(SALT_LENGTH);
+                let storage = Self {
+                    cipher: derive_cipher(secret, salt)?,
+                    inner,
+                };
+                if !matches!(storage.decrypt(METADATA_KEY, check), Ok(value) if value == CHECK_VALUE)
+                {
+                    bail!("Wrong passphrase for encrypted storage");
+                }
+                Ok(storage)
+            }
+            None => {
+                let salt = rand::random::<[u8; SALT_LENGTH]>();
+                let storage = Self {
+                    cipher: derive_cipher(secret, &salt)?,
+                    inner,
+                };
+                let mut metadata = salt.to_vec();
+                metadata.extend(storage.encrypt(METADATA_KEY, CHECK_VALUE)?);
+                storage.inner.store_data(METADATA_KEY, &metadata)?;
+                info!("Created encrypted storage");
+                Ok(storage)
+            }
+        }
+    }
+
+    fn encrypt(&self, key: &str, data: &[u8]) -> Result<Vec<u8>, Report> {
+        let nonce_bytes = rand::random::<[u8; NONCE_LENGTH]>();
+        let nonce = Nonce::<Aes256Gcm>::try_from(&nonce_bytes[..])?;
+        let mut buf = data.to_vec();
+        self.cipher
+            .encrypt_in_place(&nonce, key.as_bytes(), &mut buf)
+            .map_err(|e| report!("Failed to encrypt {}: {}", key, e))?;
+
+        let mut encrypted = Vec::with_capacity(1 + NONCE_LENGTH + buf.len());
+        encrypted.push(FORMAT_VERSION);
+        encrypted.extend_from_slice(&nonce_bytes);
+        encrypted.extend(buf);
+        Ok(encrypted)
+    }
+
+    fn decrypt(&self, key: &str, data: &[u8]) -> Result<Vec<u8>, Report> {
+        let Some((&version, rest)) = data.split_first() else {
+            bail!("{} is empty", key);
+        };
+        if version != FORMAT_VERSION {
+            bail!("{} has unsupported encryption version {}", key, version);
+        }
+        if rest.len() < NONCE_LENGTH {
+            bail!("{} is truncated", key);
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+        let nonce = Nonce::<Aes256Gcm>::try_from(nonce)?;
+        let mut buf = ciphertext.to_vec();
+        self.cipher
+            .decrypt_in_place(&nonce, key.as_bytes(), &mut buf)
+            .map_err(|e| report!("Failed to decrypt {}: {}", key, e))?;
+        Ok(buf)
+    }
+}
+
+fn derive_cipher(secret: &[u8], salt: &[u8]) -> Result<Aes256Gcm, Report> {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<hmac::Hmac<Sha256>>(secret, salt, PBKDF2_ROUNDS, &mut key)
+        .map_err(|e| report!("Failed to derive storage key: {}", e))?;
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+impl SideloadingStorage for EncryptedFsStorage {
+    fn store_data(&self, key: &str, value: &[u8]) -> Result<(), Report> {
+        self.inner.store_data(key, &self.encrypt(key, value)?)
+    }
+
+    fn retrieve_data(&self, key: &str) -> Result<Option<Vec<u8>>, Report> {
+        match self.inner.retrieve_data(key)? {
+            Some(data) => Ok(Some(self.decrypt(key, &data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&self, key: &str, value: &str) -> Result<(), Report> {
+        self.store_data(key, value.as_bytes())
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<String>, Report> {
+        Ok(self
+            .retrieve_data(key)?
+            .map(|data| String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Report> {
+        self.inner.delete(key)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Report> {
+        let mut keys = self.inner.list(prefix)?;
+        keys.retain(|key| key != METADATA_KEY);
+        Ok(keys)
+    }
+}
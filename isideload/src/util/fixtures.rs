@@ -0,0 +1,168 @@
+//! Minimal app bundles for exercising bundle parsing, bundle id rewriting and signing without a real IPA
+
+use std::path::{Path, PathBuf};
+
+use plist::Value;
+use plist_macro::plist;
+use rootcause::prelude::*;
+
+/// Size of a page on arm64 iOS, which segments are aligned to
+const PAGE_SIZE: u64 = 0x4000;
+
+/// Generate `<bundle_id's last component>.app` in `parent_dir` and return its path
+///
+/// The app contains a main executable, a framework (`Dummy.framework`) and an app extension
+/// (`Widget.appex`, identified as `<bundle_id>.widget`). The executables are tiny arm64 Mach-O files
+/// that have the structure codesigning expects, but don't do anything when launched.
+pub fn generate_app(parent_dir: &Path, bundle_id: &str) -> Result<PathBuf, Report> {
+    let name = bundle_id
+        .rsplit('.')
+        .next()
+        .unwrap_or(bundle_id)
+        .to_string();
+    let app_dir = parent_dir.join(format!("{}.app", name));
+
+    write_bundle(
+        &app_dir,
+        &name,
+        plist!({
+            "CFBundleIdentifier": bundle_id,
+            "CFBundleName": name.as_str(),
+            "CFBundleExecutable": name.as_str(),
+            "CFBundlePackageType": "APPL",
+            "CFBundleShortVersionString": "1.0",
+            "CFBundleVersion": "1",
+            "MinimumOSVersion": "14.0",
+            "UIDeviceFamily": [1, 2],
+            "UISupportedDevices": ["iPhone15,2"],
+        }),
+    )?;
+
+    write_bundle(
+        &app_dir.join("Frameworks").join("Dummy.framework"),
+        "Dummy",
+        plist!({
+            "CFBundleIdentifier": "com.example.Dummy",
+            "CFBundleName": "Dummy",
+            "CFBundleExecutable": "Dummy",
+            "CFBundlePackageType": "FMWK",
+            "CFBundleShortVersionString": "1.0",
+            "CFBundleVersion": "1",
+        }),
+    )?;
+
+    write_bundle(
+        &app_dir.join("PlugIns").join("Widget.appex"),
+        "Widget",
+        plist!({
+            "CFBundleIdentifier": format!("{}.widget", bundle_id),
+            "CFBundleName": "Widget",
+            "CFBundleExecutable": "Widget",
+            "CFBundlePackageType": "XPC!",
+            "CFBundleShortVersionString": "1.0",
+            "CFBundleVersion": "1",
+            "NSExtension": {
+                "NSExtensionPointIdentifier": "com.apple.widgetkit-extension",
+            },
+        }),
+    )?;
+
+    Ok(app_dir)
+}
+
+fn write_bundle(dir: &Path, executable: &str, info: Value) -> Result<(), Report> {
+    std::fs::create_dir_all(dir).context("Failed to create fixture bundle directory")?;
+    info.to_file_xml(dir.join("Info.plist"))
+        .context("Failed to write fixture Info.plist")?;
+    std::fs::write(dir.join(executable), minimal_macho())
+        .context("Failed to write fixture executable")?;
+    Ok(())
+}
+
+/// Build an unsigned arm64 executable with `__PAGEZERO`, a `__TEXT` segment holding a `__text` section with a single
+/// `ret`, and a `__LINKEDIT` segment
+///
+/// `__LINKEDIT` is the last segment so a code signature can be appended to it, and `__text` is at the end of its page
+/// so the signature's load command fits before it, like codesigning requires.
+pub fn minimal_macho() -> Vec<u8> {
+    const MH_MAGIC_64: u32 = 0xfeedfacf;
+    const CPU_TYPE_ARM64: u32 = 0x0100000c;
+    const MH_EXECUTE: u32 = 2;
+    // MH_NOUNDEFS | MH_DYLDLINK | MH_TWOLEVEL | MH_PIE
+    const FLAGS: u32 = 0x1 | 0x4 | 0x80 | 0x200000;
+    const LC_SEGMENT_64: u32 = 0x19;
+    const SEGMENT_COMMAND_SIZE: u32 = 72;
+    const SECTION_SIZE: u32 = 80;
+    // S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS
+    const TEXT_SECTION_FLAGS: u32 = 0x80000000 | 0x400;
+    const RET: [u8; 4] = 0xd65f03c0u32.to_le_bytes();
+    const TEXT_OFFSET: u64 = PAGE_SIZE - RET.len() as u64;
+    const LINKEDIT_SIZE: u64 = 16;
+
+    let segments: [(&str, u64, u64, u64, u64, u32); 3] = [
+        // name, vmaddr, vmsize, fileoff, filesize, protection
+        ("__PAGEZERO", 0, 0x1_0000_0000, 0, 0, 0),
+        ("__TEXT", 0x1_0000_0000, PAGE_SIZE, 0, PAGE_SIZE, 5),
+        (
+            "__LINKEDIT",
+            0x1_0000_0000 + PAGE_SIZE,
+            PAGE_SIZE,
+            PAGE_SIZE,
+            LINKEDIT_SIZE,
+            1,
+        ),
+    ];
+    let commands_size = SEGMENT_COMMAND_SIZE * segments.len() as u32 + SECTION_SIZE;
+
+    let mut data = Vec::with_capacity((PAGE_SIZE + LINKEDIT_SIZE) as usize);
+    for field in [
+        MH_MAGIC_64,
+        CPU_TYPE_ARM64,
+        0,
+        MH_EXECUTE,
+        segments.len() as u32,
+        commands_size,
+        FLAGS,
+        0,
+    ] {
+        data.extend_from_slice(&field.to_le_bytes());
+    }
+
+    for (name, vmaddr, vmsize, fileoff, filesize, protection) in segments {
+        let sections = u32::from(name == "__TEXT");
+        data.extend_from_slice(&LC_SEGMENT_64.to_le_bytes());
+        data.extend_from_slice(&(SEGMENT_COMMAND_SIZE + SECTION_SIZE * sections).to_le_bytes());
+        data.extend_from_slice(&name_field(name));
+        for value in [vmaddr, vmsize, fileoff, filesize] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        // maxprot, initprot, nsects, flags
+        for value in [protection, protection, sections, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        if sections == 1 {
+            data.extend_from_slice(&name_field("__text"));
+            data.extend_from_slice(&name_field(name));
+            for value in [vmaddr + TEXT_OFFSET, RET.len() as u64] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            // offset, align (as a power of 2), reloff, nreloc, flags, reserved1, reserved2, reserved3
+            for value in [TEXT_OFFSET as u32, 2, 0, 0, TEXT_SECTION_FLAGS, 0, 0, 0] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    data.resize(TEXT_OFFSET as usize, 0);
+    data.extend_from_slice(&RET);
+    data.resize((PAGE_SIZE + LINKEDIT_SIZE) as usize, 0);
+    data
+}
+
+/// A segment or section name, padded to the 16 bytes Mach-O stores them in
+fn name_field(name: &str) -> [u8; 16] {
+    let mut field = [0u8; 16];
+    field[..name.len()].copy_from_slice(name.as_bytes());
+    field
+}
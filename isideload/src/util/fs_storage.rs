@@ -1,4 +1,7 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use rootcause::prelude::*;
 
@@ -20,12 +23,51 @@ impl Default for FsStorage {
     }
 }
 
+/// Makes the temporary file names of concurrent writes in the same process unique
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The temporary file a value is written to before it replaces `file_name`, `<file_name>.<pid>.<counter>.tmp`
+fn temp_file_name(file_name: &str) -> String {
+    format!(
+        "{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Whether `file_name` was made by [`temp_file_name`], so keys that merely end in `.tmp` are still listed
+fn is_temp_file(file_name: &str) -> bool {
+    let Some(rest) = file_name.strip_suffix(".tmp") else {
+        return false;
+    };
+    let mut parts = rest.rsplitn(3, '.');
+    let is_number = |part: Option<&str>| {
+        part.is_some_and(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+    };
+    is_number(parts.next())
+        && is_number(parts.next())
+        && parts.next().is_some_and(|name| !name.is_empty())
+}
+
 impl SideloadingStorage for FsStorage {
     fn store_data(&self, key: &str, data: &[u8]) -> Result<(), Report> {
         let path = self.path.join(key);
         let parent = path.parent().unwrap_or(Path::new("."));
         std::fs::create_dir_all(parent).context("Failed to create storage directory")?;
-        std::fs::write(&path, data).context("Failed to write data to file")?;
+        // write to a temporary file first so an interrupted write can't leave a truncated file behind
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| report!("Invalid storage key {}", key))?
+            .to_string_lossy();
+        let temp_path = path.with_file_name(temp_file_name(&file_name));
+        let result = std::fs::write(&temp_path, data)
+            .and_then(|_| std::fs::rename(&temp_path, &path))
+            .context("Failed to write data to file");
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result?;
 
         Ok(())
     }
@@ -50,4 +92,55 @@ impl SideloadingStorage for FsStorage {
             Err(e) => Err(e),
         }
     }
+
+    fn delete(&self, key: &str) -> Result<(), Report> {
+        match std::fs::remove_file(self.path.join(key)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(report!(e).context("Failed to delete file").into()),
+        }
+    }
+
+    /// Only the directory of the prefix is walked, so listing a namespace doesn't read the whole storage directory
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Report> {
+        let directory = match prefix.rsplit_once('/') {
+            Some((directory, _)) => directory,
+            None => "",
+        };
+        let mut keys = Vec::new();
+        collect_keys(&self.path, &self.path.join(directory), &mut keys)?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+fn collect_keys(root: &Path, directory: &Path, keys: &mut Vec<String>) -> Result<(), Report> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(report!(e)
+                .context("Failed to read storage directory")
+                .into());
+        }
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_keys(root, &path, keys)?;
+        } else if !path
+            .file_name()
+            .is_some_and(|name| is_temp_file(&name.to_string_lossy()))
+            && let Ok(relative) = path.strip_prefix(root)
+        {
+            let key = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            keys.push(key);
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,352 @@
+//! A cache of the original, unsigned apps that were installed, so they can be re-signed later
+//! without asking the user for the IPA again
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use plist::{Dictionary, Value};
+use rootcause::{option_ext::OptionExt, prelude::*};
+use tracing::{debug, info, warn};
+use zip::ZipArchive;
+
+use crate::{SideloadError, sideload::package};
+
+/// The default limit for the total size of the cache, 2 GiB
+pub const DEFAULT_MAX_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+/// The default number of versions kept per bundle ID
+pub const DEFAULT_MAX_VERSIONS: usize = 2;
+
+/// A directory of unsigned IPAs, keyed by bundle ID and version
+///
+/// IPAs are stored as `<dir>/<bundle id>/<version>.ipa`. When the cache grows past its size limit, or a bundle has
+/// more versions than allowed, the least recently used IPAs are removed.
+#[derive(Debug, Clone)]
+pub struct IpaCache {
+    dir: PathBuf,
+    max_size: u64,
+    max_versions: usize,
+}
+
+/// An IPA stored in an [`IpaCache`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedIpa {
+    pub bundle_id: String,
+    /// `CFBundleShortVersionString`, falling back to `CFBundleVersion`
+    pub version: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+impl IpaCache {
+    /// Create a cache in `dir` with the default limits. The directory is created when the first IPA is stored.
+    pub fn new(dir: PathBuf) -> Self {
+        IpaCache {
+            dir,
+            max_size: DEFAULT_MAX_SIZE,
+            max_versions: DEFAULT_MAX_VERSIONS,
+        }
+    }
+
+    /// Set the limit for the total size of the cache in bytes. Defaults to [`DEFAULT_MAX_SIZE`].
+    ///
+    /// The most recently stored IPA is always kept, even if it is larger than the limit on its own.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Set how many versions of each bundle ID are kept. Defaults to [`DEFAULT_MAX_VERSIONS`].
+    pub fn max_versions(mut self, versions: usize) -> Self {
+        self.max_versions = versions.max(1);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Store the app at `app_path`, which can be an IPA or a `.app` directory, replacing any cached IPA with the
+    /// same bundle ID and version
+    pub fn insert(&self, app_path: &Path) -> Result<CachedIpa, Report> {
+        if app_path.starts_with(&self.dir) && app_path.is_file() {
+            // already cached, e.g. when refreshing from the cache
+            touch(app_path)?;
+            let entry = entry_for(app_path)?
+                .ok_or_report()
+                .context("Invalid cache entry")?;
+            return Ok(entry);
+        }
+
+        let info = if app_path.is_file() {
+            read_ipa_info(app_path)?
+        } else {
+            plist::from_file(app_path.join("Info.plist")).context(SideloadError::InvalidBundle(
+                "Failed to read Info.plist".to_string(),
+            ))?
+        };
+        let bundle_id = info
+            .get("CFBundleIdentifier")
+            .and_then(Value::as_string)
+            .ok_or_else(|| {
+                report!(SideloadError::InvalidBundle(
+                    "Info.plist has no CFBundleIdentifier".to_string()
+                ))
+            })?;
+        let version = info
+            .get("CFBundleShortVersionString")
+            .or_else(|| info.get("CFBundleVersion"))
+            .and_then(Value::as_string)
+            .unwrap_or("unknown");
+
+        let path = self.path_for(bundle_id, version);
+        let bundle_dir = path.parent().ok_or_report()?;
+        std::fs::create_dir_all(bundle_dir).context("Failed to create IPA cache directory")?;
+        // write to a temporary file first so an interrupted copy can't leave a truncated IPA behind
+        let temp_path = path.with_extension("tmp");
+        if app_path.is_file() {
+            std::fs::copy(app_path, &temp_path).context("Failed to copy IPA into cache")?;
+        } else {
+            package::write_ipa(app_path, &temp_path, None, None)
+                .context("Failed to package app for cache")?;
+        }
+        std::fs::rename(&temp_path, &path).context("Failed to copy IPA into cache")?;
+        info!("Cached {} {} at {}", bundle_id, version, path.display());
+
+        if let Err(e) = self.evict(&path) {
+            warn!("Failed to evict old IPAs from cache: {:?}", e);
+        }
+
+        let entry = entry_for(&path)?
+            .ok_or_report()
+            .context("Invalid cache entry")?;
+        Ok(entry)
+    }
+
+    /// Get a cached IPA, the most recently used version if `version` is `None`
+    pub fn get(&self, bundle_id: &str, version: Option<&str>) -> Result<Option<CachedIpa>, Report> {
+        let entry = match version {
+            Some(version) => entry_for(&self.path_for(bundle_id, version))?,
+            None => self
+                .entries()?
+                .into_iter()
+                .filter(|e| e.bundle_id == bundle_id)
+                .max_by_key(|e| e.last_used),
+        };
+        if let Some(entry) = &entry {
+            touch(&entry.path)?;
+        }
+
+        Ok(entry)
+    }
+
+    /// Find the cached IPA an installed app was made from
+    ///
+    /// Apps are installed under their bundle ID with the team ID (and possibly a suffix) appended, so this matches
+    /// the longest cached bundle ID that `installed_bundle_id` starts with, preferring `version` if it is cached.
+    pub fn find_installed(
+        &self,
+        installed_bundle_id: &str,
+        version: Option<&str>,
+    ) -> Result<Option<CachedIpa>, Report> {
+        let entry = self
+            .entries()?
+            .into_iter()
+            .filter(|e| {
+                installed_bundle_id == e.bundle_id
+                    || installed_bundle_id
+                        .strip_prefix(e.bundle_id.as_str())
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+            .max_by_key(|e| {
+                (
+                    e.bundle_id.len(),
+                    version.is_some_and(|v| sanitize(v) == e.version),
+                    e.last_used,
+                )
+            });
+        if let Some(entry) = &entry {
+            touch(&entry.path)?;
+        }
+
+        Ok(entry)
+    }
+
+    /// List every cached IPA
+    pub fn entries(&self) -> Result<Vec<CachedIpa>, Report> {
+        let mut entries = Vec::new();
+        if !self.dir.is_dir() {
+            return Ok(entries);
+        }
+        for bundle_dir in std::fs::read_dir(&self.dir).context("Failed to read IPA cache")? {
+            let bundle_dir = bundle_dir?.path();
+            if !bundle_dir.is_dir() {
+                continue;
+            }
+            for file in std::fs::read_dir(&bundle_dir).context("Failed to read IPA cache")? {
+                if let Some(entry) = entry_for(&file?.path())? {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort_by(|a, b| (&a.bundle_id, &a.version).cmp(&(&b.bundle_id, &b.version)));
+
+        Ok(entries)
+    }
+
+    /// The total size of the cached IPAs in bytes
+    pub fn size(&self) -> Result<u64, Report> {
+        Ok(self.entries()?.iter().map(|e| e.size).sum())
+    }
+
+    /// Remove a cached IPA, or every version of the bundle ID if `version` is `None`
+    pub fn remove(&self, bundle_id: &str, version: Option<&str>) -> Result<(), Report> {
+        let path = match version {
+            Some(version) => self.path_for(bundle_id, version),
+            None => self.dir.join(sanitize(bundle_id)),
+        };
+        let result = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(report!(e).context("Failed to remove cached IPA").into()),
+        }
+    }
+
+    /// Remove every cached IPA
+    pub fn clear(&self) -> Result<(), Report> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(report!(e).context("Failed to clear IPA cache").into()),
+        }
+    }
+
+    fn path_for(&self, bundle_id: &str, version: &str) -> PathBuf {
+        self.dir
+            .join(sanitize(bundle_id))
+            .join(format!("{}.ipa", sanitize(version)))
+    }
+
+    /// Remove the least recently used IPAs until the cache fits its limits, never removing `keep`
+    fn evict(&self, keep: &Path) -> Result<(), Report> {
+        let mut entries = self.entries()?;
+        // most recently used first
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used));
+
+        let mut versions = std::collections::HashMap::<String, usize>::new();
+        let mut size = 0;
+        for entry in entries {
+            let count = versions.entry(entry.bundle_id.clone()).or_default();
+            *count += 1;
+            if entry.path != keep
+                && (*count > self.max_versions || size + entry.size > self.max_size)
+            {
+                debug!(
+                    "Evicting {} {} from IPA cache",
+                    entry.bundle_id, entry.version
+                );
+                std::fs::remove_file(&entry.path).context("Failed to remove cached IPA")?;
+                *count -= 1;
+                continue;
+            }
+            size += entry.size;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the main app's Info.plist from an IPA without extracting it
+fn read_ipa_info(ipa_path: &Path) -> Result<Dictionary, Report> {
+    let file = File::open(ipa_path).context("Failed to open application archive")?;
+    let mut archive = ZipArchive::new(file).context("Failed to open application archive")?;
+    let name = archive
+        .file_names()
+        .find(|name| {
+            let parts: Vec<_> = name.split('/').collect();
+            parts.len() == 3
+                && parts[0] == "Payload"
+                && parts[1].ends_with(".app")
+                && parts[2] == "Info.plist"
+        })
+        .map(str::to_string)
+        .ok_or_else(|| {
+            report!(SideloadError::InvalidBundle(
+                "No app Info.plist found in the application archive".to_string()
+            ))
+        })?;
+
+    let mut data = Vec::new();
+    archive
+        .by_name(&name)
+        .context("Failed to read Info.plist")?
+        .read_to_end(&mut data)
+        .context("Failed to read Info.plist")?;
+    let info = plist::from_bytes(&data).context(SideloadError::InvalidBundle(
+        "Failed to parse Info.plist".to_string(),
+    ))?;
+    Ok(info)
+}
+
+fn entry_for(path: &Path) -> Result<Option<CachedIpa>, Report> {
+    if path.extension().is_none_or(|ext| ext != "ipa") {
+        return Ok(None);
+    }
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(report!(e).context("Failed to read cached IPA").into()),
+    };
+    let (Some(version), Some(bundle_id)) =
+        (path.file_stem(), path.parent().and_then(|p| p.file_name()))
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(CachedIpa {
+        bundle_id: bundle_id.to_string_lossy().to_string(),
+        version: version.to_string_lossy().to_string(),
+        path: path.to_path_buf(),
+        size: metadata.len(),
+        last_used: metadata.modified()?,
+    }))
+}
+
+/// Mark a cached IPA as used, so it is evicted last
+fn touch(path: &Path) -> Result<(), Report> {
+    File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()))
+        .context("Failed to update cached IPA")?;
+    Ok(())
+}
+
+/// Make a bundle ID or version safe to use as a file name
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    // don't allow `.`, `..` or hidden files
+    if name.is_empty() || name.starts_with('.') {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
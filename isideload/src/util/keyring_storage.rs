@@ -1,63 +1,182 @@
+use std::sync::Mutex;
+
 use crate::util::storage::SideloadingStorage;
 use keyring::Entry;
 use rootcause::prelude::*;
+use tracing::{info, warn};
+
+/// The entry listing every key stored, since keychains can't be searched portably
+const INDEX_KEY: &str = ".index";
 
+/// Stores entries in the platform keychain
+///
+/// Keys are split at their last `/` into a namespace and a name, and each namespace gets its own keychain service,
+/// `<service name>/<namespace>`. Per-account entries like `<account>/key`, `<account>/session` and
+/// `<account>/anisette_state` are therefore grouped by account and labelled by purpose, instead of all sharing one
+/// service. Entries written flatly by older versions are moved into their namespace the first time they are read.
 pub struct KeyringStorage {
     pub service_name: String,
+    index_lock: Mutex<()>,
 }
 
 impl KeyringStorage {
     pub fn new(service_name: String) -> Self {
-        KeyringStorage { service_name }
+        KeyringStorage {
+            service_name,
+            index_lock: Mutex::new(()),
+        }
+    }
+
+    fn entry(&self, key: &str) -> Result<Entry, Report> {
+        Ok(match key.rsplit_once('/') {
+            Some((namespace, name)) => {
+                Entry::new(&format!("{}/{}", self.service_name, namespace), name)?
+            }
+            None => Entry::new(&self.service_name, key)?,
+        })
+    }
+
+    /// The entry older versions stored `key` in, if it differs from the namespaced one
+    fn legacy_entry(&self, key: &str) -> Result<Option<Entry>, Report> {
+        if !key.contains('/') {
+            return Ok(None);
+        }
+        Ok(Some(Entry::new(&self.service_name, key)?))
+    }
+
+    fn index_entry(&self) -> Result<Entry, Report> {
+        Ok(Entry::new(&self.service_name, INDEX_KEY)?)
+    }
+
+    fn read_index(&self) -> Result<Vec<String>, Report> {
+        match self.index_entry()?.get_password() {
+            Ok(index) => Ok(index.lines().map(str::to_string).collect()),
+            Err(keyring::Error::NoEntry) => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update_index(&self, key: &str, present: bool) -> Result<(), Report> {
+        let _guard = self
+            .index_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut index = self.read_index()?;
+        if index.iter().any(|k| k == key) == present {
+            return Ok(());
+        }
+        if present {
+            index.push(key.to_string());
+        } else {
+            index.retain(|k| k != key);
+        }
+        self.index_entry()?.set_password(&index.join("\n"))?;
+        Ok(())
+    }
+
+    fn index(&self, key: &str, present: bool) {
+        if let Err(e) = self.update_index(key, present) {
+            warn!("Failed to update keyring index for {}: {:?}", key, e);
+        }
+    }
+
+    /// Move a flat entry written by an older version to its namespaced entry, returning its value
+    fn migrate(&self, key: &str) -> Result<Option<String>, Report> {
+        let Some(legacy) = self.legacy_entry(key)? else {
+            return Ok(None);
+        };
+        let value = match legacy.get_password() {
+            Ok(value) => value,
+            Err(keyring::Error::NoEntry) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        self.entry(key)?.set_password(&value)?;
+        self.index(key, true);
+        if let Err(e) = legacy.delete_credential() {
+            warn!("Failed to remove migrated keyring entry {}: {:?}", key, e);
+        }
+        info!("Migrated keyring entry {} into its namespace", key);
+        Ok(Some(value))
     }
 }
 
 impl Default for KeyringStorage {
     fn default() -> Self {
-        KeyringStorage {
-            service_name: "isideload".to_string(),
-        }
+        Self::new("isideload".to_string())
     }
 }
 
 impl SideloadingStorage for KeyringStorage {
     fn store(&self, key: &str, value: &str) -> Result<(), Report> {
-        Entry::new(&self.service_name, key)?.set_password(value)?;
+        self.entry(key)?.set_password(value)?;
+        self.index(key, true);
         Ok(())
     }
 
     fn retrieve(&self, key: &str) -> Result<Option<String>, Report> {
-        let entry = Entry::new(&self.service_name, key)?;
+        let entry = self.entry(key)?;
         match entry.get_password() {
             Ok(password) => Ok(Some(password)),
-            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(keyring::Error::NoEntry) => self.migrate(key),
             Err(e) => Err(e.into()),
         }
     }
 
     fn delete(&self, key: &str) -> Result<(), Report> {
-        let entry = Entry::new(&self.service_name, key)?;
-        match entry.delete_credential() {
-            Ok(()) => Ok(()),
-            Err(keyring::Error::NoEntry) => Ok(()),
-            Err(e) => Err(e.into()),
+        for entry in [Some(self.entry(key)?), self.legacy_entry(key)?]
+            .into_iter()
+            .flatten()
+        {
+            match entry.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => {}
+                Err(e) => return Err(e.into()),
+            }
         }
+        self.index(key, false);
+        Ok(())
+    }
+
+    /// Entries written by older versions are only listed once they have been migrated by reading them
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Report> {
+        let mut keys = self
+            .read_index()?
+            .into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect::<Vec<_>>();
+        keys.sort();
+        Ok(keys)
     }
 
     // Linux doesn't seem to properly retrive binary secrets, so we don't use this implementation and instead let it fall back to base64 encoding.
     // Windows fails to store the base64 encoded data because it is too long.
     #[cfg(target_os = "windows")]
     fn store_data(&self, key: &str, value: &[u8]) -> Result<(), Report> {
-        Entry::new(&self.service_name, key)?.set_secret(value)?;
+        self.entry(key)?.set_secret(value)?;
+        self.index(key, true);
         Ok(())
     }
 
     #[cfg(target_os = "windows")]
     fn retrieve_data(&self, key: &str) -> Result<Option<Vec<u8>>, Report> {
-        let entry = Entry::new(&self.service_name, key)?;
+        let entry = self.entry(key)?;
         match entry.get_secret() {
             Ok(secret) => Ok(Some(secret)),
-            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(keyring::Error::NoEntry) => {
+                let Some(legacy) = self.legacy_entry(key)? else {
+                    return Ok(None);
+                };
+                let secret = match legacy.get_secret() {
+                    Ok(secret) => secret,
+                    Err(keyring::Error::NoEntry) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                self.store_data(key, &secret)?;
+                if let Err(e) = legacy.delete_credential() {
+                    warn!("Failed to remove migrated keyring entry {}: {:?}", key, e);
+                }
+                Ok(Some(secret))
+            }
             Err(e) => Err(e.into()),
         }
     }
@@ -0,0 +1,71 @@
+use std::process::Command;
+
+/// Name used when the host's computer name can't be determined
+pub const FALLBACK_MACHINE_NAME: &str = "isideload";
+
+// Apple shows the machine name next to the certificate, long names are cut off anyway
+const MAX_MACHINE_NAME_LEN: usize = 40;
+
+/// Get a machine name for development certificates based on the host's computer name
+///
+/// Falls back to [`FALLBACK_MACHINE_NAME`] if no usable name is found.
+pub fn default_machine_name() -> String {
+    host_name()
+        .map(|name| sanitize_machine_name(&name))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| FALLBACK_MACHINE_NAME.to_string())
+}
+
+/// Strip characters that the developer portal doesn't handle well and limit the length
+pub fn sanitize_machine_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(MAX_MACHINE_NAME_LEN)
+        .collect();
+    sanitized
+        .trim_matches(|c: char| c == '-' || c.is_whitespace())
+        .to_string()
+}
+
+fn host_name() -> Option<String> {
+    // macOS has a separate user facing computer name, which is what Xcode uses
+    #[cfg(target_os = "macos")]
+    if let Some(name) = command_output("scutil", &["--get", "ComputerName"]) {
+        return Some(name);
+    }
+
+    #[cfg(windows)]
+    if let Some(name) = std::env::var("COMPUTERNAME").ok().filter(|n| !n.is_empty()) {
+        return Some(name);
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(name) = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+    {
+        return Some(name);
+    }
+
+    std::env::var("HOSTNAME")
+        .ok()
+        .filter(|n| !n.is_empty())
+        .or_else(|| command_output("hostname", &[]))
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let name = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
@@ -0,0 +1,501 @@
+//! An in-memory stand-in for Apple's developer services
+//!
+//! [`MockDeveloperServices`] answers the requests of a [`DeveloperSession`] the way a free developer team does,
+//! registering devices, app IDs and app groups and handing out provisioning profiles, so signing, installing and
+//! refreshing can be tested without an Apple ID.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use plist::{Date, Dictionary, Value};
+use plist_macro::plist;
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair, PKCS_RSA_SHA256, SerialNumber};
+use rootcause::prelude::*;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::{
+    anisette::{AnisetteClientInfo, AnisetteData, AnisetteDataGenerator, AnisetteProvider},
+    auth::{apple_account::AppToken, grandslam::GrandSlam},
+    dev::{
+        developer_session::{DeveloperSession, DeveloperTransport},
+        teams::FREE_PROFILE_VALIDITY,
+    },
+    sideload::cert_identity::private_key_storage_key,
+    util::{
+        clock::{Clock, SystemClock},
+        storage::SideloadingStorage,
+    },
+};
+
+/// The team ID of the mock team
+pub const MOCK_TEAM_ID: &str = "ABCDE12345";
+
+/// The result code returned for requests the mock can't answer
+const UNSUPPORTED_CODE: i64 = 1;
+
+/// The result code returned for requests naming an app ID, app group or certificate that doesn't exist
+const NOT_FOUND_CODE: i64 = 35;
+
+#[derive(Debug, Default)]
+struct MockDeveloperState {
+    devices: Vec<Dictionary>,
+    certificates: Vec<Dictionary>,
+    app_ids: Vec<Dictionary>,
+    app_groups: Vec<Dictionary>,
+    /// The `applicationGroup` IDs assigned to each app ID, by `appIdId`
+    assignments: BTreeMap<String, Vec<String>>,
+    /// The actions requested so far, e.g. `listTeams`
+    requests: Vec<String>,
+    next_id: u64,
+}
+
+impl MockDeveloperState {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}{:08}", prefix, self.next_id)
+    }
+}
+
+/// Developer services backed by an in-memory free team instead of Apple
+///
+/// - The account has one free team, [`MOCK_TEAM_ID`].
+/// - Devices, app IDs and app groups are registered and listed like Apple does. App IDs and provisioning profiles
+///   expire 7 days after they are created, according to the clock set with [`MockDeveloperServices::with_clock`].
+/// - Certificates can't be requested, since the mock can't issue certificates for a CSR. Use
+///   [`MockDeveloperServices::issue_certificate`] to set one up, like an earlier sideload from the machine would
+///   have.
+/// - Provisioning profiles are unsigned plists, which signing and installing on a
+///   [`crate::util::mock_device::MockDeviceProvider`] accept but a real device doesn't.
+///
+/// Clones share the same team, so a clone can be handed to code under test while the original is used to inspect
+/// the result.
+#[derive(Clone)]
+pub struct MockDeveloperServices {
+    clock: Arc<dyn Clock>,
+    state: Arc<Mutex<MockDeveloperState>>,
+}
+
+impl MockDeveloperServices {
+    /// Create a free team with no devices, certificates or app IDs
+    pub fn new() -> Self {
+        Self {
+            clock: Arc::new(SystemClock),
+            state: Arc::new(Mutex::new(MockDeveloperState::default())),
+        }
+    }
+
+    /// Set the clock app IDs, certificates and profiles are dated with. Defaults to the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Create a developer session whose requests are answered by the mock, using its clock
+    pub fn session(&self) -> Result<DeveloperSession, Report> {
+        let client_info = AnisetteClientInfo {
+            client_info: "<MacBookPro13,2> <macOS;13.1;22C65> <com.apple.AuthKit/1 (com.apple.dt.Xcode/3594.4.19)>"
+                .to_string(),
+            user_agent: "akd/1.0 CFNetwork/1494.0.7 Darwin/23.4.0".to_string(),
+        };
+        let token = AppToken {
+            token: "mock-token".to_string(),
+            duration: FREE_PROFILE_VALIDITY.as_secs(),
+            expiry: self.clock.now_millis() + FREE_PROFILE_VALIDITY.as_millis() as u64,
+        };
+        let anisette_generator = AnisetteDataGenerator::new(Arc::new(RwLock::new(
+            NoAnisetteProvider(client_info.clone()),
+        )));
+
+        let mut session = DeveloperSession::new(
+            token,
+            "000000-00-00000000-0000-0000-0000-000000000000".to_string(),
+            Arc::new(GrandSlam::without_url_bag(client_info, false)?),
+            anisette_generator,
+        );
+        session.set_clock(self.clock.clone());
+        session.set_transport(Some(self.transport()));
+        Ok(session)
+    }
+
+    /// A transport answering requests with the mock, see [`DeveloperSession::set_transport`]
+    pub fn transport(&self) -> DeveloperTransport {
+        let mock = self.clone();
+        Arc::new(move |url, body| Ok(mock.respond(url, body)))
+    }
+
+    /// Issue a certificate for `machine_name` and save its private key for `apple_email` in `storage`
+    ///
+    /// Signing with the same storage and machine name then finds the certificate instead of requesting one.
+    pub fn issue_certificate(
+        &self,
+        storage: &dyn SideloadingStorage,
+        apple_email: &str,
+        machine_name: &str,
+    ) -> Result<(), Report> {
+        let key = KeyPair::generate_for(&PKCS_RSA_SHA256)
+            .context("Failed to generate mock private key")?;
+        let mut state = self.state();
+        let serial = state.next_id("");
+
+        let mut params = CertificateParams::new(vec![])?;
+        let mut dn = DistinguishedName::new();
+        dn.push(
+            DnType::CommonName,
+            format!("Apple Development: {} ({})", apple_email, MOCK_TEAM_ID),
+        );
+        dn.push(DnType::OrganizationalUnitName, MOCK_TEAM_ID);
+        dn.push(DnType::OrganizationName, "Mock Developer");
+        params.distinguished_name = dn;
+        params.serial_number = Some(SerialNumber::from_slice(serial.as_bytes()));
+        let cert = params
+            .self_signed(&key)
+            .context("Failed to generate mock certificate")?;
+
+        storage.store_data(&private_key_storage_key(apple_email), &key.serialize_der())?;
+        let certificate = plist!(dict {
+            "name": format!("Apple Development: {}", apple_email),
+            "certificateId": state.next_id("CERT"),
+            "serialNumber": hex_serial(&serial),
+            "machineId": Uuid::new_v4().to_string().to_uppercase(),
+            "machineName": machine_name,
+            "certContent": Value::Data(cert.der().to_vec()),
+            "certificatePlatform": "ios",
+            "status": "Issued",
+            "statusCode": 0,
+            "expirationDate": self.date_in(Duration::from_secs(365 * 24 * 60 * 60)),
+        });
+        state.certificates.push(certificate);
+        Ok(())
+    }
+
+    /// The actions requested so far, in order, e.g. `downloadTeamProvisioningProfile`
+    pub fn requests(&self) -> Vec<String> {
+        self.state().requests.clone()
+    }
+
+    /// How many times `action` was requested
+    pub fn request_count(&self, action: &str) -> usize {
+        self.state()
+            .requests
+            .iter()
+            .filter(|request| *request == action)
+            .count()
+    }
+
+    /// The identifiers of the registered app IDs
+    pub fn app_ids(&self) -> Vec<String> {
+        self.state()
+            .app_ids
+            .iter()
+            .filter_map(|app_id| string(app_id, "identifier"))
+            .collect()
+    }
+
+    /// The UDIDs of the registered devices
+    pub fn devices(&self) -> Vec<String> {
+        self.state()
+            .devices
+            .iter()
+            .filter_map(|device| string(device, "deviceNumber"))
+            .collect()
+    }
+
+    fn respond(&self, url: &str, body: &Dictionary) -> Dictionary {
+        let action = url
+            .split(".action")
+            .next()
+            .and_then(|path| path.rsplit('/').next())
+            .unwrap_or_default()
+            .to_string();
+        let mut state = self.state();
+        state.requests.push(action.clone());
+
+        let mut response = match action.as_str() {
+            "listTeams" => plist!(dict {
+                "teams": [{
+                    "name": "Mock Developer",
+                    "teamId": MOCK_TEAM_ID,
+                    "type": "Individual",
+                    "status": "active",
+                    "memberships": [{
+                        "name": "Xcode Free Provisioning Program",
+                        "platform": "ios",
+                    }],
+                }],
+            }),
+            "listDevices" => plist!(dict { "devices": state.devices.clone() }),
+            "addDevice" => {
+                let device = plist!(dict {
+                    "name": string(body, "name").unwrap_or_default(),
+                    "deviceId": state.next_id("DEVICE"),
+                    "deviceNumber": string(body, "deviceNumber").unwrap_or_default(),
+                    "status": "c",
+                    "deviceClass": "iphone",
+                });
+                state.devices.push(device.clone());
+                plist!(dict { "device": device })
+            }
+            "listAllDevelopmentCerts" => {
+                plist!(dict { "certificates": state.certificates.clone() })
+            }
+            "listAppIds" => plist!(dict {
+                "appIds": state.app_ids.clone(),
+                "maxQuantity": 10,
+                "availableQuantity": 10 - state.app_ids.len() as i64,
+            }),
+            "addAppId" => {
+                let app_id = plist!(dict {
+                    "appIdId": state.next_id("APPID"),
+                    "identifier": string(body, "identifier").unwrap_or_default(),
+                    "name": string(body, "name").unwrap_or_default(),
+                    "features": { "APG3427HIY": false },
+                    "expirationDate": self.date_in(FREE_PROFILE_VALIDITY),
+                });
+                state.app_ids.push(app_id.clone());
+                plist!(dict { "appId": app_id })
+            }
+            "updateAppId" => match find(&mut state.app_ids, "appIdId", body) {
+                Some(app_id) => {
+                    if let Some(Value::Dictionary(features)) = app_id.get_mut("features") {
+                        for (key, value) in body.iter().filter(|(key, _)| is_feature(key)) {
+                            features.insert(key.clone(), value.clone());
+                        }
+                    }
+                    plist!(dict { "appId": app_id.clone() })
+                }
+                None => error(NOT_FOUND_CODE, "There is no app ID with this ID"),
+            },
+            "deleteAppId" => {
+                let id = string(body, "appIdId");
+                state
+                    .app_ids
+                    .retain(|app_id| string(app_id, "appIdId") != id);
+                Dictionary::new()
+            }
+            "listApplicationGroups" => {
+                plist!(dict { "applicationGroupList": state.app_groups.clone() })
+            }
+            "addApplicationGroup" => {
+                let group = plist!(dict {
+                    "name": string(body, "name").unwrap_or_default(),
+                    "identifier": string(body, "identifier").unwrap_or_default(),
+                    "applicationGroup": state.next_id("GROUP"),
+                });
+                state.app_groups.push(group.clone());
+                plist!(dict { "applicationGroup": group })
+            }
+            "deleteApplicationGroup" => {
+                let id = string(body, "applicationGroup");
+                state
+                    .app_groups
+                    .retain(|group| string(group, "applicationGroup") != id);
+                Dictionary::new()
+            }
+            "getAppIdDetail" => {
+                let assigned = string(body, "appIdId")
+                    .and_then(|id| state.assignments.get(&id).cloned())
+                    .unwrap_or_default();
+                let groups: Vec<Value> = state
+                    .app_groups
+                    .iter()
+                    .filter(|group| {
+                        string(group, "applicationGroup").is_some_and(|id| assigned.contains(&id))
+                    })
+                    .cloned()
+                    .map(Value::Dictionary)
+                    .collect();
+                plist!(dict { "appId": { "associatedApplicationGroups": groups } })
+            }
+            "assignApplicationGroupToAppId" => {
+                match (string(body, "appIdId"), string(body, "applicationGroups")) {
+                    (Some(app_id), Some(group)) => {
+                        let assigned = state.assignments.entry(app_id).or_default();
+                        if !assigned.contains(&group) {
+                            assigned.push(group);
+                        }
+                        Dictionary::new()
+                    }
+                    _ => error(NOT_FOUND_CODE, "An app ID and app group are required"),
+                }
+            }
+            "downloadTeamProvisioningProfile" => {
+                match find(&mut state.app_ids, "appIdId", body).cloned() {
+                    Some(app_id) => {
+                        plist!(dict { "provisioningProfile": self.profile(&mut state, &app_id) })
+                    }
+                    None => error(NOT_FOUND_CODE, "There is no app ID with this ID"),
+                }
+            }
+            _ => error(
+                UNSUPPORTED_CODE,
+                &format!("The mock developer services don't support {}", action),
+            ),
+        };
+
+        if !response.contains_key("resultCode") {
+            response.insert("resultCode".to_string(), Value::from(0));
+        }
+        response
+    }
+
+    /// A team provisioning profile for `app_id`, allowing every registered device and certificate
+    fn profile(&self, state: &mut MockDeveloperState, app_id: &Dictionary) -> Dictionary {
+        let app_id_id = string(app_id, "appIdId").unwrap_or_default();
+        let identifier = string(app_id, "identifier").unwrap_or_default();
+        let uuid = Uuid::new_v4().to_string().to_uppercase();
+        let expires = self.date_in(FREE_PROFILE_VALIDITY);
+
+        let assigned = state
+            .assignments
+            .get(&app_id_id)
+            .cloned()
+            .unwrap_or_default();
+        let groups: Vec<Value> = state
+            .app_groups
+            .iter()
+            .filter(|group| {
+                string(group, "applicationGroup").is_some_and(|id| assigned.contains(&id))
+            })
+            .filter_map(|group| string(group, "identifier"))
+            .map(Value::from)
+            .collect();
+        let certificates: Vec<Value> = state
+            .certificates
+            .iter()
+            .filter_map(|cert| cert.get("certContent").cloned())
+            .collect();
+        let devices: Vec<Value> = state
+            .devices
+            .iter()
+            .filter_map(|device| string(device, "deviceNumber"))
+            .map(Value::from)
+            .collect();
+
+        let name = format!("iOS Team Provisioning Profile: {}", identifier);
+        let embedded = plist!(dict {
+            "AppIDName": string(app_id, "name").unwrap_or_default(),
+            "ApplicationIdentifierPrefix": [MOCK_TEAM_ID],
+            "CreationDate": self.date_in(Duration::ZERO),
+            "DeveloperCertificates": certificates,
+            "Entitlements": {
+                "application-identifier": format!("{}.{}", MOCK_TEAM_ID, identifier),
+                "com.apple.developer.team-identifier": MOCK_TEAM_ID,
+                "get-task-allow": true,
+                "keychain-access-groups": [format!("{}.*", MOCK_TEAM_ID)],
+                "com.apple.security.application-groups": groups,
+            },
+            "ExpirationDate": expires.clone(),
+            "Name": name.clone(),
+            "ProvisionedDevices": devices,
+            "TeamIdentifier": [MOCK_TEAM_ID],
+            "TeamName": "Mock Developer",
+            "TimeToLive": 7,
+            "UUID": uuid.clone(),
+            "Version": 1,
+        });
+        let mut encoded = Vec::new();
+        // serializing a dictionary into memory can't fail
+        let _ = plist::to_writer_xml(&mut encoded, &embedded);
+
+        plist!(dict {
+            "encodedProfile": Value::Data(encoded),
+            "filename": format!("{}.mobileprovision", uuid),
+            "provisioningProfileId": state.next_id("PROFILE"),
+            "name": name,
+            "status": "Active",
+            "type": "iOS Development",
+            "distributionMethod": "limited",
+            "UUID": uuid,
+            "dateExpire": expires,
+            "appIdId": app_id_id,
+            "isTemplateProfile": false,
+            "isTeamProfile": true,
+            "isFreeProvisioningProfile": true,
+        })
+    }
+
+    fn date_in(&self, duration: Duration) -> Value {
+        Value::Date(Date::from(self.clock.now() + duration))
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockDeveloperState> {
+        // a panicking test shouldn't take every other request down with it
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MockDeveloperServices {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Never asked for anisette data, since requests are answered by the mock instead of Apple
+struct NoAnisetteProvider(AnisetteClientInfo);
+
+#[async_trait::async_trait]
+impl AnisetteProvider for NoAnisetteProvider {
+    async fn get_anisette_data(&self) -> Result<AnisetteData, Report> {
+        bail!("The mock developer services don't use anisette data")
+    }
+
+    async fn get_client_info(&mut self) -> Result<AnisetteClientInfo, Report> {
+        Ok(self.0.clone())
+    }
+
+    async fn provision(&mut self, _gs: Arc<GrandSlam>) -> Result<(), Report> {
+        Ok(())
+    }
+
+    fn needs_provisioning(&self) -> Result<bool, Report> {
+        Ok(false)
+    }
+}
+
+fn string(dict: &Dictionary, key: &str) -> Option<String> {
+    dict.get(key)
+        .and_then(|v| v.as_string())
+        .map(str::to_string)
+}
+
+/// The entry of `entries` whose `key` matches the one in the request `body`
+fn find<'a>(
+    entries: &'a mut [Dictionary],
+    key: &str,
+    body: &Dictionary,
+) -> Option<&'a mut Dictionary> {
+    let id = string(body, key)?;
+    entries
+        .iter_mut()
+        .find(|entry| string(entry, key).as_deref() == Some(id.as_str()))
+}
+
+/// Whether a key of an `updateAppId` request is a feature, rather than one of the keys every request has
+fn is_feature(key: &str) -> bool {
+    !matches!(
+        key,
+        "teamId" | "appIdId" | "clientId" | "protocolVersion" | "requestId" | "userLocale"
+    )
+}
+
+/// The serial number of a certificate as the developer services list it, uppercase hex without leading zeros
+fn hex_serial(serial: &str) -> String {
+    serial
+        .bytes()
+        .map(|b| format!("{:02X}", b))
+        .collect::<String>()
+        .trim_start_matches('0')
+        .to_string()
+}
+
+fn error(code: i64, message: &str) -> Dictionary {
+    plist!(dict {
+        "resultCode": code,
+        "resultString": message,
+        "userString": message,
+    })
+}
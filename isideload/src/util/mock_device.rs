@@ -0,0 +1,904 @@
+//! An in-memory stand-in for a connected device
+//!
+//! [`MockDeviceProvider`] answers lockdownd, AFC and installation proxy requests the way a real device does,
+//! so installs can be tested, and frontends demoed, without any hardware attached.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use idevice::{
+    Idevice, IdeviceError,
+    afc::{
+        MAGIC as AFC_MAGIC,
+        opcode::AfcOpcode,
+        packet::{AfcPacket, AfcPacketHeader},
+    },
+    lockdown::LockdownClient,
+    pairing_file::PairingFile,
+    provider::IdeviceProvider,
+};
+use plist::{Dictionary, Value};
+use plist_macro::plist;
+use rootcause::prelude::*;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        crypto::aws_lc_rs,
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+    },
+};
+use tracing::debug;
+
+/// The ports services are "started" on. They only need to be distinct from lockdownd's.
+const AFC_PORT: u16 = 49152;
+const INSTALLATION_PROXY_PORT: u16 = 49153;
+
+const STREAM_BUFFER_SIZE: usize = 1024 * 1024;
+
+// AFC status codes, matching `idevice::afc::errors::AfcError`
+const AFC_SUCCESS: u64 = 0;
+const AFC_INVALID_ARG: u64 = 7;
+const AFC_OBJECT_NOT_FOUND: u64 = 8;
+const AFC_OBJECT_IS_DIR: u64 = 9;
+const AFC_OP_NOT_SUPPORTED: u64 = 15;
+const AFC_DIR_NOT_EMPTY: u64 = 33;
+
+/// The phases installd reports while installing an app, with their usual percentages
+const INSTALL_STEPS: &[(&str, u64)] = &[
+    ("CreatingStagingDirectory", 5),
+    ("ExtractingPackage", 15),
+    ("InspectingPackage", 20),
+    ("TakingInstallLock", 20),
+    ("PreflightingApplication", 30),
+    ("InstallingEmbeddedProfile", 30),
+    ("VerifyingApplication", 40),
+    ("CreatingContainer", 50),
+    ("InstallingApplication", 60),
+    ("PostflightingApplication", 70),
+    ("SandboxingApplication", 80),
+    ("GeneratingApplicationMap", 90),
+];
+
+/// The phase a failure set with [`MockDeviceProvider::fail_installs`] is reported in
+const FAILING_INSTALL_STEP: &str = "VerifyingApplication";
+
+const UNINSTALL_STEPS: &[(&str, u64)] = &[
+    ("RemovingApplication", 50),
+    ("GeneratingApplicationMap", 90),
+];
+
+#[derive(Debug, Default)]
+struct MockDeviceState {
+    values: Dictionary,
+    files: BTreeMap<String, Vec<u8>>,
+    /// When each file was last written, in nanoseconds since the epoch
+    modified: BTreeMap<String, u128>,
+    dirs: BTreeSet<String>,
+    apps: BTreeMap<String, Dictionary>,
+    install_error: Option<(String, String)>,
+    progress_delay: Duration,
+}
+
+/// A device provider backed by an in-memory device instead of usbmuxd
+///
+/// - lockdownd answers `GetValue` from a set of values (see [`MockDeviceProvider::with_value`]) and starts
+///   sessions over TLS with a generated pairing file, like a paired device does.
+/// - AFC stores uploaded files in memory, where they can be inspected with [`MockDeviceProvider::file`].
+/// - The installation proxy installs apps from the uploaded files, reporting installd's usual progress phases,
+///   and answers `Lookup` and `Browse` with the apps it has installed.
+///
+/// Clones share the same device, so a clone can be handed to code under test while the original is used
+/// to inspect the result.
+#[derive(Clone, Debug)]
+pub struct MockDeviceProvider {
+    label: String,
+    pairing_file: PairingFile,
+    tls_config: Arc<ServerConfig>,
+    state: Arc<Mutex<MockDeviceState>>,
+}
+
+impl MockDeviceProvider {
+    /// Create a mock iPhone running iOS 17.5 with no apps installed
+    pub fn new() -> Result<Self, Report> {
+        let udid = "00008120-0000000000000000".to_string();
+
+        let device = rcgen::generate_simple_self_signed(vec!["Device".to_string()])
+            .context("Failed to generate mock device certificate")?;
+        let host = rcgen::generate_simple_self_signed(vec!["Host".to_string()])
+            .context("Failed to generate mock host certificate")?;
+
+        let tls_config =
+            ServerConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+                .with_safe_default_protocol_versions()
+                .context("Failed to configure mock device TLS")?
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![device.cert.der().clone()],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+                        device.signing_key.serialize_der(),
+                    )),
+                )
+                .context("Failed to configure mock device TLS")?;
+
+        let pairing_file = PairingFile {
+            device_certificate: device.cert.der().clone(),
+            host_private_key: host.signing_key.serialize_pem().into_bytes(),
+            host_certificate: host.cert.der().clone(),
+            root_private_key: device.signing_key.serialize_pem().into_bytes(),
+            root_certificate: device.cert.der().clone(),
+            system_buid: "00000000-0000-0000-0000-000000000000".to_string(),
+            host_id: "00000000-0000-0000-0000-000000000001".to_string(),
+            escrow_bag: None,
+            wifi_mac_address: "00:00:00:00:00:00".to_string(),
+            udid: Some(udid.clone()),
+        };
+
+        let state = MockDeviceState {
+            values: plist!(dict {
+                "DeviceName": "Mock iPhone",
+                "UniqueDeviceID": udid,
+                "ProductType": "iPhone15,2",
+                "ProductVersion": "17.5",
+                "BuildVersion": "21F79",
+                "DeviceClass": "iPhone",
+                "CPUArchitecture": "arm64e",
+                "DeveloperModeStatus": true,
+            }),
+            ..Default::default()
+        };
+
+        Ok(Self {
+            label: "isideload-mock".to_string(),
+            pairing_file,
+            tls_config: Arc::new(tls_config),
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    /// Set a value lockdownd returns from `GetValue`, such as `DeviceName` or `ProductVersion`
+    ///
+    /// Domains are ignored, every value is returned regardless of the domain requested.
+    pub fn with_value(mut self, key: &str, value: impl Into<Value>) -> Self {
+        let value = value.into();
+        if key == "UniqueDeviceID" {
+            self.pairing_file.udid = value.as_string().map(str::to_string);
+        }
+        self.state().values.insert(key.to_string(), value);
+        self
+    }
+
+    /// Mark an app as already installed, so installs of it upgrade it. Defaults to no apps.
+    pub fn with_installed_app(self, bundle_id: &str) -> Self {
+        let app = plist!(dict {
+            "CFBundleIdentifier": bundle_id,
+            "CFBundleShortVersionString": "1.0",
+            "CFBundleVersion": "1",
+        });
+        self.state()
+            .apps
+            .insert(bundle_id.to_string(), installed_app_record(app));
+        self
+    }
+
+    /// Make installd reject every install with the given error, after verification starts
+    ///
+    /// `error` is the installd error name, e.g. `ApplicationVerificationFailed`.
+    pub fn fail_installs(self, error: &str, description: &str) -> Self {
+        self.state().install_error = Some((error.to_string(), description.to_string()));
+        self
+    }
+
+    /// Wait between install progress updates, to make progress visible in a demo. Defaults to no delay.
+    pub fn progress_delay(self, delay: Duration) -> Self {
+        self.state().progress_delay = delay;
+        self
+    }
+
+    /// The UDID the mock device reports
+    pub fn udid(&self) -> String {
+        self.state()
+            .values
+            .get("UniqueDeviceID")
+            .and_then(|v| v.as_string())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// The contents of a file uploaded over AFC, e.g. `PublicStaging/App.app/Info.plist`
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.state().files.get(&normalize_path(path)).cloned()
+    }
+
+    /// The paths of every file stored on the device
+    pub fn files(&self) -> Vec<String> {
+        self.state().files.keys().cloned().collect()
+    }
+
+    /// The bundle IDs of the installed apps
+    pub fn installed_apps(&self) -> Vec<String> {
+        self.state().apps.keys().cloned().collect()
+    }
+
+    /// The Info.plist of an installed app, as returned from an installation proxy lookup
+    pub fn installed_app(&self, bundle_id: &str) -> Option<Dictionary> {
+        self.state().apps.get(bundle_id).cloned()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockDeviceState> {
+        lock(&self.state)
+    }
+}
+
+impl IdeviceProvider for MockDeviceProvider {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        let device = self.clone();
+        Box::pin(async move {
+            let (client, server) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+            let label = device.label.clone();
+            match port {
+                LockdownClient::LOCKDOWND_PORT => {
+                    tokio::spawn(log_errors("lockdownd", serve_lockdown(server, device)));
+                }
+                AFC_PORT => {
+                    tokio::spawn(log_errors("AFC", serve_afc(server, device.state)));
+                }
+                INSTALLATION_PROXY_PORT => {
+                    tokio::spawn(log_errors(
+                        "installation proxy",
+                        serve_installation_proxy(server, device.state),
+                    ));
+                }
+                _ => {
+                    return Err(IdeviceError::Socket(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        format!("Mock device has no service on port {}", port),
+                    )));
+                }
+            }
+            Ok(Idevice::new(Box::new(client), label))
+        })
+    }
+
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        let pairing_file = self.pairing_file.clone();
+        Box::pin(async move { Ok(pairing_file) })
+    }
+}
+
+/// A connection to one of the mock services, which lockdownd upgrades to TLS when a session starts
+trait MockStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> MockStream for T {}
+
+fn lock(state: &Mutex<MockDeviceState>) -> MutexGuard<'_, MockDeviceState> {
+    // a panicking test shouldn't take every other connection down with it
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+async fn log_errors(service: &str, connection: impl Future<Output = Result<(), Report>>) {
+    if let Err(e) = connection.await {
+        debug!("Mock {} connection failed: {}", service, e);
+    }
+}
+
+async fn serve_lockdown(stream: DuplexStream, device: MockDeviceProvider) -> Result<(), Report> {
+    let mut stream: Box<dyn MockStream> = Box::new(stream);
+    while let Some(request) = read_message(&mut stream).await? {
+        let request_type = request
+            .get("Request")
+            .and_then(|v| v.as_string())
+            .unwrap_or_default()
+            .to_string();
+
+        if request_type == "StartSession" {
+            let response = plist!(dict {
+                "Request": "StartSession",
+                "EnableSessionSSL": true,
+                "SessionID": "00000000-0000-0000-0000-000000000002",
+            });
+            write_message(&mut stream, &response).await?;
+            stream = Box::new(
+                TlsAcceptor::from(device.tls_config.clone())
+                    .accept(stream)
+                    .await
+                    .context("Failed to start mock lockdown session")?,
+            );
+            continue;
+        }
+
+        let response = lockdown_response(&device.state, &request_type, &request);
+        write_message(&mut stream, &response).await?;
+    }
+    Ok(())
+}
+
+fn lockdown_response(
+    state: &Mutex<MockDeviceState>,
+    request_type: &str,
+    request: &Dictionary,
+) -> Dictionary {
+    let mut response = plist!(dict { "Request": request_type });
+    match request_type {
+        "QueryType" => {
+            response.insert("Type".to_string(), "com.apple.mobile.lockdown".into());
+        }
+        "GetValue" => {
+            let state = lock(state);
+            match request.get("Key").and_then(|v| v.as_string()) {
+                Some(key) => match state.values.get(key) {
+                    Some(value) => {
+                        response.insert("Key".to_string(), key.into());
+                        response.insert("Value".to_string(), value.clone());
+                    }
+                    None => {
+                        response.insert("Error".to_string(), "MissingValue".into());
+                    }
+                },
+                None => {
+                    response.insert("Value".to_string(), state.values.clone().into());
+                }
+            }
+        }
+        "StartService" => {
+            let service = request
+                .get("Service")
+                .and_then(|v| v.as_string())
+                .unwrap_or_default();
+            let port = match service {
+                "com.apple.afc" => Some(AFC_PORT),
+                "com.apple.mobile.installation_proxy" => Some(INSTALLATION_PROXY_PORT),
+                _ => None,
+            };
+            response.insert("Service".to_string(), service.into());
+            match port {
+                Some(port) => {
+                    response.insert("Port".to_string(), (port as u64).into());
+                    response.insert("EnableServiceSSL".to_string(), false.into());
+                }
+                None => {
+                    response.insert("Error".to_string(), "InvalidService".into());
+                }
+            }
+        }
+        "StopSession" | "Goodbye" => {}
+        _ => {
+            response.insert("Error".to_string(), "InvalidRequest".into());
+        }
+    }
+    response
+}
+
+async fn serve_afc(
+    mut stream: DuplexStream,
+    state: Arc<Mutex<MockDeviceState>>,
+) -> Result<(), Report> {
+    let mut connection = AfcConnection {
+        state,
+        open_files: HashMap::new(),
+        next_fd: 1,
+    };
+
+    loop {
+        let mut header = [0u8; AfcPacketHeader::LEN as usize];
+        if !read_exact_or_eof(&mut stream, &mut header).await? {
+            return Ok(());
+        }
+        let field = |i: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&header[i * 8..(i + 1) * 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let (magic, entire_len, header_payload_len, packet_num, operation) =
+            (field(0), field(1), field(2), field(3), field(4));
+        if magic != AFC_MAGIC
+            || header_payload_len < AfcPacketHeader::LEN
+            || entire_len < header_payload_len
+        {
+            bail!("Invalid AFC packet header");
+        }
+
+        let mut header_payload = vec![0u8; (header_payload_len - AfcPacketHeader::LEN) as usize];
+        stream.read_exact(&mut header_payload).await?;
+        let mut payload = vec![0u8; (entire_len - header_payload_len) as usize];
+        stream.read_exact(&mut payload).await?;
+
+        let (operation, header_payload, payload) = match AfcOpcode::try_from(operation) {
+            Ok(operation) => match connection.handle(operation, &header_payload, payload) {
+                Ok(response) => response,
+                Err(code) => afc_status(code),
+            },
+            Err(_) => afc_status(AFC_OP_NOT_SUPPORTED),
+        };
+        let header_len = header_payload.len() as u64 + AfcPacketHeader::LEN;
+        let packet = AfcPacket {
+            header: AfcPacketHeader {
+                magic: AFC_MAGIC,
+                entire_len: header_len + payload.len() as u64,
+                header_payload_len: header_len,
+                packet_num,
+                operation,
+            },
+            header_payload,
+            payload,
+        };
+        stream.write_all(&packet.serialize()).await?;
+    }
+}
+
+type AfcResponse = (AfcOpcode, Vec<u8>, Vec<u8>);
+
+fn afc_status(code: u64) -> AfcResponse {
+    (AfcOpcode::Status, code.to_le_bytes().to_vec(), Vec::new())
+}
+
+struct OpenFile {
+    path: String,
+    position: usize,
+}
+
+/// The open file handles of one AFC connection
+struct AfcConnection {
+    state: Arc<Mutex<MockDeviceState>>,
+    open_files: HashMap<u64, OpenFile>,
+    next_fd: u64,
+}
+
+impl AfcConnection {
+    /// Handle a request, returning the response or an AFC error code
+    fn handle(
+        &mut self,
+        operation: AfcOpcode,
+        header_payload: &[u8],
+        payload: Vec<u8>,
+    ) -> Result<AfcResponse, u64> {
+        let mut state = lock(&self.state);
+        match operation {
+            AfcOpcode::MakeDir => {
+                let path = normalize_path(&String::from_utf8_lossy(header_payload));
+                let mut current = String::new();
+                for component in path.split('/').filter(|c| !c.is_empty()) {
+                    if !current.is_empty() {
+                        current.push('/');
+                    }
+                    current.push_str(component);
+                    if state.files.contains_key(&current) {
+                        return Err(AFC_INVALID_ARG);
+                    }
+                    state.dirs.insert(current.clone());
+                }
+                Ok(afc_status(AFC_SUCCESS))
+            }
+            AfcOpcode::GetFileInfo => {
+                let path = normalize_path(&String::from_utf8_lossy(header_payload));
+                let (size, kind) = if let Some(file) = state.files.get(&path) {
+                    (file.len(), "S_IFREG")
+                } else if state.is_dir(&path) {
+                    (0, "S_IFDIR")
+                } else {
+                    return Err(AFC_OBJECT_NOT_FOUND);
+                };
+                let now = now_nanos();
+                let modified = state
+                    .modified
+                    .get(&path)
+                    .copied()
+                    .unwrap_or(now)
+                    .to_string();
+                let now = now.to_string();
+                Ok((
+                    AfcOpcode::Data,
+                    Vec::new(),
+                    afc_strings(&[
+                        "st_size",
+                        &size.to_string(),
+                        "st_blocks",
+                        &size.div_ceil(512).to_string(),
+                        "st_nlink",
+                        "1",
+                        "st_ifmt",
+                        kind,
+                        "st_mtime",
+                        &modified,
+                        "st_birthtime",
+                        &now,
+                    ]),
+                ))
+            }
+            AfcOpcode::ReadDir => {
+                let path = normalize_path(&String::from_utf8_lossy(header_payload));
+                if !state.is_dir(&path) {
+                    return Err(AFC_OBJECT_NOT_FOUND);
+                }
+                let mut entries = vec![".".to_string(), "..".to_string()];
+                entries.extend(state.children(&path));
+                let entries: Vec<&str> = entries.iter().map(String::as_str).collect();
+                Ok((AfcOpcode::Data, Vec::new(), afc_strings(&entries)))
+            }
+            AfcOpcode::FileOpen => {
+                if header_payload.len() < 8 {
+                    return Err(AFC_INVALID_ARG);
+                }
+                let mode = read_u64(header_payload);
+                let path = normalize_path(&String::from_utf8_lossy(&header_payload[8..]));
+                if state.is_dir(&path) {
+                    return Err(AFC_OBJECT_IS_DIR);
+                }
+                if !state.is_dir(parent(&path)) {
+                    return Err(AFC_OBJECT_NOT_FOUND);
+                }
+                match mode {
+                    // read only
+                    1 if !state.files.contains_key(&path) => return Err(AFC_OBJECT_NOT_FOUND),
+                    // write only or read/write, truncating
+                    3 | 4 => {
+                        state.files.insert(path.clone(), Vec::new());
+                        state.modified.insert(path.clone(), now_nanos());
+                    }
+                    _ => {
+                        state.files.entry(path.clone()).or_default();
+                    }
+                }
+                let fd = self.next_fd;
+                self.next_fd += 1;
+                self.open_files.insert(fd, OpenFile { path, position: 0 });
+                Ok((
+                    AfcOpcode::FileOpenRes,
+                    fd.to_le_bytes().to_vec(),
+                    Vec::new(),
+                ))
+            }
+            AfcOpcode::Read => {
+                if header_payload.len() < 16 {
+                    return Err(AFC_INVALID_ARG);
+                }
+                let file = self
+                    .open_files
+                    .get_mut(&read_u64(header_payload))
+                    .ok_or(AFC_INVALID_ARG)?;
+                let contents = state.files.get(&file.path).ok_or(AFC_OBJECT_NOT_FOUND)?;
+                let start = file.position.min(contents.len());
+                let end = (start + read_u64(&header_payload[8..]) as usize).min(contents.len());
+                file.position = end;
+                Ok((AfcOpcode::Data, Vec::new(), contents[start..end].to_vec()))
+            }
+            AfcOpcode::Write => {
+                if header_payload.len() < 8 {
+                    return Err(AFC_INVALID_ARG);
+                }
+                let file = self
+                    .open_files
+                    .get_mut(&read_u64(header_payload))
+                    .ok_or(AFC_INVALID_ARG)?;
+                let contents = state
+                    .files
+                    .get_mut(&file.path)
+                    .ok_or(AFC_OBJECT_NOT_FOUND)?;
+                let end = file.position + payload.len();
+                if contents.len() < end {
+                    contents.resize(end, 0);
+                }
+                contents[file.position..end].copy_from_slice(&payload);
+                file.position = end;
+                state.modified.insert(file.path.clone(), now_nanos());
+                Ok(afc_status(AFC_SUCCESS))
+            }
+            AfcOpcode::FileClose => {
+                if header_payload.len() < 8 {
+                    return Err(AFC_INVALID_ARG);
+                }
+                self.open_files
+                    .remove(&read_u64(header_payload))
+                    .ok_or(AFC_INVALID_ARG)?;
+                Ok(afc_status(AFC_SUCCESS))
+            }
+            AfcOpcode::RemovePath => {
+                let path = normalize_path(&String::from_utf8_lossy(header_payload));
+                if state.files.remove(&path).is_some() {
+                    return Ok(afc_status(AFC_SUCCESS));
+                }
+                if !state.dirs.contains(&path) {
+                    return Err(AFC_OBJECT_NOT_FOUND);
+                }
+                if !state.children(&path).is_empty() {
+                    return Err(AFC_DIR_NOT_EMPTY);
+                }
+                state.dirs.remove(&path);
+                Ok(afc_status(AFC_SUCCESS))
+            }
+            AfcOpcode::RemovePathAndContents => {
+                let path = normalize_path(&String::from_utf8_lossy(header_payload));
+                if !state.files.contains_key(&path) && !state.dirs.contains(&path) {
+                    return Err(AFC_OBJECT_NOT_FOUND);
+                }
+                let prefix = format!("{}/", path);
+                state
+                    .files
+                    .retain(|p, _| *p != path && !p.starts_with(&prefix));
+                state.dirs.retain(|p| *p != path && !p.starts_with(&prefix));
+                Ok(afc_status(AFC_SUCCESS))
+            }
+            _ => Err(AFC_OP_NOT_SUPPORTED),
+        }
+    }
+}
+
+impl MockDeviceState {
+    fn is_dir(&self, path: &str) -> bool {
+        path.is_empty() || self.dirs.contains(path)
+    }
+
+    /// The names of the files and directories directly inside `path`
+    fn children(&self, path: &str) -> Vec<String> {
+        self.dirs
+            .iter()
+            .chain(self.files.keys())
+            .filter(|p| !p.is_empty() && parent(p) == path)
+            .map(|p| p.rsplit('/').next().unwrap_or(p).to_string())
+            .collect()
+    }
+}
+
+async fn serve_installation_proxy(
+    mut stream: DuplexStream,
+    state: Arc<Mutex<MockDeviceState>>,
+) -> Result<(), Report> {
+    while let Some(request) = read_message(&mut stream).await? {
+        let command = request
+            .get("Command")
+            .and_then(|v| v.as_string())
+            .unwrap_or_default();
+        let options = request
+            .get("ClientOptions")
+            .and_then(|v| v.as_dictionary())
+            .cloned()
+            .unwrap_or_default();
+
+        match command {
+            "Install" | "Upgrade" => {
+                let package_path = request
+                    .get("PackagePath")
+                    .and_then(|v| v.as_string())
+                    .unwrap_or_default();
+                install(&mut stream, &state, package_path).await?;
+            }
+            "Uninstall" => {
+                let bundle_id = request
+                    .get("ApplicationIdentifier")
+                    .and_then(|v| v.as_string())
+                    .unwrap_or_default()
+                    .to_string();
+                let delay = lock(&state).progress_delay;
+                for (status, percent) in UNINSTALL_STEPS {
+                    send_progress(&mut stream, status, *percent, delay).await?;
+                }
+                lock(&state).apps.remove(&bundle_id);
+                write_message(&mut stream, &plist!(dict { "Status": "Complete" })).await?;
+            }
+            "Lookup" => {
+                let apps = matching_apps(&state, &options);
+                let result: Dictionary = apps
+                    .into_iter()
+                    .map(|(bundle_id, app)| (bundle_id, Value::Dictionary(app)))
+                    .collect();
+                let response = plist!(dict {
+                    "LookupResult": result,
+                    "Status": "Complete",
+                });
+                write_message(&mut stream, &response).await?;
+            }
+            "Browse" => {
+                let apps: Vec<Value> = matching_apps(&state, &options)
+                    .into_iter()
+                    .map(|(_, app)| Value::Dictionary(app))
+                    .collect();
+                let total = apps.len() as u64;
+                let response = plist!(dict {
+                    "CurrentIndex": 0,
+                    "CurrentAmount": total,
+                    "Total": total,
+                    "CurrentList": apps,
+                    "Status": "Complete",
+                });
+                write_message(&mut stream, &response).await?;
+            }
+            _ => {
+                let response = plist!(dict {
+                    "Error": "UnknownCommand",
+                    "ErrorDescription": format!("Unknown command {}", command),
+                });
+                write_message(&mut stream, &response).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn install(
+    stream: &mut DuplexStream,
+    state: &Mutex<MockDeviceState>,
+    package_path: &str,
+) -> Result<(), Report> {
+    let (info, install_error, delay) = {
+        let state = lock(state);
+        let info = state
+            .files
+            .get(&format!("{}/Info.plist", normalize_path(package_path)))
+            .and_then(|data| plist::from_bytes::<Dictionary>(data).ok());
+        (info, state.install_error.clone(), state.progress_delay)
+    };
+    let Some(info) = info.filter(|info| info.contains_key("CFBundleIdentifier")) else {
+        let response = plist!(dict {
+            "Error": "PackageInspectionFailed",
+            "ErrorDescription": format!("Failed to read the Info.plist of {}", package_path),
+        });
+        return write_message(stream, &response).await;
+    };
+
+    for (status, percent) in INSTALL_STEPS {
+        send_progress(stream, status, *percent, delay).await?;
+        if *status == FAILING_INSTALL_STEP
+            && let Some((error, description)) = &install_error
+        {
+            let response = plist!(dict {
+                "Error": error.as_str(),
+                "ErrorDescription": description.as_str(),
+            });
+            return write_message(stream, &response).await;
+        }
+    }
+
+    let bundle_id = info
+        .get("CFBundleIdentifier")
+        .and_then(|v| v.as_string())
+        .unwrap_or_default()
+        .to_string();
+    lock(state)
+        .apps
+        .insert(bundle_id, installed_app_record(info));
+    write_message(stream, &plist!(dict { "Status": "Complete" })).await
+}
+
+async fn send_progress(
+    stream: &mut DuplexStream,
+    status: &str,
+    percent: u64,
+    delay: Duration,
+) -> Result<(), Report> {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    let response = plist!(dict {
+        "Status": status,
+        "PercentComplete": percent,
+    });
+    write_message(stream, &response).await
+}
+
+/// Add the keys installd adds to the Info.plist of an installed app
+fn installed_app_record(mut info: Dictionary) -> Dictionary {
+    let bundle_id = info
+        .get("CFBundleIdentifier")
+        .and_then(|v| v.as_string())
+        .unwrap_or_default()
+        .to_string();
+    info.insert("ApplicationType".to_string(), "User".into());
+    info.insert(
+        "Path".to_string(),
+        format!("/private/var/containers/Bundle/Application/{}", bundle_id).into(),
+    );
+    info
+}
+
+/// The installed apps matching a lookup or browse's `ApplicationType` and `BundleIDs` options
+fn matching_apps(
+    state: &Mutex<MockDeviceState>,
+    options: &Dictionary,
+) -> Vec<(String, Dictionary)> {
+    let application_type = options
+        .get("ApplicationType")
+        .and_then(|v| v.as_string())
+        .unwrap_or("Any");
+    let bundle_ids: Option<Vec<&str>> = options
+        .get("BundleIDs")
+        .and_then(|v| v.as_array())
+        .map(|ids| ids.iter().filter_map(|id| id.as_string()).collect());
+
+    lock(state)
+        .apps
+        .iter()
+        .filter(|(_, app)| {
+            application_type == "Any"
+                || app.get("ApplicationType").and_then(|v| v.as_string()) == Some(application_type)
+        })
+        .filter(|(bundle_id, _)| {
+            bundle_ids
+                .as_ref()
+                .is_none_or(|ids| ids.contains(&bundle_id.as_str()))
+        })
+        .map(|(bundle_id, app)| (bundle_id.clone(), app.clone()))
+        .collect()
+}
+
+/// Read a length-prefixed XML plist, or `None` if the client disconnected
+async fn read_message(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<Dictionary>, Report> {
+    let mut len = [0u8; 4];
+    if !read_exact_or_eof(stream, &mut len).await? {
+        return Ok(None);
+    }
+    let mut buf = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf).await?;
+    Ok(Some(plist::from_bytes(&buf).context(
+        "Failed to parse request sent to the mock device",
+    )?))
+}
+
+async fn write_message(
+    stream: &mut (impl AsyncWrite + Unpin),
+    message: &Dictionary,
+) -> Result<(), Report> {
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, message)?;
+    stream.write_all(&(buf.len() as u32).to_be_bytes()).await?;
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Fill `buf`, returning `false` if the stream ended before anything was read
+async fn read_exact_or_eof(
+    stream: &mut (impl AsyncRead + Unpin),
+    buf: &mut [u8],
+) -> Result<bool, Report> {
+    match stream.read_exact(buf).await {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// Encode AFC's NUL separated list of strings
+fn afc_strings(strings: &[&str]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for s in strings {
+        buf.extend_from_slice(s.as_bytes());
+        buf.push(0);
+    }
+    buf
+}
+
+fn normalize_path(path: &str) -> String {
+    path.trim_end_matches('\0').trim_matches('/').to_string()
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/')
+        .map(|(parent, _)| parent)
+        .unwrap_or("")
+}
@@ -1,7 +1,20 @@
+pub mod clock;
+#[cfg(feature = "install")]
 pub mod device;
+#[cfg(feature = "encrypted-fs-storage")]
+pub mod encrypted_fs_storage;
+#[cfg(feature = "test-util")]
+pub mod fixtures;
 #[cfg(feature = "fs-storage")]
 pub mod fs_storage;
+#[cfg(feature = "sign")]
+pub mod ipa_cache;
 #[cfg(feature = "keyring-storage")]
 pub mod keyring_storage;
+pub mod machine_name;
+#[cfg(all(feature = "test-util", feature = "sign"))]
+pub mod mock_developer;
+#[cfg(feature = "test-util")]
+pub mod mock_device;
 pub mod plist;
 pub mod storage;
@@ -1,9 +1,124 @@
-use plist::Dictionary;
-use plist_macro::pretty_print_dictionary;
+use plist::{Dictionary, Value};
+use plist_macro::{pretty_print_dictionary, pretty_print_plist};
 use rootcause::prelude::*;
 use serde::de::DeserializeOwned;
 use tracing::error;
 
+/// Format a dictionary as indented, human readable text
+pub fn pretty_print(dict: &Dictionary) -> String {
+    pretty_print_dictionary(dict)
+}
+
+/// Format any plist value as indented, human readable text
+pub fn pretty_print_value(value: &Value) -> String {
+    pretty_print_plist(value)
+}
+
+/// A single difference between two dictionaries, as found by [`diff`]
+///
+/// Key paths use `:` to separate nested keys, like `PlistBuddy` does (e.g. `NSExtension:NSExtensionPointIdentifier`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlistChange {
+    Added {
+        key_path: String,
+        value: Value,
+    },
+    Removed {
+        key_path: String,
+        value: Value,
+    },
+    Changed {
+        key_path: String,
+        before: Value,
+        after: Value,
+    },
+}
+
+impl PlistChange {
+    pub fn key_path(&self) -> &str {
+        match self {
+            PlistChange::Added { key_path, .. }
+            | PlistChange::Removed { key_path, .. }
+            | PlistChange::Changed { key_path, .. } => key_path,
+        }
+    }
+}
+
+impl std::fmt::Display for PlistChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlistChange::Added { key_path, value } => {
+                write!(f, "+ {}: {}", key_path, pretty_print_value(value))
+            }
+            PlistChange::Removed { key_path, value } => {
+                write!(f, "- {}: {}", key_path, pretty_print_value(value))
+            }
+            PlistChange::Changed {
+                key_path,
+                before,
+                after,
+            } => write!(
+                f,
+                "~ {}: {} -> {}",
+                key_path,
+                pretty_print_value(before),
+                pretty_print_value(after)
+            ),
+        }
+    }
+}
+
+/// List the differences between two dictionaries
+///
+/// Nested dictionaries are compared key by key, while arrays and other values are compared as a whole.
+pub fn diff(before: &Dictionary, after: &Dictionary) -> Vec<PlistChange> {
+    let mut changes = Vec::new();
+    diff_into(before, after, "", &mut changes);
+    changes
+}
+
+fn diff_into(
+    before: &Dictionary,
+    after: &Dictionary,
+    prefix: &str,
+    changes: &mut Vec<PlistChange>,
+) {
+    let key_path = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}:{}", prefix, key)
+        }
+    };
+
+    for (key, old) in before {
+        match (old, after.get(key)) {
+            (_, None) => changes.push(PlistChange::Removed {
+                key_path: key_path(key),
+                value: old.clone(),
+            }),
+            (Value::Dictionary(old_dict), Some(Value::Dictionary(new_dict))) => {
+                diff_into(old_dict, new_dict, &key_path(key), changes)
+            }
+            (_, Some(new)) if new != old => changes.push(PlistChange::Changed {
+                key_path: key_path(key),
+                before: old.clone(),
+                after: new.clone(),
+            }),
+            (_, Some(_)) => {}
+        }
+    }
+
+    for (key, new) in after {
+        if !before.contains_key(key) {
+            changes.push(PlistChange::Added {
+                key_path: key_path(key),
+                value: new.clone(),
+            });
+        }
+    }
+}
+
 pub struct SensitivePlistAttachment {
     pub plist: Dictionary,
 }
@@ -30,7 +145,7 @@ impl SensitivePlistAttachment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // if env variable DEBUG_SENSITIVE is set, print full plist
         if std::env::var("DEBUG_SENSITIVE").is_ok() {
-            return writeln!(f, "{}", pretty_print_dictionary(&self.plist));
+            return writeln!(f, "{}", pretty_print(&self.plist));
         }
         writeln!(
             f,
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
 use base64::prelude::*;
 use rootcause::prelude::*;
@@ -23,6 +26,35 @@ pub trait SideloadingStorage: Send + Sync {
     fn delete(&self, key: &str) -> Result<(), Report> {
         self.store(key, "")
     }
+
+    /// The keys of every stored entry that starts with `prefix`
+    ///
+    /// Keys are `/` separated, e.g. `<account>/session`, so passing a namespace followed by `/` lists everything
+    /// stored for it. Backends that can't enumerate their entries fail.
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Report> {
+        bail!(
+            "This storage backend can't list entries (prefix {})",
+            prefix
+        )
+    }
+
+    /// Delete every entry whose key starts with `prefix`, returning how many were deleted
+    ///
+    /// Used to forget everything stored for an account, see [`crate::auth::account_manager::AccountManager::forget`].
+    fn delete_all(&self, prefix: &str) -> Result<usize, Report> {
+        let keys = self.list(prefix)?;
+        for key in &keys {
+            self.delete(key)?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Write any buffered changes to the underlying storage
+    ///
+    /// Storage backends that write through immediately don't need to implement this.
+    fn flush(&self) -> Result<(), Report> {
+        Ok(())
+    }
 }
 
 /// Factory function to create a new storage instance based on enabled features. The priority is `keyring-storage`, then `fs-storage`, and finally an in-memory storage if neither of those features are enabled.
@@ -88,4 +120,84 @@ impl SideloadingStorage for InMemoryStorage {
         storage.remove(key);
         Ok(())
     }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Report> {
+        let storage = self
+            .storage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut keys = storage
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// A view of another storage that keeps its keys under a prefix, so several users can share one backend
+///
+/// Used by [`crate::auth::account_manager::AccountManager`] to keep the data of each Apple ID apart.
+#[derive(Clone)]
+pub struct NamespacedStorage {
+    inner: Arc<dyn SideloadingStorage>,
+    namespace: String,
+}
+
+impl NamespacedStorage {
+    pub fn new(inner: Arc<dyn SideloadingStorage>, namespace: &str) -> Self {
+        NamespacedStorage {
+            inner,
+            namespace: namespace.to_string(),
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}/{}", self.namespace, key)
+    }
+}
+
+impl SideloadingStorage for NamespacedStorage {
+    fn store(&self, key: &str, value: &str) -> Result<(), Report> {
+        self.inner.store(&self.key(key), value)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<String>, Report> {
+        self.inner.retrieve(&self.key(key))
+    }
+
+    fn store_data(&self, key: &str, value: &[u8]) -> Result<(), Report> {
+        self.inner.store_data(&self.key(key), value)
+    }
+
+    fn retrieve_data(&self, key: &str) -> Result<Option<Vec<u8>>, Report> {
+        self.inner.retrieve_data(&self.key(key))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Report> {
+        self.inner.delete(&self.key(key))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Report> {
+        let namespace = self.key("");
+        Ok(self
+            .inner
+            .list(&self.key(prefix))?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&namespace).map(str::to_string))
+            .collect())
+    }
+
+    fn delete_all(&self, prefix: &str) -> Result<usize, Report> {
+        self.inner.delete_all(&self.key(prefix))
+    }
+
+    fn flush(&self) -> Result<(), Report> {
+        self.inner.flush()
+    }
 }
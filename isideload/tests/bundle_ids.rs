@@ -0,0 +1,167 @@
+#![cfg(all(feature = "sign", feature = "test-util"))]
+
+use std::path::Path;
+
+use isideload::{
+    sideload::{application::Application, bundle::Bundle},
+    util::fixtures::{generate_app, minimal_macho},
+};
+use plist::{Dictionary, Value};
+use plist_macro::plist;
+
+const MAIN_ID: &str = "com.example.Widgets";
+const NEW_ID: &str = "com.example.Widgets.ABCDE12345";
+
+fn edit_info(bundle_dir: &Path, edit: impl FnOnce(&mut Dictionary)) {
+    let path = bundle_dir.join("Info.plist");
+    let mut info: Dictionary = plist::from_file(&path).unwrap();
+    edit(&mut info);
+    plist::to_file_xml(&path, &info).unwrap();
+}
+
+/// The fixture app, with a widget that points at itself and a watch app with an extension of its own
+fn widget_app(dir: &Path) -> Application {
+    let app_dir = generate_app(dir, MAIN_ID).unwrap();
+    edit_info(&app_dir.join("PlugIns/Widget.appex"), |info| {
+        let extension = plist!({
+            "NSExtensionPointIdentifier": "com.apple.widgetkit-extension",
+            "NSExtensionAttributes": {
+                "WKAppBundleIdentifier": format!("{}.widget", MAIN_ID),
+                "IntentsSupported": [format!("{}.widget", MAIN_ID), "INSendMessageIntent"],
+            },
+        });
+        info.insert("NSExtension".to_string(), extension);
+        info.insert("CFBundleURLName".to_string(), Value::from(MAIN_ID));
+    });
+
+    let watch_dir = app_dir.join("Watch/WatchApp.app");
+    let watch_ext_dir = watch_dir.join("PlugIns/WatchExtension.appex");
+    std::fs::create_dir_all(&watch_ext_dir).unwrap();
+    plist::to_file_xml(
+        watch_dir.join("Info.plist"),
+        &plist!({
+            "CFBundleIdentifier": format!("{}.watchkitapp", MAIN_ID),
+            "CFBundleName": "WatchApp",
+            "CFBundleExecutable": "WatchApp",
+            "WKWatchKitApp": true,
+            "WKCompanionAppBundleIdentifier": MAIN_ID,
+        }),
+    )
+    .unwrap();
+    std::fs::write(watch_dir.join("WatchApp"), minimal_macho()).unwrap();
+    plist::to_file_xml(
+        watch_ext_dir.join("Info.plist"),
+        &plist!({
+            "CFBundleIdentifier": format!("{}.watchkitapp.watchkitextension", MAIN_ID),
+            "CFBundleName": "WatchExtension",
+            "CFBundleExecutable": "WatchExtension",
+            "NSExtension": {
+                "NSExtensionPointIdentifier": "com.apple.watchkit",
+                "NSExtensionAttributes": {
+                    "WKAppBundleIdentifier": format!("{}.watchkitapp", MAIN_ID),
+                },
+            },
+        }),
+    )
+    .unwrap();
+    std::fs::write(watch_ext_dir.join("WatchExtension"), minimal_macho()).unwrap();
+
+    Application::new(app_dir).unwrap()
+}
+
+fn attribute<'a>(bundle: &'a Bundle, key: &str) -> &'a Value {
+    bundle
+        .app_info
+        .get("NSExtension")
+        .and_then(|e| e.as_dictionary())
+        .and_then(|e| e.get("NSExtensionAttributes"))
+        .and_then(|a| a.as_dictionary())
+        .and_then(|a| a.get(key))
+        .unwrap()
+}
+
+#[test]
+fn renames_nested_bundles() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut app = widget_app(dir.path());
+
+    app.update_bundle_id(MAIN_ID, NEW_ID).unwrap();
+
+    assert_eq!(app.main_bundle_id().unwrap(), NEW_ID);
+    let widget = &app.bundle.app_extensions()[0];
+    assert_eq!(
+        widget.bundle_identifier(),
+        Some(format!("{}.widget", NEW_ID).as_str())
+    );
+    let watch_app = &app.bundle.watch_apps()[0];
+    assert_eq!(
+        watch_app.bundle_identifier(),
+        Some(format!("{}.watchkitapp", NEW_ID).as_str())
+    );
+    assert_eq!(
+        watch_app.app_extensions()[0].bundle_identifier(),
+        Some(format!("{}.watchkitapp.watchkitextension", NEW_ID).as_str())
+    );
+}
+
+#[test]
+fn rewrites_references_to_renamed_bundles() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut app = widget_app(dir.path());
+
+    app.update_bundle_id(MAIN_ID, NEW_ID).unwrap();
+
+    let widget = &app.bundle.app_extensions()[0];
+    assert_eq!(
+        attribute(widget, "WKAppBundleIdentifier"),
+        &Value::from(format!("{}.widget", NEW_ID))
+    );
+    assert_eq!(
+        attribute(widget, "IntentsSupported"),
+        &Value::Array(vec![
+            Value::from(format!("{}.widget", NEW_ID)),
+            Value::from("INSendMessageIntent"),
+        ])
+    );
+
+    let watch_app = &app.bundle.watch_apps()[0];
+    assert_eq!(
+        watch_app.app_info.get("WKCompanionAppBundleIdentifier"),
+        Some(&Value::from(NEW_ID))
+    );
+    assert_eq!(
+        attribute(&watch_app.app_extensions()[0], "WKAppBundleIdentifier"),
+        &Value::from(format!("{}.watchkitapp", NEW_ID))
+    );
+}
+
+#[test]
+fn leaves_other_keys_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut app = widget_app(dir.path());
+
+    app.update_bundle_id(MAIN_ID, NEW_ID).unwrap();
+
+    // values outside NSExtension and the known keys are usually hardcoded in the binary too
+    assert_eq!(
+        app.bundle.app_extensions()[0]
+            .app_info
+            .get("CFBundleURLName"),
+        Some(&Value::from(MAIN_ID))
+    );
+}
+
+#[test]
+fn written_info_plists_keep_the_new_references() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut app = widget_app(dir.path());
+
+    app.update_bundle_id(MAIN_ID, NEW_ID).unwrap();
+    app.bundle.write_info_recursive().unwrap();
+
+    let reloaded = Bundle::new(app.bundle.bundle_dir.clone()).unwrap();
+    assert_eq!(
+        attribute(&reloaded.app_extensions()[0], "WKAppBundleIdentifier"),
+        &Value::from(format!("{}.widget", NEW_ID))
+    );
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "dev-api")]
+
+use std::time::{Duration, SystemTime};
+
+use isideload::{
+    dev::certificates::DevelopmentCertificate,
+    util::clock::{Clock, FixedClock},
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn certificate(expiration: SystemTime) -> DevelopmentCertificate {
+    DevelopmentCertificate {
+        name: None,
+        certificate_id: None,
+        serial_number: None,
+        machine_id: None,
+        machine_name: None,
+        cert_content: None,
+        certificate_platform: None,
+        certificate_type: None,
+        status: None,
+        status_code: None,
+        expiration_date: Some(expiration.into()),
+    }
+}
+
+#[test]
+fn fixed_clock_clones_share_time() {
+    let start = SystemTime::UNIX_EPOCH + 1000 * DAY;
+    let clock = FixedClock::new(start);
+    let shared = clock.clone();
+
+    clock.advance(DAY);
+    assert_eq!(shared.now(), start + DAY);
+    assert_eq!(shared.age(start), Some(DAY));
+    assert_eq!(shared.age(start + 2 * DAY), None);
+}
+
+#[test]
+fn expiry_follows_the_injected_clock() {
+    let start = SystemTime::UNIX_EPOCH + 1000 * DAY;
+    let clock = FixedClock::new(start);
+    // a free profile's 7 day boundary
+    let expiration = start + 7 * DAY;
+
+    assert!(!clock.expires_within(expiration, 2 * DAY));
+    clock.advance(5 * DAY);
+    assert!(clock.expires_within(expiration, 2 * DAY));
+    assert!(!clock.expires_within(expiration, Duration::ZERO));
+}
+
+#[test]
+fn certificate_expiry_uses_the_clock() {
+    let start = SystemTime::UNIX_EPOCH + 1000 * DAY;
+    let clock = FixedClock::new(start);
+    let cert = certificate(start + 7 * DAY);
+
+    assert!(!cert.is_expired(&clock));
+    clock.advance(7 * DAY);
+    assert!(cert.is_expired(&clock));
+    // other clocks aren't affected
+    assert!(!cert.is_expired(&FixedClock::new(start)));
+}
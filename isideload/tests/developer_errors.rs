@@ -0,0 +1,90 @@
+#![cfg(feature = "dev-api")]
+
+use isideload::{
+    SideloadError,
+    dev::errors::{DeveloperServicesError, LICENSE_AGREEMENT_URL, RetryHint},
+};
+use rootcause::prelude::*;
+
+#[test]
+fn classifies_known_codes() {
+    assert!(matches!(
+        DeveloperServicesError::from_result(7460, "too many certificates"),
+        DeveloperServicesError::MaxCertificatesReached { code: 7460, .. }
+    ));
+    for code in [35, 3008] {
+        assert!(matches!(
+            DeveloperServicesError::from_result(code, "already exists"),
+            DeveloperServicesError::AlreadyExists { .. }
+        ));
+    }
+    assert!(matches!(
+        DeveloperServicesError::from_result(9401, "not available"),
+        DeveloperServicesError::IdentifierUnavailable { .. }
+    ));
+    assert!(matches!(
+        DeveloperServicesError::from_result(9455, "accept the agreement"),
+        DeveloperServicesError::AgreementNotAccepted { .. }
+    ));
+    assert!(matches!(
+        DeveloperServicesError::from_result(3512, "invalid identifier"),
+        DeveloperServicesError::InvalidIdentifier { .. }
+    ));
+    assert!(matches!(
+        DeveloperServicesError::from_result(9120, "maximum App ID limit reached"),
+        DeveloperServicesError::AppIdLimitReached { code: 9120, .. }
+    ));
+    assert!(matches!(
+        DeveloperServicesError::from_result(8202, "maximum number of devices"),
+        DeveloperServicesError::DeviceLimitReached { code: 8202, .. }
+    ));
+}
+
+#[test]
+fn unknown_codes_keep_code_and_message() {
+    let error = DeveloperServicesError::from_result(1234, "something new");
+
+    assert!(matches!(error, DeveloperServicesError::Other { .. }));
+    assert_eq!(error.code(), 1234);
+    assert_eq!(error.message(), "something new");
+    assert_eq!(error.retry_hint(), RetryHint::Never);
+}
+
+#[test]
+fn retry_hints() {
+    let agreement = DeveloperServicesError::from_result(9455, "");
+    assert_eq!(agreement.retry_hint(), RetryHint::AfterUserAction);
+    assert_eq!(agreement.action_url(), Some(LICENSE_AGREEMENT_URL));
+
+    let max_certificates = DeveloperServicesError::from_result(7460, "");
+    assert_eq!(max_certificates.retry_hint(), RetryHint::AfterUserAction);
+    assert_eq!(max_certificates.action_url(), None);
+
+    for code in [9120, 8202] {
+        let limit = DeveloperServicesError::from_result(code, "");
+        assert_eq!(limit.retry_hint(), RetryHint::AfterUserAction);
+        assert!(!limit.is_retryable());
+    }
+
+    assert!(DeveloperServicesError::from_result(-22421, "").is_retryable());
+    assert!(!DeveloperServicesError::from_result(35, "").is_retryable());
+}
+
+#[test]
+fn finds_the_error_in_a_report() {
+    let report = report!(SideloadError::DeveloperError(
+        7460,
+        "too many certificates".to_string()
+    ))
+    .context("Failed to create certificate")
+    .into_dynamic();
+
+    let error = DeveloperServicesError::from_report(&report);
+    assert!(matches!(
+        error,
+        Some(DeveloperServicesError::MaxCertificatesReached { code: 7460, .. })
+    ));
+
+    let unrelated = report!("Failed to read the app").into_dynamic();
+    assert_eq!(DeveloperServicesError::from_report(&unrelated), None);
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "sign")]
+
+use isideload::sideload::entitlements::merge_entitlements;
+use plist::{Dictionary, Value};
+
+fn dict(entries: &[(&str, Value)]) -> Dictionary {
+    entries
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect()
+}
+
+fn strings(values: &[&str]) -> Value {
+    Value::Array(values.iter().map(|v| Value::from(*v)).collect())
+}
+
+#[test]
+fn keeps_only_requested_entitlements() {
+    let requested = dict(&[("com.apple.developer.healthkit", Value::Boolean(true))]);
+    let granted = dict(&[
+        ("com.apple.developer.healthkit", Value::Boolean(true)),
+        ("com.apple.developer.siri", Value::Boolean(true)),
+        ("get-task-allow", Value::Boolean(true)),
+    ]);
+
+    let merged = merge_entitlements(Some(&requested), &granted);
+
+    assert!(merged.contains_key("com.apple.developer.healthkit"));
+    assert!(!merged.contains_key("com.apple.developer.siri"));
+    // profile entitlements are always kept
+    assert_eq!(merged.get("get-task-allow"), Some(&Value::Boolean(true)));
+}
+
+#[test]
+fn narrows_granted_lists_to_the_requested_entries() {
+    let requested = dict(&[(
+        "com.apple.developer.healthkit.access",
+        strings(&["health-records"]),
+    )]);
+    let granted = dict(&[(
+        "com.apple.developer.healthkit.access",
+        strings(&["health-records", "clinical"]),
+    )]);
+
+    let merged = merge_entitlements(Some(&requested), &granted);
+
+    assert_eq!(
+        merged.get("com.apple.developer.healthkit.access"),
+        Some(&strings(&["health-records"]))
+    );
+}
+
+#[test]
+fn drops_lists_without_a_requested_entry() {
+    let requested = dict(&[(
+        "com.apple.developer.healthkit.access",
+        strings(&["health-records"]),
+    )]);
+    let granted = dict(&[(
+        "com.apple.developer.healthkit.access",
+        strings(&["clinical"]),
+    )]);
+
+    let merged = merge_entitlements(Some(&requested), &granted);
+
+    assert!(!merged.contains_key("com.apple.developer.healthkit.access"));
+}
+
+#[test]
+fn uses_everything_granted_without_requested_entitlements() {
+    let granted = dict(&[("com.apple.developer.siri", Value::Boolean(true))]);
+
+    assert_eq!(merge_entitlements(None, &granted), granted);
+}
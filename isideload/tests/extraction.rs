@@ -0,0 +1,187 @@
+#![cfg(all(feature = "sign", feature = "test-util"))]
+
+use std::{
+    io::{Cursor, Write},
+    path::Path,
+};
+
+use isideload::{
+    SideloadError,
+    sideload::application::{Application, ExtractOptions},
+    util::fixtures::generate_app,
+};
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+/// Add every file below `dir` to the archive, named `<prefix>/<path relative to dir>`
+fn add_dir(zip: &mut ZipWriter<Cursor<Vec<u8>>>, dir: &Path, prefix: &str) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = format!("{}/{}", prefix, path.file_name().unwrap().to_string_lossy());
+        if path.is_dir() {
+            add_dir(zip, &path, &name);
+        } else {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(&std::fs::read(&path).unwrap()).unwrap();
+        }
+    }
+}
+
+fn add_file(zip: &mut ZipWriter<Cursor<Vec<u8>>>, name: &str, data: &[u8]) {
+    zip.start_file(name, SimpleFileOptions::default()).unwrap();
+    zip.write_all(data).unwrap();
+}
+
+/// An IPA of the fixture app, with the extra files App Store exports contain
+fn fixture_ipa(bundle_id: &str) -> Vec<u8> {
+    let source = tempfile::tempdir().unwrap();
+    let app_dir = generate_app(source.path(), bundle_id).unwrap();
+    let app_name = app_dir.file_name().unwrap().to_string_lossy().to_string();
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    add_dir(&mut zip, &app_dir, &format!("Payload/{}", app_name));
+    let mut metadata = Vec::new();
+    plist::to_writer_xml(
+        &mut metadata,
+        &plist::Value::Dictionary(
+            [("itemName".to_string(), plist::Value::from("Fixture"))]
+                .into_iter()
+                .collect(),
+        ),
+    )
+    .unwrap();
+    add_file(&mut zip, "iTunesMetadata.plist", &metadata);
+    add_file(
+        &mut zip,
+        "SwiftSupport/iphoneos/libswiftCore.dylib",
+        b"swift",
+    );
+    add_file(&mut zip, "Symbols/ABCD.symbols", b"symbols");
+    add_file(
+        &mut zip,
+        &format!("__MACOSX/Payload/{}/._Info.plist", app_name),
+        b"fork",
+    );
+    zip.finish().unwrap().into_inner()
+}
+
+#[test]
+fn extracts_only_what_signing_needs() {
+    let working_dir = tempfile::tempdir().unwrap();
+    let options = ExtractOptions::default()
+        .working_dir(working_dir.path().to_path_buf())
+        .skip_symbols(true);
+
+    let app = Application::from_reader_with_options(
+        Cursor::new(fixture_ipa("com.example.Fixture")),
+        "Fixture.ipa",
+        &options,
+    )
+    .unwrap();
+
+    let temp_path = app.temp_path.clone().unwrap();
+    assert!(temp_path.starts_with(working_dir.path()));
+    assert_eq!(app.main_bundle_id().unwrap(), "com.example.Fixture");
+    assert_eq!(app.bundle.app_extensions().len(), 1);
+    assert_eq!(app.bundle.frameworks().len(), 1);
+    assert!(app.itunes_metadata.is_some());
+    assert!(
+        temp_path
+            .join("Payload/Fixture.app/PlugIns/Widget.appex/Widget")
+            .is_file()
+    );
+    assert!(!temp_path.join("SwiftSupport").exists());
+    assert!(!temp_path.join("Symbols").exists());
+    assert!(!temp_path.join("__MACOSX").exists());
+}
+
+#[test]
+fn keeps_symbols_unless_skipped() {
+    let working_dir = tempfile::tempdir().unwrap();
+    let options = ExtractOptions::default().working_dir(working_dir.path().to_path_buf());
+
+    let app = Application::from_reader_with_options(
+        Cursor::new(fixture_ipa("com.example.Fixture")),
+        "Fixture.ipa",
+        &options,
+    )
+    .unwrap();
+
+    assert!(
+        app.temp_path
+            .unwrap()
+            .join("Symbols/ABCD.symbols")
+            .is_file()
+    );
+}
+
+#[test]
+fn loads_apps_at_the_root_of_the_archive() {
+    let source = tempfile::tempdir().unwrap();
+    let app_dir = generate_app(source.path(), "com.example.Root").unwrap();
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    add_dir(&mut zip, &app_dir, "Root.app");
+    let ipa = zip.finish().unwrap().into_inner();
+
+    let working_dir = tempfile::tempdir().unwrap();
+    let options = ExtractOptions::default().working_dir(working_dir.path().to_path_buf());
+    let app =
+        Application::from_reader_with_options(Cursor::new(ipa), "Root.ipa", &options).unwrap();
+
+    assert_eq!(app.main_bundle_id().unwrap(), "com.example.Root");
+}
+
+#[test]
+fn describes_archives_without_an_app() {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    add_file(&mut zip, "README.txt", b"not an app");
+    let ipa = zip.finish().unwrap().into_inner();
+
+    let working_dir = tempfile::tempdir().unwrap();
+    let options = ExtractOptions::default().working_dir(working_dir.path().to_path_buf());
+    let Err(error) = Application::from_reader_with_options(Cursor::new(ipa), "Empty.ipa", &options)
+    else {
+        panic!("Loaded an archive without an app");
+    };
+
+    match error.downcast_current_context::<SideloadError>() {
+        Some(SideloadError::InvalidBundle(message)) => assert!(message.contains("README.txt")),
+        other => panic!("Expected an invalid bundle error, got {:?}", other),
+    }
+    // the extracted directory is removed when loading fails
+    assert_eq!(std::fs::read_dir(working_dir.path()).unwrap().count(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn skips_symlinks_pointing_outside_the_archive() {
+    let source = tempfile::tempdir().unwrap();
+    let app_dir = generate_app(source.path(), "com.example.Fixture").unwrap();
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    add_dir(&mut zip, &app_dir, "Payload/Fixture.app");
+    zip.add_symlink(
+        "Payload/Fixture.app/escape",
+        "../../../../etc",
+        SimpleFileOptions::default(),
+    )
+    .unwrap();
+    zip.add_symlink(
+        "Payload/Fixture.app/Current",
+        "Frameworks",
+        SimpleFileOptions::default(),
+    )
+    .unwrap();
+    let ipa = zip.finish().unwrap().into_inner();
+
+    let working_dir = tempfile::tempdir().unwrap();
+    let options = ExtractOptions::default().working_dir(working_dir.path().to_path_buf());
+    let app =
+        Application::from_reader_with_options(Cursor::new(ipa), "Fixture.ipa", &options).unwrap();
+
+    let app_dir = app.bundle.bundle_dir;
+    assert!(std::fs::symlink_metadata(app_dir.join("escape")).is_err());
+    assert!(
+        std::fs::symlink_metadata(app_dir.join("Current"))
+            .unwrap()
+            .is_symlink()
+    );
+}
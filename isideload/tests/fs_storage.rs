@@ -0,0 +1,39 @@
+#![cfg(feature = "fs-storage")]
+
+use isideload::util::{fs_storage::FsStorage, storage::SideloadingStorage};
+
+#[test]
+fn keys_differing_only_in_extension_are_kept_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = FsStorage::new(dir.path().to_path_buf());
+
+    storage.store("certs/key.pem", "key").unwrap();
+    storage.store("certs/key.der", "der").unwrap();
+    storage
+        .store("certs/key.tmp", "not a temporary file")
+        .unwrap();
+
+    assert_eq!(
+        storage.retrieve("certs/key.pem").unwrap().as_deref(),
+        Some("key")
+    );
+    assert_eq!(
+        storage.retrieve("certs/key.der").unwrap().as_deref(),
+        Some("der")
+    );
+    assert_eq!(
+        storage.list("certs/").unwrap(),
+        vec!["certs/key.der", "certs/key.pem", "certs/key.tmp"]
+    );
+}
+
+#[test]
+fn skips_interrupted_writes_when_listing() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = FsStorage::new(dir.path().to_path_buf());
+
+    storage.store("certs/key.pem", "key").unwrap();
+    std::fs::write(dir.path().join("certs/key.pem.1234.0.tmp"), "partial").unwrap();
+
+    assert_eq!(storage.list("certs/").unwrap(), vec!["certs/key.pem"]);
+}
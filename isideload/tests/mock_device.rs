@@ -0,0 +1,139 @@
+#![cfg(feature = "test-util")]
+
+use std::{future::Future, sync::Mutex};
+
+use isideload::{
+    SideloadError,
+    sideload::install::{
+        InstallOptions, InstallProgress, UploadVerification, install_app_with_options,
+    },
+    util::{
+        device::{
+            IdeviceInfo,
+            app_manager::{list_installed_apps, lookup_app, uninstall_app},
+        },
+        fixtures::{generate_app, minimal_macho},
+        mock_device::MockDeviceProvider,
+    },
+};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Runtime::new().unwrap().block_on(future)
+}
+
+#[test]
+fn reports_lockdown_values() {
+    let device = MockDeviceProvider::new()
+        .unwrap()
+        .with_value("DeviceName", "Test iPhone")
+        .with_value("UniqueDeviceID", "00008030-0000000000000001");
+
+    let info = block_on(IdeviceInfo::from_device(&device)).unwrap();
+
+    assert_eq!(info.name, "Test iPhone");
+    assert_eq!(info.udid, "00008030-0000000000000001");
+    assert_eq!(info.product_type.as_deref(), Some("iPhone15,2"));
+}
+
+#[test]
+fn installs_uploaded_apps() {
+    let dir = tempfile::tempdir().unwrap();
+    let app_dir = generate_app(dir.path(), "com.example.Fixture").unwrap();
+    let device = MockDeviceProvider::new().unwrap();
+    let phases = Mutex::new(Vec::new());
+    let options = InstallOptions {
+        upload_concurrency: 2,
+        verification: UploadVerification::Critical,
+        ..Default::default()
+    };
+
+    block_on(install_app_with_options(
+        &device,
+        &app_dir,
+        &options,
+        |progress| {
+            if let InstallProgress::Installing(status) = progress {
+                phases.lock().unwrap().push(status.phase);
+            }
+        },
+    ))
+    .unwrap();
+
+    assert_eq!(device.installed_apps(), vec!["com.example.Fixture"]);
+    assert_eq!(
+        device.file("PublicStaging/Fixture.app/Fixture"),
+        Some(minimal_macho())
+    );
+    assert!(
+        device
+            .file("PublicStaging/Fixture.app/PlugIns/Widget.appex/Info.plist")
+            .is_some()
+    );
+    let phases = phases.into_inner().unwrap();
+    assert_eq!(
+        phases.first().map(String::as_str),
+        Some("CreatingStagingDirectory")
+    );
+    assert!(phases.iter().any(|phase| phase == "VerifyingApplication"));
+}
+
+#[test]
+fn reports_installd_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let app_dir = generate_app(dir.path(), "com.example.Fixture").unwrap();
+    let device = MockDeviceProvider::new().unwrap().fail_installs(
+        "ApplicationVerificationFailed",
+        "The code signature is invalid",
+    );
+
+    let error = block_on(install_app_with_options(
+        &device,
+        &app_dir,
+        &InstallOptions::default(),
+        |_| {},
+    ))
+    .unwrap_err();
+
+    let status = error.iter_reports().find_map(|node| {
+        match node.downcast_current_context::<SideloadError>() {
+            Some(SideloadError::InstallFailed(status)) => Some(status.clone()),
+            _ => None,
+        }
+    });
+    let status = status.expect("Expected an install failure");
+    assert_eq!(
+        status.error.as_deref(),
+        Some("ApplicationVerificationFailed")
+    );
+    assert_eq!(
+        status.error_description.as_deref(),
+        Some("The code signature is invalid")
+    );
+    assert!(device.installed_apps().is_empty());
+}
+
+#[test]
+fn looks_up_and_uninstalls_apps() {
+    let device = MockDeviceProvider::new()
+        .unwrap()
+        .with_installed_app("com.example.First")
+        .with_installed_app("com.example.Second");
+
+    block_on(async {
+        let apps = list_installed_apps(&device).await.unwrap();
+        let bundle_ids: Vec<_> = apps.iter().map(|app| app.bundle_id.as_str()).collect();
+        assert_eq!(bundle_ids, vec!["com.example.First", "com.example.Second"]);
+
+        let app = lookup_app(&device, "com.example.First").await.unwrap();
+        assert_eq!(app.and_then(|app| app.version), Some("1.0".to_string()));
+
+        uninstall_app(&device, "com.example.First").await.unwrap();
+        assert!(
+            lookup_app(&device, "com.example.First")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    });
+    assert_eq!(device.installed_apps(), vec!["com.example.Second"]);
+}
@@ -0,0 +1,138 @@
+#![cfg(all(feature = "sign", feature = "test-util"))]
+
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use isideload::{
+    dev::teams::FREE_PROFILE_VALIDITY,
+    sideload::{
+        SideloaderBuilder,
+        sideloader::{RefreshOutcome, Sideloader},
+    },
+    util::{
+        clock::FixedClock,
+        fixtures::generate_app,
+        mock_developer::{MOCK_TEAM_ID, MockDeveloperServices},
+        mock_device::MockDeviceProvider,
+        storage::InMemoryStorage,
+    },
+};
+
+const EMAIL: &str = "test@example.com";
+const MACHINE_NAME: &str = "isideload-test";
+const PROFILE: &str = "PublicStaging/Fixture.app/embedded.mobileprovision";
+const CODE_RESOURCES: &str = "PublicStaging/Fixture.app/_CodeSignature/CodeResources";
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Runtime::new().unwrap().block_on(future)
+}
+
+fn sideloader(developer: &MockDeveloperServices, clock: &FixedClock) -> Sideloader {
+    let storage = InMemoryStorage::new();
+    developer
+        .issue_certificate(&storage, EMAIL, MACHINE_NAME)
+        .unwrap();
+    SideloaderBuilder::new(developer.session().unwrap(), EMAIL.to_string())
+        .storage(Box::new(storage))
+        .machine_name(MACHINE_NAME.to_string())
+        .clock(Arc::new(clock.clone()))
+        .build()
+}
+
+/// A fresh copy of the fixture app, since signing an app directory consumes it
+fn fixture_app(dir: &Path, copy: &str) -> PathBuf {
+    generate_app(&dir.join(copy), "com.example.Fixture").unwrap()
+}
+
+fn bundle_ids(sideloader: &Sideloader, device: &MockDeviceProvider) -> Vec<String> {
+    block_on(sideloader.apps_needing_refresh(device))
+        .unwrap()
+        .into_iter()
+        .map(|app| app.bundle_id)
+        .collect()
+}
+
+#[test]
+fn refreshes_apps_once_their_profile_is_about_to_expire() {
+    let start = SystemTime::now();
+    let clock = FixedClock::new(start);
+    let developer = MockDeveloperServices::new().with_clock(Arc::new(clock.clone()));
+    let device = MockDeviceProvider::new().unwrap();
+    let mut sideloader = sideloader(&developer, &clock);
+    let dir = tempfile::tempdir().unwrap();
+    let bundle_id = format!("com.example.Fixture.{}", MOCK_TEAM_ID);
+
+    block_on(sideloader.install_app(&device, fixture_app(dir.path(), "install"), false)).unwrap();
+
+    assert_eq!(device.installed_apps(), vec![bundle_id.clone()]);
+    assert_eq!(developer.devices(), vec![device.udid()]);
+    assert_eq!(
+        developer.request_count("downloadTeamProvisioningProfile"),
+        1
+    );
+    // the app and its extension
+    assert_eq!(developer.app_ids().len(), 2);
+    let first_profile = device.file(PROFILE).unwrap();
+    let first_signature = device.file(CODE_RESOURCES).unwrap();
+
+    // three days before the profile expires, it is outside the default two day margin
+    clock.set(start + FREE_PROFILE_VALIDITY - Duration::from_secs(3 * 24 * 60 * 60));
+    assert!(bundle_ids(&sideloader, &device).is_empty());
+    let outcome =
+        block_on(sideloader.refresh_app(&device, &bundle_id, fixture_app(dir.path(), "early")))
+            .unwrap();
+    assert!(matches!(outcome, RefreshOutcome::NotNeeded { .. }));
+    assert_eq!(
+        developer.request_count("downloadTeamProvisioningProfile"),
+        1
+    );
+
+    clock.set(start + FREE_PROFILE_VALIDITY - Duration::from_secs(24 * 60 * 60));
+    assert_eq!(bundle_ids(&sideloader, &device), vec![bundle_id.clone()]);
+
+    let outcome =
+        block_on(sideloader.refresh_app(&device, &bundle_id, fixture_app(dir.path(), "refresh")))
+            .unwrap();
+
+    assert_eq!(outcome, RefreshOutcome::Refreshed(None));
+    // the cached profile expires within the margin too, so a new one was downloaded
+    assert_eq!(
+        developer.request_count("downloadTeamProvisioningProfile"),
+        2
+    );
+    // the app IDs registered by the install are reused
+    assert_eq!(developer.request_count("addAppId"), 2);
+    assert_eq!(device.installed_apps(), vec![bundle_id.clone()]);
+    assert_ne!(device.file(PROFILE).unwrap(), first_profile);
+    assert_ne!(device.file(CODE_RESOURCES).unwrap(), first_signature);
+    // the install record now has the new profile's expiry
+    assert!(bundle_ids(&sideloader, &device).is_empty());
+    let outcome =
+        block_on(sideloader.refresh_app(&device, &bundle_id, fixture_app(dir.path(), "again")))
+            .unwrap();
+    assert!(matches!(outcome, RefreshOutcome::NotNeeded { .. }));
+}
+
+#[test]
+fn leaves_apps_it_did_not_install_alone() {
+    let clock = FixedClock::new(SystemTime::now());
+    let developer = MockDeveloperServices::new().with_clock(Arc::new(clock.clone()));
+    let bundle_id = format!("com.example.Fixture.{}", MOCK_TEAM_ID);
+    let device = MockDeviceProvider::new()
+        .unwrap()
+        .with_installed_app(&bundle_id);
+    let mut sideloader = sideloader(&developer, &clock);
+    let dir = tempfile::tempdir().unwrap();
+
+    assert!(bundle_ids(&sideloader, &device).is_empty());
+    let outcome =
+        block_on(sideloader.refresh_app(&device, &bundle_id, fixture_app(dir.path(), "refresh")))
+            .unwrap();
+
+    assert_eq!(outcome, RefreshOutcome::NotManaged);
+    assert!(developer.requests().is_empty());
+}
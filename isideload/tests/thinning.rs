@@ -0,0 +1,148 @@
+#![cfg(all(feature = "sign", feature = "test-util"))]
+
+use std::path::Path;
+
+use isideload::{
+    sideload::thinning::thin_app,
+    util::fixtures::{generate_app, minimal_macho},
+};
+
+const FAT_MAGIC: u32 = 0xcafebabe;
+const CPU_TYPE_X86_64: u32 = 0x01000007;
+const CPU_TYPE_ARM64: u32 = 0x0100000c;
+const CPU_SUBTYPE_ARM64E: u32 = 2;
+const ALIGN: u32 = 14;
+
+/// Build a universal binary from `(cpu type, cpu subtype, slice)`s, each aligned to 2^14 bytes
+fn fat_binary(slices: &[(u32, u32, &[u8])]) -> Vec<u8> {
+    let mut offsets = Vec::new();
+    let mut position = 8 + 20 * slices.len();
+    for (_, _, slice) in slices {
+        position = position.next_multiple_of(1 << ALIGN);
+        offsets.push(position);
+        position += slice.len();
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+    data.extend_from_slice(&(slices.len() as u32).to_be_bytes());
+    for ((cpu_type, cpu_subtype, slice), offset) in slices.iter().zip(&offsets) {
+        for value in [
+            *cpu_type,
+            *cpu_subtype,
+            *offset as u32,
+            slice.len() as u32,
+            ALIGN,
+        ] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    for ((_, _, slice), offset) in slices.iter().zip(&offsets) {
+        data.resize(*offset, 0);
+        data.extend_from_slice(slice);
+    }
+    data
+}
+
+fn fixture_app(dir: &Path) -> std::path::PathBuf {
+    generate_app(dir, "com.example.Fixture").unwrap()
+}
+
+#[test]
+fn keeps_only_the_arm64_slice() {
+    let dir = tempfile::tempdir().unwrap();
+    let app_dir = fixture_app(dir.path());
+    let arm64 = minimal_macho();
+    let executable = app_dir.join("Fixture");
+    let fat = fat_binary(&[
+        (CPU_TYPE_X86_64, 3, &[0xaa; 64]),
+        (CPU_TYPE_ARM64, 0, &arm64),
+    ]);
+    std::fs::write(&executable, &fat).unwrap();
+
+    let report = thin_app(&app_dir).unwrap();
+
+    assert_eq!(report.thinned_binaries, vec!["Fixture".to_string()]);
+    assert_eq!(std::fs::read(&executable).unwrap(), arm64);
+    assert_eq!(report.bytes_saved, (fat.len() - arm64.len()) as u64);
+}
+
+#[test]
+fn keeps_every_arm64_slice() {
+    let dir = tempfile::tempdir().unwrap();
+    let app_dir = fixture_app(dir.path());
+    let arm64 = minimal_macho();
+    let arm64e = minimal_macho();
+    let executable = app_dir.join("Fixture");
+    std::fs::write(
+        &executable,
+        fat_binary(&[
+            (CPU_TYPE_ARM64, 0, &arm64),
+            (CPU_TYPE_X86_64, 3, &[0xaa; 64]),
+            (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64E, &arm64e),
+        ]),
+    )
+    .unwrap();
+
+    thin_app(&app_dir).unwrap();
+
+    assert_eq!(
+        std::fs::read(&executable).unwrap(),
+        fat_binary(&[
+            (CPU_TYPE_ARM64, 0, &arm64),
+            (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64E, &arm64e),
+        ])
+    );
+}
+
+#[test]
+fn leaves_binaries_without_slices_to_remove_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let app_dir = fixture_app(dir.path());
+    let executable = app_dir.join("Fixture");
+    let only_arm64 = fat_binary(&[(CPU_TYPE_ARM64, 0, &minimal_macho())]);
+    std::fs::write(&executable, &only_arm64).unwrap();
+    let no_arm64 = fat_binary(&[(CPU_TYPE_X86_64, 3, &[0xaa; 64])]);
+    let framework = app_dir.join("Frameworks/Dummy.framework/Dummy");
+    std::fs::write(&framework, &no_arm64).unwrap();
+
+    let report = thin_app(&app_dir).unwrap();
+
+    assert!(report.thinned_binaries.is_empty());
+    assert_eq!(std::fs::read(&executable).unwrap(), only_arm64);
+    assert_eq!(std::fs::read(&framework).unwrap(), no_arm64);
+    // thin binaries aren't touched either
+    assert_eq!(
+        std::fs::read(app_dir.join("PlugIns/Widget.appex/Widget")).unwrap(),
+        minimal_macho()
+    );
+}
+
+#[test]
+fn removes_symbols_and_watch_placeholders() {
+    let dir = tempfile::tempdir().unwrap();
+    let app_dir = fixture_app(dir.path());
+    std::fs::write(app_dir.join("ABCD.symbols"), [0; 100]).unwrap();
+    let placeholder = app_dir.join("Watch").join("com.apple.WatchPlaceholder");
+    std::fs::create_dir_all(&placeholder).unwrap();
+    std::fs::write(placeholder.join("Placeholder"), [0; 50]).unwrap();
+    // Watch apps run arm64_32, so their binaries are never thinned
+    let watch_app = app_dir.join("Watch").join("WatchApp.app");
+    std::fs::create_dir_all(&watch_app).unwrap();
+    let watch_binary = fat_binary(&[
+        (CPU_TYPE_X86_64, 3, &[0xaa; 64]),
+        (CPU_TYPE_ARM64, 0, &minimal_macho()),
+    ]);
+    std::fs::write(watch_app.join("WatchApp"), &watch_binary).unwrap();
+
+    let report = thin_app(&app_dir).unwrap();
+
+    assert_eq!(report.bytes_saved, 150);
+    assert_eq!(report.removed.len(), 2);
+    assert!(!app_dir.join("ABCD.symbols").exists());
+    assert!(!placeholder.exists());
+    assert_eq!(
+        std::fs::read(watch_app.join("WatchApp")).unwrap(),
+        watch_binary
+    );
+}